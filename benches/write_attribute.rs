@@ -0,0 +1,43 @@
+//! Time `Cfg::write_attribute` on a large definition
+//!
+//! Run with `cargo bench --bench write_attribute`.
+
+use canpi_config::Cfg;
+use std::time::Instant;
+
+/// Number of items in the definition
+const ITEMS: usize = 5000;
+/// Number of attributes written
+const WRITES: u32 = 2000;
+
+fn main() {
+    let items: Vec<String> = (0..ITEMS)
+        .map(|i| {
+            format!(
+                r#""key{}": {{"prompt": "Item {}", "tooltip": "What item {} is for",
+                "current": "1", "default": "1", "format": "[0-9]+", "action": "Edit"}}"#,
+                i, i, i
+            )
+        })
+        .collect();
+    let defn = format!("{{{}}}", items.join(","));
+    let mut cfg = Cfg::new();
+    cfg.load_configuration_from_str("", &defn)
+        .expect("configuration failed to load");
+    let mut attr = cfg.read_attribute("key0").expect("key0 defined").clone();
+
+    let start = Instant::now();
+    for i in 0..WRITES {
+        attr.current = (i % 10).to_string();
+        cfg.write_attribute(format!("key{}", i as usize % ITEMS), &attr)
+            .expect("write_attribute failed");
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "write_attribute: {} writes on {} items in {:?} ({:?} per write)",
+        WRITES,
+        ITEMS,
+        elapsed,
+        elapsed / WRITES
+    );
+}
@@ -0,0 +1,249 @@
+//! `#[derive(CanpiConfig)]` and `#[derive(CanpiDefinition)]`
+//!
+//! `CanpiConfig` implements `canpi_config::CanpiConfig` for a struct with named fields.  Each
+//! field is read from the configuration item of the same name with `Cfg::try_get`, and written
+//! back from its `Display` form.  `CanpiDefinition` implements `canpi_config::CanpiDefinition`,
+//! giving the attribute definition of each field.  Field attributes:
+//!
+//! - `#[canpi(key = "canid")]` - the configuration item the field maps to
+//! - `#[canpi(skip)]` - leave the field out; it is `Default::default()` after `from_cfg`
+//! - `#[canpi(prompt = "...", tooltip = "...")]` - text shown on the form; the prompt defaults
+//!   to the key and the tooltip to the field's doc comment
+//! - `#[canpi(default = "...")]` - the default value, empty if not given
+//! - `#[canpi(format = "...")]` - the regular expression values must match, or a named format
+//!   such as `port`; `.*` if not given
+//! - `#[canpi(action = "Edit")]` - `Edit` (the default), `Display` or `Hide`
+//! - `#[canpi(kind = "Integer")]` - `Text` (the default), `Integer`, `Decimal`,
+//!   `DateTime`, `Boolean` or `IpAddr`
+//! - `#[canpi(section = "apmode")]` - the INI section the item is written to
+//! - `#[canpi(units = "ms", placeholder = "...")]` - shown beside and in an empty edit box
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Expr, Fields, Ident, Lit, LitStr, Meta};
+
+struct FieldMap {
+    ident: Ident,
+    key: Option<String>,
+    prompt: Option<String>,
+    tooltip: Option<String>,
+    default: String,
+    format: String,
+    action: Ident,
+    kind: Ident,
+    section: Option<String>,
+    units: Option<String>,
+    placeholder: Option<String>,
+}
+
+/// The text of the field's doc comment, lines joined with spaces
+fn doc_comment(field: &syn::Field) -> String {
+    let lines: Vec<String> = field
+        .attrs
+        .iter()
+        .filter(|a| a.path().is_ident("doc"))
+        .filter_map(|a| match &a.meta {
+            Meta::NameValue(nv) => match &nv.value {
+                Expr::Lit(e) => match &e.lit {
+                    Lit::Str(s) => Some(s.value().trim().to_string()),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    lines.join(" ")
+}
+
+fn variant(lit: &LitStr, allowed: &[&str]) -> syn::Result<Ident> {
+    let value = lit.value();
+    if allowed.contains(&value.as_str()) {
+        Ok(Ident::new(&value, Span::call_site()))
+    } else {
+        Err(Error::new_spanned(
+            lit,
+            format!("expected one of {}", allowed.join(", ")),
+        ))
+    }
+}
+
+fn field_map(field: &syn::Field) -> syn::Result<FieldMap> {
+    let ident = field.ident.clone().expect("named field");
+    let mut map = FieldMap {
+        key: Some(ident.to_string()),
+        ident,
+        prompt: None,
+        tooltip: None,
+        default: String::new(),
+        format: ".*".to_string(),
+        action: Ident::new("Edit", Span::call_site()),
+        kind: Ident::new("Text", Span::call_site()),
+        section: None,
+        units: None,
+        placeholder: None,
+    };
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("canpi")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                map.key = None;
+                return Ok(());
+            }
+            let name = meta
+                .path
+                .get_ident()
+                .map(Ident::to_string)
+                .unwrap_or_default();
+            let lit = meta.value()?.parse::<LitStr>()?;
+            match name.as_str() {
+                "key" => map.key = Some(lit.value()),
+                "prompt" => map.prompt = Some(lit.value()),
+                "tooltip" => map.tooltip = Some(lit.value()),
+                "default" => map.default = lit.value(),
+                "format" => map.format = lit.value(),
+                "action" => map.action = variant(&lit, &["Edit", "Display", "Hide"])?,
+                "kind" => {
+                    map.kind = variant(
+                        &lit,
+                        &[
+                            "Text", "Integer", "Decimal", "DateTime", "Boolean", "IpAddr",
+                        ],
+                    )?
+                }
+                "section" => map.section = Some(lit.value()),
+                "units" => map.units = Some(lit.value()),
+                "placeholder" => map.placeholder = Some(lit.value()),
+                _ => return Err(meta.error("unknown canpi attribute")),
+            }
+            Ok(())
+        })?;
+    }
+    if map.tooltip.is_none() {
+        map.tooltip = Some(doc_comment(field));
+    }
+    Ok(map)
+}
+
+fn field_maps(input: &DeriveInput, derive: &str) -> syn::Result<Vec<FieldMap>> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    format!("{} needs a struct with named fields", derive),
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                format!("{} can only be derived for structs", derive),
+            ))
+        }
+    };
+    fields.iter().map(field_map).collect()
+}
+
+fn expand_config(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let maps = field_maps(&input, "CanpiConfig")?;
+
+    let reads = maps.iter().map(|m| {
+        let ident = &m.ident;
+        match &m.key {
+            Some(key) => quote! { #ident: cfg.try_get(#key)? },
+            None => quote! { #ident: ::std::default::Default::default() },
+        }
+    });
+    let mapped: Vec<_> = maps.iter().filter(|m| m.key.is_some()).collect();
+    let keys = mapped.iter().map(|m| m.key.as_ref().unwrap());
+    let idents = mapped.iter().map(|m| &m.ident);
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::canpi_config::CanpiConfig for #name #ty_generics #where_clause {
+            fn from_cfg(cfg: &::canpi_config::Cfg) -> ::std::result::Result<Self, ::canpi_config::CfgError> {
+                ::std::result::Result::Ok(#name {
+                    #(#reads,)*
+                })
+            }
+
+            fn apply_to(&self, cfg: &mut ::canpi_config::Cfg) -> ::std::result::Result<(), ::canpi_config::CfgError> {
+                let values = [#(self.#idents.to_string()),*];
+                let keys = [#(#keys),*];
+                let changes: ::std::vec::Vec<(&str, &str)> = keys
+                    .iter()
+                    .zip(values.iter())
+                    .map(|(k, v)| (*k, v.as_str()))
+                    .collect();
+                cfg.apply_changes(&changes, ::std::option::Option::None)
+            }
+        }
+    })
+}
+
+fn expand_definition(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let maps = field_maps(&input, "CanpiDefinition")?;
+
+    let inserts = maps.iter().filter_map(|m| {
+        let key = m.key.as_ref()?;
+        let prompt = m.prompt.as_ref().unwrap_or(key);
+        let tooltip = m.tooltip.as_deref().unwrap_or_default();
+        let (default, format, action, kind) = (&m.default, &m.format, &m.action, &m.kind);
+        let optional = |text: &Option<String>| match text {
+            Some(s) => quote! { ::std::option::Option::Some(#s.into()) },
+            None => quote! { ::std::option::Option::None },
+        };
+        let (section, units, placeholder) = (
+            optional(&m.section),
+            optional(&m.units),
+            optional(&m.placeholder),
+        );
+        Some(quote! {
+            let mut attr = ::canpi_config::Attribute::new(
+                #prompt,
+                #tooltip,
+                #default,
+                #format,
+                ::canpi_config::ActionBehaviour::#action,
+            );
+            attr.kind = ::canpi_config::ValueKind::#kind;
+            attr.section = #section;
+            attr.units = #units;
+            attr.placeholder = #placeholder;
+            defn.insert(#key.to_string(), attr);
+        })
+    });
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::canpi_config::CanpiDefinition for #name #ty_generics #where_clause {
+            fn definition() -> ::canpi_config::ConfigHash {
+                let mut defn = ::canpi_config::ConfigHash::default();
+                #(#inserts)*
+                defn
+            }
+        }
+    })
+}
+
+/// Derive `canpi_config::CanpiConfig`; see the crate documentation for field attributes
+#[proc_macro_derive(CanpiConfig, attributes(canpi))]
+pub fn derive_canpi_config(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_config(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derive `canpi_config::CanpiDefinition`; see the crate documentation for field attributes
+#[proc_macro_derive(CanpiDefinition, attributes(canpi))]
+pub fn derive_canpi_definition(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_definition(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
@@ -0,0 +1,246 @@
+//! Convenience accessors for current values
+//!
+//! A current value left empty in the INI file means the attribute's default applies.
+
+use crate::policy::is_set;
+use crate::value::parse_bool;
+use crate::{ActionBehaviour, Cfg, CfgError, REDACTED};
+
+use serde_json::{Map, Value};
+
+use std::any::type_name;
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+impl Cfg {
+    /// The current value of `key`, or its default if the current value is empty
+    ///
+    /// An unknown key gives an empty string.
+    pub fn get_or_default<K: AsRef<str>>(&self, key: K) -> &str {
+        let key = key.as_ref();
+        match self.cfg.as_ref().and_then(|cfg| cfg.get(key)) {
+            Some(attr) if attr.current.is_empty() => &attr.default,
+            Some(attr) => &attr.current,
+            None => "",
+        }
+    }
+
+    /// The current value of `key` parsed as `T`, falling back to the parsed default if the
+    /// current value is empty or does not parse
+    pub fn get_or_default_as<T: FromStr>(&self, key: impl AsRef<str>) -> Option<T> {
+        let key = key.as_ref();
+        let attr = self.cfg.as_ref()?.get(key)?;
+        attr.current
+            .trim()
+            .parse()
+            .or_else(|_| attr.default.trim().parse())
+            .ok()
+    }
+
+    /// The current value of `key` converted to `T`
+    ///
+    /// A value that does not convert gives [`CfgError::Conversion`] naming the key, the value
+    /// and the type asked for.
+    pub fn try_get<T>(&self, key: impl AsRef<str>) -> Result<T, CfgError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let key = key.as_ref();
+        let cfg = self.cfg.as_ref().ok_or(CfgError::Cfg())?;
+        let attr = cfg
+            .get(key)
+            .ok_or_else(|| CfgError::UnknownKey(key.to_string()))?;
+        attr.current
+            .trim()
+            .parse()
+            .map_err(|e: T::Err| CfgError::Conversion {
+                key: key.to_string(),
+                value: attr.shown(&attr.current).to_string(),
+                target: type_name::<T>(),
+                reason: e.to_string(),
+            })
+    }
+
+    /// [`Cfg::get_or_default`] as a flag: empty, `0`, `false`, `no` and `off` are false
+    pub fn get_or_default_bool<K: AsRef<str>>(&self, key: K) -> bool {
+        is_set(self.get_or_default(key))
+    }
+
+    /// The current value of `key` as a signed integer
+    pub fn get_i64<K: AsRef<str>>(&self, key: K) -> Result<i64, CfgError> {
+        self.try_get(key)
+    }
+
+    /// The current value of `key` as a flag
+    ///
+    /// `1`, `true`, `yes` and `on` are true and `0`, `false`, `no` and `off` are false, in any
+    /// case; anything else gives [`CfgError::Conversion`].
+    pub fn get_bool<K: AsRef<str>>(&self, key: K) -> Result<bool, CfgError> {
+        let key = key.as_ref();
+        let attr = self.defined(key)?;
+        parse_bool(&attr.current).ok_or_else(|| CfgError::Conversion {
+            key: key.to_string(),
+            value: attr.shown(&attr.current).to_string(),
+            target: "bool",
+            reason: "not one of 1, true, yes, on, 0, false, no or off".to_string(),
+        })
+    }
+
+    /// The current value of `key` as an IPv4 address
+    pub fn get_ipv4<K: AsRef<str>>(&self, key: K) -> Result<Ipv4Addr, CfgError> {
+        self.try_get(key)
+    }
+
+    /// The current value of `key` as a TCP or UDP port; port 0 gives [`CfgError::Conversion`]
+    pub fn get_port<K: AsRef<str>>(&self, key: K) -> Result<u16, CfgError> {
+        let key = key.as_ref();
+        match self.try_get(key)? {
+            0 => Err(CfgError::Conversion {
+                key: key.to_string(),
+                value: "0".to_string(),
+                target: "port",
+                reason: "port 0 is reserved".to_string(),
+            }),
+            port => Ok(port),
+        }
+    }
+
+    /// The value of every item, or of those whose action is `action`, as one flat JSON object
+    ///
+    /// Values are as written to the INI file, so an empty value stays empty, with those of
+    /// secret items [`REDACTED`], e.g. `{"canid": "101", "node_number": "4321"}`.
+    pub fn to_value_json(&self, action: Option<ActionBehaviour>) -> Value {
+        let values: Map<String, Value> = match &self.cfg {
+            Some(cfg) => cfg
+                .iter()
+                .filter(|(_, v)| action.is_none_or(|a| v.action == a))
+                .map(|(k, v)| {
+                    let value = match v.secret {
+                        true => Cow::Borrowed(REDACTED),
+                        false => self
+                            .stored_value(v)
+                            .unwrap_or(Cow::Borrowed(v.current.as_str())),
+                    };
+                    (k.clone(), value.into())
+                })
+                .collect(),
+            None => Map::new(),
+        };
+        Value::Object(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::load_example_defn;
+
+    #[test]
+    fn defaults_fill_in() {
+        let mut cfg = load_example_defn("scratch/access_test.json");
+
+        cfg.set_value("canid", "").expect("set_value failed");
+        let default = cfg.read_attribute("canid").unwrap().default.clone();
+        assert_eq!(cfg.get_or_default("canid"), default);
+        assert_eq!(cfg.get_or_default_as::<u32>("canid"), default.parse().ok());
+        cfg.set_value("canid", "101").expect("set_value failed");
+        assert_eq!(cfg.get_or_default("canid"), "101");
+        assert_eq!(cfg.get_or_default_as::<u32>("canid"), Some(101));
+        assert_eq!(cfg.get_or_default("no_such_key"), "");
+        assert_eq!(cfg.get_or_default_as::<u32>("no_such_key"), None);
+        assert!(!cfg.get_or_default_bool("no_such_key"));
+        assert!(!cfg.get_or_default_bool("ap_mode"));
+        cfg.set_value("ap_mode", "1").expect("set_value failed");
+        assert!(cfg.get_or_default_bool("ap_mode"));
+    }
+
+    #[test]
+    fn conversion_errors_name_the_value() {
+        let mut cfg = load_example_defn("scratch/try_get_test.json");
+
+        cfg.set_value("canid", "101").expect("set_value failed");
+        assert_eq!(cfg.try_get::<u16>("canid").expect("try_get failed"), 101);
+        cfg.set_value("canid", "1000").expect("set_value failed");
+        let err = cfg.try_get::<u8>("canid").unwrap_err();
+        assert!(matches!(
+            &err,
+            CfgError::Conversion { key, value, target: "u8", .. } if key == "canid" && value == "1000"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "'canid' value '1000' cannot be read as u8: number too large to fit in target type"
+        );
+        assert!(matches!(
+            cfg.try_get::<u8>("no_such_key"),
+            Err(CfgError::UnknownKey(_))
+        ));
+    }
+
+    #[test]
+    fn owned_keys() {
+        let mut cfg = load_example_defn("scratch/owned_keys_test.json");
+
+        let key = String::from("canid");
+        cfg.set_value(&key, "101").expect("set_value failed");
+        cfg.set_value(key.clone(), "102").expect("set_value failed");
+        assert_eq!(cfg.get_or_default(&key), "102");
+        assert_eq!(cfg.try_get::<u16>(&key).expect("try_get failed"), 102);
+        assert_eq!(cfg.get_i64(key.clone()).expect("get_i64 failed"), 102);
+        assert!(cfg.provenance(&key).is_some());
+        assert!(cfg.last_modified(key).is_some());
+    }
+
+    #[test]
+    fn typed_getters() {
+        let mut cfg = load_example_defn("scratch/typed_getters_test.json");
+
+        cfg.set_value("canid", "101").expect("set_value failed");
+        assert_eq!(cfg.get_i64("canid").unwrap(), 101);
+        // A hand edited INI file need not satisfy the formats
+        let edit_ini = |cfg: &mut Cfg, key: &str, value: &str| {
+            cfg.iter_mut()
+                .filter(|(k, _)| *k == key)
+                .for_each(|(_, a)| a.current = value.to_string())
+        };
+        edit_ini(&mut cfg, "ap_mode", "On");
+        assert!(cfg.get_bool("ap_mode").unwrap());
+        cfg.set_value("ap_mode", "0").expect("set_value failed");
+        assert!(!cfg.get_bool("ap_mode").unwrap());
+        cfg.set_value("tcpport", "5550").expect("set_value failed");
+        assert_eq!(cfg.get_port("tcpport").unwrap(), 5550);
+        cfg.set_value("tcpport", "0000").expect("set_value failed");
+        assert!(matches!(
+            cfg.get_port("tcpport"),
+            Err(CfgError::Conversion { target: "port", .. })
+        ));
+        edit_ini(&mut cfg, "service_name", "192.168.4.1");
+        assert_eq!(
+            cfg.get_ipv4("service_name").unwrap(),
+            Ipv4Addr::new(192, 168, 4, 1)
+        );
+        let err = cfg.get_bool("service_name").unwrap_err();
+        assert_eq!(err.key(), Some("service_name"));
+    }
+
+    #[test]
+    fn values_as_json() {
+        let mut cfg = load_example_defn("scratch/value_json_test.json");
+
+        cfg.set_value("tcpport", "5556").expect("set_value failed");
+        let all = cfg.to_value_json(None);
+        assert_eq!(all["canid"], "100");
+        assert_eq!(all["router_password"], "bKFcLGgAW6fV");
+        let edit = cfg.to_value_json(Some(ActionBehaviour::Edit));
+        assert_eq!(edit["tcpport"], "5556");
+        assert!(edit.get("canid").is_none());
+        cfg.set_value("tcpport", "").expect("set_value failed");
+        assert_eq!(cfg.to_value_json(None)["tcpport"], "");
+        assert_eq!(
+            edit.as_object().unwrap().len(),
+            cfg.attributes_with_action(ActionBehaviour::Edit).len()
+        );
+    }
+}
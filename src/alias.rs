@@ -0,0 +1,95 @@
+//! Deprecated key names
+//!
+//! Older canpi installs spell some keys differently, e.g. `can_id` for `canid`.  An attribute
+//! lists such spellings in `deprecated_aliases`; an INI entry under one of them is read as the
+//! attribute's key and reported in [`LoadReport::aliased_keys`], so that the next write stores it
+//! under the current key.  An entry under the current key takes precedence over any alias.
+//!
+//! [`LoadReport::aliased_keys`]: crate::LoadReport::aliased_keys
+
+use crate::ConfigHash;
+
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// An INI entry read under a deprecated alias or former name of its key
+pub struct AliasedKey {
+    /// The key the entry was found under
+    pub alias: String,
+    /// The key it was read as
+    pub key: String,
+}
+
+/// Each deprecated alias in `defn` and the key that replaced it
+pub(crate) fn aliases(defn: &ConfigHash) -> HashMap<String, String> {
+    defn.iter()
+        .flat_map(|(k, v)| {
+            v.deprecated_aliases
+                .iter()
+                .map(move |alias| (alias.to_string(), k.clone()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cfg, MemoryFs};
+    use std::sync::Arc;
+
+    const DEFN_DATA: &str = r#"
+        {
+            "canid" : {
+                "prompt": "CAN Id",
+                "tooltip": "The CAN Id used by the CAN Pi CAP/Zero on the CBUS",
+                "current": "100",
+                "default": "100",
+                "format": "[0-9]{1,4}",
+                "action": "Edit",
+                "deprecated_aliases": ["can_id", "CANID"]
+            },
+            "node_number" : {
+                "prompt": "Node Number",
+                "tooltip": "The node number of the CAN Pi CAP/Zero on the CBUS",
+                "current": "4321",
+                "default": "4321",
+                "format": "[0-9]{1,4}",
+                "action": "Edit",
+                "deprecated_aliases": ["node_num"]
+            }
+        }"#;
+
+    #[test]
+    fn aliases_are_read_as_their_key() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        fs.insert("canpi.cfg", "node_number=1234\ncan_id=101\nnode_num=99\n");
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        let report = cfg
+            .load_configuration("canpi.cfg", "defn.json")
+            .expect("configuration failed to load");
+        assert_eq!(cfg.get_or_default("canid"), "101");
+        assert_eq!(cfg.get_or_default("node_number"), "1234");
+        assert_eq!(
+            report.aliased_keys,
+            vec![
+                AliasedKey {
+                    alias: "can_id".into(),
+                    key: "canid".into()
+                },
+                AliasedKey {
+                    alias: "node_num".into(),
+                    key: "node_number".into()
+                },
+            ]
+        );
+        assert!(report.unknown_keys.is_empty());
+        assert!(!report.is_clean());
+
+        cfg.write_cfg_file("canpi.cfg", None).expect("write failed");
+        let text = fs.contents("canpi.cfg").unwrap();
+        assert!(text.contains("canid=101"));
+        assert!(!text.contains("can_id"));
+    }
+}
@@ -0,0 +1,336 @@
+//! Audit trail of changes to current values
+//!
+//! Every change made through the API is recorded with the time it was made and, if supplied, an
+//! [`Annotation`] saying who made it and why.  When written with [`WriteOptions::audit_log`]
+//! set, the changes made since the last write are appended as JSON lines to `<cfg file>.audit.jsonl`, and any
+//! backup taken is listed in `<cfg file>.backups.jsonl` together with the annotations of the
+//! changes that replaced it.
+//!
+//! [`WriteOptions::audit_log`]: crate::WriteOptions::audit_log
+
+use crate::{Cfg, CfgError, Fs, Provenance, REDACTED};
+
+use serde_json::json;
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// Who made a change and why
+pub struct Annotation {
+    /// The person or system making the change
+    pub actor: String,
+    /// Why the change was made
+    pub reason: String,
+}
+
+impl Annotation {
+    /// Creates an annotation for a change made by `actor` for `reason`
+    pub fn new(actor: &str, reason: &str) -> Annotation {
+        Annotation {
+            actor: actor.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// A single change to the current value of a configuration item
+pub struct AuditEntry {
+    /// When the change was made
+    pub time: SystemTime,
+    /// Key of the configuration item changed
+    pub key: String,
+    /// Current value before the change
+    pub old: String,
+    /// Current value after the change
+    pub new: String,
+    /// Who made the change and why, if given
+    pub annotation: Option<Annotation>,
+}
+
+impl AuditEntry {
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        let annotation = self.annotation.as_ref();
+        json!({
+            "time": epoch_secs(self.time),
+            "key": self.key,
+            "old": self.old,
+            "new": self.new,
+            "actor": annotation.map(|a| &a.actor),
+            "reason": annotation.map(|a| &a.reason),
+        })
+    }
+}
+
+pub(crate) fn epoch_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Path of `path` with `suffix` appended to its file name
+fn sidecar(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Path of the audit log kept alongside the INI file at `path`
+pub(crate) fn audit_log_path(path: &Path) -> PathBuf {
+    sidecar(path, ".audit.jsonl")
+}
+
+/// Path of the backup manifest kept alongside the INI file at `path`
+pub(crate) fn manifest_path(path: &Path) -> PathBuf {
+    sidecar(path, ".backups.jsonl")
+}
+
+fn append_lines<I>(fs: &dyn Fs, path: &Path, lines: I) -> Result<(), CfgError>
+where
+    I: IntoIterator<Item = serde_json::Value>,
+{
+    let mut text = Vec::new();
+    for line in lines {
+        serde_json::to_writer(&mut text, &line)?;
+        text.push(b'\n');
+    }
+    fs.append(path, &text)?;
+    Ok(())
+}
+
+impl Cfg {
+    /// As [`Cfg::set_value`], recording `annotation` against the change in the audit trail
+    pub fn set_value_annotated<K: AsRef<str>>(
+        &mut self,
+        key: K,
+        value: &str,
+        annotation: Option<&Annotation>,
+    ) -> Result<(), CfgError> {
+        let key = key.as_ref();
+        self.apply_changes(&[(key, value)], annotation)
+    }
+
+    /// Set the current values of several configuration items as one change
+    ///
+    /// Either every key is known and every value satisfies its item's `format`, `min`, `max` and
+    /// `choices` and all values are set, or nothing is changed.  An empty value, meaning the
+    /// default applies, is not checked.  Numeric values are read using the
+    /// [number locale](Cfg::set_number_locale).
+    pub fn apply_changes(
+        &mut self,
+        changes: &[(&str, &str)],
+        annotation: Option<&Annotation>,
+    ) -> Result<(), CfgError> {
+        let cfg = self.cfg.as_ref().ok_or(CfgError::Cfg())?;
+        let mut canonical = Vec::with_capacity(changes.len());
+        for (key, value) in changes {
+            let attr = cfg
+                .get(*key)
+                .ok_or_else(|| CfgError::UnknownKey(key.to_string()))?;
+            self.check_value_len(key, value)?;
+            canonical.push(self.canonical_input(key, attr, value)?);
+        }
+        let changes: Vec<(&str, &str)> = changes
+            .iter()
+            .zip(&canonical)
+            .map(|((k, _), v)| (*k, v.as_ref()))
+            .collect();
+        self.store_changes(&changes, annotation)
+    }
+
+    /// As [`Cfg::apply_changes`] for values already in canonical form
+    pub(crate) fn store_changes(
+        &mut self,
+        changes: &[(&str, &str)],
+        annotation: Option<&Annotation>,
+    ) -> Result<(), CfgError> {
+        let cfg = self.cfg.as_ref().ok_or(CfgError::Cfg())?;
+        for (key, value) in changes {
+            let attr = cfg
+                .get(*key)
+                .ok_or_else(|| CfgError::UnknownKey(key.to_string()))?;
+            self.check_value_len(key, value)?;
+            let value = attr.sanitize(key, value)?;
+            if !value.is_empty() {
+                self.check_constraints(key, attr, &value)?;
+            }
+        }
+        for (key, value) in changes {
+            self.store_value(key, value, annotation)?;
+        }
+        Ok(())
+    }
+
+    fn store_value(
+        &mut self,
+        key: &str,
+        value: &str,
+        annotation: Option<&Annotation>,
+    ) -> Result<(), CfgError> {
+        let cfg = self.cfg.as_mut().ok_or(CfgError::Cfg())?;
+        let attr = cfg
+            .get_mut(key)
+            .ok_or_else(|| CfgError::UnknownKey(key.to_string()))?;
+        let value = attr.sanitize(key, value)?.into_owned();
+        let value = value.as_str();
+        if attr.current != value {
+            let old = std::mem::replace(&mut attr.current, value.to_string());
+            self.record_change(key, old, value, annotation);
+        }
+        self.provenance.insert(key.to_string(), Provenance::User);
+        self.invalidate_caches();
+        Ok(())
+    }
+
+    /// The changes made to current values since the configuration was loaded, oldest first
+    pub fn audit_log(&self) -> &[AuditEntry] {
+        &self.audit
+    }
+
+    /// Add a change to the audit trail and note when the value was modified
+    pub(crate) fn record_change(
+        &mut self,
+        key: &str,
+        old: String,
+        new: &str,
+        annotation: Option<&Annotation>,
+    ) {
+        let time = SystemTime::now();
+        self.modified.insert(key.to_string(), time);
+        let (old, new) = match self.cfg.as_ref().and_then(|c| c.get(key)) {
+            Some(attr) if attr.secret => (REDACTED.to_string(), REDACTED),
+            _ => (old, new),
+        };
+        self.audit.push(AuditEntry {
+            time,
+            key: key.to_string(),
+            old,
+            new: new.to_string(),
+            annotation: annotation.cloned(),
+        });
+    }
+
+    /// The changes made since the configuration was last written
+    pub(crate) fn unsaved_changes(&self) -> &[AuditEntry] {
+        let saved = self.audit_saved.load(Ordering::Relaxed);
+        &self.audit[saved.min(self.audit.len())..]
+    }
+
+    /// Note that every change so far has been written
+    pub(crate) fn mark_saved(&self) {
+        self.audit_saved.store(self.audit.len(), Ordering::Relaxed);
+    }
+
+    /// Append the changes not yet saved to the audit log of the INI file at `path`, and list
+    /// `backup` in its manifest with the annotations of those changes
+    pub(crate) fn save_audit(&self, path: &Path, backup: Option<&Path>) -> Result<(), CfgError> {
+        let pending = self.unsaved_changes();
+        if !pending.is_empty() {
+            append_lines(
+                &*self.fs,
+                &audit_log_path(path),
+                pending.iter().map(AuditEntry::to_json),
+            )?;
+        }
+        if let Some(backup) = backup {
+            let mut annotations: Vec<&Annotation> = Vec::new();
+            for a in pending.iter().filter_map(|e| e.annotation.as_ref()) {
+                if !annotations.contains(&a) {
+                    annotations.push(a);
+                }
+            }
+            let annotations: Vec<_> = annotations
+                .iter()
+                .map(|a| json!({ "actor": a.actor, "reason": a.reason }))
+                .collect();
+            let entry = json!({
+                "time": epoch_secs(SystemTime::now()),
+                "backup": backup.display().to_string(),
+                "changes": pending.iter().map(|e| &e.key).collect::<Vec<_>>(),
+                "annotations": annotations,
+            });
+            append_lines(&*self.fs, &manifest_path(path), Some(entry))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{load_defn, CANID_DISPLAY_NODE_DEFN};
+    use crate::ValidationError;
+    use std::fs;
+
+    #[test]
+    fn invalid_values_are_refused() {
+        let mut cfg = load_defn(
+            "scratch/audit_invalid_test.json",
+            r#"{
+                "canid" : {
+                    "prompt": "CAN Id", "tooltip": "", "current": "100", "default": "100",
+                    "format": "[0-9]{1,4}", "action": "Edit", "max": 127
+                },
+                "node_number" : {
+                    "prompt": "Node Number", "tooltip": "", "current": "4321", "default": "4321",
+                    "format": "[0-9]{1,5}", "action": "Edit"
+                }
+            }"#,
+        );
+        assert!(matches!(
+            cfg.set_value("canid", "abc"),
+            Err(CfgError::Validation(ValidationError::Format { .. }))
+        ));
+        assert!(matches!(
+            cfg.set_value("canid", "5000"),
+            Err(CfgError::Validation(ValidationError::OutOfRange { .. }))
+        ));
+        assert!(cfg
+            .apply_changes(&[("node_number", "7"), ("canid", "128")], None)
+            .is_err());
+        assert_eq!(cfg.read_attribute("canid").unwrap().current, "100");
+        assert_eq!(cfg.read_attribute("node_number").unwrap().current, "4321");
+        assert!(cfg.audit_log().is_empty());
+        cfg.set_value("canid", "").expect("set_value failed");
+        assert_eq!(cfg.get_or_default("canid"), "100");
+    }
+
+    #[test]
+    fn annotated_changes_are_logged() {
+        let cfg_file = "scratch/audit_test.cfg";
+        let mut cfg = load_defn("scratch/audit_test.json", CANID_DISPLAY_NODE_DEFN);
+
+        let who = Annotation::new("fred", "new layout module");
+        assert!(matches!(
+            cfg.apply_changes(&[("canid", "101"), ("no_such_key", "1")], Some(&who)),
+            Err(CfgError::UnknownKey(_))
+        ));
+        assert!(cfg.audit_log().is_empty());
+        cfg.apply_changes(&[("canid", "101"), ("node_number", "7")], Some(&who))
+            .expect("changes failed");
+        cfg.set_value("canid", "102").expect("set_value failed");
+        let log = cfg.audit_log();
+        assert_eq!(log.len(), 3);
+        assert_eq!(log[0].annotation.as_ref(), Some(&who));
+        assert_eq!((log[2].old.as_str(), log[2].new.as_str()), ("101", "102"));
+        assert!(log[2].annotation.is_none());
+
+        let path = Path::new(cfg_file);
+        cfg.save_audit(path, Some(Path::new("audit_test.cfg.1.bak")))
+            .expect("audit save failed");
+        cfg.mark_saved();
+        assert!(cfg.unsaved_changes().is_empty());
+        cfg.save_audit(path, None).expect("audit save failed");
+        let log_text = fs::read_to_string(audit_log_path(path)).expect("no audit log");
+        assert_eq!(log_text.lines().count(), 3);
+        assert!(log_text.contains(r#""actor":"fred""#));
+        let manifest = fs::read_to_string(manifest_path(path)).expect("no manifest");
+        assert_eq!(manifest.lines().count(), 1);
+        assert!(manifest.contains(r#""reason":"new layout module""#));
+        assert!(manifest.contains(r#""changes":["canid","node_number","canid"]"#));
+        fs::remove_file(audit_log_path(path)).expect("file deletion failed");
+        fs::remove_file(manifest_path(path)).expect("file deletion failed");
+    }
+}
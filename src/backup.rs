@@ -0,0 +1,467 @@
+//! Backups of the INI file before it is replaced
+//!
+//! [`Cfg::write_cfg_file_with`] hands the existing file to the [`BackupStrategy`] registered
+//! with [`Cfg::set_backup_strategy`] when [`WriteOptions::make_backup`] is set.  The default is
+//! [`TimestampedCopy`] with the `backup` feature and [`NumberedRotation`] without it.
+//!
+//! [`WriteOptions::backup_dir`] keeps the copies in another directory, such as a mounted USB
+//! stick.
+//!
+//! [`Cfg::list_backups`] lists the copies the strategy has made, for a restore menu, and
+//! [`Cfg::restore_from_backup`] puts the one chosen back in place.
+//!
+//! [`WriteOptions::make_backup`]: crate::WriteOptions::make_backup
+//! [`WriteOptions::backup_dir`]: crate::WriteOptions::backup_dir
+
+use crate::context::ResultExt;
+use crate::{
+    Cfg, CfgError, FileAccess, Fs, InvalidValues, Layer, LoadOptions, LoadReport, Operation,
+};
+
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Endings of the files the crate keeps alongside an INI file, which are not backups of it
+const SIDECAR_SUFFIXES: [&str; 5] = [
+    "modified.json",
+    "audit.jsonl",
+    "backups.jsonl",
+    "version",
+    "lock",
+];
+
+/// Takes a copy of a file before it is overwritten
+pub trait BackupStrategy: Send + Sync {
+    /// Back up the file at `path` in `fs`, returning where the copy was made, or `None` if no
+    /// copy was needed
+    fn backup(&self, fs: &dyn Fs, path: &Path) -> io::Result<Option<PathBuf>>;
+
+    /// The backups of the file at `path` in `fs`
+    ///
+    /// The default lists the files beside `path` whose names are its name followed by a dot and
+    /// a suffix, other than the files the crate keeps alongside it.
+    fn list(&self, fs: &dyn Fs, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let prefix = format!("{}.", name);
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        Ok(fs
+            .list_dir(dir)?
+            .into_iter()
+            .filter(|p| {
+                let file_name = p.file_name().unwrap_or_default().to_string_lossy();
+                match file_name.strip_prefix(&prefix) {
+                    Some(suffix) => !suffix.is_empty() && !SIDECAR_SUFFIXES.contains(&suffix),
+                    None => false,
+                }
+            })
+            .collect())
+    }
+
+    /// Back up the file at `path` in `fs` into the directory `dir`, creating it if needed
+    ///
+    /// The default makes the backup as usual and then moves it into `dir`.
+    fn backup_in(&self, fs: &dyn Fs, path: &Path, dir: &Path) -> io::Result<Option<PathBuf>> {
+        let made = match self.backup(fs, path)? {
+            Some(made) => made,
+            None => return Ok(None),
+        };
+        fs.create_dir_all(dir)?;
+        let moved = dir.join(made.file_name().unwrap_or_default());
+        // Copied rather than renamed as `dir` may be on another filesystem
+        fs.copy(&made, &moved)?;
+        fs.remove(&made)?;
+        Ok(Some(moved))
+    }
+
+    /// The backups of the file at `path` in `fs` kept in the directory `dir`
+    ///
+    /// The default lists them as [`BackupStrategy::list`] would for a file of the same name in
+    /// `dir`.
+    fn list_in(&self, fs: &dyn Fs, path: &Path, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        self.list(fs, &dir.join(path.file_name().unwrap_or_default()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A backup of the INI file
+pub struct BackupInfo {
+    /// Where the backup is
+    pub path: PathBuf,
+    /// When the backup was made, if known
+    pub modified: Option<SystemTime>,
+    /// Length in bytes
+    pub len: u64,
+}
+
+#[cfg(feature = "backup")]
+#[derive(Clone, Copy, Debug, Default)]
+/// Copies the file alongside itself with the UTC time in the name, e.g.
+/// `canpi.cfg.20240131T101500.250Z`
+pub struct TimestampedCopy;
+
+#[cfg(feature = "backup")]
+impl BackupStrategy for TimestampedCopy {
+    fn backup(&self, fs: &dyn Fs, path: &Path) -> io::Result<Option<PathBuf>> {
+        if !fs.exists(path) {
+            return Ok(None);
+        }
+        let time: chrono::DateTime<chrono::Utc> = SystemTime::now().into();
+        let mut name = path.file_name().unwrap_or_default().to_owned();
+        name.push(format!(".{}", time.format("%Y%m%dT%H%M%S%.3fZ")));
+        let copy = path.with_file_name(name);
+        fs.copy(path, &copy)?;
+        Ok(Some(copy))
+    }
+}
+
+#[derive(Clone, Debug)]
+/// Keeps the last `keep` versions as `<name>.1` (newest) to `<name>.<keep>`
+pub struct NumberedRotation {
+    /// Number of backups to keep
+    pub keep: usize,
+    /// Directory for the backups; `None` puts them next to the file
+    pub dir: Option<PathBuf>,
+}
+
+impl Default for NumberedRotation {
+    fn default() -> Self {
+        NumberedRotation { keep: 3, dir: None }
+    }
+}
+
+impl NumberedRotation {
+    /// The same rotation kept in `dir`
+    fn in_dir(&self, dir: &Path) -> NumberedRotation {
+        NumberedRotation {
+            keep: self.keep,
+            dir: Some(dir.to_path_buf()),
+        }
+    }
+
+    /// The path of backup number `n` of `path`
+    pub fn backup_path(&self, path: &Path, n: usize) -> PathBuf {
+        let mut name = path.file_name().unwrap_or_default().to_owned();
+        name.push(format!(".{}", n));
+        match &self.dir {
+            Some(dir) => dir.join(name),
+            None => path.with_file_name(name),
+        }
+    }
+}
+
+impl BackupStrategy for NumberedRotation {
+    fn backup(&self, fs: &dyn Fs, path: &Path) -> io::Result<Option<PathBuf>> {
+        if self.keep == 0 || !fs.exists(path) {
+            return Ok(None);
+        }
+        if let Some(dir) = &self.dir {
+            fs.create_dir_all(dir)?;
+        }
+        for n in (1..self.keep).rev() {
+            match fs.rename(&self.backup_path(path, n), &self.backup_path(path, n + 1)) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        let newest = self.backup_path(path, 1);
+        fs.copy(path, &newest)?;
+        Ok(Some(newest))
+    }
+
+    fn list(&self, fs: &dyn Fs, path: &Path) -> io::Result<Vec<PathBuf>> {
+        Ok((1..=self.keep)
+            .map(|n| self.backup_path(path, n))
+            .filter(|p| fs.exists(p))
+            .collect())
+    }
+
+    fn backup_in(&self, fs: &dyn Fs, path: &Path, dir: &Path) -> io::Result<Option<PathBuf>> {
+        self.in_dir(dir).backup(fs, path)
+    }
+
+    fn list_in(&self, fs: &dyn Fs, path: &Path, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        self.in_dir(dir).list(fs, path)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+/// Takes no backups
+pub struct NoBackup;
+
+impl BackupStrategy for NoBackup {
+    fn backup(&self, _fs: &dyn Fs, _path: &Path) -> io::Result<Option<PathBuf>> {
+        Ok(None)
+    }
+}
+
+/// The strategy used until another is set
+pub(crate) fn default_strategy() -> Box<dyn BackupStrategy> {
+    #[cfg(feature = "backup")]
+    return Box::new(TimestampedCopy);
+    #[cfg(not(feature = "backup"))]
+    return Box::new(NumberedRotation::default());
+}
+
+impl Cfg {
+    /// Use `strategy` for the backups taken by [`Cfg::write_cfg_file_with`]
+    pub fn set_backup_strategy<S: BackupStrategy + 'static>(&mut self, strategy: S) {
+        self.backup_strategy = Box::new(strategy);
+    }
+
+    /// The backups of the INI file `path` made by the backup strategy, newest first
+    pub fn list_backups<P: AsRef<Path>>(&self, path: P) -> Result<Vec<BackupInfo>, CfgError> {
+        self.backups(path.as_ref(), None)
+            .context(Operation::Load, path)
+    }
+
+    /// The backups of the INI file `path` kept in `dir`, as set by
+    /// [`WriteOptions::backup_dir`](crate::WriteOptions::backup_dir), newest first
+    pub fn list_backups_in<P: AsRef<Path>, D: AsRef<Path>>(
+        &self,
+        path: P,
+        dir: D,
+    ) -> Result<Vec<BackupInfo>, CfgError> {
+        self.backups(path.as_ref(), Some(dir.as_ref()))
+            .context(Operation::Load, path)
+    }
+
+    fn backups(&self, path: &Path, dir: Option<&Path>) -> Result<Vec<BackupInfo>, CfgError> {
+        let listed = match dir {
+            Some(dir) => self.backup_strategy.list_in(&*self.fs, path, dir)?,
+            None => self.backup_strategy.list(&*self.fs, path)?,
+        };
+        let mut backups = Vec::new();
+        for backup in listed {
+            let metadata = self.fs.metadata(&backup)?;
+            backups.push(BackupInfo {
+                path: backup,
+                modified: metadata.modified,
+                len: metadata.len,
+            });
+        }
+        backups.sort_by(|a, b| b.modified.cmp(&a.modified).then(a.path.cmp(&b.path)));
+        Ok(backups)
+    }
+
+    /// Replace the INI file the configuration was loaded from with the backup at `backup_path`
+    /// and reload the current values from it
+    ///
+    /// The backup must hold only defined keys with values that satisfy their formats, or it is
+    /// rejected and nothing is changed.  The file it replaces is itself backed up first, and
+    /// changes not yet written are discarded.
+    pub fn restore_from_backup<P: AsRef<Path>>(
+        &mut self,
+        backup_path: P,
+    ) -> Result<LoadReport, CfgError> {
+        self.restore(backup_path.as_ref())
+            .context(Operation::Load, backup_path)
+    }
+
+    fn restore(&mut self, backup_path: &Path) -> Result<LoadReport, CfgError> {
+        let defn = self.cfg.clone().ok_or(CfgError::Cfg())?;
+        let path = crate::layer::writable_path(&self.layers)
+            .ok_or(CfgError::Cfg())?
+            .to_path_buf();
+
+        // Check the backup against the definitions before touching the live file
+        let mut check = Cfg::new();
+        check.set_fs(self.fs.clone());
+        let options = LoadOptions {
+            invalid_values: InvalidValues::Reject,
+            ..self.load_options.clone()
+        };
+        let layers = [Layer::Ini(backup_path.to_path_buf())];
+        let report =
+            check.update_cfg_from_defn(defn.clone(), &self.key_migrations, &layers, &options)?;
+        if let Some(key) = report.unknown_keys.first() {
+            return Err(CfgError::UnknownKey(key.clone()));
+        }
+
+        let contents = self.fs.read(backup_path)?;
+        self.make_backup(&path, None);
+        let access = FileAccess {
+            private: defn.values().any(|a| a.secret),
+            owner: None,
+        };
+        self.fs
+            .write_atomic(&path, &contents, &access)
+            .map_err(CfgError::from)
+            .context(Operation::Write, &path)?;
+
+        let layers = self.layers.clone();
+        let options = self.load_options.clone();
+        let migrations = self.key_migrations.clone();
+        let mut report = self
+            .update_cfg_from_defn(defn, &migrations, &layers, &options)
+            .context(Operation::Load, &path)?;
+        self.audit.clear();
+        *self.audit_saved.get_mut() = 0;
+        report.invalid_values = self.format_violations();
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::CANID_DEFN;
+    use crate::{MemoryFs, WriteOptions};
+    use std::sync::Arc;
+
+    #[test]
+    fn numbered_rotation() {
+        let fs = MemoryFs::new();
+        let file = Path::new("scratch/rotation_test.cfg");
+        let strategy = NumberedRotation {
+            keep: 2,
+            dir: Some(PathBuf::from("scratch/rotation_test")),
+        };
+        assert_eq!(strategy.backup(&fs, file).expect("backup failed"), None);
+        for version in ["one", "two", "three"] {
+            fs.insert(file, version);
+            let copy = strategy.backup(&fs, file).expect("backup failed");
+            assert_eq!(
+                copy,
+                Some(PathBuf::from("scratch/rotation_test/rotation_test.cfg.1"))
+            );
+        }
+        let read = |n| fs.contents(strategy.backup_path(file, n));
+        assert_eq!(read(1).as_deref(), Some("three"));
+        assert_eq!(read(2).as_deref(), Some("two"));
+        assert_eq!(read(3), None);
+        assert_eq!(NoBackup.backup(&fs, file).expect("backup failed"), None);
+    }
+
+    #[cfg(feature = "backup")]
+    #[test]
+    fn timestamped_copy() {
+        let fs = MemoryFs::new();
+        let file = Path::new("scratch/timestamped_test.cfg");
+        assert_eq!(
+            TimestampedCopy.backup(&fs, file).expect("backup failed"),
+            None
+        );
+        fs.insert(file, "canid=101\n");
+        let copy = TimestampedCopy
+            .backup(&fs, file)
+            .expect("backup failed")
+            .expect("no backup made");
+        assert_eq!(fs.contents(&copy).as_deref(), Some("canid=101\n"));
+        assert!(!copy.exists(), "backup made on disk");
+        assert_eq!(
+            TimestampedCopy.list(&fs, file).expect("list failed"),
+            [copy]
+        );
+    }
+
+    #[test]
+    fn restore_a_backup() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", CANID_DEFN);
+        fs.insert("canpi.cfg", "canid=101\n");
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.set_backup_strategy(NumberedRotation::default());
+        assert!(matches!(
+            cfg.restore_from_backup("canpi.cfg.1").unwrap_err().root(),
+            CfgError::Cfg()
+        ));
+        cfg.load_configuration("canpi.cfg", "defn.json")
+            .expect("configuration failed to load");
+        cfg.set_value("canid", "102").expect("set_value failed");
+        cfg.write_cfg_file("canpi.cfg", Some(true))
+            .expect("write failed");
+
+        fs.insert("bad.cfg", "canid=abc\n");
+        assert!(cfg.restore_from_backup("bad.cfg").is_err());
+        fs.insert("unknown.cfg", "wifi=on\n");
+        assert!(cfg.restore_from_backup("unknown.cfg").is_err());
+        assert_eq!(fs.contents("canpi.cfg").unwrap(), "canid=102\n");
+
+        cfg.set_value("canid", "103").expect("set_value failed");
+        cfg.restore_from_backup("canpi.cfg.1")
+            .expect("restore failed");
+        assert_eq!(cfg.get_or_default("canid"), "101");
+        assert_eq!(fs.contents("canpi.cfg").unwrap(), "canid=101\n");
+        assert_eq!(fs.contents("canpi.cfg.1").unwrap(), "canid=102\n");
+        assert!(cfg.unsaved_changes().is_empty());
+    }
+
+    #[test]
+    fn backups_are_listed() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("canpi/canpi.cfg", "canid=103\n");
+        fs.insert("canpi/canpi.cfg.1", "canid=102\n");
+        fs.insert("canpi/canpi.cfg.2", "canid=1\n");
+        fs.insert("canpi/canpi.cfg.modified.json", "{}");
+        fs.insert("canpi/other.cfg.1", "");
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs);
+        cfg.set_backup_strategy(NumberedRotation { keep: 1, dir: None });
+        let backups = cfg.list_backups("canpi/canpi.cfg").expect("list failed");
+        assert_eq!(backups.len(), 1);
+        assert_eq!(backups[0].path, PathBuf::from("canpi/canpi.cfg.1"));
+        assert_eq!(backups[0].len, 10);
+
+        cfg.set_backup_strategy(NoBackup);
+        let paths: Vec<PathBuf> = cfg
+            .list_backups("canpi/canpi.cfg")
+            .expect("list failed")
+            .into_iter()
+            .map(|b| b.path)
+            .collect();
+        assert_eq!(paths.len(), 2);
+        assert!(paths.contains(&PathBuf::from("canpi/canpi.cfg.2")));
+    }
+
+    #[derive(Debug)]
+    /// Copies the file beside itself with a fixed suffix
+    struct BesideCopy;
+
+    impl BackupStrategy for BesideCopy {
+        fn backup(&self, fs: &dyn Fs, path: &Path) -> io::Result<Option<PathBuf>> {
+            let copy = path.with_extension("cfg.bak");
+            fs.copy(path, &copy)?;
+            Ok(Some(copy))
+        }
+    }
+
+    #[test]
+    fn backups_kept_in_another_directory() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("canpi/canpi.cfg", "canid=101\n");
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.load_configuration_from_str("canid=102\n", CANID_DEFN)
+            .expect("configuration failed to load");
+        let options = WriteOptions {
+            make_backup: true,
+            backup_dir: Some("usb/canpi".into()),
+            ..WriteOptions::default()
+        };
+        cfg.set_backup_strategy(NumberedRotation::default());
+        cfg.write_cfg_file_with("canpi/canpi.cfg", &options)
+            .expect("write failed");
+        assert_eq!(fs.contents("usb/canpi/canpi.cfg.1").unwrap(), "canid=101\n");
+        assert!(!fs.exists(Path::new("canpi/canpi.cfg.1")));
+        assert!(cfg.list_backups("canpi/canpi.cfg").unwrap().is_empty());
+        let backups = cfg
+            .list_backups_in("canpi/canpi.cfg", "usb/canpi")
+            .expect("list failed");
+        assert_eq!(backups[0].path, PathBuf::from("usb/canpi/canpi.cfg.1"));
+
+        cfg.set_backup_strategy(BesideCopy);
+        cfg.write_cfg_file_with("canpi/canpi.cfg", &options)
+            .expect("write failed");
+        assert_eq!(
+            fs.contents("usb/canpi/canpi.cfg.bak").unwrap(),
+            "canid=102\n"
+        );
+        assert!(!fs.exists(Path::new("canpi/canpi.cfg.bak")));
+        let backups = cfg
+            .list_backups_in("canpi/canpi.cfg", "usb/canpi")
+            .expect("list failed");
+        assert_eq!(backups.len(), 2);
+    }
+}
@@ -0,0 +1,241 @@
+//! # canpi-cfg
+//!
+//! Command line tool to check canpi configuration files, intended for use in CI pipelines.
+//!
+//! Usage:
+//!
+//! - `canpi-cfg validate --defn <file.json> [--cfg <file.cfg>] [--policy <policy.json>]
+//!   [--partial-match]`
+//! - `canpi-cfg validate-all --pkg <packages.json>`
+//!
+//! The exit code reports the most serious problem found:
+//!
+//! | Code | Meaning                                                 |
+//! |------|---------------------------------------------------------|
+//! | 0    | No problems found                                       |
+//! | 1    | Warnings only (e.g. INI keys not in the definition)     |
+//! | 2    | Current values that break their format or a rule        |
+//! | 3    | Definition file failed to parse or validate (schema)    |
+//! | 4    | Usage error or a file could not be read                 |
+//!
+//! The INI files are checked as they are on disk: `CANPI_` environment variables do not override
+//! their values.
+//!
+//! `--partial-match` accepts values where a format matches only part of the value, as formats
+//! were matched before whole-value matching; see `RegexOptions::partial_match`.
+
+use canpi_config::{ActionBehaviour, Cfg, CfgError, LoadOptions, Pkg, RegexOptions};
+
+use std::env;
+use std::process;
+
+const EXIT_OK: i32 = 0;
+const EXIT_WARNINGS: i32 = 1;
+const EXIT_FORMAT: i32 = 2;
+const EXIT_SCHEMA: i32 = 3;
+const EXIT_USAGE: i32 = 4;
+
+const USAGE: &str =
+    "Usage: canpi-cfg validate --defn <file.json> [--cfg <file.cfg>] [--policy <policy.json>]
+                          [--partial-match]
+       canpi-cfg validate-all --pkg <packages.json>";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    process::exit(run(&args));
+}
+
+fn run(args: &[String]) -> i32 {
+    match args.first().map(|s| s.as_str()) {
+        Some("validate") => match parse_validate_args(&args[1..]) {
+            Ok(args) => validate(&args),
+            Err(msg) => usage_error(&msg),
+        },
+        Some("validate-all") => match args.get(1..) {
+            Some([flag, path]) if flag == "--pkg" => validate_all(path),
+            _ => usage_error("validate-all requires --pkg <packages.json>"),
+        },
+        Some("-h") | Some("--help") => {
+            println!("{}", USAGE);
+            EXIT_OK
+        }
+        Some(cmd) => usage_error(&format!("unknown command '{}'", cmd)),
+        None => usage_error("no command given"),
+    }
+}
+
+fn usage_error(msg: &str) -> i32 {
+    eprintln!("canpi-cfg: {}", msg);
+    eprintln!("{}", USAGE);
+    EXIT_USAGE
+}
+
+/// Files and flags given on the command line of `validate`
+struct ValidateArgs {
+    defn: String,
+    cfg: Option<String>,
+    policy: Option<String>,
+    partial_match: bool,
+}
+
+/// Extract the definition and optional cfg and policy paths and flags from the arguments of
+/// `validate`
+fn parse_validate_args(args: &[String]) -> Result<ValidateArgs, String> {
+    let mut defn = None;
+    let mut cfg = None;
+    let mut policy = None;
+    let mut partial_match = false;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let slot = match arg.as_str() {
+            "--defn" => &mut defn,
+            "--cfg" => &mut cfg,
+            "--policy" => &mut policy,
+            "--partial-match" => {
+                partial_match = true;
+                continue;
+            }
+            other => return Err(format!("unexpected argument '{}'", other)),
+        };
+        match iter.next() {
+            Some(value) => *slot = Some(value.clone()),
+            None => return Err(format!("{} requires a file name", arg)),
+        }
+    }
+    match defn {
+        Some(defn) => Ok(ValidateArgs {
+            defn,
+            cfg,
+            policy,
+            partial_match,
+        }),
+        None => Err("--defn is required".to_string()),
+    }
+}
+
+/// Map a library error onto the exit code for its category
+fn error_exit_code(err: &CfgError) -> i32 {
+    match err.root() {
+        CfgError::Schema(_)
+        | CfgError::Json(_)
+        | CfgError::Defn(_)
+        | CfgError::Signature(_)
+        | CfgError::UnknownField(_) => EXIT_SCHEMA,
+        _ => EXIT_USAGE,
+    }
+}
+
+fn validate(args: &ValidateArgs) -> i32 {
+    let defn_path = args.defn.as_str();
+    let mut cfg = Cfg::new();
+    cfg.set_regex_options(RegexOptions {
+        partial_match: args.partial_match,
+        ..RegexOptions::default()
+    });
+    if let Err(e) = cfg.load_definition(defn_path) {
+        eprintln!("error: {}: {}", defn_path, e);
+        return error_exit_code(&e);
+    }
+
+    let mut warnings = Vec::new();
+    for action in [ActionBehaviour::Edit, ActionBehaviour::Display] {
+        for (k, v) in cfg.attributes_with_action(action) {
+            if v.prompt.is_empty() {
+                warnings.push(format!("attribute '{}' is visible but has no prompt", k));
+            }
+        }
+    }
+
+    if let Some(path) = args.cfg.as_deref() {
+        let options = LoadOptions {
+            env_prefix: None,
+            ..LoadOptions::default()
+        };
+        match cfg.load_configuration_with(path, defn_path, &options) {
+            Ok(report) => {
+                for k in report.unknown_keys {
+                    warnings.push(format!("key '{}' in {} is not defined", k, path));
+                }
+            }
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return error_exit_code(&e);
+            }
+        }
+    }
+
+    if let Some(path) = args.policy.as_deref() {
+        if let Err(e) = cfg.load_policy(path) {
+            eprintln!("error: {}: {}", path, e);
+            return error_exit_code(&e);
+        }
+    }
+    let rule_violations = cfg.validate_all();
+    for v in &rule_violations {
+        eprintln!("rule: {}", v.message);
+    }
+
+    let violations = cfg.format_violations();
+    for v in &violations {
+        match &v.error {
+            Some(e) => eprintln!(
+                "format: '{}' has an invalid format '{}': {}",
+                v.key, v.format, e
+            ),
+            None => eprintln!(
+                "format: '{}' value '{}' does not match '{}'",
+                v.key, v.value, v.format
+            ),
+        }
+    }
+    warnings.sort();
+    for w in &warnings {
+        eprintln!("warning: {}", w);
+    }
+
+    if !violations.is_empty() || !rule_violations.is_empty() {
+        EXIT_FORMAT
+    } else if !warnings.is_empty() {
+        EXIT_WARNINGS
+    } else {
+        EXIT_OK
+    }
+}
+
+fn validate_all(pkg_path: &str) -> i32 {
+    let mut pkg = Pkg::new();
+    if let Err(e) = pkg.load_packages(pkg_path) {
+        eprintln!("error: {}: {}", pkg_path, e);
+        return error_exit_code(&e);
+    }
+
+    let mut exit_code = EXIT_OK;
+    for validation in pkg.validate_all() {
+        let code = match &validation.result {
+            Ok(violations) => {
+                for v in violations {
+                    eprintln!(
+                        "format: {}: '{}' value '{}' does not match '{}'",
+                        validation.name, v.key, v.value, v.format
+                    );
+                }
+                for w in &validation.warnings {
+                    eprintln!("warning: {}: {}", validation.name, w);
+                }
+                if !violations.is_empty() {
+                    EXIT_FORMAT
+                } else if !validation.warnings.is_empty() {
+                    EXIT_WARNINGS
+                } else {
+                    EXIT_OK
+                }
+            }
+            Err(e) => {
+                eprintln!("error: {}: {}", validation.name, e);
+                error_exit_code(e)
+            }
+        };
+        exit_code = exit_code.max(code);
+    }
+    exit_code
+}
@@ -0,0 +1,279 @@
+//! Fluent construction of a loaded Cfg
+//!
+//! [`Cfg::new`] gives a structure with no items, on which most methods fail with
+//! [`CfgError::Cfg`] until a configuration is loaded.  A [`CfgBuilder`] collects the definition,
+//! the INI files and the options and returns a `Cfg` that is already loaded:
+//!
+//! ```no_run
+//! use canpi_config::Cfg;
+//!
+//! let cfg = Cfg::builder()
+//!     .defn_file("canpi-config-defn.json")
+//!     .ini_file("/etc/canpi/canpi.cfg")
+//!     .env_prefix("CANPI_")
+//!     .strict(true)
+//!     .build()?;
+//! # Ok::<(), canpi_config::CfgError>(())
+//! ```
+
+use crate::{
+    Cfg, CfgError, Definition, Fs, InvalidValues, Layer, LoadOptions, LoadReport, UnknownFields,
+};
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Name given in errors to a definition passed as a string
+const DEFN_STR_NAME: &str = "(definition string)";
+
+/// Where the attribute definitions are read from
+pub(crate) enum DefnSource {
+    /// A definition file
+    File(PathBuf),
+    /// The text of a definition file
+    Text(String),
+}
+
+impl DefnSource {
+    /// The path errors are reported against
+    pub(crate) fn path(&self) -> &Path {
+        match self {
+            DefnSource::File(path) => path,
+            DefnSource::Text(_) => Path::new(DEFN_STR_NAME),
+        }
+    }
+
+    /// Read the definitions, checking them against the schema and any signing key of `cfg` and
+    /// compiling their formats with its regex options
+    ///
+    /// Unknown fields in the definition are treated as `unknown_fields` says.
+    pub(crate) fn read(
+        &self,
+        cfg: &Cfg,
+        unknown_fields: UnknownFields,
+    ) -> Result<Definition, CfgError> {
+        let text = match self {
+            DefnSource::File(path) => Cow::Owned(read_file(cfg, path)?),
+            DefnSource::Text(text) => Cow::Borrowed(text.as_str()),
+        };
+        #[cfg(feature = "signing")]
+        if let Some(key) = &cfg.defn_key {
+            crate::signature::verify_defn(key, &*cfg.fs, self, &text)?;
+        }
+        let defn = Cfg::read_defn_text(self.path(), &text, cfg.defn_schema(), unknown_fields)?;
+        cfg.check_formats(&defn.items)?;
+        Ok(defn)
+    }
+}
+
+/// The text of the definition file `path`, fetching it if it is a URL
+fn read_file(cfg: &Cfg, path: &Path) -> Result<String, CfgError> {
+    #[cfg(feature = "http")]
+    if let Some(url) = crate::fetch::url(path) {
+        return cfg.fetch_defn(url);
+    }
+    Ok(cfg.fs.read_to_string(path)?)
+}
+
+#[derive(Default)]
+/// Builds a [`Cfg`] with its configuration loaded
+///
+/// The definition must be given, with [`CfgBuilder::defn_file`] or [`CfgBuilder::defn_str`].
+/// Values are read from each INI file or text given, later ones overriding earlier, then from the
+/// environment and then from any overrides.  Items with no value from any of these take their
+/// default, as with [`Cfg::load_configuration`].
+pub struct CfgBuilder {
+    defn: Option<DefnSource>,
+    schema: Option<PathBuf>,
+    ini: Vec<Layer>,
+    overrides: BTreeMap<String, String>,
+    options: LoadOptions,
+    strict: bool,
+    fs: Option<Arc<dyn Fs>>,
+    locale: Option<String>,
+}
+
+impl CfgBuilder {
+    /// A builder with the default [`LoadOptions`]
+    pub fn new() -> CfgBuilder {
+        CfgBuilder::default()
+    }
+
+    /// Read the attribute definitions from the file at `path`
+    pub fn defn_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.defn = Some(DefnSource::File(path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Read the attribute definitions from `text`, in any format a definition file may use
+    pub fn defn_str(mut self, text: &str) -> Self {
+        self.defn = Some(DefnSource::Text(text.to_string()));
+        self
+    }
+
+    /// Validate the definition against the JSON schema in the file at `path`
+    ///
+    /// See [`Cfg::with_schema_file`].
+    pub fn schema_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.schema = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Read values from the INI file at `path`, overriding those of any INI file given before
+    pub fn ini_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.ini.push(Layer::Ini(path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Read values from the INI text `text`, overriding those of any INI file given before
+    ///
+    /// Include directives in the text are not followed.
+    pub fn ini_str(mut self, text: &str) -> Self {
+        self.ini.push(Layer::IniStr(text.to_string()));
+        self
+    }
+
+    /// Read overriding values from environment variables named with `prefix`
+    pub fn env_prefix(mut self, prefix: &str) -> Self {
+        self.options.env_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Ignore environment variables
+    pub fn ignore_env(mut self) -> Self {
+        self.options.env_prefix = None;
+        self
+    }
+
+    /// Set `key` to `value`, overriding the INI files and the environment
+    ///
+    /// The override is not written back to the INI file.
+    pub fn override_value(mut self, key: &str, value: &str) -> Self {
+        self.overrides.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Fail the build on any value that does not satisfy its format, any INI entry that is not
+    /// defined or any field of the definition that is not known
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Interpret the INI files according to `options`
+    ///
+    /// Replaces any environment prefix set before.
+    pub fn options(mut self, options: LoadOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Perform file operations through `fs`
+    pub fn fs(mut self, fs: Arc<dyn Fs>) -> Self {
+        self.fs = Some(fs);
+        self
+    }
+
+    /// Show prompts and tooltips in `locale`
+    pub fn locale(mut self, locale: &str) -> Self {
+        self.locale = Some(locale.to_string());
+        self
+    }
+
+    /// Load the configuration and return the `Cfg`
+    pub fn build(self) -> Result<Cfg, CfgError> {
+        self.build_with_report().map(|(cfg, _)| cfg)
+    }
+
+    /// Load the configuration and return the `Cfg` along with what loading found
+    pub fn build_with_report(self) -> Result<(Cfg, LoadReport), CfgError> {
+        let defn = self.defn.ok_or(CfgError::Cfg())?;
+        let mut options = self.options;
+        if self.strict {
+            options.invalid_values = InvalidValues::Reject;
+            options.unknown_fields = UnknownFields::Reject;
+        }
+        let mut layers = self.ini;
+        if let Some(prefix) = &options.env_prefix {
+            layers.push(Layer::Env(prefix.clone()));
+        }
+        if !self.overrides.is_empty() {
+            layers.push(Layer::Overrides(self.overrides));
+        }
+
+        let mut cfg = Cfg::new();
+        if let Some(fs) = self.fs {
+            cfg.set_fs(fs);
+        }
+        if let Some(locale) = &self.locale {
+            cfg.set_locale(locale);
+        }
+        if let Some(schema) = &self.schema {
+            cfg = cfg.with_schema_file(schema)?;
+        }
+        let cfg_path = crate::layer::writable_path(&layers)
+            .unwrap_or_else(|| defn.path())
+            .to_path_buf();
+        let report = cfg.load_stack(&cfg_path, &defn, layers, &options)?;
+        if self.strict {
+            if let Some(key) = report.unknown_keys.first() {
+                return Err(CfgError::UnknownKey(key.clone()));
+            }
+        }
+        Ok((cfg, report))
+    }
+}
+
+impl Cfg {
+    /// A [`CfgBuilder`] for a loaded `Cfg`
+    pub fn builder() -> CfgBuilder {
+        CfgBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::CANID_NODE_DEFN;
+    use crate::MemoryFs;
+
+    #[test]
+    fn builds_a_loaded_cfg() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", CANID_NODE_DEFN);
+        fs.insert("system.cfg", "canid=101\nnode_number=5000\n");
+        fs.insert("user.cfg", "node_number=5001\nwifi=on\n");
+        let cfg = Cfg::builder()
+            .fs(fs.clone())
+            .defn_file("defn.json")
+            .ini_file("system.cfg")
+            .ini_file("user.cfg")
+            .ignore_env()
+            .override_value("canid", "102")
+            .build()
+            .expect("build failed");
+        assert_eq!(cfg.get_or_default("canid"), "102");
+        assert_eq!(cfg.get_or_default("node_number"), "5001");
+
+        let err = Cfg::builder()
+            .fs(fs.clone())
+            .defn_str(CANID_NODE_DEFN)
+            .ini_file("user.cfg")
+            .strict(true)
+            .build()
+            .err()
+            .expect("unknown key accepted");
+        assert!(matches!(err, CfgError::UnknownKey(k) if k == "wifi"));
+
+        let (cfg, report) = Cfg::builder()
+            .fs(fs)
+            .defn_str(CANID_NODE_DEFN)
+            .build_with_report()
+            .expect("build failed");
+        assert_eq!(cfg.get_or_default("canid"), "100");
+        assert_eq!(report.missing_keys.len(), 2);
+        assert!(Cfg::builder().build().is_err());
+    }
+}
@@ -0,0 +1,120 @@
+//! Grouping configuration items for presentation
+//!
+//! Each attribute may name a `category`, such as `CAN` or `Wi-Fi`, and a `display_order`.
+//! [`Cfg::attributes_by_category`] gathers the items into one [`Category`] per name so that a
+//! front end can show each group on a tab of its own, always in the same order.
+
+use crate::{Attribute, Cfg};
+
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug)]
+/// The configuration items of one category
+pub struct Category {
+    /// Name of the category; `None` for the items that do not give one
+    pub name: Option<String>,
+    /// The items as (key, attribute) pairs, by display order and then key
+    pub items: Vec<(String, Attribute)>,
+}
+
+/// Items of a category while they are being sorted
+type Items<'a> = Vec<(&'a String, &'a Attribute)>;
+
+/// Sort position of an attribute; items without a display order come after those with one
+fn position(attr: &Attribute) -> u32 {
+    attr.display_order.unwrap_or(u32::MAX)
+}
+
+impl Cfg {
+    /// Every configuration item grouped by category
+    ///
+    /// Categories are ordered by the smallest display order among their items and then by name,
+    /// with the uncategorised items last.
+    pub fn attributes_by_category(&self) -> Vec<Category> {
+        let mut groups: BTreeMap<Option<&str>, Items> = BTreeMap::new();
+        if let Some(cfg) = &self.cfg {
+            for (k, v) in cfg {
+                groups
+                    .entry(v.category.as_deref())
+                    .or_default()
+                    .push((k, v));
+            }
+        }
+        let mut groups: Vec<(Option<&str>, Items)> = groups.into_iter().collect();
+        for (_, items) in &mut groups {
+            items.sort_by(|a, b| position(a.1).cmp(&position(b.1)).then(a.0.cmp(b.0)));
+        }
+        groups.sort_by_key(|(name, items)| (name.is_none(), position(items[0].1), *name));
+        groups
+            .into_iter()
+            .map(|(name, items)| Category {
+                name: name.map(String::from),
+                items: items
+                    .into_iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryFs;
+    use std::sync::Arc;
+
+    const DEFN_DATA: &str = r#"
+        {
+            "ap_ssid" : {
+                "prompt": "Access point SSID", "tooltip": "", "current": "", "default": "",
+                "format": ".*", "action": "Edit", "category": "Wi-Fi", "display_order": 20
+            },
+            "ap_mode" : {
+                "prompt": "Access point mode", "tooltip": "", "current": "", "default": "",
+                "format": ".*", "action": "Edit", "category": "Wi-Fi", "display_order": 10
+            },
+            "canid" : {
+                "prompt": "CAN Id", "tooltip": "", "current": "", "default": "",
+                "format": ".*", "action": "Edit", "category": "CAN", "display_order": 1
+            },
+            "node_number" : {
+                "prompt": "Node Number", "tooltip": "", "current": "", "default": "",
+                "format": ".*", "action": "Edit", "category": "CAN"
+            },
+            "logfile" : {
+                "prompt": "Log file", "tooltip": "", "current": "", "default": "",
+                "format": ".*", "action": "Edit"
+            }
+        }"#;
+
+    #[test]
+    fn groups_in_display_order() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs);
+        cfg.load_definition("defn.json")
+            .expect("definition failed to load");
+
+        let groups: Vec<(Option<String>, Vec<String>)> = cfg
+            .attributes_by_category()
+            .into_iter()
+            .map(|c| (c.name, c.items.into_iter().map(|(k, _)| k).collect()))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                (
+                    Some("CAN".into()),
+                    vec!["canid".into(), "node_number".into()]
+                ),
+                (
+                    Some("Wi-Fi".into()),
+                    vec!["ap_mode".into(), "ap_ssid".into()]
+                ),
+                (None, vec!["logfile".into()]),
+            ]
+        );
+    }
+}
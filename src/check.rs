@@ -0,0 +1,513 @@
+//! Checking candidate values before they are committed
+//!
+//! [`Cfg::validate_value`] applies every check a value must pass for a configuration item -
+//! the input limits, the number locale, the control character policy and the item's `format` -
+//! without changing anything, so a form can report problems as the user types.
+//! [`Cfg::write_attribute_checked`] makes the same format check before storing an attribute.
+//! Items with a `min` or `max` must also hold a number in that range, and one with a `step` a
+//! whole number of steps from `min`; `Enum` and `MultiSelect`
+//! items may only hold their `choices`, and a `required` item may not be left empty.
+
+use crate::{ActionBehaviour, Attribute, Cfg, CfgError, ValueKind};
+
+use thiserror::Error;
+
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+/// Why a candidate value is not acceptable for a configuration item
+pub enum ValidationError {
+    /// The configuration item is not defined
+    #[error("configuration item '{0}' is not defined")]
+    UnknownKey(String),
+    /// The value was refused before its format was checked, e.g. as too long
+    #[error("value for '{key}' rejected: {reason}")]
+    Rejected {
+        /// Key of the configuration item
+        key: String,
+        /// Why the value was refused
+        reason: String,
+    },
+    /// The value does not match the item's format
+    #[error("value '{value}' for '{key}' does not match '{format}'")]
+    Format {
+        /// Key of the configuration item
+        key: String,
+        /// The value checked, after any locale conversion
+        value: String,
+        /// The format it was checked against
+        format: String,
+    },
+    /// The value matches the item's format but is outside its `min` and `max`
+    #[error("value for '{key}' rejected: {reason}")]
+    OutOfRange {
+        /// Key of the configuration item
+        key: String,
+        /// The value checked
+        value: String,
+        /// Which limit it breaks
+        reason: String,
+    },
+    /// The value, or one of the items selected, is not among the item's `choices`
+    #[error("value for '{key}' rejected: {reason}")]
+    NotAChoice {
+        /// Key of the configuration item
+        key: String,
+        /// The value checked
+        value: String,
+        /// Which item is not a choice
+        reason: String,
+    },
+    /// The item's action is not `Edit`, so it may not be changed
+    #[error("configuration item '{0}' cannot be edited")]
+    NotEditable(String),
+    /// The item's format is not a valid regular expression
+    #[error("format '{format}' of '{key}' is invalid: {reason}")]
+    InvalidFormat {
+        /// Key of the configuration item
+        key: String,
+        /// The format that failed to compile
+        format: String,
+        /// The compiler's message
+        reason: String,
+    },
+}
+
+impl ValidationError {
+    /// Key of the configuration item the value was for
+    pub fn key(&self) -> &str {
+        match self {
+            ValidationError::UnknownKey(key)
+            | ValidationError::NotEditable(key)
+            | ValidationError::Rejected { key, .. }
+            | ValidationError::Format { key, .. }
+            | ValidationError::OutOfRange { key, .. }
+            | ValidationError::NotAChoice { key, .. }
+            | ValidationError::InvalidFormat { key, .. } => key,
+        }
+    }
+}
+
+impl Cfg {
+    /// Keys of the required items whose current value is empty, in key order
+    pub fn empty_required_keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = match &self.cfg {
+            Some(cfg) => cfg
+                .iter()
+                .filter(|(_, v)| v.required && v.current.trim().is_empty())
+                .map(|(k, _)| k.as_str())
+                .collect(),
+            None => Vec::new(),
+        };
+        keys.sort_unstable();
+        keys
+    }
+
+    /// Check that `value` would be accepted as the current value of `key`
+    ///
+    /// The same checks are made as by [`Cfg::set_value`], except that an empty value must also
+    /// satisfy the item's `format` and may not be given for a `required` item.
+    pub fn validate_value<K: AsRef<str>>(
+        &self,
+        key: K,
+        value: &str,
+    ) -> Result<(), ValidationError> {
+        let key = key.as_ref();
+        let attr = self
+            .cfg
+            .as_ref()
+            .and_then(|cfg| cfg.get(key))
+            .ok_or_else(|| ValidationError::UnknownKey(key.to_string()))?;
+        let rejected = |e: CfgError| ValidationError::Rejected {
+            key: key.to_string(),
+            reason: e.to_string(),
+        };
+        self.check_value_len(key, value).map_err(rejected)?;
+        if attr.required && value.trim().is_empty() {
+            return Err(rejected(CfgError::InvalidValue(
+                "a value is required".to_string(),
+            )));
+        }
+        let canonical = self.canonical_input(key, attr, value).map_err(rejected)?;
+        let value = attr.sanitize(key, &canonical).map_err(rejected)?;
+        self.check_constraints(key, attr, &value)
+    }
+
+    /// As [`Cfg::write_attribute`], refusing with `edit_only` set to replace an item whose action
+    /// is not `Edit`
+    pub fn write_attribute_checked<K: AsRef<str>>(
+        &mut self,
+        key: K,
+        value: &Attribute,
+        edit_only: bool,
+    ) -> Result<(), CfgError> {
+        let key = key.as_ref();
+        let existing = self.cfg.as_ref().ok_or(CfgError::Cfg())?.get(key);
+        if edit_only && existing.is_some_and(|a| a.action != ActionBehaviour::Edit) {
+            return Err(ValidationError::NotEditable(key.to_string()).into());
+        }
+        self.write_attribute(key, value)
+    }
+
+    pub(crate) fn check_constraints(
+        &self,
+        key: &str,
+        attr: &Attribute,
+        value: &str,
+    ) -> Result<(), ValidationError> {
+        match self.format_matches(attr, value) {
+            Ok(true) => match (attr.range_error(value), attr.choice_error(value)) {
+                (Some(reason), _) => Err(ValidationError::OutOfRange {
+                    key: key.to_string(),
+                    value: attr.shown(value).to_string(),
+                    reason,
+                }),
+                (None, Some(reason)) => Err(ValidationError::NotAChoice {
+                    key: key.to_string(),
+                    value: attr.shown(value).to_string(),
+                    reason,
+                }),
+                (None, None) => Ok(()),
+            },
+            Ok(false) => Err(ValidationError::Format {
+                key: key.to_string(),
+                value: attr.shown(value).to_string(),
+                format: attr.format.to_string(),
+            }),
+            Err(e) => Err(ValidationError::InvalidFormat {
+                key: key.to_string(),
+                format: attr.format.to_string(),
+                reason: match e {
+                    CfgError::Format(reason) => reason,
+                    e => e.to_string(),
+                },
+            }),
+        }
+    }
+}
+
+impl Attribute {
+    /// Why `value` is outside `min` and `max`, or between steps, if it is
+    ///
+    /// An empty value, meaning the default applies, is never out of range.
+    pub fn range_error(&self, value: &str) -> Option<String> {
+        if (self.min.is_none() && self.max.is_none() && self.step.is_none()) || value.is_empty() {
+            return None;
+        }
+        let number: f64 = match value.trim().parse() {
+            Ok(n) => n,
+            Err(_) => return Some(format!("'{}' is not a number", self.shown(value))),
+        };
+        match (self.min, self.max) {
+            (Some(min), _) if number < min => Some(format!(
+                "{} is less than the minimum {}",
+                self.shown(value),
+                min
+            )),
+            (_, Some(max)) if number > max => Some(format!(
+                "{} is more than the maximum {}",
+                self.shown(value),
+                max
+            )),
+            _ => match self.step {
+                Some(step)
+                    if step > 0.0 && !is_whole((number - self.min.unwrap_or(0.0)) / step) =>
+                {
+                    Some(format!(
+                        "{} is not a multiple of {} from {}",
+                        self.shown(value),
+                        step,
+                        self.min.unwrap_or(0.0)
+                    ))
+                }
+                _ => None,
+            },
+        }
+    }
+
+    /// Why `value` is not among `choices`, if it is not
+    ///
+    /// Only `Enum` and `MultiSelect` items with choices are checked; every item selected must be
+    /// a choice.  An empty value is never refused.
+    pub fn choice_error(&self, value: &str) -> Option<String> {
+        let selected: Vec<&str> = match self.kind {
+            _ if self.choices.is_empty() => return None,
+            ValueKind::Enum => vec![value.trim()],
+            ValueKind::MultiSelect => split_selection(value).collect(),
+            _ => return None,
+        };
+        selected
+            .into_iter()
+            .filter(|s| !s.is_empty())
+            .find(|s| !self.choices.iter().any(|c| **c == **s))
+            .map(|s| format!("'{}' is not one of the choices", self.shown(s)))
+    }
+
+    /// Why `value` breaks the item's `min`, `max` or `choices`, if it does
+    pub(crate) fn constraint_error(&self, value: &str) -> Option<String> {
+        self.range_error(value).or_else(|| self.choice_error(value))
+    }
+}
+
+/// Whether `steps` is a whole number, allowing for rounding in decimal steps
+fn is_whole(steps: f64) -> bool {
+    (steps - steps.round()).abs() < 1e-9
+}
+
+/// The items of a `MultiSelect` value, trimmed
+pub(crate) fn split_selection(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|s| !s.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InputLimits, MemoryFs};
+    use std::sync::Arc;
+
+    #[test]
+    fn steps_count_from_min_or_zero() {
+        let mut attr = Attribute::new("Gain", "", "0", ".*", ActionBehaviour::Edit);
+        attr.step = Some(0.1);
+        assert_eq!(attr.range_error("0.3"), None);
+        assert_eq!(attr.range_error(""), None);
+        assert_eq!(
+            attr.range_error("0.35"),
+            Some("0.35 is not a multiple of 0.1 from 0".to_string())
+        );
+        attr.min = Some(1.0);
+        attr.step = Some(2.0);
+        assert_eq!(attr.range_error("5"), None);
+        assert!(attr.range_error("4").is_some());
+        attr.step = Some(0.0);
+        assert_eq!(attr.range_error("4"), None);
+    }
+
+    const DEFN_DATA: &str = r#"
+        {
+            "canid" : {
+                "prompt": "CAN Id",
+                "tooltip": "The CAN Id used by the CAN Pi CAP/Zero on the CBUS",
+                "current": "100",
+                "default": "100",
+                "format": "[0-9]{1,4}",
+                "action": "Display"
+            },
+            "node_number" : {
+                "prompt": "Node Number",
+                "tooltip": "The node number of the CAN Pi CAP/Zero on the CBUS",
+                "current": "4321",
+                "default": "4321",
+                "format": "u16",
+                "action": "Edit",
+                "min": 1,
+                "max": 4999
+            },
+            "timeout" : {
+                "prompt": "Timeout",
+                "tooltip": "Seconds to wait for the network",
+                "current": "30",
+                "default": "30",
+                "format": "[0-9.]+",
+                "action": "Edit",
+                "min": 10,
+                "step": 2.5
+            },
+            "router_ssid" : {
+                "prompt": "Router SSID",
+                "tooltip": "Network to join",
+                "current": "",
+                "default": "",
+                "format": ".*",
+                "action": "Edit",
+                "required": true
+            },
+            "services" : {
+                "prompt": "Enabled services",
+                "tooltip": "",
+                "current": "",
+                "default": "",
+                "format": ".*",
+                "action": "Edit",
+                "kind": "MultiSelect",
+                "choices": ["ed", "ftp", "wifi"]
+            }
+        }"#;
+
+    #[test]
+    fn candidate_values() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs);
+        cfg.load_definition("defn.json")
+            .expect("definition failed to load");
+        cfg.set_input_limits(InputLimits {
+            max_value_len: 8,
+            ..InputLimits::default()
+        });
+
+        assert_eq!(cfg.validate_value("canid", "1234"), Ok(()));
+        let err = cfg.validate_value("canid", "12a").unwrap_err();
+        assert_eq!(err.key(), "canid");
+        assert!(matches!(err, ValidationError::Format { .. }));
+        assert!(matches!(
+            cfg.validate_value("canid", "123456789"),
+            Err(ValidationError::Rejected { .. })
+        ));
+        // A format written after loading is compiled before the attribute is stored
+        let broken: Attribute = serde_json::from_str(
+            r#"{
+                "prompt": "Broken", "tooltip": "", "current": "", "default": "",
+                "format": "[0-9", "action": "Edit"
+            }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            cfg.write_attribute("broken", &broken),
+            Err(CfgError::Validation(ValidationError::InvalidFormat { .. }))
+        ));
+        assert!(matches!(
+            cfg.validate_value("broken", "1"),
+            Err(ValidationError::UnknownKey(_))
+        ));
+        assert_eq!(
+            cfg.validate_value("wifi", "on"),
+            Err(ValidationError::UnknownKey("wifi".to_string()))
+        );
+        assert_eq!(cfg.get_or_default("canid"), "100");
+    }
+
+    #[test]
+    fn range_limits() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        fs.insert("canpi.cfg", "node_number=6000\n");
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs);
+        let report = cfg
+            .load_configuration("canpi.cfg", "defn.json")
+            .expect("configuration failed to load");
+        let violation = report
+            .invalid_values
+            .iter()
+            .find(|v| v.key == "node_number")
+            .expect("range violation not reported");
+        assert_eq!(
+            violation.error.as_deref(),
+            Some("6000 is more than the maximum 4999")
+        );
+
+        assert_eq!(cfg.validate_value("node_number", "4999"), Ok(()));
+        assert!(matches!(
+            cfg.validate_value("node_number", "0"),
+            Err(ValidationError::OutOfRange { .. })
+        ));
+        assert_eq!(cfg.validate_value("timeout", "17.5"), Ok(()));
+        let err = cfg.validate_value("timeout", "18").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "value for 'timeout' rejected: 18 is not a multiple of 2.5 from 10"
+        );
+        let mut attr = cfg.read_attribute("node_number").unwrap().clone();
+        attr.current = "5000".to_string();
+        assert!(cfg.write_attribute("node_number", &attr).is_err());
+        attr.current = "3x".to_string();
+        assert!(matches!(
+            cfg.write_attribute("node_number", &attr),
+            Err(CfgError::Validation(ValidationError::Format { .. }))
+        ));
+        assert!(cfg.validate_value("node_number", "3x").is_err());
+        attr.current = "300".to_string();
+        cfg.write_attribute("node_number", &attr)
+            .expect("write_attribute failed");
+        assert!(cfg
+            .format_violations()
+            .iter()
+            .all(|v| v.key != "node_number"));
+    }
+
+    #[test]
+    fn selections_are_choices() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        fs.insert("canpi.cfg", "services=ed,ssh\n");
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs);
+        let report = cfg
+            .load_configuration("canpi.cfg", "defn.json")
+            .expect("configuration failed to load");
+        let violation = report
+            .invalid_values
+            .iter()
+            .find(|v| v.key == "services")
+            .expect("choice violation not reported");
+        assert_eq!(
+            violation.error.as_deref(),
+            Some("'ssh' is not one of the choices")
+        );
+
+        assert_eq!(cfg.validate_value("services", "ed, wifi"), Ok(()));
+        assert_eq!(cfg.validate_value("services", ""), Ok(()));
+        assert!(matches!(
+            cfg.validate_value("services", "ftp,telnet"),
+            Err(ValidationError::NotAChoice { .. })
+        ));
+    }
+
+    #[test]
+    fn required_values() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        fs.insert("canpi.cfg", "canid=100\n");
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        let report = cfg
+            .load_configuration("canpi.cfg", "defn.json")
+            .expect("configuration failed to load");
+        assert_eq!(report.empty_required, vec!["router_ssid"]);
+        assert!(!report.is_clean());
+        assert!(matches!(
+            cfg.validate_value("router_ssid", " "),
+            Err(ValidationError::Rejected { .. })
+        ));
+
+        let err = cfg.write_cfg_file("canpi.cfg", None).unwrap_err();
+        assert_eq!(err.key(), Some("router_ssid"));
+        assert_eq!(fs.contents("canpi.cfg").unwrap(), "canid=100\n");
+        cfg.set_value("router_ssid", "club")
+            .expect("set_value failed");
+        assert!(cfg.empty_required_keys().is_empty());
+        cfg.write_cfg_file("canpi.cfg", None).expect("write failed");
+        assert!(fs
+            .contents("canpi.cfg")
+            .unwrap()
+            .contains("router_ssid=club"));
+    }
+
+    #[test]
+    fn checked_attribute_writes() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs);
+        cfg.load_definition("defn.json")
+            .expect("definition failed to load");
+
+        let mut attr = cfg.read_attribute("canid").unwrap().clone();
+        attr.current = "abc".to_string();
+        let err = cfg
+            .write_attribute_checked("canid", &attr, false)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            CfgError::Validation(ValidationError::Format { .. })
+        ));
+        attr.current = "101".to_string();
+        let err = cfg
+            .write_attribute_checked("canid", &attr, true)
+            .unwrap_err();
+        assert_eq!(err.key(), Some("canid"));
+        cfg.write_attribute_checked("canid", &attr, false)
+            .expect("valid attribute refused");
+        assert_eq!(cfg.get_or_default("canid"), "101");
+    }
+}
@@ -0,0 +1,175 @@
+//! Where an error happened
+//!
+//! Errors from loading, validating and writing are wrapped in [`CfgError::Context`], recording
+//! the [`Operation`], the file involved and, where known, the package and configuration item.
+//! The accessors on [`CfgError`] read these whether or not the error is wrapped, and
+//! [`CfgError::root`] gives the underlying error.
+
+use crate::CfgError;
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The operation an error happened in
+pub enum Operation {
+    /// Loading definitions, INI files, policies or packages
+    Load,
+    /// Validating a package
+    Validate,
+    /// Writing an INI file
+    Write,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Operation::Load => "load",
+            Operation::Validate => "validate",
+            Operation::Write => "write",
+        })
+    }
+}
+
+#[derive(Debug)]
+/// An error with the operation, file, package and key it concerns
+pub struct ErrorContext {
+    operation: Operation,
+    file: Option<PathBuf>,
+    package: Option<String>,
+    key: Option<String>,
+    source: CfgError,
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot {}", self.operation)?;
+        if let Some(file) = &self.file {
+            write!(f, " '{}'", file.display())?;
+        }
+        if let Some(package) = &self.package {
+            write!(f, " of package '{}'", package)?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, " at '{}'", key)?;
+        }
+        write!(f, ": {}", self.source)
+    }
+}
+
+impl CfgError {
+    /// The operation the error happened in, if recorded
+    pub fn operation(&self) -> Option<Operation> {
+        match self {
+            CfgError::Context(c) => Some(c.operation),
+            _ => None,
+        }
+    }
+
+    /// The file the error concerns, if recorded
+    pub fn file(&self) -> Option<&Path> {
+        match self {
+            CfgError::Context(c) => c.file.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The package the error concerns, if recorded
+    pub fn package(&self) -> Option<&str> {
+        match self {
+            CfgError::Context(c) => c.package.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The configuration item the error concerns, if known
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            CfgError::Context(c) => c.key.as_deref().or_else(|| c.source.key()),
+            CfgError::UnknownKey(key) | CfgError::Conversion { key, .. } => Some(key),
+            CfgError::Validation(e) => Some(e.key()),
+            _ => None,
+        }
+    }
+
+    /// The underlying error, without any context
+    pub fn root(&self) -> &CfgError {
+        match self {
+            CfgError::Context(c) => c.source.root(),
+            e => e,
+        }
+    }
+
+    fn into_context(self, operation: Operation) -> Box<ErrorContext> {
+        match self {
+            CfgError::Context(c) => c,
+            source => Box::new(ErrorContext {
+                operation,
+                file: None,
+                package: None,
+                key: None,
+                source,
+            }),
+        }
+    }
+
+    /// Record that the error happened during `operation` on `file`
+    ///
+    /// Context already recorded is kept, as it was recorded closer to the failure.
+    pub(crate) fn context(self, operation: Operation, file: &Path) -> CfgError {
+        let mut context = self.into_context(operation);
+        context.file.get_or_insert_with(|| file.to_path_buf());
+        CfgError::Context(context)
+    }
+
+    /// Record that the error concerns the package `name`
+    pub(crate) fn in_package(self, name: &str) -> CfgError {
+        let mut context = self.into_context(Operation::Validate);
+        context.package.get_or_insert_with(|| name.to_string());
+        CfgError::Context(context)
+    }
+
+    /// Record that the error concerns the configuration item `key`
+    pub(crate) fn at_key(self, operation: Operation, key: &str) -> CfgError {
+        let mut context = self.into_context(operation);
+        context.key.get_or_insert_with(|| key.to_string());
+        CfgError::Context(context)
+    }
+}
+
+/// Adding context to the error of a result
+pub(crate) trait ResultExt<T> {
+    /// As [`CfgError::context`]
+    fn context<P: AsRef<Path>>(self, operation: Operation, file: P) -> Result<T, CfgError>;
+}
+
+impl<T> ResultExt<T> for Result<T, CfgError> {
+    fn context<P: AsRef<Path>>(self, operation: Operation, file: P) -> Result<T, CfgError> {
+        self.map_err(|e| e.context(operation, file.as_ref()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context_is_kept_and_merged() {
+        let err = CfgError::UnknownKey("wifi".to_string())
+            .context(Operation::Load, Path::new("inner.json"))
+            .context(Operation::Write, Path::new("outer.cfg"))
+            .in_package("canpi");
+        assert_eq!(err.operation(), Some(Operation::Load));
+        assert_eq!(err.file(), Some(Path::new("inner.json")));
+        assert_eq!(err.package(), Some("canpi"));
+        assert_eq!(err.key(), Some("wifi"));
+        assert!(matches!(err.root(), CfgError::UnknownKey(_)));
+        assert_eq!(
+            err.to_string(),
+            "cannot load 'inner.json' of package 'canpi': configuration item 'wifi' is not defined"
+        );
+        let err = CfgError::Cfg().at_key(Operation::Load, "canid");
+        assert_eq!(err.key(), Some("canid"));
+        assert_eq!(err.file(), None);
+    }
+}
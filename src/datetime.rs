@@ -0,0 +1,159 @@
+//! Datetime values
+//!
+//! Attributes of kind [`ValueKind::DateTime`] hold an instant such as a scheduled shutdown time.
+//! The INI file holds it in RFC 3339 form in UTC, e.g. `2026-10-16T22:30:00Z`.  Values supplied
+//! through the API may be in any RFC 3339 form, or without an offset as sent by an HTML
+//! `datetime-local` input, in which case they are taken to be in the display timezone.
+
+use crate::{Attribute, Cfg, CfgError, ValueKind};
+
+use chrono::{DateTime, FixedOffset, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+
+/// Forms accepted for a datetime without an offset
+const LOCAL_FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+];
+
+pub(crate) fn utc() -> FixedOffset {
+    FixedOffset::east_opt(0).expect("zero offset is valid")
+}
+
+/// The form a datetime is stored in
+pub(crate) fn canonical<Tz: TimeZone>(time: &DateTime<Tz>) -> String {
+    time.with_timezone(&Utc)
+        .to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+/// Read `input` as a datetime, taking it to be in `zone` if it has no offset
+pub(crate) fn parse(input: &str, zone: FixedOffset) -> Result<DateTime<FixedOffset>, String> {
+    let input = input.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(input) {
+        return Ok(time);
+    }
+    LOCAL_FORMATS
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(input, f).ok())
+        .and_then(|naive| zone.from_local_datetime(&naive).single())
+        .ok_or_else(|| format!("'{}' is not an RFC 3339 datetime", input))
+}
+
+/// Convert a datetime supplied for an attribute to the form stored in the INI file
+pub(crate) fn canonical_input(input: &str, zone: FixedOffset) -> Result<String, String> {
+    parse(input, zone).map(|time| canonical(&time))
+}
+
+impl Cfg {
+    /// Show datetime values in, and read datetimes without an offset as, `zone`
+    ///
+    /// The default is UTC.
+    pub fn set_display_timezone(&mut self, zone: FixedOffset) {
+        self.display_timezone = zone;
+    }
+
+    /// The timezone datetime values are shown in
+    pub fn display_timezone(&self) -> FixedOffset {
+        self.display_timezone
+    }
+
+    /// The current value of the datetime configuration item `key` in the display timezone
+    pub fn get_datetime<K: AsRef<str>>(&self, key: K) -> Result<DateTime<FixedOffset>, CfgError> {
+        let key = key.as_ref();
+        let attr = self.datetime_attribute(key)?;
+        parse(&attr.current, utc())
+            .map(|time| time.with_timezone(&self.display_timezone))
+            .map_err(|e| CfgError::InvalidValue(format!("value for '{}': {}", key, e)))
+    }
+
+    /// Set the current value of the datetime configuration item `key`
+    pub fn set_datetime<Tz: TimeZone, K: AsRef<str>>(
+        &mut self,
+        key: K,
+        time: &DateTime<Tz>,
+    ) -> Result<(), CfgError> {
+        let key = key.as_ref();
+        self.datetime_attribute(key)?;
+        self.set_value(key, &canonical(time))
+    }
+
+    fn datetime_attribute(&self, key: &str) -> Result<&Attribute, CfgError> {
+        let cfg = self.cfg.as_ref().ok_or(CfgError::Cfg())?;
+        let attr = cfg
+            .get(key)
+            .ok_or_else(|| CfgError::UnknownKey(key.to_string()))?;
+        if attr.kind != ValueKind::DateTime {
+            return Err(CfgError::InvalidValue(format!(
+                "'{}' does not hold a datetime",
+                key
+            )));
+        }
+        Ok(attr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::load_defn;
+
+    const DEFN_DATA: &str = r#"
+        {
+            "shutdown_at" : {
+                "prompt": "Scheduled shutdown",
+                "tooltip": "When the CAN Pi shuts down",
+                "current": "2026-10-16T22:30:00Z",
+                "default": "",
+                "format": ".*",
+                "action": "Edit",
+                "kind": "DateTime"
+            },
+            "canid" : {
+                "prompt": "CAN Id",
+                "tooltip": "The CAN Id used by the CAN Pi CAP/Zero on the CBUS",
+                "current": "100",
+                "default": "100",
+                "format": "[0-9]{1,4}",
+                "action": "Display"
+            }
+        }"#;
+
+    #[test]
+    fn datetimes_are_stored_in_utc() {
+        let mut cfg = load_defn("scratch/datetime_test.json", DEFN_DATA);
+
+        let bst = FixedOffset::east_opt(3600).unwrap();
+        cfg.set_display_timezone(bst);
+        let time = cfg
+            .get_datetime("shutdown_at")
+            .expect("get_datetime failed");
+        assert_eq!(time.to_rfc3339(), "2026-10-16T23:30:00+01:00");
+        assert_eq!(
+            cfg.localized_value("shutdown_at").as_deref(),
+            Some("2026-10-16T23:30:00+01:00")
+        );
+
+        cfg.set_value("shutdown_at", "2026-10-17T06:00")
+            .expect("set_value failed");
+        let current = |cfg: &Cfg| cfg.cfg.as_ref().unwrap()["shutdown_at"].current.clone();
+        assert_eq!(current(&cfg), "2026-10-17T05:00:00Z");
+        let later = time + chrono::Duration::hours(1);
+        cfg.set_datetime("shutdown_at", &later)
+            .expect("set_datetime failed");
+        assert_eq!(current(&cfg), "2026-10-16T23:30:00Z");
+
+        assert!(matches!(
+            cfg.set_value("shutdown_at", "tomorrow"),
+            Err(CfgError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            cfg.get_datetime("canid"),
+            Err(CfgError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            cfg.set_datetime("canid", &later),
+            Err(CfgError::InvalidValue(_))
+        ));
+    }
+}
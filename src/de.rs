@@ -0,0 +1,331 @@
+//! serde deserialisation of current values
+//!
+//! [`Cfg::deserialize_into`] populates any `#[derive(Deserialize)]` struct from the current
+//! values, each field taking the configuration item of the same name.  Values are converted as
+//! the field type asks: numbers are parsed, flags accept `true`/`false`, `yes`/`no`, `on`/`off`
+//! and `1`/`0`, an empty value is `None` for an `Option`, a sequence is read from a comma
+//! separated list and an enum from the name of a unit variant.
+
+use crate::{Cfg, CfgError};
+
+use serde::de::value::{BorrowedStrDeserializer, SeqDeserializer};
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, Visitor};
+use serde::forward_to_deserialize_any;
+
+use std::fmt;
+
+#[derive(Debug)]
+/// An error deserialising current values, with the key and value involved where known
+pub struct DeError {
+    key: Option<String>,
+    value: Option<String>,
+    target: Option<&'static str>,
+    message: String,
+}
+
+impl de::Error for DeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DeError {
+            key: None,
+            value: None,
+            target: None,
+            message: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for DeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "'{}': {}", key, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for DeError {}
+
+impl From<DeError> for CfgError {
+    fn from(e: DeError) -> Self {
+        match e.key {
+            Some(key) => CfgError::Conversion {
+                key,
+                value: e.value.unwrap_or_default(),
+                target: e.target.unwrap_or("the field type"),
+                reason: e.message,
+            },
+            None => CfgError::Deserialize(e.message),
+        }
+    }
+}
+
+/// Deserializes one current value
+struct ValueDeserializer<'a> {
+    value: &'a str,
+}
+
+impl<'a> ValueDeserializer<'a> {
+    fn parse<T: std::str::FromStr>(&self, target: &'static str) -> Result<T, DeError>
+    where
+        T::Err: fmt::Display,
+    {
+        self.value.trim().parse().map_err(|e: T::Err| DeError {
+            key: None,
+            value: None,
+            target: Some(target),
+            message: e.to_string(),
+        })
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident, $ty:ty;)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+                visitor.$visit(self.parse::<$ty>(stringify!($ty))?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_borrowed_str(self.value)
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8, i8;
+        deserialize_i16 => visit_i16, i16;
+        deserialize_i32 => visit_i32, i32;
+        deserialize_i64 => visit_i64, i64;
+        deserialize_u8 => visit_u8, u8;
+        deserialize_u16 => visit_u16, u16;
+        deserialize_u32 => visit_u32, u32;
+        deserialize_u64 => visit_u64, u64;
+        deserialize_f32 => visit_f32, f32;
+        deserialize_f64 => visit_f64, f64;
+        deserialize_char => visit_char, char;
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self.value.trim().to_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => visitor.visit_bool(true),
+            "false" | "no" | "off" | "0" => visitor.visit_bool(false),
+            _ => Err(DeError {
+                key: None,
+                value: None,
+                target: Some("bool"),
+                message: "expected true/false, yes/no, on/off or 1/0".to_string(),
+            }),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        if self.value.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        let items = self
+            .value
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(|value| ValueDeserializer { value });
+        visitor.visit_seq(SeqDeserializer::new(items))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        visitor.visit_enum(BorrowedStrDeserializer::new(self.value.trim()))
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 str string bytes byte_buf unit_struct tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, DeError> for ValueDeserializer<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// The current values as a map from key to value
+struct Entries<'a> {
+    entries: std::vec::IntoIter<(&'a str, &'a str)>,
+    value: Option<(&'a str, &'a str)>,
+}
+
+impl<'de> MapAccess<'de> for Entries<'de> {
+    type Error = DeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, DeError> {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.value = Some((key, value));
+                seed.deserialize(BorrowedStrDeserializer::new(key))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, DeError> {
+        let (key, value) = self
+            .value
+            .take()
+            .ok_or_else(|| de::Error::custom("value requested before key"))?;
+        seed.deserialize(ValueDeserializer { value })
+            .map_err(|e| DeError {
+                key: Some(key.to_string()),
+                value: Some(value.to_string()),
+                ..e
+            })
+    }
+}
+
+/// A [`serde::Deserializer`] over the current values of a [`Cfg`]
+pub struct CfgDeserializer<'a> {
+    cfg: &'a Cfg,
+}
+
+impl<'de> de::Deserializer<'de> for CfgDeserializer<'de> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        let entries: Vec<(&str, &str)> = self
+            .cfg
+            .cfg
+            .iter()
+            .flatten()
+            .map(|(k, v)| (k.as_str(), v.current.as_str()))
+            .collect();
+        visitor.visit_map(Entries {
+            entries: entries.into_iter(),
+            value: None,
+        })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map struct enum
+        identifier ignored_any
+    }
+}
+
+impl Cfg {
+    /// A [`serde::Deserializer`] over the current values
+    pub fn deserializer(&self) -> CfgDeserializer<'_> {
+        CfgDeserializer { cfg: self }
+    }
+
+    /// Populate a settings struct from the current values
+    ///
+    /// A value that does not convert to its field's type gives [`CfgError::Conversion`]
+    /// naming the key; other failures, such as a field with no configuration item, give
+    /// [`CfgError::Deserialize`].
+    pub fn deserialize_into<T: DeserializeOwned>(&self) -> Result<T, CfgError> {
+        if self.cfg.is_none() {
+            return Err(CfgError::Cfg());
+        }
+        Ok(T::deserialize(self.deserializer())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::load_example_defn;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Level {
+        #[serde(rename = "INFO")]
+        Info,
+        #[serde(rename = "DEBUG")]
+        Debug,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Settings {
+        canid: u16,
+        node_number: u32,
+        ap_mode: bool,
+        loglevel: Level,
+        #[serde(default)]
+        missing: Option<String>,
+    }
+
+    #[test]
+    fn settings_from_current_values() {
+        let mut cfg = load_example_defn("scratch/de_test.json");
+
+        cfg.set_value("loglevel", "DEBUG").expect("set failed");
+        let settings: Settings = cfg.deserialize_into().expect("deserialize failed");
+        assert_eq!(settings.canid, 100);
+        assert_eq!(settings.node_number, 4321);
+        assert!(!settings.ap_mode);
+        assert_eq!(settings.loglevel, Level::Debug);
+        assert_eq!(settings.missing, None);
+
+        cfg.set_value("canid", "1000").expect("set failed");
+        #[derive(Debug, Deserialize)]
+        struct Narrow {
+            #[allow(dead_code)]
+            canid: u8,
+        }
+        let err = cfg.deserialize_into::<Narrow>().unwrap_err();
+        assert!(matches!(
+            &err,
+            CfgError::Conversion { key, value, target: "u8", .. } if key == "canid" && value == "1000"
+        ));
+
+        #[derive(Debug, Deserialize)]
+        struct Absent {
+            #[allow(dead_code)]
+            no_such_key: String,
+        }
+        assert!(matches!(
+            cfg.deserialize_into::<Absent>(),
+            Err(CfgError::Deserialize(_))
+        ));
+    }
+
+    #[test]
+    fn lists_and_options() {
+        let list = ValueDeserializer { value: "1, 2,3" };
+        let values: Vec<u8> = Deserialize::deserialize(list).expect("list failed");
+        assert_eq!(values, vec![1, 2, 3]);
+        let empty = ValueDeserializer { value: "" };
+        let value: Option<u8> = Deserialize::deserialize(empty).expect("option failed");
+        assert_eq!(value, None);
+    }
+}
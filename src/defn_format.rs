@@ -0,0 +1,142 @@
+//! Definition files in TOML and YAML
+//!
+//! A definition file may be written in JSON, TOML or, with the `yaml` feature, YAML; long
+//! tooltips are easier to maintain by hand in the latter two.  The format is taken from the file
+//! extension (`.json`, `.toml`, `.yaml` or `.yml`) and otherwise sniffed from the first line of
+//! content.  Whatever the format, the document is read into the same JSON value, so it is checked
+//! against the same schema.
+
+use crate::CfgError;
+
+use serde_json::Value;
+
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The formats a definition file may be written in
+enum DefnFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl DefnFormat {
+    /// The format named by the extension of `path`, if it names one
+    fn from_extension(path: &Path) -> Option<DefnFormat> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "json" => Some(DefnFormat::Json),
+            "toml" => Some(DefnFormat::Toml),
+            "yaml" | "yml" => Some(DefnFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    /// The format `text` appears to be written in, judged by its first line of content
+    ///
+    /// A JSON definition is an object so opens with `{`; a TOML one opens with a `[table]` or a
+    /// `key = value` line; anything else is taken to be YAML.
+    fn sniff(text: &str) -> DefnFormat {
+        let line = text
+            .lines()
+            .map(str::trim)
+            .find(|l| !l.is_empty() && !l.starts_with('#') && l != &"---")
+            .unwrap_or("{");
+        if line.starts_with('{') {
+            return DefnFormat::Json;
+        }
+        if line.starts_with('[') {
+            return DefnFormat::Toml;
+        }
+        match (line.find('='), line.find(':')) {
+            (Some(eq), Some(colon)) if eq < colon => DefnFormat::Toml,
+            (Some(_), None) => DefnFormat::Toml,
+            _ => DefnFormat::Yaml,
+        }
+    }
+}
+
+/// Read the definition file `text`, read from `path`, as a JSON value
+pub(crate) fn parse_defn(path: &Path, text: &str) -> Result<Value, CfgError> {
+    let format = DefnFormat::from_extension(path).unwrap_or_else(|| DefnFormat::sniff(text));
+    match format {
+        DefnFormat::Json => Ok(serde_json::from_str(text)?),
+        DefnFormat::Toml => toml::from_str(text).map_err(|e| CfgError::Defn(e.to_string())),
+        #[cfg(feature = "yaml")]
+        DefnFormat::Yaml => serde_yaml::from_str(text).map_err(|e| CfgError::Defn(e.to_string())),
+        #[cfg(not(feature = "yaml"))]
+        DefnFormat::Yaml => Err(CfgError::Defn(
+            "YAML definitions need the 'yaml' feature".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cfg, MemoryFs};
+    use std::sync::Arc;
+
+    const DEFN_TOML: &str = r#"
+        # CAN settings
+        [canid]
+        prompt = "CAN Id"
+        tooltip = """
+        The CAN Id used by the CAN Pi CAP/Zero on the CBUS.
+        Each node on the bus needs its own."""
+        current = "100"
+        default = "100"
+        format = "[0-9]{1,4}"
+        action = "Edit"
+        kind = "Integer"
+        min = 1
+        max = 127
+    "#;
+
+    #[test]
+    fn formats_are_detected() {
+        let sniff = |text: &str| DefnFormat::sniff(text);
+        assert_eq!(sniff("  {\n \"canid\": {} }"), DefnFormat::Json);
+        assert_eq!(sniff(DEFN_TOML), DefnFormat::Toml);
+        assert_eq!(sniff("canid = { prompt = \"a:b\" }"), DefnFormat::Toml);
+        assert_eq!(sniff("---\ncanid:\n  prompt: CAN Id\n"), DefnFormat::Yaml);
+        assert_eq!(
+            DefnFormat::from_extension(Path::new("defn.YML")),
+            Some(DefnFormat::Yaml)
+        );
+        assert_eq!(DefnFormat::from_extension(Path::new("defn")), None);
+    }
+
+    #[test]
+    fn toml_definition() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.toml", DEFN_TOML);
+        fs.insert("defn", DEFN_TOML);
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs);
+        for path in ["defn.toml", "defn"] {
+            cfg.load_definition(path)
+                .expect("definition failed to load");
+            let attr = cfg.read_attribute("canid").unwrap();
+            assert!(attr.tooltip.ends_with("needs its own."));
+            assert_eq!(attr.max, Some(127.0));
+        }
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn yaml_definition() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert(
+            "defn.yaml",
+            "canid:\n  prompt: CAN Id\n  tooltip: >\n    The CAN Id used on the CBUS\n  \
+             current: \"100\"\n  default: \"100\"\n  format: \"[0-9]{1,4}\"\n  action: Edit\n",
+        );
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs);
+        cfg.load_definition("defn.yaml")
+            .expect("definition failed to load");
+        let attr = cfg.read_attribute("canid").unwrap();
+        assert_eq!(&*attr.tooltip, "The CAN Id used on the CBUS\n");
+    }
+}
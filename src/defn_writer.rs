@@ -0,0 +1,173 @@
+//! Writing the definition file
+//!
+//! The attributes, as changed by [`Cfg::write_attribute`] and the like, can be saved as a new
+//! definition file.  The file holds every item in ConfigHash order, followed by any `rules`,
+//! `migrations` and `defn_version` the loaded definition gave, and is checked against the
+//! definition schema before it is written.
+
+use crate::context::ResultExt;
+use crate::{Cfg, CfgError, ConfigHash, Operation};
+
+use jsonschema::JSONSchema;
+use serde_json::{Map, Value};
+
+use std::path::Path;
+
+/// The items of `defn` as a definition file, if valid against `schema`
+pub(crate) fn defn_items(
+    defn: &ConfigHash,
+    schema: &JSONSchema,
+) -> Result<Map<String, Value>, CfgError> {
+    let items = defn
+        .iter()
+        .map(|(k, v)| Ok((k.clone(), serde_json::to_value(v)?)))
+        .collect::<Result<Map<String, Value>, CfgError>>()?;
+    let items = Value::Object(items);
+    if !schema.is_valid(&items) {
+        return Err(CfgError::Schema("attribute definitions".to_string()));
+    }
+    match items {
+        Value::Object(items) => Ok(items),
+        _ => unreachable!("built as an object"),
+    }
+}
+
+impl Cfg {
+    /// Write the attribute definitions to `path` as a definition file
+    ///
+    /// The current values are written as each item's `current`.
+    pub fn write_defn_file<P: AsRef<Path>>(&self, path: P) -> Result<(), CfgError> {
+        self.write_defn(path.as_ref())
+            .context(Operation::Write, path)
+    }
+
+    fn write_defn(&self, path: &Path) -> Result<(), CfgError> {
+        let mut defn = defn_items(
+            self.cfg.as_ref().ok_or(CfgError::Cfg())?,
+            self.defn_schema(),
+        )?;
+        if !self.defn_rules.is_empty() {
+            defn.insert("rules".to_string(), serde_json::to_value(&self.defn_rules)?);
+        }
+        if !self.key_migrations.is_empty() {
+            defn.insert(
+                "migrations".to_string(),
+                serde_json::to_value(&self.key_migrations)?,
+            );
+        }
+        if let Some(version) = self.defn_version {
+            defn.insert("defn_version".to_string(), version.into());
+        }
+        let mut text = serde_json::to_vec_pretty(&Value::Object(defn))?;
+        text.push(b'\n');
+        self.fs.write(path, &text)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cfg, MemoryFs};
+    use std::sync::Arc;
+
+    const DEFN_DATA: &str = r#"
+        {
+            "canid" : {
+                "prompt": "CAN Id",
+                "tooltip": "The CAN Id used by the CAN Pi CAP/Zero on the CBUS",
+                "current": "100",
+                "default": "100",
+                "format": "[0-9]{1,4}",
+                "action": "Edit",
+                "kind": "Integer",
+                "min": 1,
+                "deprecated_aliases": ["can_id"]
+            },
+            "ap_mode" : {
+                "prompt": { "en": "Access point mode", "de": "Zugangspunktmodus" },
+                "tooltip": "Run as a Wi-Fi access point",
+                "current": "0",
+                "default": "0",
+                "format": "[01]",
+                "action": "Edit",
+                "kind": "Boolean",
+                "depends_on": { "key": "canid", "value": "100" }
+            },
+            "rules": [
+                { "rule": "compare", "left": "canid", "relation": "<", "right": 2048 }
+            ],
+            "migrations": [ { "from": "ap", "to": "ap_mode" } ],
+            "defn_version": 2
+        }"#;
+
+    #[test]
+    fn definitions_round_trip() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.load_definition("defn.json")
+            .expect("definition failed to load");
+        let mut attr = cfg.read_attribute("canid").unwrap().clone();
+        attr.tooltip = "CAN Id on the CBUS".into();
+        attr.current = "101".to_string();
+        cfg.write_attribute("canid", &attr)
+            .expect("write_attribute failed");
+        cfg.write_defn_file("new.json").expect("write failed");
+
+        let mut reloaded = Cfg::new();
+        reloaded.set_fs(fs);
+        reloaded
+            .load_definition("new.json")
+            .expect("written definition failed to load");
+        let canid = reloaded.read_attribute("canid").unwrap();
+        assert_eq!(&*canid.tooltip, "CAN Id on the CBUS");
+        assert_eq!(canid.current, "101");
+        assert_eq!(canid.min, Some(1.0));
+        assert_eq!(reloaded.defn_version(), Some(2));
+        assert_eq!(reloaded.validate_all().len(), 0);
+        reloaded.set_locale("de");
+        let ap_mode = reloaded.read_attribute("ap_mode").unwrap();
+        assert_eq!(&*ap_mode.prompt, "Zugangspunktmodus");
+        assert_eq!(
+            format!("{:?}", ap_mode.depends_on),
+            format!("{:?}", cfg.read_attribute("ap_mode").unwrap().depends_on)
+        );
+    }
+
+    #[test]
+    fn units_and_placeholder_round_trip() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert(
+            "defn.json",
+            r#"{
+                "tcpport" : {
+                    "prompt": "TCP port", "tooltip": "", "current": "", "default": "5550",
+                    "format": "[0-9]{1,5}", "action": "Edit", "kind": "Integer",
+                    "units": "port", "placeholder": "e.g. 5550"
+                },
+                "canid" : {
+                    "prompt": "CAN Id", "tooltip": "", "current": "100", "default": "100",
+                    "format": "[0-9]{1,4}", "action": "Edit"
+                }
+            }"#,
+        );
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.load_definition("defn.json")
+            .expect("definition failed to load");
+        cfg.write_defn_file("new.json").expect("write failed");
+
+        let mut reloaded = Cfg::new();
+        reloaded.set_fs(fs);
+        reloaded
+            .load_definition("new.json")
+            .expect("written definition failed to load");
+        let tcpport = reloaded.read_attribute("tcpport").unwrap();
+        assert_eq!(tcpport.units.as_deref(), Some("port"));
+        assert_eq!(tcpport.placeholder.as_deref(), Some("e.g. 5550"));
+        let canid = reloaded.read_attribute("canid").unwrap();
+        assert_eq!(canid.units, None);
+        assert_eq!(canid.placeholder, None);
+    }
+}
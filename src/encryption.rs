@@ -0,0 +1,216 @@
+//! Encryption of secret values at rest
+//!
+//! With a [`SecretKey`] set, the values of attributes marked `secret` are written to the INI file
+//! encrypted with AES-256-GCM, as [`ENCRYPTED_PREFIX`] followed by the base64 of a random nonce
+//! and the ciphertext, and are decrypted as the file is loaded.  A plain value read from the file
+//! is accepted as it is and is encrypted when the file is next written.
+
+use crate::{Attribute, Cfg, CfgError};
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use std::borrow::Cow;
+use std::convert::TryInto;
+use std::fmt;
+use std::path::Path;
+
+/// Marks an encrypted value in the INI file
+pub const ENCRYPTED_PREFIX: &str = "enc:v1:";
+
+/// Length in bytes of the nonce stored before each ciphertext
+const NONCE_LEN: usize = 12;
+
+#[derive(Clone)]
+/// A 256 bit key that secret values are encrypted with
+pub struct SecretKey(Key<Aes256Gcm>);
+
+impl SecretKey {
+    /// A new random key
+    pub fn generate() -> SecretKey {
+        SecretKey(Aes256Gcm::generate_key(OsRng))
+    }
+
+    /// The key made of `bytes`
+    pub fn from_bytes(bytes: [u8; 32]) -> SecretKey {
+        SecretKey(bytes.into())
+    }
+
+    /// Read a key from its base64 text, as held in a key file
+    pub fn from_base64(text: &str) -> Result<SecretKey, CfgError> {
+        let bytes = STANDARD
+            .decode(text.trim())
+            .map_err(|e| CfgError::Encryption(format!("key is not base64: {}", e)))?;
+        let bytes: [u8; 32] = bytes.try_into().map_err(|b: Vec<u8>| {
+            CfgError::Encryption(format!("key is {} bytes, not 32", b.len()))
+        })?;
+        Ok(SecretKey::from_bytes(bytes))
+    }
+
+    /// The key as base64 text, as held in a key file
+    pub fn to_base64(&self) -> String {
+        STANDARD.encode(self.0)
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(&self.0)
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretKey(***)")
+    }
+}
+
+impl Cfg {
+    /// Set the key secret values are encrypted with, or `None` to store them as plain text
+    ///
+    /// The key must be set before loading a file that holds encrypted values.
+    pub fn set_secret_key(&mut self, key: Option<SecretKey>) {
+        self.secret_key = key;
+    }
+
+    /// Set the key secret values are encrypted with from the key file at `path`
+    ///
+    /// The file holds the base64 of the 32 key bytes, e.g. as made by
+    /// `head -c 32 /dev/urandom | base64`.
+    pub fn load_secret_key<P: AsRef<Path>>(&mut self, path: P) -> Result<(), CfgError> {
+        let text = self.fs.read_to_string(path.as_ref())?;
+        self.secret_key = Some(SecretKey::from_base64(&text)?);
+        Ok(())
+    }
+
+    /// `value`, stored for `attr`, encrypted if the attribute is secret and a key is set
+    pub(crate) fn encrypt_value<'a>(
+        &self,
+        attr: &Attribute,
+        value: Cow<'a, str>,
+    ) -> Result<Cow<'a, str>, CfgError> {
+        let key = match &self.secret_key {
+            Some(key) if attr.secret && !value.is_empty() => key,
+            _ => return Ok(value),
+        };
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = key
+            .cipher()
+            .encrypt(&nonce, value.as_bytes())
+            .map_err(|_| CfgError::Encryption("value cannot be encrypted".to_string()))?;
+        let mut bytes = nonce.to_vec();
+        bytes.extend(ciphertext);
+        Ok(Cow::Owned(format!(
+            "{}{}",
+            ENCRYPTED_PREFIX,
+            STANDARD.encode(bytes)
+        )))
+    }
+
+    /// The plain text of `value`, read from the INI file for `attr`
+    pub(crate) fn decrypt_value(
+        &self,
+        attr: &Attribute,
+        value: String,
+    ) -> Result<String, CfgError> {
+        let encoded = match value.strip_prefix(ENCRYPTED_PREFIX) {
+            Some(encoded) if attr.secret => encoded,
+            _ => return Ok(value),
+        };
+        let key = self
+            .secret_key
+            .as_ref()
+            .ok_or_else(|| CfgError::Encryption("no key to decrypt the value with".to_string()))?;
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| CfgError::Encryption(format!("value is not base64: {}", e)))?;
+        if bytes.len() < NONCE_LEN {
+            return Err(CfgError::Encryption("value is truncated".to_string()));
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        let plain = key
+            .cipher()
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                CfgError::Encryption("value cannot be decrypted with the key".to_string())
+            })?;
+        String::from_utf8(plain)
+            .map_err(|_| CfgError::Encryption("decrypted value is not UTF-8".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryFs;
+    use std::sync::Arc;
+
+    const DEFN_DATA: &str = r#"
+        {
+            "router_password" : {
+                "prompt": "Router password",
+                "tooltip": "Password of the network to join",
+                "current": "",
+                "default": "",
+                "format": "[a-z0-9]{8,}",
+                "action": "Edit",
+                "secret": true
+            },
+            "router_ssid" : {
+                "prompt": "Router SSID",
+                "tooltip": "Network to join",
+                "current": "",
+                "default": "",
+                "format": ".*",
+                "action": "Edit"
+            }
+        }"#;
+
+    #[test]
+    fn secrets_are_encrypted_at_rest() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        fs.insert("canpi.cfg", "router_password=hunter22\nrouter_ssid=club\n");
+        let key = SecretKey::generate();
+        fs.insert("canpi.key", key.to_base64());
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.load_secret_key("canpi.key")
+            .expect("key failed to load");
+        cfg.load_configuration("canpi.cfg", "defn.json")
+            .expect("configuration failed to load");
+        assert_eq!(cfg.get_or_default("router_password"), "hunter22");
+
+        cfg.write_cfg_file("canpi.cfg", None).expect("write failed");
+        let text = fs.contents("canpi.cfg").unwrap();
+        assert!(!text.contains("hunter22"));
+        assert!(text.contains("router_password=enc:v1:"));
+        assert!(text.contains("router_ssid=club"));
+
+        let mut reloaded = Cfg::new();
+        reloaded.set_fs(fs.clone());
+        reloaded.set_secret_key(Some(key));
+        reloaded
+            .load_configuration("canpi.cfg", "defn.json")
+            .expect("configuration failed to load");
+        assert_eq!(reloaded.get_or_default("router_password"), "hunter22");
+
+        let mut wrong_key = Cfg::new();
+        wrong_key.set_fs(fs);
+        wrong_key.set_secret_key(Some(SecretKey::from_bytes([7; 32])));
+        let err = wrong_key
+            .load_configuration("canpi.cfg", "defn.json")
+            .unwrap_err();
+        assert_eq!(err.key(), Some("router_password"));
+    }
+
+    #[test]
+    fn key_files() {
+        assert!(SecretKey::from_base64("not a key").is_err());
+        assert!(SecretKey::from_base64("AAAA").is_err());
+        let key = SecretKey::from_bytes([1; 32]);
+        let text = key.to_base64();
+        assert_eq!(SecretKey::from_base64(&text).unwrap().to_base64(), text);
+        assert_eq!(format!("{:?}", key), "SecretKey(***)");
+    }
+}
@@ -0,0 +1,123 @@
+//! Editing an attribute in place
+//!
+//! [`Cfg::entry`] gives an [`AttributeEntry`] holding a copy of one attribute.  Its fields are
+//! changed on the copy and only stored by [`AttributeEntry::commit`], which checks the current
+//! value as [`Cfg::write_attribute_checked`] does; dropping the entry discards the changes.
+//!
+//! ```no_run
+//! # let mut cfg = canpi_config::Cfg::new();
+//! let mut entry = cfg.entry("canid")?;
+//! entry.set_prompt("CAN bus Id").set_current("101");
+//! entry.commit()?;
+//! # Ok::<(), canpi_config::CfgError>(())
+//! ```
+
+use crate::{Attribute, Cfg, CfgError};
+
+use std::sync::Arc;
+
+/// Changes to one attribute, stored in the Cfg when committed
+pub struct AttributeEntry<'a> {
+    cfg: &'a mut Cfg,
+    key: String,
+    attr: Attribute,
+}
+
+impl AttributeEntry<'_> {
+    /// Key of the configuration item
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The attribute with the changes made so far
+    pub fn attribute(&self) -> &Attribute {
+        &self.attr
+    }
+
+    /// The attribute, for changes to fields without a setter
+    pub fn attribute_mut(&mut self) -> &mut Attribute {
+        &mut self.attr
+    }
+
+    /// Change the prompt
+    pub fn set_prompt(&mut self, prompt: &str) -> &mut Self {
+        self.attr.prompt = Arc::from(prompt);
+        self
+    }
+
+    /// Change the tooltip
+    pub fn set_tooltip(&mut self, tooltip: &str) -> &mut Self {
+        self.attr.tooltip = Arc::from(tooltip);
+        self
+    }
+
+    /// Change the current value
+    pub fn set_current(&mut self, value: &str) -> &mut Self {
+        self.attr.current = value.to_string();
+        self
+    }
+
+    /// Store the changed attribute, refusing it as [`Cfg::write_attribute_checked`] does
+    ///
+    /// Nothing is stored if it is refused.
+    pub fn commit(self) -> Result<(), CfgError> {
+        self.cfg
+            .write_attribute_checked(&self.key, &self.attr, false)
+    }
+}
+
+impl Cfg {
+    /// An entry for changing the attribute of the configuration item `key` in place
+    pub fn entry<K: AsRef<str>>(&mut self, key: K) -> Result<AttributeEntry<'_>, CfgError> {
+        let key = key.as_ref();
+        let attr = self
+            .cfg
+            .as_ref()
+            .ok_or(CfgError::Cfg())?
+            .get(key)
+            .ok_or_else(|| CfgError::UnknownKey(key.to_string()))?
+            .clone();
+        Ok(AttributeEntry {
+            cfg: self,
+            key: key.to_string(),
+            attr,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::CANID_DEFN;
+    use crate::Provenance;
+
+    #[test]
+    fn entries_are_committed() {
+        let mut cfg = Cfg::new();
+        assert!(matches!(cfg.entry("canid"), Err(CfgError::Cfg())));
+        cfg.load_configuration_from_str("canid=101\n", CANID_DEFN)
+            .expect("configuration failed to load");
+        assert!(matches!(cfg.entry("wifi"), Err(CfgError::UnknownKey(k)) if k == "wifi"));
+
+        let mut entry = cfg.entry("canid").expect("canid defined");
+        entry.set_prompt("CAN bus Id").set_current("102");
+        assert_eq!(entry.key(), "canid");
+        assert_eq!(entry.attribute().current, "102");
+        entry.commit().expect("commit failed");
+        let attr = cfg.read_attribute("canid").unwrap();
+        assert_eq!(&*attr.prompt, "CAN bus Id");
+        assert_eq!(attr.current, "102");
+        assert_eq!(cfg.provenance("canid"), Some(&Provenance::User));
+        assert_eq!(cfg.unsaved_changes().len(), 1);
+
+        let mut entry = cfg.entry("canid").expect("canid defined");
+        entry.set_current("12a");
+        assert!(entry.commit().is_err());
+        let mut entry = cfg.entry("canid").expect("canid defined");
+        entry.attribute_mut().tooltip = Arc::from("Not kept");
+        drop(entry);
+        let attr = cfg.read_attribute(String::from("canid")).unwrap();
+        assert_eq!(attr.current, "102");
+        assert_eq!(&*attr.tooltip, "");
+    }
+}
@@ -0,0 +1,253 @@
+//! Writing the configuration in other formats
+//!
+//! Some canpi tooling reads TOML rather than INI.  [`Cfg::write_cfg_toml`] writes the same items
+//! as the INI writer, placed in the same sections: general entries at the top level and each
+//! section as a table.  Integer, decimal and boolean values that read as their kind are written
+//! as TOML numbers and booleans and the items of a multi-select value as an array; every other
+//! value, and every secret one, is written as a string.  Secret values are redacted unless
+//! [`Cfg::set_export_secrets`] is set.
+//!
+//! With the `yaml` feature [`Cfg::write_cfg_yaml`] writes the same tree as YAML, each section a
+//! mapping, for provisioning tools such as Ansible.  Like the INI writer it can back up the file
+//! it replaces.
+//!
+//! [`Cfg::write_cfg_env`] writes each item as a `KEY="value"` line for a `.env` file or a systemd
+//! `EnvironmentFile`, so other services on the Pi can share the settings without reading INI.
+//!
+//! Each file is replaced atomically and, like the INI file, is readable by its owner only when
+//! any attribute is secret.
+
+use crate::context::ResultExt;
+use crate::{Attribute, AttributeValue, Cfg, CfgError, FileAccess, Operation};
+
+use serde_json::{Map, Value};
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// `stored`, the value of `attr` as written to the INI file, as it is exported
+fn export_value(attr: &Attribute, stored: &str) -> Value {
+    if attr.secret {
+        return stored.into();
+    }
+    match attr.parse_value(stored) {
+        Ok(AttributeValue::Integer(n)) => n.into(),
+        Ok(AttributeValue::Decimal(n)) if n.is_finite() => n.into(),
+        Ok(AttributeValue::Boolean(flag)) => flag.into(),
+        Ok(AttributeValue::MultiSelect(selected)) => selected.into(),
+        _ => stored.into(),
+    }
+}
+
+/// The environment variable name for `key`: upper case, with anything but letters, digits and
+/// underscores replaced by an underscore
+pub(crate) fn env_var_name(key: &str) -> String {
+    key.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect()
+}
+
+/// `value` in double quotes, with the characters a shell or systemd would interpret escaped
+fn env_quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' | '\\' | '$' | '`' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Cfg {
+    /// The items as written to the INI file, with each section as a nested object
+    pub(crate) fn export_tree(&self) -> Result<Map<String, Value>, CfgError> {
+        let cfg = self.cfg.as_ref().ok_or(CfgError::Cfg())?;
+        let mut tree = Map::new();
+        for (k, v) in cfg {
+            let stored = self
+                .stored_value(v)
+                .map_err(|e| e.at_key(Operation::Write, k))?;
+            let value = export_value(v, self.exported(v, &stored));
+            match self.placement(k, v) {
+                (Some(section), name) => {
+                    let table = tree
+                        .entry(section.to_string())
+                        .or_insert_with(|| Value::Object(Map::new()));
+                    if let Value::Object(table) = table {
+                        table.insert(name.to_string(), value);
+                    }
+                }
+                (None, name) => {
+                    tree.insert(name.to_string(), value);
+                }
+            }
+        }
+        Ok(tree)
+    }
+
+    /// Replace the file `path` with `contents`, private if any attribute is secret
+    fn write_export(&self, path: &Path, contents: &[u8]) -> Result<(), CfgError> {
+        let cfg = self.cfg.as_ref().ok_or(CfgError::Cfg())?;
+        let access = FileAccess {
+            private: cfg.values().any(|a| a.secret),
+            owner: None,
+        };
+        self.fs.write_atomic(path, contents, &access)?;
+        Ok(())
+    }
+
+    /// Output the keys and current values of items to `path` as TOML
+    pub fn write_cfg_toml<P: AsRef<Path>>(&self, path: P) -> Result<(), CfgError> {
+        self.write_toml(path.as_ref())
+            .context(Operation::Write, path)
+    }
+
+    fn write_toml(&self, path: &Path) -> Result<(), CfgError> {
+        let text =
+            toml::to_string(&self.export_tree()?).map_err(|e| CfgError::Export(e.to_string()))?;
+        self.write_export(path, text.as_bytes())
+    }
+
+    /// Output the keys and current values of items to `path` as `KEY="value"` lines, sorted by key
+    pub fn write_cfg_env<P: AsRef<Path>>(&self, path: P) -> Result<(), CfgError> {
+        self.write_env(path.as_ref())
+            .context(Operation::Write, path)
+    }
+
+    fn write_env(&self, path: &Path) -> Result<(), CfgError> {
+        let cfg = self.cfg.as_ref().ok_or(CfgError::Cfg())?;
+        let mut lines = BTreeMap::new();
+        for (k, v) in cfg {
+            let stored = self
+                .stored_value(v)
+                .map_err(|e| e.at_key(Operation::Write, k))?;
+            lines.insert(env_var_name(k), env_quote(self.exported(v, &stored)));
+        }
+        let text: String = lines
+            .iter()
+            .map(|(name, value)| format!("{}={}\n", name, value))
+            .collect();
+        self.write_export(path, text.as_bytes())
+    }
+
+    /// Output the keys and current values of items to `path` as YAML
+    ///
+    /// If make_backup is true the existing file is backed up first, as decided by the strategy
+    /// set with [`Cfg::set_backup_strategy`].
+    #[cfg(feature = "yaml")]
+    pub fn write_cfg_yaml<P: AsRef<Path>>(
+        &self,
+        path: P,
+        make_backup: Option<bool>,
+    ) -> Result<(), CfgError> {
+        self.write_yaml(path.as_ref(), make_backup.unwrap_or(false))
+            .context(Operation::Write, path)
+    }
+
+    #[cfg(feature = "yaml")]
+    fn write_yaml(&self, path: &Path, make_backup: bool) -> Result<(), CfgError> {
+        let text = serde_yaml::to_string(&self.export_tree()?)
+            .map_err(|e| CfgError::Export(e.to_string()))?;
+        if make_backup {
+            self.make_backup(path, None);
+        }
+        self.write_export(path, text.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cfg, MemoryFs};
+    use std::sync::Arc;
+
+    const DEFN_DATA: &str = r#"
+        {
+            "canid" : {
+                "prompt": "CAN Id", "tooltip": "", "current": "100", "default": "100",
+                "format": "[0-9]{1,4}", "action": "Edit", "kind": "Integer"
+            },
+            "logfile" : {
+                "prompt": "Log file", "tooltip": "", "current": "canpi.log", "default": "",
+                "format": ".*", "action": "Edit"
+            },
+            "ap_mode" : {
+                "prompt": "Access point mode", "tooltip": "", "current": "yes", "default": "0",
+                "format": ".*", "action": "Edit", "kind": "Boolean", "section": "apmode"
+            },
+            "network.wifi.ssid" : {
+                "prompt": "SSID", "tooltip": "", "current": "club \"net\"", "default": "",
+                "format": ".*", "action": "Edit"
+            }
+        }"#;
+
+    #[test]
+    fn sections_become_tables() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.load_definition("defn.json")
+            .expect("definition failed to load");
+        cfg.write_cfg_toml("canpi.toml").expect("write failed");
+
+        let text = fs.contents("canpi.toml").unwrap();
+        let doc: toml::Table = text.parse().expect("output is not TOML");
+        assert_eq!(doc["canid"].as_integer(), Some(100));
+        assert_eq!(doc["logfile"].as_str(), Some("canpi.log"));
+        assert_eq!(doc["apmode"]["ap_mode"].as_bool(), Some(true));
+        assert_eq!(doc["network.wifi"]["ssid"].as_str(), Some("club \"net\""));
+    }
+
+    #[test]
+    fn env_file() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.load_definition("defn.json")
+            .expect("definition failed to load");
+        cfg.set_value("logfile", "$HOME/canpi.log")
+            .expect("set_value failed");
+        cfg.write_cfg_env("canpi.env").expect("write failed");
+
+        assert_eq!(
+            fs.contents("canpi.env").unwrap(),
+            "AP_MODE=\"1\"\n\
+             CANID=\"100\"\n\
+             LOGFILE=\"\\$HOME/canpi.log\"\n\
+             NETWORK_WIFI_SSID=\"club \\\"net\\\"\"\n"
+        );
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn yaml_with_backup() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        fs.insert("canpi.yaml", "canid: 99\n");
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.set_backup_strategy(crate::NumberedRotation::default());
+        cfg.load_definition("defn.json")
+            .expect("definition failed to load");
+        cfg.write_cfg_yaml("canpi.yaml", Some(true))
+            .expect("write failed");
+
+        let text = fs.contents("canpi.yaml").unwrap();
+        let doc: serde_yaml::Value = serde_yaml::from_str(&text).expect("output is not YAML");
+        assert_eq!(doc["canid"].as_i64(), Some(100));
+        assert_eq!(doc["apmode"]["ap_mode"].as_bool(), Some(true));
+        assert_eq!(doc["network.wifi"]["ssid"].as_str(), Some("club \"net\""));
+        assert_eq!(fs.contents("canpi.yaml.1").unwrap(), "canid: 99\n");
+    }
+}
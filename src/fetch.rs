@@ -0,0 +1,175 @@
+//! Definition files fetched over HTTP
+//!
+//! With the `http` feature the definition file given to [`Cfg::load_configuration`] and the
+//! other loaders may be an `http://` or `https://` URL, so that a fleet of nodes can share one
+//! centrally maintained definition.  The file is cached, along with its ETag, in the directory
+//! set by [`Cfg::set_defn_cache_dir`] and is downloaded again only when the server reports that
+//! it has changed.  If the server cannot be reached the cached copy is used.
+//!
+//! A fetched definition that must be signed needs an embedded signature.
+
+use crate::{Cfg, CfgError, FileAccess};
+
+use tracing::warn;
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long to wait for the server before falling back on the cached copy
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The URL `path` gives, if it is an HTTP or HTTPS one
+pub(crate) fn url(path: &Path) -> Option<&str> {
+    let text = path.to_str()?;
+    match text.starts_with("http://") || text.starts_with("https://") {
+        true => Some(text),
+        false => None,
+    }
+}
+
+/// The file in `dir` that the definition at `url` is cached in
+fn cache_path(dir: &Path, url: &str) -> PathBuf {
+    let name: String = url
+        .split_once("://")
+        .map_or(url, |(_, rest)| rest)
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                true => c,
+                false => '_',
+            },
+        )
+        .collect();
+    dir.join(name)
+}
+
+/// The file holding the ETag of the cached definition `cache`
+fn etag_path(cache: &Path) -> PathBuf {
+    let mut name = cache.as_os_str().to_owned();
+    name.push(".etag");
+    PathBuf::from(name)
+}
+
+/// The directory fetched definitions are cached in until another is set
+pub(crate) fn default_cache_dir() -> PathBuf {
+    std::env::temp_dir().join("canpi-config")
+}
+
+impl Cfg {
+    /// Cache definition files fetched over HTTP in the directory `dir`
+    pub fn set_defn_cache_dir<P: AsRef<Path>>(&mut self, dir: P) {
+        self.defn_cache_dir = dir.as_ref().to_path_buf();
+    }
+
+    /// The text of the definition file at `url`, from the cache if it is unchanged
+    pub(crate) fn fetch_defn(&self, url: &str) -> Result<String, CfgError> {
+        let cache = cache_path(&self.defn_cache_dir, url);
+        let etag_file = etag_path(&cache);
+        let cached = self.fs.read_to_string(&cache).ok();
+        let mut request = ureq::get(url).timeout(FETCH_TIMEOUT);
+        if cached.is_some() {
+            if let Ok(etag) = self.fs.read_to_string(&etag_file) {
+                request = request.set("If-None-Match", etag.trim());
+            }
+        }
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(err) => {
+                return match cached {
+                    Some(text) => {
+                        warn!(url, error = %err, "fetch failed, using cached copy");
+                        Ok(text)
+                    }
+                    None => Err(CfgError::Fetch(format!("{}: {}", url, err))),
+                }
+            }
+        };
+        if response.status() == 304 {
+            return cached.ok_or_else(|| CfgError::Fetch(format!("{}: not modified", url)));
+        }
+        let etag = response.header("ETag").map(String::from);
+        let text = response.into_string()?;
+        self.fs.create_dir_all(&self.defn_cache_dir)?;
+        self.fs
+            .write_atomic(&cache, text.as_bytes(), &FileAccess::default())?;
+        match etag {
+            Some(etag) => self.fs.write(&etag_file, etag.as_bytes())?,
+            None if self.fs.exists(&etag_file) => self.fs.remove(&etag_file)?,
+            None => {}
+        }
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::CANID_DEFN;
+    use crate::MemoryFs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn definition_is_fetched_and_cached() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind failed");
+        let url = format!("http://{}/canpi/defn.json", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut conditional = Vec::new();
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().expect("accept failed");
+                let mut reader = BufReader::new(stream);
+                let mut etag = None;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).expect("read failed");
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("if-none-match") {
+                            etag = Some(value.trim().to_string());
+                        }
+                    }
+                }
+                let response = match &etag {
+                    Some(_) => "HTTP/1.1 304 Not Modified\r\nContent-Length: 0\r\n\r\n".to_string(),
+                    None => format!(
+                        "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: {}\r\n\r\n{}",
+                        CANID_DEFN.len(),
+                        CANID_DEFN
+                    ),
+                };
+                reader
+                    .get_mut()
+                    .write_all(response.as_bytes())
+                    .expect("write failed");
+                conditional.push(etag);
+            }
+            conditional
+        });
+
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("canpi.cfg", "canid=101\n");
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.set_defn_cache_dir("cache");
+        for _ in 0..2 {
+            cfg.load_configuration("canpi.cfg", &url)
+                .expect("configuration failed to load");
+            assert_eq!(cfg.get_or_default("canid"), "101");
+        }
+        let conditional = server.join().expect("server failed");
+        assert_eq!(conditional, [None, Some("\"v1\"".to_string())]);
+        let cache = cache_path(Path::new("cache"), &url);
+        assert_eq!(fs.contents(etag_path(&cache)).unwrap(), "\"v1\"");
+
+        // The server has gone, so the cached copy is used
+        cfg.load_configuration("canpi.cfg", &url)
+            .expect("cached definition not used");
+        cfg.set_defn_cache_dir("empty");
+        let err = cfg.load_configuration("canpi.cfg", &url).unwrap_err();
+        assert!(matches!(err.root(), CfgError::Fetch(_)));
+    }
+}
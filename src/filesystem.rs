@@ -0,0 +1,508 @@
+//! Filesystem access
+//!
+//! Every file the crate reads or writes on behalf of a [`Cfg`] or [`Pkg`] goes through the
+//! [`Fs`] set with [`Cfg::set_fs`] or [`Pkg::set_fs`].  [`StdFs`], the default, is the real
+//! filesystem; [`MemoryFs`] keeps files in memory so tests need no writable directory.
+//!
+//! INI files are replaced with [`Fs::write_atomic`], so a power cut part way through a write
+//! leaves either the old file or the new one, never a truncated mix.
+
+use crate::lock::{self, FileLock};
+use crate::permissions::{FileAccess, PRIVATE_MODE};
+use crate::{Cfg, Pkg};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Cursor, ErrorKind, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// What [`Fs::metadata`] reports about a file
+pub struct FileMetadata {
+    /// Length in bytes
+    pub len: u64,
+    /// When the file was last written, if known
+    pub modified: Option<SystemTime>,
+}
+
+/// The file operations used by the crate
+pub trait Fs: Send + Sync {
+    /// The whole contents of the file at `path`
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Replace the file at `path` with `contents`, creating it if needed
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Add `contents` to the end of the file at `path`, creating it if needed
+    fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Move the file at `from` to `to`, replacing any file there
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Delete the file at `path`
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    /// The length and modification time of the file at `path`
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata>;
+
+    /// The absolute form of `path`, used to detect include cycles
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Create the directory `path` and any missing parents
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// The paths of the files in the directory `path`
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// A reader over the file at `path`
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(Cursor::new(self.read(path)?)))
+    }
+
+    /// The contents of the file at `path` as UTF-8 text
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+    }
+
+    /// Copy the file at `from` to `to`
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let contents = self.read(from)?;
+        self.write(to, &contents)
+    }
+
+    /// True if there is a file at `path`
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+
+    /// Take an exclusive advisory lock on the file `path`, waiting up to `timeout` for another
+    /// holder to release it
+    ///
+    /// The default takes no lock, as there is no other process to exclude.
+    fn lock(&self, _path: &Path, _timeout: Duration) -> io::Result<FileLock> {
+        Ok(FileLock::none())
+    }
+
+    /// Restrict who may use the file at `path` as `access` directs
+    ///
+    /// The default does nothing, as there are no other users to keep out.
+    fn set_access(&self, _path: &Path, _access: &FileAccess) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Replace the file at `path` with `contents`, restricted as `access` directs, such that a
+    /// failure part way through leaves the file as it was
+    ///
+    /// The contents are written to a temporary file beside `path`, which is then renamed over it.
+    fn write_atomic(&self, path: &Path, contents: &[u8], access: &FileAccess) -> io::Result<()> {
+        let temp = temp_path(path);
+        self.write(&temp, contents)?;
+        self.set_access(&temp, access)
+            .and_then(|_| self.rename(&temp, path))
+            .inspect_err(|_| {
+                let _ = self.remove(&temp);
+            })
+    }
+}
+
+/// A fresh name for the temporary file `path` is written to before being renamed into place
+///
+/// Each call gives a different name, so concurrent writes of one file never share a temporary.
+fn temp_path(path: &Path) -> PathBuf {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    path.with_file_name(name)
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+/// The real filesystem
+pub struct StdFs;
+
+impl Fs for StdFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+
+    fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        file.write_all(contents)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        let metadata = fs::metadata(path)?;
+        Ok(FileMetadata {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let dir = match path.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => path,
+        };
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                files.push(path.join(entry.file_name()));
+            }
+        }
+        Ok(files)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(io::BufReader::new(fs::File::open(path)?)))
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::copy(from, to).map(|_| ())
+    }
+
+    fn lock(&self, path: &Path, timeout: Duration) -> io::Result<FileLock> {
+        lock::lock_file(path, timeout)
+    }
+
+    #[cfg(unix)]
+    fn set_access(&self, path: &Path, access: &FileAccess) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        if access.private {
+            fs::set_permissions(path, fs::Permissions::from_mode(PRIVATE_MODE))?;
+        }
+        if let Some(owner) = access.owner {
+            std::os::unix::fs::chown(path, Some(owner.uid), Some(owner.gid))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the temporary file and flushes it to the device before the rename, then flushes
+    /// the directory so the rename itself survives a power cut
+    ///
+    /// A private file is created with its restricted mode so its contents are never readable by
+    /// others.  Otherwise the mode of the file being replaced is kept, as is its owner unless
+    /// `access` gives one and the process is allowed to change it.
+    fn write_atomic(&self, path: &Path, contents: &[u8], access: &FileAccess) -> io::Result<()> {
+        let existing = fs::metadata(path).ok();
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+            match &existing {
+                _ if access.private => {
+                    options.mode(PRIVATE_MODE);
+                }
+                Some(metadata) => {
+                    options.mode(metadata.permissions().mode() & 0o7777);
+                }
+                None => {}
+            }
+        }
+        let (temp, file) = loop {
+            let temp = temp_path(path);
+            match options.open(&temp) {
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                opened => break (temp, opened),
+            }
+        };
+        let written = file.and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()?;
+            if let Some(metadata) = &existing {
+                if !access.private {
+                    fs::set_permissions(&temp, metadata.permissions())?;
+                }
+                #[cfg(unix)]
+                if access.owner.is_none() {
+                    use std::os::unix::fs::MetadataExt;
+                    let (uid, gid) = (metadata.uid(), metadata.gid());
+                    // Only a privileged process may give the file away; otherwise it stays ours
+                    let _ = std::os::unix::fs::chown(&temp, Some(uid), Some(gid));
+                }
+            }
+            self.set_access(&temp, access)
+        });
+        if let Err(e) = written.and_then(|_| fs::rename(&temp, path)) {
+            let _ = fs::remove_file(&temp);
+            return Err(e);
+        }
+        #[cfg(unix)]
+        {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            fs::File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+/// Files held in memory, for tests
+///
+/// Paths are compared after removing `.` and resolving `..`, and directories need not be
+/// created first.
+pub struct MemoryFs {
+    files: Mutex<BTreeMap<PathBuf, (Vec<u8>, SystemTime)>>,
+}
+
+/// `path` with `.` removed and `..` resolved
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            c => normal.push(c),
+        }
+    }
+    normal
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(ErrorKind::NotFound, format!("{} not found", path.display()))
+}
+
+impl MemoryFs {
+    /// Creates an empty filesystem
+    pub fn new() -> MemoryFs {
+        MemoryFs::default()
+    }
+
+    /// Add a file, replacing any already at `path`
+    pub fn insert<P: AsRef<Path>, C: AsRef<[u8]>>(&self, path: P, contents: C) {
+        // Writing to memory cannot fail
+        let _ = self.write(path.as_ref(), contents.as_ref());
+    }
+
+    /// The contents of the file at `path` as text, if there is one
+    pub fn contents<P: AsRef<Path>>(&self, path: P) -> Option<String> {
+        self.read_to_string(path.as_ref()).ok()
+    }
+
+    /// The paths of every file held, in order
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.lock().keys().cloned().collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<PathBuf, (Vec<u8>, SystemTime)>> {
+        self.files
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Fs for MemoryFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.lock()
+            .get(&normalize(path))
+            .map(|(contents, _)| contents.clone())
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.lock()
+            .insert(normalize(path), (contents.to_vec(), SystemTime::now()));
+        Ok(())
+    }
+
+    fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut files = self.lock();
+        let file = files
+            .entry(normalize(path))
+            .or_insert_with(|| (Vec::new(), SystemTime::now()));
+        file.0.extend_from_slice(contents);
+        file.1 = SystemTime::now();
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut files = self.lock();
+        let file = files
+            .remove(&normalize(from))
+            .ok_or_else(|| not_found(from))?;
+        files.insert(normalize(to), file);
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.lock()
+            .remove(&normalize(path))
+            .map(|_| ())
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        self.lock()
+            .get(&normalize(path))
+            .map(|(contents, modified)| FileMetadata {
+                len: contents.len() as u64,
+                modified: Some(*modified),
+            })
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let normal = normalize(path);
+        if self.lock().contains_key(&normal) {
+            Ok(normal)
+        } else {
+            Err(not_found(path))
+        }
+    }
+
+    fn create_dir_all(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let dir = normalize(path);
+        Ok(self
+            .lock()
+            .keys()
+            .filter(|p| p.parent() == Some(&dir))
+            .cloned()
+            .collect())
+    }
+}
+
+impl Cfg {
+    /// Read and write files through `fs` from now on
+    pub fn set_fs(&mut self, fs: Arc<dyn Fs>) {
+        self.fs = fs;
+    }
+
+    /// The filesystem files are read and written through
+    pub fn fs(&self) -> &Arc<dyn Fs> {
+        &self.fs
+    }
+}
+
+impl Pkg {
+    /// Read package definitions, and the packages' files when validating, through `fs`
+    pub fn set_fs(&mut self, fs: Arc<dyn Fs>) {
+        self.fs = fs;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::CANID_DISPLAY_NODE_DEFN;
+    use crate::{NumberedRotation, WriteOptions};
+
+    #[test]
+    fn load_and_write_in_memory() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("/canpi/defn.json", CANID_DISPLAY_NODE_DEFN);
+        fs.insert("/canpi/canpi.cfg", "canid=101\n");
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.set_backup_strategy(NumberedRotation::default());
+        cfg.load_configuration("/canpi/./canpi.cfg", "/canpi/defn.json")
+            .expect("configuration failed to load");
+        assert_eq!(cfg.try_get::<u16>("canid").unwrap(), 101);
+
+        cfg.set_value("canid", "102").expect("set_value failed");
+        let options = WriteOptions {
+            make_backup: true,
+            audit_log: true,
+            ..WriteOptions::default()
+        };
+        cfg.write_cfg_file_with("/canpi/canpi.cfg", &options)
+            .expect("write failed");
+        assert!(fs
+            .contents("/canpi/canpi.cfg")
+            .unwrap()
+            .contains("canid=102"));
+        assert_eq!(fs.contents("/canpi/canpi.cfg.1").unwrap(), "canid=101\n");
+        assert!(fs.exists(Path::new("/canpi/canpi.cfg.audit.jsonl")));
+        assert!(fs.exists(Path::new("/canpi/canpi.cfg.modified.json")));
+        assert!(!fs
+            .paths()
+            .iter()
+            .any(|p| p.to_string_lossy().ends_with(".tmp")));
+        assert!(!Path::new("/canpi/canpi.cfg").exists());
+        assert!(cfg.load_definition("/canpi/missing.json").is_err());
+    }
+
+    #[test]
+    fn atomic_write_replaces_the_file() {
+        let dir = std::env::temp_dir().join(format!("canpi-atomic-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("cannot create test directory");
+        let path = dir.join("canpi.cfg");
+        fs::write(&path, "canid=101\n").expect("cannot write test file");
+        StdFs
+            .write_atomic(&path, b"canid=102\n", &FileAccess::default())
+            .expect("atomic write failed");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "canid=102\n");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        assert!(StdFs
+            .write_atomic(&dir.join("missing/canpi.cfg"), b"", &FileAccess::default())
+            .is_err());
+        fs::remove_dir_all(&dir).expect("cannot remove test directory");
+    }
+
+    #[test]
+    fn concurrent_atomic_writes() {
+        let dir = std::env::temp_dir().join(format!("canpi-concurrent-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("cannot create test directory");
+        let path = dir.join("canpi.cfg");
+        let writers: Vec<_> = (0..8)
+            .map(|n| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let contents = format!("canid={}\n", 100 + n);
+                    for _ in 0..20 {
+                        StdFs
+                            .write_atomic(&path, contents.as_bytes(), &FileAccess::default())
+                            .expect("atomic write failed");
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().expect("writer panicked");
+        }
+        let written = fs::read_to_string(&path).unwrap();
+        assert!((100..108).any(|n| written == format!("canid={}\n", n)));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).expect("cannot remove test directory");
+    }
+}
@@ -0,0 +1,431 @@
+//! Compilation of attribute formats
+//!
+//! Formats are compiled with the [`RegexOptions`] of the Cfg, which choose the engine, whether
+//! character classes are Unicode-aware and how large a compiled format may grow.  With the
+//! `fancy-regex` feature, [`RegexEngine::Fancy`] adds look-around and backreferences.
+//!
+//! A format that is exactly the name of a [`NamedFormat`], such as `ipv4` or `u8`, is checked by
+//! the crate's own validator instead of being compiled as a regular expression.
+//!
+//! Every format is compiled as its definition is loaded, so that a mistake in one is reported
+//! then, with its key, rather than when a value is first checked against it.  The compiled
+//! formats are kept by the Cfg so that checking each value from a web form does not compile its
+//! format again.
+
+use crate::value::parse_bool;
+use crate::{Attribute, Cfg, CfgError, ConfigHash, RegexEngine, RegexOptions};
+
+use regex::RegexBuilder;
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+
+/// Compiled formats by format and whether it may match part of a value
+pub(crate) type MatcherCache = Mutex<HashMap<(Arc<str>, bool), Arc<Matcher>>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// A built-in format, used by giving its name as an attribute's `format`
+pub enum NamedFormat {
+    /// `ipv4`: a dotted quad such as `192.168.4.1`
+    Ipv4,
+    /// `ipv6`: an IPv6 address such as `fe80::1`
+    Ipv6,
+    /// `port`: a TCP or UDP port from 1 to 65535
+    Port,
+    /// `hostname`: a host name as allowed by RFC 1123
+    Hostname,
+    /// `bool`: `1`, `true`, `yes`, `on`, `0`, `false`, `no` or `off` in any case
+    Bool,
+    /// `u8`: a whole number from 0 to 255
+    U8,
+    /// `u16`: a whole number from 0 to 65535
+    U16,
+    /// `u32`: a whole number from 0 to 4294967295
+    U32,
+    /// `i32`: a whole number from -2147483648 to 2147483647
+    I32,
+}
+
+impl NamedFormat {
+    /// Every built-in format
+    pub const ALL: [NamedFormat; 9] = [
+        NamedFormat::Ipv4,
+        NamedFormat::Ipv6,
+        NamedFormat::Port,
+        NamedFormat::Hostname,
+        NamedFormat::Bool,
+        NamedFormat::U8,
+        NamedFormat::U16,
+        NamedFormat::U32,
+        NamedFormat::I32,
+    ];
+
+    /// The name given as a format
+    pub fn name(self) -> &'static str {
+        match self {
+            NamedFormat::Ipv4 => "ipv4",
+            NamedFormat::Ipv6 => "ipv6",
+            NamedFormat::Port => "port",
+            NamedFormat::Hostname => "hostname",
+            NamedFormat::Bool => "bool",
+            NamedFormat::U8 => "u8",
+            NamedFormat::U16 => "u16",
+            NamedFormat::U32 => "u32",
+            NamedFormat::I32 => "i32",
+        }
+    }
+
+    /// The built-in format called `name`, if there is one
+    pub fn from_name(name: &str) -> Option<NamedFormat> {
+        NamedFormat::ALL.iter().copied().find(|f| f.name() == name)
+    }
+
+    /// Check whether the whole of `value` is acceptable
+    pub fn matches(self, value: &str) -> bool {
+        match self {
+            NamedFormat::Ipv4 => value.parse::<Ipv4Addr>().is_ok(),
+            NamedFormat::Ipv6 => value.parse::<Ipv6Addr>().is_ok(),
+            NamedFormat::Port => digits(value) && value.parse::<u16>().is_ok_and(|p| p != 0),
+            NamedFormat::Hostname => is_hostname(value),
+            NamedFormat::Bool => parse_bool(value).is_some() && value.trim() == value,
+            NamedFormat::U8 => digits(value) && value.parse::<u8>().is_ok(),
+            NamedFormat::U16 => digits(value) && value.parse::<u16>().is_ok(),
+            NamedFormat::U32 => digits(value) && value.parse::<u32>().is_ok(),
+            NamedFormat::I32 => {
+                digits(value.strip_prefix('-').unwrap_or(value)) && value.parse::<i32>().is_ok()
+            }
+        }
+    }
+}
+
+/// True if `value` is one or more ASCII digits, so `+1` and ` 1` are refused
+fn digits(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// True if `value` is dot separated labels of letters, digits and inner hyphens
+fn is_hostname(value: &str) -> bool {
+    value.len() <= 253
+        && value.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+/// A compiled format
+pub(crate) enum Matcher {
+    Named(NamedFormat),
+    Standard(regex::Regex),
+    #[cfg(feature = "fancy-regex")]
+    Fancy(fancy_regex::Regex),
+}
+
+impl Matcher {
+    /// Compile `format` as directed by `options`, anchored at both ends unless `partial`
+    ///
+    /// A named format always checks the whole value.
+    pub fn compile(format: &str, partial: bool, options: &RegexOptions) -> Result<Matcher, String> {
+        if let Some(named) = NamedFormat::from_name(format) {
+            return Ok(Matcher::Named(named));
+        }
+        let pattern = if partial {
+            format.to_string()
+        } else {
+            format!("^(?:{})$", format)
+        };
+        match options.engine {
+            RegexEngine::Standard => {
+                let mut builder = RegexBuilder::new(&pattern);
+                builder.unicode(options.unicode);
+                if let Some(limit) = options.size_limit {
+                    builder.size_limit(limit);
+                }
+                builder
+                    .build()
+                    .map(Matcher::Standard)
+                    .map_err(|e| e.to_string())
+            }
+            #[cfg(feature = "fancy-regex")]
+            RegexEngine::Fancy => {
+                let pattern = if options.unicode {
+                    pattern
+                } else {
+                    format!("(?-u){}", pattern)
+                };
+                let mut builder = fancy_regex::RegexBuilder::new(&pattern);
+                if let Some(limit) = options.size_limit {
+                    builder.delegate_size_limit(limit);
+                }
+                if let Some(limit) = options.backtrack_limit {
+                    builder.backtrack_limit(limit);
+                }
+                builder
+                    .build()
+                    .map(Matcher::Fancy)
+                    .map_err(|e| e.to_string())
+            }
+        }
+    }
+
+    /// Check whether `value` matches
+    ///
+    /// Only the fancy engine can fail here, when it exceeds its backtracking limit.
+    pub fn is_match(&self, value: &str) -> Result<bool, String> {
+        match self {
+            Matcher::Named(named) => Ok(named.matches(value)),
+            Matcher::Standard(re) => Ok(re.is_match(value)),
+            #[cfg(feature = "fancy-regex")]
+            Matcher::Fancy(re) => re.is_match(value).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+impl Attribute {
+    /// The built-in format named by `format`, if it names one
+    pub fn named_format(&self) -> Option<NamedFormat> {
+        NamedFormat::from_name(&self.format)
+    }
+
+    /// As [`Attribute::format_matches`] with the regular expression compiled as directed by
+    /// `options`
+    pub fn format_matches_with(
+        &self,
+        value: &str,
+        options: &RegexOptions,
+    ) -> Result<bool, CfgError> {
+        Matcher::compile(
+            &self.format,
+            self.partial_match || options.partial_match,
+            options,
+        )
+        .and_then(|m| m.is_match(value))
+        .map_err(CfgError::Format)
+    }
+}
+
+impl Cfg {
+    /// Compile formats as directed by `options` from now on
+    pub fn set_regex_options(&mut self, options: RegexOptions) {
+        self.regex_options = options;
+        self.matcher_cache
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clear();
+    }
+
+    /// The options formats are compiled with
+    pub fn regex_options(&self) -> &RegexOptions {
+        &self.regex_options
+    }
+
+    /// As [`Attribute::format_matches_with`] with the regex options of the Cfg, compiling each
+    /// format only once
+    pub(crate) fn format_matches(&self, attr: &Attribute, value: &str) -> Result<bool, CfgError> {
+        self.matcher(attr)
+            .and_then(|m| m.is_match(value))
+            .map_err(CfgError::Format)
+    }
+
+    /// The compiled format of `attr`, from the cache if it has been compiled before
+    fn matcher(&self, attr: &Attribute) -> Result<Arc<Matcher>, String> {
+        let key = (attr.format.clone(), attr.partial_match);
+        let mut cache = self
+            .matcher_cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(matcher) = cache.get(&key) {
+            return Ok(matcher.clone());
+        }
+        let partial = attr.partial_match || self.regex_options.partial_match;
+        let matcher = Matcher::compile(&attr.format, partial, &self.regex_options)?;
+        let matcher = Arc::new(matcher);
+        cache.insert(key, matcher.clone());
+        Ok(matcher)
+    }
+
+    /// Compile the format of each of `items`, failing with every one that does not compile
+    pub(crate) fn check_formats(&self, items: &ConfigHash) -> Result<(), CfgError> {
+        let mut errors: Vec<String> = items
+            .iter()
+            .filter_map(|(key, attr)| {
+                let error = self.matcher(attr).err()?;
+                Some(format!("'{}' format '{}': {}", key, attr.format, error))
+            })
+            .collect();
+        if errors.is_empty() {
+            return Ok(());
+        }
+        errors.sort();
+        Err(CfgError::Format(errors.join("; ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attribute(format: &str) -> Attribute {
+        let json = serde_json::json!({
+            "prompt": "", "tooltip": "", "current": "", "default": "",
+            "format": format, "action": "Edit"
+        });
+        serde_json::from_value(json).expect("attribute failed to deserialize")
+    }
+
+    #[test]
+    fn unicode_and_size_limits() {
+        let word = attribute(r"\w+");
+        let unicode = RegexOptions::default();
+        let ascii = RegexOptions {
+            unicode: false,
+            ..RegexOptions::default()
+        };
+        assert!(matches!(
+            word.format_matches_with("Größe", &unicode),
+            Ok(true)
+        ));
+        assert!(matches!(
+            word.format_matches_with("Größe", &ascii),
+            Ok(false)
+        ));
+
+        let tiny = RegexOptions {
+            size_limit: Some(64),
+            ..RegexOptions::default()
+        };
+        assert!(matches!(
+            attribute("[0-9]{1,100}").format_matches_with("1", &tiny),
+            Err(CfgError::Format(_))
+        ));
+        assert!(matches!(
+            attribute("(").format_matches_with("1", &unicode),
+            Err(CfgError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn partial_match_for_every_format() {
+        let ssid = attribute("[[:alnum:]]{1,}");
+        let partial = RegexOptions {
+            partial_match: true,
+            ..RegexOptions::default()
+        };
+        assert!(matches!(
+            ssid.format_matches_with("BTWholeHome-VFC", &RegexOptions::default()),
+            Ok(false)
+        ));
+        assert!(matches!(
+            ssid.format_matches_with("BTWholeHome-VFC", &partial),
+            Ok(true)
+        ));
+    }
+
+    #[test]
+    fn named_formats() {
+        let options = RegexOptions::default();
+        let check = |format: &str, value: &str| {
+            matches!(
+                attribute(format).format_matches_with(value, &options),
+                Ok(true)
+            )
+        };
+        assert!(check("u8", "127"));
+        assert!(!check("u8", "9999"));
+        assert!(!check("u8", "+1"));
+        assert!(check("i32", "-40"));
+        assert!(check("port", "5550"));
+        assert!(!check("port", "0"));
+        assert!(check("ipv4", "192.168.4.1"));
+        assert!(!check("ipv4", "192.168.4"));
+        assert!(check("ipv6", "fe80::1"));
+        assert!(check("hostname", "canpi.local"));
+        assert!(!check("hostname", "-canpi"));
+        assert!(check("bool", "Yes"));
+        assert!(!check("bool", "maybe"));
+        assert_eq!(attribute("port").named_format(), Some(NamedFormat::Port));
+        assert_eq!(attribute("port|ipv4").named_format(), None);
+        assert_eq!(attribute("u8").format_matches("300"), Ok(false));
+    }
+
+    #[cfg(feature = "fancy-regex")]
+    #[test]
+    fn fancy_engine() {
+        let passphrase = attribute("(?=.*[0-9])(?=.*[a-z]).{8,}");
+        let fancy = RegexOptions {
+            engine: RegexEngine::Fancy,
+            ..RegexOptions::default()
+        };
+        assert!(passphrase
+            .format_matches_with("abcdefg1", &RegexOptions::default())
+            .is_err());
+        assert!(matches!(
+            passphrase.format_matches_with("abcdefg1", &fancy),
+            Ok(true)
+        ));
+        assert!(matches!(
+            passphrase.format_matches_with("abcdefgh", &fancy),
+            Ok(false)
+        ));
+    }
+
+    #[test]
+    fn compiled_formats_are_cached() {
+        let mut cfg = Cfg::new();
+        cfg.load_configuration_from_str(
+            "",
+            r#"{
+                "canid" : {
+                    "prompt": "CAN Id", "tooltip": "", "current": "100", "default": "100",
+                    "format": "[0-9]{1,4}", "action": "Edit"
+                }
+            }"#,
+        )
+        .expect("configuration failed to load");
+        let attr = cfg.read_attribute("canid").unwrap().clone();
+        let first = cfg.matcher(&attr).unwrap();
+        assert!(cfg.validate_value("canid", "12").is_ok());
+        assert!(cfg.validate_value("canid", "1x").is_err());
+        assert!(Arc::ptr_eq(&first, &cfg.matcher(&attr).unwrap()));
+        assert_eq!(cfg.matcher_cache.lock().unwrap().len(), 1);
+
+        cfg.set_regex_options(RegexOptions {
+            size_limit: Some(1 << 20),
+            ..RegexOptions::default()
+        });
+        assert!(cfg.matcher_cache.lock().unwrap().is_empty());
+        assert!(!Arc::ptr_eq(&first, &cfg.matcher(&attr).unwrap()));
+    }
+
+    #[test]
+    fn formats_are_compiled_on_load() {
+        let defn = r#"{
+            "canid" : {
+                "prompt": "CAN Id", "tooltip": "", "current": "100", "default": "100",
+                "format": "[0-9", "action": "Edit"
+            },
+            "node_number" : {
+                "prompt": "Node Number", "tooltip": "", "current": "1", "default": "1",
+                "format": "u16", "action": "Edit"
+            },
+            "router_ssid" : {
+                "prompt": "Router SSID", "tooltip": "", "current": "", "default": "",
+                "format": "(a", "action": "Edit"
+            }
+        }"#;
+        let mut cfg = Cfg::new();
+        let err = cfg.load_configuration_from_str("", defn).unwrap_err();
+        match err.root() {
+            CfgError::Format(message) => {
+                assert!(message.starts_with("'canid' format '[0-9': "));
+                assert!(message.contains("; 'router_ssid' format '(a': "));
+                assert!(!message.contains("node_number"));
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Random configuration values
+//!
+//! Values are generated from the regular expression in each attribute's `format`, so they
+//! satisfy the format but are otherwise arbitrary.  Unbounded repetitions such as `.*` produce at
+//! most [`MAX_REPEAT`] repeats.  A [`NamedFormat`] is generated directly.  Policy rules are not
+//! taken into account.
+
+use crate::{Cfg, CfgError, NamedFormat};
+
+use rand::distributions::Distribution;
+use rand::Rng;
+
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Upper limit on the repeats generated for `*`, `+` and open ranges such as `{2,}`
+pub const MAX_REPEAT: u32 = 8;
+
+/// Generate a random string matching `format`
+///
+/// A leading `^` and trailing `$` are dropped; any other anchor in the format is an error.
+fn random_match<R: Rng + ?Sized>(format: &str, rng: &mut R) -> Result<String, String> {
+    let pattern = format.strip_prefix('^').unwrap_or(format);
+    let pattern = match pattern.strip_suffix('$') {
+        Some(p) if !p.ends_with('\\') => p,
+        _ => pattern,
+    };
+    let generator = rand_regex::Regex::compile(pattern, MAX_REPEAT).map_err(|e| e.to_string())?;
+    let sample: Result<String, _> = generator.sample(rng);
+    sample.map_err(|e| e.to_string())
+}
+
+/// Generate a random value satisfying the built-in format `named`
+fn random_named<R: Rng + ?Sized>(named: NamedFormat, rng: &mut R) -> String {
+    match named {
+        NamedFormat::Ipv4 => Ipv4Addr::from(rng.gen::<u32>()).to_string(),
+        NamedFormat::Ipv6 => Ipv6Addr::from(rng.gen::<u128>()).to_string(),
+        NamedFormat::Port => rng.gen_range(1..=u16::MAX).to_string(),
+        NamedFormat::Hostname => {
+            random_match("[a-z][a-z0-9]{0,7}(\\.[a-z][a-z0-9]{0,7}){0,2}", rng)
+                .expect("valid pattern")
+        }
+        NamedFormat::Bool => ["0", "1"][rng.gen_range(0..2)].to_string(),
+        NamedFormat::U8 => rng.gen::<u8>().to_string(),
+        NamedFormat::U16 => rng.gen::<u16>().to_string(),
+        NamedFormat::U32 => rng.gen::<u32>().to_string(),
+        NamedFormat::I32 => rng.gen::<i32>().to_string(),
+    }
+}
+
+impl Cfg {
+    /// Generate a random current value for every configuration item that satisfies its format
+    pub fn random_values<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+    ) -> Result<BTreeMap<String, String>, CfgError> {
+        let cfg = self.cfg.as_ref().ok_or(CfgError::Cfg())?;
+        let mut values = BTreeMap::new();
+        for (k, v) in cfg {
+            let value = match v.named_format() {
+                Some(named) => random_named(named, rng),
+                None => random_match(&v.format, rng)
+                    .map_err(|e| CfgError::Generate(format!("{}: {}", k, e)))?,
+            };
+            values.insert(k.clone(), value);
+        }
+        Ok(values)
+    }
+
+    /// Replace every current value with a random one that satisfies its format
+    ///
+    /// Useful for populating demonstration instances or fuzzing consumers of the INI file.
+    pub fn randomize<R: Rng + ?Sized>(&mut self, rng: &mut R) -> Result<(), CfgError> {
+        let values = self.random_values(rng)?;
+        let changes: Vec<(&str, &str)> = values
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        self.store_changes(&changes, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::load_example_defn;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn generated_values_match_formats() {
+        let mut cfg = load_example_defn("scratch/generate_test.json");
+
+        let mut rng = StdRng::seed_from_u64(2021);
+        for _ in 0..20 {
+            cfg.randomize(&mut rng).expect("randomize failed");
+            assert!(cfg.format_violations().is_empty());
+        }
+        let again = cfg.random_values(&mut StdRng::seed_from_u64(7)).unwrap();
+        assert_eq!(
+            again,
+            cfg.random_values(&mut StdRng::seed_from_u64(7)).unwrap()
+        );
+    }
+
+    #[test]
+    fn anchors() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let value = random_match("^[0-9]{4}$", &mut rng).expect("generation failed");
+        assert_eq!(value.len(), 4);
+        assert_eq!(random_match("a\\$", &mut rng).unwrap(), "a$");
+        assert!(random_match("a\\bb", &mut rng).is_err());
+        for named in NamedFormat::ALL {
+            assert!(named.matches(&random_named(named, &mut rng)), "{:?}", named);
+        }
+    }
+}
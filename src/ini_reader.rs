@@ -0,0 +1,226 @@
+//! INI input with include processing and environment expansion
+//!
+//! When [`LoadOptions::include_directive`] is set, a line consisting of the directive followed by
+//! a file name (e.g. `!include site.cfg`) is replaced by the entries of that file.  Relative
+//! names are resolved against the directory of the including file.  The result is a list of
+//! parsed documents in the order their entries take effect, each tagged with the file it came
+//! from, so later entries override earlier ones and every value can be traced to its file.
+
+use crate::{CfgError, Fs, LoadOptions, Operation};
+
+use ini::{Ini, ParseOption};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// A parsed portion of an INI file and the file it was read from
+pub(crate) struct IniDocument {
+    pub file: PathBuf,
+    pub ini: Ini,
+}
+
+/// The rust-ini options matching the output of IniWriter
+pub(crate) fn parse_options(options: &LoadOptions) -> ParseOption {
+    ParseOption {
+        enabled_quote: true,
+        enabled_escape: true,
+        enabled_indented_mutiline_value: options.multiline_values,
+        ..ParseOption::default()
+    }
+}
+
+/// Read `path`, expanding any include directives, into documents in precedence order
+pub(crate) fn read_documents(
+    fs: &dyn Fs,
+    path: &Path,
+    options: &LoadOptions,
+) -> Result<Vec<IniDocument>, CfgError> {
+    let mut documents = Vec::new();
+    let mut stack = Vec::new();
+    expand(fs, path, options, &mut stack, &mut documents)?;
+    Ok(documents)
+}
+
+fn expand(
+    fs: &dyn Fs,
+    path: &Path,
+    options: &LoadOptions,
+    stack: &mut Vec<PathBuf>,
+    documents: &mut Vec<IniDocument>,
+) -> Result<(), CfgError> {
+    let canonical = fs.canonicalize(path)?;
+    if stack.contains(&canonical) {
+        let chain: Vec<String> = stack
+            .iter()
+            .chain(std::iter::once(&canonical))
+            .map(|p| p.display().to_string())
+            .collect();
+        return Err(CfgError::Include(format!("cycle {}", chain.join(" -> "))));
+    }
+    stack.push(canonical);
+
+    let text = fs.read_to_string(path)?;
+    let directive = match options.include_directive.as_deref() {
+        Some(d) => d,
+        None => {
+            push_document(path, &text, options, documents)?;
+            stack.pop();
+            return Ok(());
+        }
+    };
+
+    // Text since the last include; restarted with the current section header so that
+    // entries following an include stay in the section they were written in
+    let mut chunk = String::new();
+    let mut section_header: Option<&str> = None;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        match trimmed.strip_prefix(directive) {
+            Some(target) if target.starts_with(char::is_whitespace) => {
+                push_document(path, &chunk, options, documents)?;
+                let target = path
+                    .parent()
+                    .unwrap_or_else(|| Path::new(""))
+                    .join(target.trim());
+                expand(fs, &target, options, stack, documents)?;
+                chunk.clear();
+                if let Some(header) = section_header {
+                    chunk.push_str(header);
+                    chunk.push('\n');
+                }
+            }
+            _ => {
+                if trimmed.starts_with('[') && trimmed.ends_with(']') {
+                    section_header = Some(trimmed);
+                }
+                chunk.push_str(line);
+                chunk.push('\n');
+            }
+        }
+    }
+    push_document(path, &chunk, options, documents)?;
+    stack.pop();
+    Ok(())
+}
+
+/// Read `text`, named `name` in provenance and errors, into documents
+///
+/// Include directives in `text` are not followed, as there is no file to resolve them against.
+pub(crate) fn read_str_documents(
+    name: &Path,
+    text: &str,
+    options: &LoadOptions,
+) -> Result<Vec<IniDocument>, CfgError> {
+    let mut documents = Vec::new();
+    push_document(name, text, options, &mut documents)?;
+    Ok(documents)
+}
+
+fn push_document(
+    path: &Path,
+    text: &str,
+    options: &LoadOptions,
+    documents: &mut Vec<IniDocument>,
+) -> Result<(), CfgError> {
+    if text.trim().is_empty() {
+        return Ok(());
+    }
+    let ini = Ini::load_from_str_opt(text, parse_options(options)).map_err(ini::Error::Parse)?;
+    documents.push(IniDocument {
+        file: path.to_path_buf(),
+        ini,
+    });
+    Ok(())
+}
+
+/// The configuration key of entry `name` in `section`
+///
+/// Keys outside the general section are prefixed with their section name, e.g. `network.ssid`.
+/// The section named by [`LoadOptions::general_section`] is read as the general section.
+pub(crate) fn entry_key(section: Option<&str>, name: &str, options: &LoadOptions) -> String {
+    match section.filter(|s| Some(*s) != options.general_section.as_deref()) {
+        Some(s) => format!("{}.{}", s, name),
+        None => name.to_string(),
+    }
+}
+
+/// Read the values in `path` by configuration key, later entries overriding earlier ones
+pub(crate) fn read_values(
+    fs: &dyn Fs,
+    path: &Path,
+    options: &LoadOptions,
+) -> Result<BTreeMap<String, String>, CfgError> {
+    let mut values = BTreeMap::new();
+    for document in read_documents(fs, path, options)? {
+        for (section, properties) in document.ini.iter() {
+            for (name, v) in properties.iter() {
+                let k = entry_key(section, name, options);
+                let v = if options.expand_env {
+                    expand_env(v).map_err(|e| CfgError::Env(e).at_key(Operation::Load, &k))?
+                } else {
+                    v.to_string()
+                };
+                values.insert(k, v);
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Replace `${VAR}` references in `value` with the value of environment variable `VAR`
+///
+/// `${VAR:-fallback}` uses `fallback` when `VAR` is unset and `$$` is a literal `$`.  A reference
+/// to an unset variable without a fallback, or an unterminated reference, is an error.
+pub(crate) fn expand_env(value: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        if let Some(tail) = after.strip_prefix('$') {
+            out.push('$');
+            rest = tail;
+        } else if let Some(reference) = after.strip_prefix('{') {
+            let end = reference
+                .find('}')
+                .ok_or_else(|| format!("unterminated reference in '{}'", value))?;
+            let (name, fallback) = match reference[..end].split_once(":-") {
+                Some((n, f)) => (n, Some(f)),
+                None => (&reference[..end], None),
+            };
+            match (env::var(name), fallback) {
+                (Ok(v), _) => out.push_str(&v),
+                (Err(_), Some(f)) => out.push_str(f),
+                (Err(_), None) => return Err(format!("'{}' is not set", name)),
+            }
+            rest = &reference[end + 1..];
+        } else {
+            out.push('$');
+            rest = after;
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_references() {
+        env::set_var("CANPI_INI_READER_TEST", "node7");
+        assert_eq!(
+            expand_env("host-${CANPI_INI_READER_TEST}.local").as_deref(),
+            Ok("host-node7.local")
+        );
+        assert_eq!(
+            expand_env("${CANPI_INI_READER_UNSET:-5550}").as_deref(),
+            Ok("5550")
+        );
+        assert_eq!(expand_env("cost $$5 or $5").as_deref(), Ok("cost $5 or $5"));
+        assert!(expand_env("${CANPI_INI_READER_UNSET}").is_err());
+        assert!(expand_env("${CANPI_INI_READER_TEST").is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! Incremental INI output
+//!
+//! [`IniWriter`] writes sections and key/value pairs straight to any `io::Write` as they are
+//! produced, so large configurations are never assembled in memory as a whole `Ini` object.
+//!
+//! Backslashes and control characters are escaped as the `rust-ini` writer does.  Values that
+//! would otherwise be altered when read back - those with leading or trailing whitespace, an
+//! opening quote, or one of `;`, `#` or `=` - are also wrapped in double quotes, so passwords
+//! and SSIDs survive a round trip intact.
+//!
+//! With multiline output enabled, values containing newlines are written with each further line
+//! indented on a continuation line.  They must be read back with
+//! `ParseOption::enabled_indented_mutiline_value` set.  Values whose lines would not survive that
+//! (blank lines, or lines with surrounding whitespace) fall back to a single escaped line.
+
+use std::io::{self, Write};
+
+/// Writes INI formatted output section by section
+pub struct IniWriter<W: Write> {
+    inner: W,
+    started: bool,
+    multiline: bool,
+}
+
+impl<W: Write> IniWriter<W> {
+    /// Creates a writer over `inner`.  Wrap files in a `BufWriter` before passing them in.
+    pub fn new(inner: W) -> IniWriter<W> {
+        IniWriter {
+            inner,
+            started: false,
+            multiline: false,
+        }
+    }
+
+    /// Write values containing newlines as indented continuation lines
+    pub fn set_multiline(&mut self, enabled: bool) {
+        self.multiline = enabled;
+    }
+
+    /// Start a new section; `None` is the general section and must come first
+    ///
+    /// A blank line separates each section from the previous one.
+    pub fn section(&mut self, name: Option<&str>) -> io::Result<()> {
+        if self.started {
+            writeln!(self.inner)?;
+        }
+        if let Some(n) = name {
+            writeln!(self.inner, "[{}]", escape(n))?;
+            self.started = true;
+        }
+        Ok(())
+    }
+
+    /// Write a single `key=value` line to the current section
+    pub fn entry(&mut self, key: &str, value: &str) -> io::Result<()> {
+        self.started = true;
+        writeln!(
+            self.inner,
+            "{}={}",
+            escape(key),
+            format_value(value, self.multiline)
+        )
+    }
+
+    /// Write a `; comment` line; any newlines in `text` start further comment lines
+    pub fn comment(&mut self, text: &str) -> io::Result<()> {
+        self.started = true;
+        for line in text.lines() {
+            writeln!(self.inner, "; {}", line)?;
+        }
+        Ok(())
+    }
+
+    /// Flush any buffered output and return the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// `value` as written after the `=` of an entry, on continuation lines if `multiline` allows
+pub(crate) fn format_value(value: &str, multiline: bool) -> String {
+    let continued = if multiline {
+        continuation_lines(value)
+    } else {
+        None
+    };
+    continued.unwrap_or_else(|| quote_value(value))
+}
+
+/// Check whether the INI parser would alter `s` unless it is quoted
+fn needs_quotes(s: &str) -> bool {
+    s.starts_with(char::is_whitespace)
+        || s.ends_with(char::is_whitespace)
+        || s.starts_with(['"', '\''])
+        || s.contains([';', '#', '='])
+}
+
+/// Split a value containing newlines into indented continuation lines, if it can be read back
+fn continuation_lines(s: &str) -> Option<String> {
+    let mut lines = s.split('\n');
+    let first = lines.next()?;
+    let rest: Vec<&str> = lines.collect();
+    if rest.is_empty() || first.is_empty() || needs_quotes(first) {
+        return None;
+    }
+    let unsafe_line = |l: &&str| {
+        l.is_empty()
+            || l.starts_with(char::is_whitespace)
+            || l.ends_with(char::is_whitespace)
+            || l.starts_with(['"', '\'', '[', ';', '#'])
+    };
+    if rest.iter().any(unsafe_line) {
+        return None;
+    }
+    let mut out = escape(first);
+    for line in rest {
+        out.push_str("\n  ");
+        out.push_str(&escape(line));
+    }
+    Some(out)
+}
+
+/// Escape a value, wrapping it in double quotes if the INI parser would not read it back as is
+fn quote_value(s: &str) -> String {
+    if needs_quotes(s) {
+        format!("\"{}\"", escape(s).replace('"', "\\\""))
+    } else {
+        escape(s)
+    }
+}
+
+/// Escape backslashes and control characters as `rust-ini` does with `EscapePolicy::Basics`
+pub(crate) fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\0' => escaped.push_str("\\0"),
+            '\x07' => escaped.push_str("\\a"),
+            '\x08' => escaped.push_str("\\b"),
+            '\x0c' => escaped.push_str("\\f"),
+            '\x0b' => escaped.push_str("\\v"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\x01'..='\x06' | '\x0e'..='\x1f' | '\x7f' => {
+                escaped.push_str(&format!("\\x{:04x}", c as u32))
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ini::Ini;
+
+    #[test]
+    fn special_values_round_trip() {
+        let values = [
+            "pass;word",
+            "#hash",
+            "a=b",
+            "  padded  ",
+            "\"quoted\"",
+            "'single",
+            "back\\slash\\",
+            "mixed \\\" ;#= ",
+            "",
+        ];
+        let mut w = IniWriter::new(Vec::new());
+        w.section(None).unwrap();
+        for (i, v) in values.iter().enumerate() {
+            w.entry(&format!("key{}", i), v).unwrap();
+        }
+        let text = String::from_utf8(w.finish().unwrap()).unwrap();
+        let ini = Ini::load_from_str(&text).unwrap();
+        for (i, v) in values.iter().enumerate() {
+            assert_eq!(
+                ini.general_section().get(format!("key{}", i)),
+                Some(*v),
+                "value {:?} written as {}",
+                v,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn multiline_round_trip() {
+        let values = [
+            "-----BEGIN CERTIFICATE-----\nMIIB=;#\n-----END CERTIFICATE-----",
+            "blank\n\nline",
+            "indented\n  line",
+            "single line",
+        ];
+        let mut w = IniWriter::new(Vec::new());
+        w.set_multiline(true);
+        w.section(None).unwrap();
+        for (i, v) in values.iter().enumerate() {
+            w.entry(&format!("key{}", i), v).unwrap();
+        }
+        let text = String::from_utf8(w.finish().unwrap()).unwrap();
+        assert!(text.starts_with("key0=-----BEGIN CERTIFICATE-----\n  MIIB=;#\n"));
+        let options = ini::ParseOption {
+            enabled_indented_mutiline_value: true,
+            ..ini::ParseOption::default()
+        };
+        let ini = Ini::load_from_str_opt(&text, options).unwrap();
+        for (i, v) in values.iter().enumerate() {
+            assert_eq!(ini.general_section().get(format!("key{}", i)), Some(*v));
+        }
+    }
+
+    #[test]
+    fn sections_round_trip() {
+        let mut w = IniWriter::new(Vec::new());
+        w.section(None).unwrap();
+        w.entry("canid", "100").unwrap();
+        w.section(Some("network")).unwrap();
+        w.entry("router_ssid", "back\\slash\ttab").unwrap();
+        let bytes = w.finish().unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+        assert_eq!(
+            text,
+            "canid=100\n\n[network]\nrouter_ssid=back\\\\slash\\ttab\n"
+        );
+
+        let ini = Ini::load_from_str(&text).unwrap();
+        assert_eq!(ini.general_section().get("canid"), Some("100"));
+        assert_eq!(
+            ini.get_from(Some("network"), "router_ssid"),
+            Some("back\\slash\ttab")
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! Iteration over the attributes of a Cfg
+//!
+//! [`Cfg::iter`] and `&Cfg` in a `for` loop borrow each item's key and attribute in the iteration
+//! order of [`ConfigHash`], without cloning it.  Nothing is yielded until a configuration is
+//! loaded.
+
+use crate::{Attribute, Cfg, ConfigHash};
+
+/// The items of a [`Cfg`], from [`Cfg::iter`]
+pub struct Attributes<'a> {
+    inner: Option<<&'a ConfigHash as IntoIterator>::IntoIter>,
+}
+
+impl<'a> Iterator for Attributes<'a> {
+    type Item = (&'a str, &'a Attribute);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, attr) = self.inner.as_mut()?.next()?;
+        Some((key.as_str(), attr))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner
+            .as_ref()
+            .map_or((0, Some(0)), Iterator::size_hint)
+    }
+}
+
+impl ExactSizeIterator for Attributes<'_> {}
+
+/// The items of a [`Cfg`] with their attributes mutable, from [`Cfg::iter_mut`]
+pub struct AttributesMut<'a> {
+    inner: Option<<&'a mut ConfigHash as IntoIterator>::IntoIter>,
+}
+
+impl<'a> Iterator for AttributesMut<'a> {
+    type Item = (&'a str, &'a mut Attribute);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, attr) = self.inner.as_mut()?.next()?;
+        Some((key.as_str(), attr))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner
+            .as_ref()
+            .map_or((0, Some(0)), Iterator::size_hint)
+    }
+}
+
+impl ExactSizeIterator for AttributesMut<'_> {}
+
+impl Cfg {
+    /// The key and attribute of each configuration item
+    pub fn iter(&self) -> Attributes<'_> {
+        Attributes {
+            inner: self.cfg.as_ref().map(|c| c.iter()),
+        }
+    }
+
+    /// The key and attribute of each configuration item, with the attribute mutable
+    ///
+    /// Changes made this way are not checked and are not recorded as changes to the current
+    /// value; use [`Cfg::set_value`] or [`Cfg::entry`] for those.
+    pub fn iter_mut(&mut self) -> AttributesMut<'_> {
+        self.invalidate_caches();
+        AttributesMut {
+            inner: self.cfg.as_mut().map(|c| c.iter_mut()),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Cfg {
+    type Item = (&'a str, &'a Attribute);
+    type IntoIter = Attributes<'a>;
+
+    fn into_iter(self) -> Attributes<'a> {
+        self.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut Cfg {
+    type Item = (&'a str, &'a mut Attribute);
+    type IntoIter = AttributesMut<'a>;
+
+    fn into_iter(self) -> AttributesMut<'a> {
+        self.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ActionBehaviour;
+    use std::sync::Arc;
+
+    const DEFN_DATA: &str = r#"{
+        "canid" : {
+            "prompt": "CAN Id", "tooltip": "", "current": "100", "default": "100",
+            "format": "[0-9]{1,4}", "action": "Edit"
+        },
+        "node_mode" : {
+            "prompt": "", "tooltip": "", "current": "0", "default": "0",
+            "format": "[0-9]{1,2}", "action": "Hide"
+        }
+    }"#;
+
+    #[test]
+    fn attributes_are_iterated() {
+        let mut cfg = Cfg::new();
+        assert_eq!(cfg.iter().count(), 0);
+        assert_eq!(cfg.iter_mut().size_hint(), (0, Some(0)));
+        cfg.load_configuration_from_str("canid=101\n", DEFN_DATA)
+            .expect("configuration failed to load");
+
+        let mut keys: Vec<&str> = cfg.iter().map(|(k, _)| k).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["canid", "node_mode"]);
+        assert_eq!(cfg.iter().len(), 2);
+        for (key, attr) in &cfg {
+            assert!(std::ptr::eq(attr, cfg.read_attribute(key).unwrap()));
+        }
+
+        assert_eq!(cfg.attributes_with_action(ActionBehaviour::Edit).len(), 1);
+        for (_, attr) in &mut cfg {
+            attr.action = ActionBehaviour::Edit;
+            attr.prompt = Arc::from("Changed");
+        }
+        assert_eq!(cfg.attributes_with_action(ActionBehaviour::Edit).len(), 2);
+        assert!(cfg.iter().all(|(_, a)| &*a.prompt == "Changed"));
+    }
+}
@@ -0,0 +1,173 @@
+//! Layered configuration sources
+//!
+//! [`Cfg::load_layers`] reads the current values from a stack of [`Layer`]s above the defaults
+//! given in the definition file, such as a system INI file, a user INI file, environment
+//! variables and overrides set by the application.  Each layer overrides those before it, and
+//! [`Cfg::value_layer`] tells which one supplied each current value.
+
+use crate::builder::DefnSource;
+use crate::{Cfg, CfgError, LoadOptions, LoadReport, Provenance};
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Name given in provenance and errors to INI text held in memory
+pub(crate) const INI_STR_NAME: &str = "(INI string)";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A source of configuration values
+pub enum Layer {
+    /// An INI file and the files it includes
+    ///
+    /// The file must exist unless [`LoadOptions::allow_missing_file`] is set.
+    Ini(PathBuf),
+    /// INI text held in memory
+    ///
+    /// Include directives in the text are not followed.
+    IniStr(String),
+    /// Environment variables named with this prefix, as for [`LoadOptions::env_prefix`]
+    Env(String),
+    /// Values by key, as set by the application
+    ///
+    /// Like environment variables, overrides are not written back to the INI file.
+    Overrides(BTreeMap<String, String>),
+}
+
+/// The last INI file in `layers`, which edits are written back to
+pub(crate) fn writable_path(layers: &[Layer]) -> Option<&Path> {
+    layers.iter().rev().find_map(|layer| match layer {
+        Layer::Ini(path) => Some(path.as_path()),
+        _ => None,
+    })
+}
+
+impl Cfg {
+    /// Load the attribute definitions from `def_path` and then the current values from `layers`,
+    /// each overriding those before it
+    ///
+    /// [`LoadOptions::env_prefix`] is not used; add a [`Layer::Env`] where the environment should
+    /// take precedence.  An override for a key that is not defined fails the load with
+    /// [`CfgError::UnknownKey`].
+    pub fn load_layers<P: AsRef<Path>>(
+        &mut self,
+        def_path: P,
+        layers: Vec<Layer>,
+        options: &LoadOptions,
+    ) -> Result<LoadReport, CfgError> {
+        let cfg_path = writable_path(&layers)
+            .unwrap_or(def_path.as_ref())
+            .to_path_buf();
+        let defn = DefnSource::File(def_path.as_ref().to_path_buf());
+        self.load_stack(&cfg_path, &defn, layers, options)
+    }
+
+    /// The layers the configuration was loaded from, lowest precedence first
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    /// The index in [`Cfg::layers`] of the layer that supplied the current value of `key`
+    ///
+    /// Returns `None` if the value is the default from the definition file or has been changed
+    /// since loading.
+    pub fn value_layer<K: AsRef<str>>(&self, key: K) -> Option<usize> {
+        let key = key.as_ref();
+        match self.provenance.get(key) {
+            Some(Provenance::User) | Some(Provenance::Default) => None,
+            _ => self.value_layers.get(key).copied(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryFs;
+    use std::sync::Arc;
+
+    const DEFN_DATA: &str = r#"
+        {
+            "canid" : {
+                "prompt": "CAN Id", "tooltip": "", "current": "100", "default": "100",
+                "format": "[0-9]{1,4}", "action": "Edit"
+            },
+            "node_number" : {
+                "prompt": "Node Number", "tooltip": "", "current": "4321", "default": "4321",
+                "format": "[0-9]{1,4}", "action": "Edit"
+            },
+            "logfile" : {
+                "prompt": "Log file", "tooltip": "", "current": "canpi.log", "default": "canpi.log",
+                "format": ".*", "action": "Edit"
+            },
+            "loglevel" : {
+                "prompt": "Log level", "tooltip": "", "current": "INFO", "default": "INFO",
+                "format": ".*", "action": "Edit"
+            }
+        }"#;
+
+    #[test]
+    fn later_layers_take_precedence() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        fs.insert(
+            "system.cfg",
+            "canid=101\nnode_number=5000\nlogfile=sys.log\n",
+        );
+        fs.insert("user.cfg", "node_number=5001\nlogfile=user.log\n");
+        let overrides: BTreeMap<String, String> =
+            [("logfile".to_string(), "override.log".to_string())].into();
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs);
+        let layers = vec![
+            Layer::Ini("system.cfg".into()),
+            Layer::Ini("user.cfg".into()),
+            Layer::Overrides(overrides),
+        ];
+        cfg.load_layers("defn.json", layers.clone(), &LoadOptions::default())
+            .expect("configuration failed to load");
+        assert_eq!(cfg.layers(), &layers[..]);
+        assert_eq!(cfg.get_or_default("canid"), "101");
+        assert_eq!(cfg.value_layer("canid"), Some(0));
+        assert_eq!(cfg.get_or_default("node_number"), "5001");
+        assert_eq!(cfg.value_layer("node_number"), Some(1));
+        assert_eq!(cfg.get_or_default("logfile"), "override.log");
+        assert_eq!(cfg.value_layer("logfile"), Some(2));
+        assert_eq!(cfg.provenance("logfile"), Some(&Provenance::Override));
+        assert_eq!(cfg.value_layer("loglevel"), None);
+
+        cfg.set_value("canid", "102").expect("set_value failed");
+        assert_eq!(cfg.value_layer("canid"), None);
+
+        let unknown: BTreeMap<String, String> = [("wifi".to_string(), "on".to_string())].into();
+        let err = cfg
+            .load_layers(
+                "defn.json",
+                vec![Layer::Overrides(unknown)],
+                &LoadOptions::default(),
+            )
+            .unwrap_err();
+        assert!(matches!(err.root(), CfgError::UnknownKey(k) if k == "wifi"));
+    }
+
+    #[test]
+    fn overrides_are_not_written() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        fs.insert("user.cfg", "logfile=user.log\n");
+        let overrides: BTreeMap<String, String> = [
+            ("logfile".to_string(), "override.log".to_string()),
+            ("loglevel".to_string(), "DEBUG".to_string()),
+        ]
+        .into();
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        let layers = vec![Layer::Ini("user.cfg".into()), Layer::Overrides(overrides)];
+        cfg.load_layers("defn.json", layers, &LoadOptions::default())
+            .expect("configuration failed to load");
+        cfg.write_cfg_file("user.cfg", None).expect("write failed");
+        let text = fs.contents("user.cfg").unwrap();
+        assert!(text.contains("logfile=user.log\n"));
+        assert!(text.contains("loglevel=INFO\n"));
+        assert_eq!(cfg.get_or_default("logfile"), "override.log");
+    }
+}
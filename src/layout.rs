@@ -0,0 +1,270 @@
+//! Rewriting an INI file in place
+//!
+//! With [`WriteOptions::preserve_layout`] set, the existing file is kept line for line: comments,
+//! blank lines, key order and entries for keys that are not defined are left alone, and only the
+//! value of each defined key is replaced.  Defined keys missing from the file are added after the
+//! last entry of their section, and sections missing from the file are added at its end.
+//!
+//! [`WriteOptions::preserve_layout`]: crate::WriteOptions::preserve_layout
+
+use crate::ini_writer::{escape, format_value};
+use crate::WriteOptions;
+
+/// A value to be written: its section (`None` for the general section), entry name and value
+pub(crate) type Entry<'a> = (Option<&'a str>, &'a str, &'a str);
+
+/// The section a header line names, if `line` is one
+fn header(line: &str) -> Option<&str> {
+    let trimmed = line.trim();
+    trimmed
+        .strip_prefix('[')
+        .and_then(|t| t.strip_suffix(']'))
+        .map(str::trim)
+}
+
+/// The entry name on `line`, if it is a `name=value` line
+fn entry_name(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with([';', '#']) {
+        return None;
+    }
+    trimmed.split_once('=').map(|(name, _)| name.trim())
+}
+
+struct Merge<'a> {
+    entries: &'a [Entry<'a>],
+    /// Section the general entries are written under, if named
+    general: Option<&'a str>,
+    multiline: bool,
+    written: Vec<bool>,
+    lines: Vec<String>,
+}
+
+impl<'a> Merge<'a> {
+    /// The section header `entry` goes under
+    fn section_of(&self, entry: &Entry<'a>) -> Option<&'a str> {
+        entry.0.or(self.general)
+    }
+
+    /// The index of the entry `name` in the file's `section`, if there is one
+    ///
+    /// Entries before the first header belong to the general section, however it is written.
+    fn find(&self, section: Option<&str>, name: &str) -> Option<usize> {
+        let section = section.or(self.general);
+        self.entries
+            .iter()
+            .position(|e| e.1 == name && self.section_of(e) == section)
+    }
+
+    /// Insert the entries of the file's `section` not yet written at line `at`
+    fn insert_missing(&mut self, section: Option<&str>, at: usize) {
+        let section = section.or(self.general);
+        let mut missing = Vec::new();
+        for (i, entry) in self.entries.iter().enumerate() {
+            if !self.written[i] && self.section_of(entry) == section {
+                self.written[i] = true;
+                missing.push(format!(
+                    "{}={}",
+                    escape(entry.1),
+                    format_value(entry.2, self.multiline)
+                ));
+            }
+        }
+        self.lines.splice(at..at, missing);
+    }
+}
+
+/// `text` with the values of `entries` replaced or added as described in the module docs
+pub(crate) fn merge(text: &str, entries: &[Entry], options: &WriteOptions) -> String {
+    let mut merge = Merge {
+        entries,
+        general: options.general_section.as_deref(),
+        multiline: options.multiline_values,
+        written: vec![false; entries.len()],
+        lines: Vec::new(),
+    };
+    let mut section: Option<String> = None;
+    // Where entries missing from the current section are inserted: after its last entry
+    let mut insert_at = 0;
+    // Set while skipping the continuation lines of a replaced multiline value
+    let mut replaced = false;
+    for line in text.lines() {
+        // Without multiline values an indented entry or comment is a line of its own
+        let continuation = line.starts_with(char::is_whitespace)
+            && !line.trim().is_empty()
+            && (options.multiline_values
+                || entry_name(line).is_none() && !line.trim_start().starts_with([';', '#']));
+        if replaced && continuation {
+            continue;
+        }
+        replaced = false;
+        if let Some(name) = header(line) {
+            // With a named general section, general entries go before the first header only if
+            // the file already has some there
+            if section.is_some() || merge.general.is_none() || insert_at > 0 {
+                merge.insert_missing(section.as_deref(), insert_at);
+            }
+            section = Some(name.to_string());
+            merge.lines.push(line.to_string());
+            insert_at = merge.lines.len();
+            continue;
+        }
+        let found = entry_name(line).and_then(|name| merge.find(section.as_deref(), name));
+        match (found, line.split_once('=')) {
+            (Some(i), Some((name, rest))) => {
+                merge.written[i] = true;
+                // Keep the spacing around the `=` as it was
+                let gap = &rest[..rest.len() - rest.trim_start().len()];
+                let value = format_value(entries[i].2, options.multiline_values);
+                merge.lines.push(format!("{}={}{}", name, gap, value));
+                replaced = true;
+                insert_at = merge.lines.len();
+            }
+            _ => {
+                if entry_name(line).is_some() {
+                    insert_at = merge.lines.len() + 1;
+                }
+                merge.lines.push(line.to_string());
+            }
+        }
+    }
+    merge.insert_missing(section.as_deref(), insert_at);
+
+    // General entries have all been placed by now, before the first header or at the end
+    let mut remaining: Vec<&str> = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        match merge.section_of(entry) {
+            Some(s) if !merge.written[i] && !remaining.contains(&s) => remaining.push(s),
+            _ => {}
+        }
+    }
+    for s in remaining {
+        if merge.lines.last().is_some_and(|l| !l.trim().is_empty()) {
+            merge.lines.push(String::new());
+        }
+        merge.lines.push(format!("[{}]", escape(s)));
+        let at = merge.lines.len();
+        merge.insert_missing(Some(s), at);
+    }
+
+    let mut out = merge.lines.join("\n");
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXISTING: &str = "\
+; CAN Pi configuration
+canid = 100
+
+# unused
+node_number=300
+unknown=kept
+
+[network]
+; the home router
+router_ssid=home
+";
+
+    #[test]
+    fn values_replaced_and_layout_kept() {
+        let entries = [
+            (None, "canid", "101"),
+            (None, "node_number", "300"),
+            (None, "node_mode", "1"),
+            (Some("network"), "router_ssid", "club;house"),
+            (Some("network"), "router_password", "secret"),
+            (Some("apmode"), "ap_ssid", "canpiwi"),
+        ];
+        let merged = merge(EXISTING, &entries, &WriteOptions::default());
+        assert_eq!(
+            merged,
+            "\
+; CAN Pi configuration
+canid = 101
+
+# unused
+node_number=300
+unknown=kept
+node_mode=1
+
+[network]
+; the home router
+router_ssid=\"club;house\"
+router_password=secret
+
+[apmode]
+ap_ssid=canpiwi
+"
+        );
+    }
+
+    #[test]
+    fn general_entries_before_first_section() {
+        let options = WriteOptions::default();
+        let merged = merge(
+            "[network]\nrouter_ssid=home\n",
+            &[(None, "canid", "100")],
+            &options,
+        );
+        assert_eq!(merged, "canid=100\n[network]\nrouter_ssid=home\n");
+
+        let options = WriteOptions {
+            multiline_values: true,
+            ..WriteOptions::default()
+        };
+        let merged = merge("cert=a\n  b\nnext=1\n", &[(None, "cert", "c\nd")], &options);
+        assert_eq!(merged, "cert=c\n  d\nnext=1\n");
+    }
+
+    #[test]
+    fn named_general_section() {
+        let options = WriteOptions {
+            general_section: Some("canpi".to_string()),
+            ..WriteOptions::default()
+        };
+        let entries = [
+            (None, "canid", "101"),
+            (None, "node_number", "300"),
+            (Some("network"), "router_ssid", "club"),
+        ];
+        let merged = merge(
+            "canid=100\n[network]\nrouter_ssid=home\n",
+            &entries,
+            &options,
+        );
+        assert_eq!(
+            merged,
+            "canid=101\nnode_number=300\n[network]\nrouter_ssid=club\n"
+        );
+
+        let merged = merge(
+            "[canpi]\ncanid=100\n[network]\nrouter_ssid=home\n",
+            &entries,
+            &options,
+        );
+        assert_eq!(
+            merged,
+            "[canpi]\ncanid=101\nnode_number=300\n[network]\nrouter_ssid=club\n"
+        );
+
+        let merged = merge("[network]\nrouter_ssid=home\n", &entries, &options);
+        assert_eq!(
+            merged,
+            "[network]\nrouter_ssid=club\n\n[canpi]\ncanid=101\nnode_number=300\n"
+        );
+    }
+
+    #[test]
+    fn stale_continuation_lines_dropped() {
+        let merged = merge(
+            "cert=a\n  b\n  ; note\n  indented=1\nnext=1\n",
+            &[(None, "cert", "c")],
+            &WriteOptions::default(),
+        );
+        assert_eq!(merged, "cert=c\n  ; note\n  indented=1\nnext=1\n");
+    }
+}
@@ -13,22 +13,150 @@
 //  30 November, 2021 - E M Thornber
 //
 
-use ini::Ini;
-
+use chrono::FixedOffset;
 use jsonschema::JSONSchema;
+use once_cell::sync::Lazy;
+use regex::Regex;
 use schemars::{schema_for, JsonSchema};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
-use std::path::Path;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::string::String;
-
-use backitup::backup;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
 use thiserror::Error;
+use tracing::field::Empty;
+use tracing::{debug_span, info, info_span, warn};
+
+use builder::DefnSource;
+use context::ResultExt;
+use format::MatcherCache;
+use trace::Timed;
+
+mod access;
+mod alias;
+mod audit;
+mod backup;
+mod builder;
+mod category;
+mod check;
+mod context;
+mod datetime;
+mod de;
+mod defn_format;
+mod defn_writer;
+#[cfg(feature = "encryption")]
+mod encryption;
+mod entry;
+mod export;
+#[cfg(feature = "http")]
+mod fetch;
+mod filesystem;
+mod format;
+#[cfg(feature = "generate")]
+mod generate;
+mod ini_reader;
+mod ini_writer;
+mod iter;
+mod layer;
+mod layout;
+mod limits;
+mod locale;
+mod lock;
+mod migration;
+mod modified;
+mod notify;
+mod options;
+mod page;
+mod patch;
+mod permissions;
+mod policy;
+mod provenance;
+mod query;
+mod reader;
+mod report;
+mod sanitize;
+mod schema;
+mod search;
+mod secret;
+mod service;
+mod settings;
+mod signature;
+mod snapshot;
+#[cfg(feature = "proptest")]
+mod strategy;
+#[cfg(test)]
+mod test_util;
+mod trace;
+mod translation;
+mod unknown_fields;
+mod validate;
+mod value;
+mod verify;
+mod visibility;
+#[cfg(feature = "http")]
+mod webhook;
+mod widget;
+pub use alias::AliasedKey;
+pub use audit::{Annotation, AuditEntry};
+#[cfg(feature = "backup")]
+pub use backup::TimestampedCopy;
+pub use backup::{BackupInfo, BackupStrategy, NoBackup, NumberedRotation};
+pub use builder::CfgBuilder;
+#[cfg(feature = "derive")]
+pub use canpi_config_derive::{CanpiConfig, CanpiDefinition};
+pub use category::Category;
+pub use check::ValidationError;
+pub use context::{ErrorContext, Operation};
+pub use de::{CfgDeserializer, DeError};
+#[cfg(feature = "encryption")]
+pub use encryption::{SecretKey, ENCRYPTED_PREFIX};
+pub use entry::AttributeEntry;
+pub use filesystem::{FileMetadata, Fs, MemoryFs, StdFs};
+pub use format::NamedFormat;
+#[cfg(feature = "generate")]
+pub use generate::MAX_REPEAT;
+pub use ini_writer::IniWriter;
+pub use iter::{Attributes, AttributesMut};
+pub use layer::Layer;
+pub use locale::NumberLocale;
+pub use lock::{lock_path, FileLock};
+pub use migration::{KeyMigration, Migration};
+pub use notify::{ChangeSummary, LogNotifier, Notifier, NotifyError};
+pub use options::{
+    InputLimits, InvalidValues, LoadOptions, RegexEngine, RegexOptions, UnknownFields,
+    WriteOptions, ENV_PREFIX,
+};
+pub use page::{Page, SortBy};
+pub use permissions::{FileAccess, FileOwner, PRIVATE_MODE};
+pub use policy::{Operand, Policy, PolicyViolation, Relation, Rule};
+pub use provenance::Provenance;
+pub use query::Query;
+pub use report::LoadReport;
+pub use sanitize::ControlChars;
+pub use search::{MatchField, SearchHit};
+pub use secret::REDACTED;
+pub use service::{CfgHandle, CfgService, ServiceEvent, ServiceOptions};
+pub use settings::{definition_json, CanpiConfig, CanpiDefinition};
+#[cfg(feature = "signing")]
+pub use signature::{defn_signing_bytes, DefnPublicKey};
+pub use snapshot::UPDATE_SNAPSHOTS_VAR;
+#[cfg(feature = "proptest")]
+pub use strategy::{arb_action, arb_attribute, arb_config_hash, arb_ini_content, arb_key};
+pub use translation::Translations;
+pub use validate::PackageValidation;
+pub use value::AttributeValue;
+pub use visibility::Dependency;
+#[cfg(feature = "http")]
+pub use webhook::WebhookNotifier;
+pub use widget::Widget;
 
 #[derive(Error, Debug)]
 /// Categorizes the cause of errors when processing the configuration files
@@ -48,6 +176,84 @@ pub enum CfgError {
     /// The error was caused by a lack of attribute definitions
     #[error("Cfg structure not properly initialised")]
     Cfg(),
+    /// The error was caused by a malformed attribute query expression
+    #[error("invalid query: {0}")]
+    Query(String),
+    /// The error was caused by an include directive that could not be processed
+    #[error("cannot process include: {0}")]
+    Include(String),
+    /// The error was caused by a key that is not in the configuration
+    #[error("configuration item '{0}' is not defined")]
+    UnknownKey(String),
+    /// The error was caused by an environment variable reference that could not be expanded
+    #[error("cannot expand environment reference: {0}")]
+    Env(String),
+    /// The error was caused by a patch that could not be applied
+    #[error("cannot apply patch: {0}")]
+    Patch(String),
+    /// The error was caused by a format that random values cannot be generated from
+    #[error("cannot generate a value: {0}")]
+    Generate(String),
+    /// The error was caused by a configuration that differs from its stored snapshot
+    #[error("snapshot mismatch: {0}")]
+    Snapshot(String),
+    /// The error was caused by a format that could not be compiled or matched
+    #[error("invalid format: {0}")]
+    Format(String),
+    /// The error was caused by input larger than the configured limits
+    #[error("input rejected: {0}")]
+    Limit(String),
+    /// The error was caused by a value refused for its content
+    #[error("invalid value: {0}")]
+    InvalidValue(String),
+    /// The error was caused by a current value that could not be converted to the type asked for
+    #[error("'{key}' value '{value}' cannot be read as {target}: {reason}")]
+    Conversion {
+        /// Key of the configuration item
+        key: String,
+        /// The value that failed to convert
+        value: String,
+        /// Name of the type asked for
+        target: &'static str,
+        /// Why the conversion failed
+        reason: String,
+    },
+    /// The error was caused by current values that do not fit the struct asked for
+    #[error("cannot deserialize settings: {0}")]
+    Deserialize(String),
+    /// An error with the operation, file, package and key it concerns
+    #[error("{0}")]
+    Context(Box<ErrorContext>),
+    /// The error was caused by a value or attribute that failed validation
+    #[error("{0}")]
+    Validation(#[from] ValidationError),
+    /// The error was caused by a secret value or key that could not be used
+    #[error("cannot encrypt or decrypt: {0}")]
+    Encryption(String),
+    /// The error was caused by a configuration that could not be written in another format
+    #[error("cannot export configuration: {0}")]
+    Export(String),
+    /// The error was caused by a definition file that is not valid TOML or YAML
+    #[error("cannot parse definition file: {0}")]
+    Defn(String),
+    /// The error was caused by a definition file without a valid signature
+    #[error("definition signature rejected: {0}")]
+    Signature(String),
+    /// The error was caused by a definition file that could not be fetched over HTTP
+    #[error("cannot fetch definition file: {0}")]
+    Fetch(String),
+    /// The error was caused by a written file that does not read back as written
+    #[error("written file failed verification: {0}")]
+    Verify(String),
+    /// The error was caused by a schema file that is not a valid JSON schema
+    #[error("invalid schema file: {0}")]
+    SchemaFile(String),
+    /// The error was caused by a field of a definition file that is not known
+    #[error("definition field '{0}' is not known")]
+    UnknownField(String),
+    /// The configuration service has shut down
+    #[error("configuration service has stopped")]
+    ServiceStopped,
 }
 
 impl std::convert::From<jsonschema::SchemaResolverError> for CfgError {
@@ -59,7 +265,9 @@ impl std::convert::From<jsonschema::SchemaResolverError> for CfgError {
 ///
 /// Attribute Definitions
 ///
-#[derive(Clone, Deserialize, Debug, JsonSchema, PartialEq)]
+#[derive(
+    Clone, Copy, Deserialize, Serialize, Debug, JsonSchema, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
 /// Defines the possible behaviours for an attribute
 pub enum ActionBehaviour {
     /// User can update the value of current field
@@ -70,66 +278,459 @@ pub enum ActionBehaviour {
     Hide,
 }
 
-#[derive(Clone, Deserialize, Debug, JsonSchema)]
+#[derive(Clone, Copy, Default, Deserialize, Serialize, Debug, JsonSchema, PartialEq, Eq, Hash)]
+/// The kind of value an attribute holds
+pub enum ValueKind {
+    /// Any text matching the format
+    #[default]
+    Text,
+    /// A whole number
+    Integer,
+    /// A number that may have a fraction
+    Decimal,
+    /// A date and time, stored in RFC 3339 form
+    DateTime,
+    /// A flag, stored as `1` or `0`
+    Boolean,
+    /// An IPv4 or IPv6 address
+    IpAddr,
+    /// One of the attribute's `choices`
+    Enum,
+    /// Any number of the attribute's `choices`, stored separated by commas
+    MultiSelect,
+}
+
+#[derive(Clone, Deserialize, Serialize, JsonSchema)]
 /// Definition of an attribute
+///
+/// The descriptive fields are reference counted so cloning an Attribute, or a whole ConfigHash,
+/// only copies the current and default values
 pub struct Attribute {
     /// Text used to label edit box on form
-    pub prompt: String,
+    pub prompt: Arc<str>,
     /// Text displayed when the user hovers over edit box
-    pub tooltip: String,
+    pub tooltip: Arc<str>,
     /// Current value of attribute.  Used to populate .cfg file
     pub current: String,
     /// Default value of attribute
     pub default: String,
-    /// Regular expression to validate user input
-    pub format: String,
+    /// Regular expression to validate user input, or the name of a [`NamedFormat`]
+    pub format: Arc<str>,
     /// How the attribute is presented on a webpage
     pub action: ActionBehaviour,
+    /// Accept values where `format` matches only part of the value
+    ///
+    /// By default the whole value must match, as if `format` were wrapped in `^(?:...)$`.
+    #[serde(default)]
+    pub partial_match: bool,
+    /// What happens to control characters in values supplied through the API
+    #[serde(default)]
+    pub control_chars: ControlChars,
+    /// The kind of value held, deciding how values supplied through the API are read
+    #[serde(default)]
+    pub kind: ValueKind,
+    /// INI section the item is written to; `None` leaves it to the key
+    ///
+    /// An item in a section is written there under its key, e.g. `ap_ssid` in `[apmode]`.
+    #[serde(default)]
+    pub section: Option<Arc<str>>,
+    /// The values an `Enum` attribute may take
+    #[serde(default)]
+    pub choices: Vec<Arc<str>>,
+    /// Smallest number the value may be
+    #[serde(default)]
+    pub min: Option<f64>,
+    /// Largest number the value may be
+    #[serde(default)]
+    pub max: Option<f64>,
+    /// Interval between the numbers the value may be, counted from `min` or else from zero
+    #[serde(default)]
+    pub step: Option<f64>,
+    /// The value another item must hold for this one to be presented
+    #[serde(default)]
+    pub depends_on: Option<Arc<Dependency>>,
+    /// Group the item is presented in, e.g. `CAN` or `Wi-Fi`
+    #[serde(default)]
+    pub category: Option<Arc<str>>,
+    /// Position of the item within its category; items without one come last
+    #[serde(default)]
+    pub display_order: Option<u32>,
+    /// Units the value is in, e.g. `ms` or `dBm`, shown beside the edit box
+    #[serde(default)]
+    pub units: Option<Arc<str>>,
+    /// Example text shown in an empty edit box
+    #[serde(default)]
+    pub placeholder: Option<Arc<str>>,
+    /// The item must have a value; the INI file is not written while it is empty
+    #[serde(default)]
+    pub required: bool,
+    /// The values are never shown, e.g. a password; see [`REDACTED`]
+    #[serde(default)]
+    pub secret: bool,
+    /// The control the item is presented with, if not the one suited to its kind
+    #[serde(default)]
+    pub widget: Option<Widget>,
+    /// The prompt and tooltip in other locales; see [`Cfg::set_locale`]
+    #[serde(default)]
+    pub translations: Option<Arc<Translations>>,
+    /// Former names of the key, read from the INI file as the key itself
+    #[serde(default)]
+    pub deprecated_aliases: Vec<Arc<str>>,
 }
 
-/// Type alias based on a HashMap
-pub type ConfigHash = HashMap<String, Attribute>;
+impl Attribute {
+    /// Creates an attribute whose current value is its default
+    ///
+    /// Values are matched against the whole of `format`, control characters are rejected and
+    /// the value is text.
+    pub fn new(
+        prompt: &str,
+        tooltip: &str,
+        default: &str,
+        format: &str,
+        action: ActionBehaviour,
+    ) -> Attribute {
+        Attribute {
+            prompt: prompt.into(),
+            tooltip: tooltip.into(),
+            current: default.to_string(),
+            default: default.to_string(),
+            format: format.into(),
+            action,
+            partial_match: false,
+            control_chars: ControlChars::default(),
+            kind: ValueKind::default(),
+            section: None,
+            choices: Vec::new(),
+            min: None,
+            max: None,
+            step: None,
+            depends_on: None,
+            category: None,
+            display_order: None,
+            units: None,
+            placeholder: None,
+            required: false,
+            secret: false,
+            widget: None,
+            translations: None,
+            deprecated_aliases: Vec::new(),
+        }
+    }
+
+    /// Check `value` against the regular expression held in `format`
+    ///
+    /// The whole of `value` must match unless `partial_match` is set.  A [`NamedFormat`] is
+    /// checked by its own validator.
+    pub fn format_matches(&self, value: &str) -> Result<bool, regex::Error> {
+        if let Some(named) = self.named_format() {
+            return Ok(named.matches(value));
+        }
+        let re = if self.partial_match {
+            Regex::new(&self.format)?
+        } else {
+            Regex::new(&format!("^(?:{})$", self.format))?
+        };
+        Ok(re.is_match(value))
+    }
+}
+
+/// Map type used for ConfigHash, selected by cargo feature
+#[cfg(feature = "indexmap")]
+pub type Map<K, V> = indexmap::IndexMap<K, V>;
+/// Map type used for ConfigHash, selected by cargo feature
+#[cfg(all(feature = "btreemap", not(feature = "indexmap")))]
+pub type Map<K, V> = std::collections::BTreeMap<K, V>;
+/// Map type used for ConfigHash, selected by cargo feature
+///
+/// - default: `HashMap`, fastest lookups with arbitrary iteration order
+/// - `btreemap`: `BTreeMap`, iterates in key order
+/// - `indexmap`: `IndexMap`, iterates in insertion (definition file) order
+///
+/// If both features are enabled `indexmap` takes precedence.
+#[cfg(not(any(feature = "btreemap", feature = "indexmap")))]
+pub type Map<K, V> = HashMap<K, V>;
+
+/// Type alias based on the feature selected [`Map`]
+pub type ConfigHash = Map<String, Attribute>;
+
+#[derive(Clone, Debug, PartialEq)]
+/// A configuration item whose current value does not satisfy its `format`
+pub struct FormatViolation {
+    /// Key of the configuration item
+    pub key: String,
+    /// The offending current value
+    pub value: String,
+    /// The format the value was checked against
+    pub format: String,
+    /// Set when the format itself is not a valid regular expression, or when the value matches
+    /// it but is outside the item's `min` and `max` or not among its `choices`
+    pub error: Option<String>,
+}
+
+/// Compiled schema for attribute definition files, shared by every Cfg instance
+static CFG_SCHEMA: Lazy<JSONSchema> = Lazy::new(Cfg::create_defn_schema);
+
+/// The contents of a definition file
+struct Definition {
+    /// The attributes
+    items: ConfigHash,
+    /// Cross-field rules given in the file
+    rules: Vec<Rule>,
+    /// Keys renamed since earlier versions of the file
+    migrations: Vec<KeyMigration>,
+    /// Version of the file, if given
+    version: Option<u32>,
+    /// Fields dropped as unknown, as `key.field`
+    unknown_fields: Vec<String>,
+}
+
+/// An item to be written: its key, its name within its section and the value stored
+type StoredEntry<'a> = (&'a str, &'a str, Cow<'a, str>);
+
+/// The items to be written in each named section
+type StoredSections<'a> = BTreeMap<&'a str, Vec<StoredEntry<'a>>>;
 
 /// The structure that holds the definition of configuration items
 pub struct Cfg {
-    schema: JSONSchema,
+    schema: &'static JSONSchema,
+    /// Schema read from a file, used in place of `schema`
+    custom_schema: Option<Arc<JSONSchema>>,
     cfg: Option<ConfigHash>,
+    /// Partitions of `cfg` by action, filled on demand and cleared whenever `cfg` changes
+    action_cache: Mutex<HashMap<ActionBehaviour, ConfigHash>>,
+    /// Compiled formats, filled on demand and cleared whenever the regex options change
+    matcher_cache: MatcherCache,
+    /// Where the current value of each key came from
+    provenance: HashMap<String, Provenance>,
+    /// The sources the configuration was loaded from, lowest precedence first
+    layers: Vec<Layer>,
+    /// Index in `layers` of the source that supplied the value of each key
+    value_layers: HashMap<String, usize>,
+    /// The INI value beneath each key last set by an environment or override layer, `None` if
+    /// there was none; it is written in place of the override until the value is changed
+    persisted: HashMap<String, Option<String>>,
+    /// How the INI files in `layers` were interpreted
+    load_options: LoadOptions,
+    /// When the current value of each key was last changed, where known
+    modified: HashMap<String, SystemTime>,
+    /// Changes made to current values since loading
+    audit: Vec<AuditEntry>,
+    /// Number of `audit` entries already written
+    audit_saved: AtomicUsize,
+    /// Told of the changes written by each save
+    notifiers: Vec<Box<dyn Notifier>>,
+    /// Cross-field rules checked by `policy_violations`
+    policy: Policy,
+    /// Cross-field rules given in the definition file, checked by `validate_all`
+    defn_rules: Vec<Rule>,
+    /// Keys renamed since earlier versions of the definition file
+    key_migrations: Vec<KeyMigration>,
+    /// Copies the INI file before it is replaced
+    backup_strategy: Box<dyn BackupStrategy>,
+    /// How formats are compiled
+    regex_options: RegexOptions,
+    /// Limits on values supplied through the API
+    input_limits: InputLimits,
+    /// How numeric values supplied through the API are written, if not canonically
+    number_locale: Option<NumberLocale>,
+    /// Timezone datetime values are shown in and entered without an offset
+    display_timezone: FixedOffset,
+    /// Files are read and written through this
+    fs: Arc<dyn Fs>,
+    /// The INI section of each key defined without a section prefix but read from a section
+    sections: HashMap<String, String>,
+    /// Locale prompts and tooltips are shown in
+    locale: Option<String>,
+    /// Version of the definition file loaded, if it gives one
+    defn_version: Option<u32>,
+    /// Changes to INI files written under earlier definition versions, by the version needing them
+    migrations: BTreeMap<u32, Vec<Migration>>,
+    /// Whether exports and patches hold secret values rather than [`REDACTED`]
+    export_secrets: bool,
+    /// Key the values of secret attributes are encrypted with in the INI file
+    #[cfg(feature = "encryption")]
+    secret_key: Option<SecretKey>,
+    /// Key definition files must be signed for
+    #[cfg(feature = "signing")]
+    defn_key: Option<DefnPublicKey>,
+    /// Directory definition files fetched over HTTP are cached in
+    #[cfg(feature = "http")]
+    defn_cache_dir: PathBuf,
 }
 
 impl Cfg {
     /// Creates a new instance of the structure
     ///
     /// The type definition of ConfigHash is used to create a compiled JSON schema that will be used
-    /// to validate the Attribute definitions being loaded to ConfigHash
+    /// to validate the Attribute definitions being loaded to ConfigHash.  The schema is compiled
+    /// once per process and shared by all instances; see [`Cfg::with_schema_file`] to use another.
     ///
-    /// Note: load_configuration must be called to fully initialise the structure
+    /// Note: load_configuration must be called to fully initialise the structure; see
+    /// [`Cfg::builder`] for a `Cfg` that is loaded when it is made
     pub fn new() -> Cfg {
-        let schema = Self::create_defn_schema();
         Cfg {
-            schema: schema,
+            schema: &CFG_SCHEMA,
+            custom_schema: None,
             cfg: None,
+            action_cache: Mutex::new(HashMap::new()),
+            matcher_cache: Mutex::new(HashMap::new()),
+            provenance: HashMap::new(),
+            layers: Vec::new(),
+            value_layers: HashMap::new(),
+            persisted: HashMap::new(),
+            load_options: LoadOptions::default(),
+            modified: HashMap::new(),
+            audit: Vec::new(),
+            audit_saved: AtomicUsize::new(0),
+            notifiers: Vec::new(),
+            policy: Policy::default(),
+            defn_rules: Vec::new(),
+            key_migrations: Vec::new(),
+            backup_strategy: backup::default_strategy(),
+            regex_options: RegexOptions::default(),
+            input_limits: InputLimits::default(),
+            number_locale: None,
+            display_timezone: datetime::utc(),
+            fs: Arc::new(StdFs),
+            sections: HashMap::new(),
+            locale: None,
+            defn_version: None,
+            migrations: BTreeMap::new(),
+            export_secrets: false,
+            #[cfg(feature = "encryption")]
+            secret_key: None,
+            #[cfg(feature = "signing")]
+            defn_key: None,
+            #[cfg(feature = "http")]
+            defn_cache_dir: fetch::default_cache_dir(),
         }
     }
 
     /// Load the attribute definitions from `def_path` and then update the current values from `cfg_path`
+    ///
+    /// The [`LoadReport`] lists INI entries that are not defined, items the INI file has no
+    /// entry for and values that do not satisfy their format.
     pub fn load_configuration<P: AsRef<Path>>(
         &mut self,
         cfg_path: P,
         def_path: P,
-    ) -> Result<(), CfgError> {
-        let defn = Self::read_defn_file(def_path, &self.schema)?;
-        self.update_cfg_from_defn(defn, cfg_path)?;
+    ) -> Result<LoadReport, CfgError> {
+        self.load_configuration_with(cfg_path, def_path, &LoadOptions::default())
+    }
+
+    /// As [`Cfg::load_configuration`] with the INI file interpreted according to `options`
+    pub fn load_configuration_with<P: AsRef<Path>>(
+        &mut self,
+        cfg_path: P,
+        def_path: P,
+        options: &LoadOptions,
+    ) -> Result<LoadReport, CfgError> {
+        let mut layers = vec![Layer::Ini(cfg_path.as_ref().to_path_buf())];
+        if let Some(prefix) = &options.env_prefix {
+            layers.push(Layer::Env(prefix.clone()));
+        }
+        let defn = DefnSource::File(def_path.as_ref().to_path_buf());
+        self.load_stack(cfg_path.as_ref(), &defn, layers, options)
+    }
+
+    /// Load the attribute definitions from `defn` and then the values from each of `layers` in
+    /// turn, along with the files it includes
+    fn load_stack(
+        &mut self,
+        cfg_path: &Path,
+        defn: &DefnSource,
+        layers: Vec<Layer>,
+        options: &LoadOptions,
+    ) -> Result<LoadReport, CfgError> {
+        let timed = Timed::new(info_span!(
+            "load_configuration",
+            cfg_file = %cfg_path.display(),
+            defn_file = %defn.path().display(),
+            keys = Empty,
+            duration_us = Empty,
+        ));
+        let Definition {
+            items: mut defn,
+            rules,
+            migrations,
+            version,
+            unknown_fields,
+        } = defn
+            .read(self, options.unknown_fields)
+            .context(Operation::Load, defn.path())?;
+        self.localize(&mut defn);
+        self.defn_rules = rules;
+        self.defn_version = version;
+        let mut report = self
+            .update_cfg_from_defn(defn, &migrations, &layers, options)
+            .context(Operation::Load, cfg_path)?;
+        self.layers = layers;
+        self.load_options = options.clone();
+        self.key_migrations = migrations;
+        timed.record("keys", self.cfg.as_ref().map_or(0, |c| c.len()));
+        if let Some(policy) = &options.policy {
+            self.load_policy(policy)?;
+        }
+
+        report.unknown_fields = unknown_fields;
+        report.invalid_values = self.format_violations();
+        report.empty_required = self
+            .empty_required_keys()
+            .into_iter()
+            .map(String::from)
+            .collect();
+        Ok(report)
+    }
+
+    /// Load the attribute definitions from `def_path` without reading an INI file
+    ///
+    /// The current values are those given in the definition file
+    pub fn load_definition<P: AsRef<Path>>(&mut self, def_path: P) -> Result<(), CfgError> {
+        let timed = Timed::new(info_span!(
+            "load_definition",
+            defn_file = %def_path.as_ref().display(),
+            keys = Empty,
+            duration_us = Empty,
+        ));
+        let Definition {
+            items: mut defn,
+            rules,
+            migrations,
+            version,
+            ..
+        } = DefnSource::File(def_path.as_ref().to_path_buf())
+            .read(self, UnknownFields::default())
+            .context(Operation::Load, &def_path)?;
+        self.localize(&mut defn);
+        timed.record("keys", defn.len());
+        self.defn_rules = rules;
+        self.key_migrations = migrations;
+        self.defn_version = version;
+        self.provenance = defn
+            .keys()
+            .map(|k| (k.clone(), Provenance::Definition))
+            .collect();
+        self.layers.clear();
+        self.value_layers.clear();
+        self.persisted.clear();
+        self.modified.clear();
+        self.sections.clear();
+        self.audit.clear();
+        *self.audit_saved.get_mut() = 0;
+        self.cfg = Some(defn);
+        self.invalidate_caches();
 
         Ok(())
     }
 
     /// Get the attribute definition for the configuration item defined by `key`
-    pub fn read_attribute(&self, key: String) -> Option<&Attribute> {
+    pub fn read_attribute<K: AsRef<str>>(&self, key: K) -> Option<&Attribute> {
         match &self.cfg {
             Some(c) => {
-                let attr = c.get(&key);
+                let attr = c.get(key.as_ref());
                 match attr {
-                    Some(a) => Some(a).clone(),
+                    Some(a) => Some(a),
                     _ => None,
                 }
             }
@@ -138,69 +739,167 @@ impl Cfg {
     }
 
     /// Store an updated attribute definition for the configuration item defined by `key`
-    pub fn write_attribute(&mut self, key: String, value: &Attribute) -> Result<(), CfgError> {
-        let cfg = self.cfg.clone();
-        match cfg {
-            Some(mut c) => {
-                c.insert(key.to_string(), value.clone());
-                self.cfg = Some(c);
-                return Ok(());
-            }
-            _ => {}
+    ///
+    /// A current value that does not satisfy the attribute's own `format`, `min`, `max` and
+    /// `choices` is refused, as by [`Cfg::validate_value`].  See [`Cfg::entry`] to change some
+    /// fields of the attribute in place.
+    pub fn write_attribute<K: AsRef<str>>(
+        &mut self,
+        key: K,
+        value: &Attribute,
+    ) -> Result<(), CfgError> {
+        let key = key.as_ref();
+        self.check_value_len(key, &value.current)?;
+        let current = value.sanitize(key, &value.current)?.into_owned();
+        self.check_constraints(key, value, &current)?;
+        let cfg = self.cfg.as_mut().ok_or(CfgError::Cfg())?;
+        let value = Attribute {
+            current: current.clone(),
+            ..value.clone()
+        };
+        let old = cfg.insert(key.to_string(), value).map(|p| p.current);
+        if old.as_ref() != Some(&current) {
+            self.record_change(key, old.unwrap_or_default(), &current, None);
         }
-        Err(CfgError::Cfg())
+        self.provenance.insert(key.to_string(), Provenance::User);
+        self.invalidate_caches();
+        Ok(())
+    }
+
+    /// Set the current value of the configuration item defined by `key`
+    ///
+    /// A value that does not satisfy the item's `format`, `min`, `max` and `choices` is refused.
+    pub fn set_value<K: AsRef<str>>(&mut self, key: K, value: &str) -> Result<(), CfgError> {
+        self.set_value_annotated(key, value, None)
     }
 
     /// Create a compiled JSON schema from Attribute definition via type alias ConfigHash
     fn create_defn_schema() -> JSONSchema {
         let attr_schema = schema_for!(ConfigHash);
         //println!("{}", serde_json::to_string_pretty(&attr_schema).unwrap());
-        let schema_string = serde_json::to_string(&attr_schema).unwrap();
-        let json_value: Value =
-            serde_json::from_slice(schema_string.as_bytes()).expect("convert schema to json");
+        let json_value = serde_json::to_value(&attr_schema).expect("convert schema to json");
         JSONSchema::options()
             .compile(&json_value)
             .expect("A valid schema")
     }
 
-    /// Read the contents of a file as JSON and, if valid against the schema, return an instance
-    /// of 'ConfigHash'
-    fn read_defn_file<P: AsRef<Path>>(
-        path: P,
+    /// Read `text`, the contents of the definition file `path`, as JSON, TOML or YAML and, if
+    /// valid against the schema, return an instance of 'ConfigHash'
+    fn read_defn_text(
+        path: &Path,
+        text: &str,
         schema: &JSONSchema,
-    ) -> Result<ConfigHash, CfgError> {
-        // Open the file in read-only mode with buffer
-        let file = File::open(path.as_ref())?;
-        let reader = BufReader::new(file);
-
-        let json_value: Value = serde_json::from_reader(reader)?;
+        unknown_fields: UnknownFields,
+    ) -> Result<Definition, CfgError> {
+        let mut json_value = defn_format::parse_defn(path, text)?;
+        signature::take_signature(&mut json_value);
+        let rules = policy::take_defn_rules(&mut json_value)?;
+        let migrations = migration::take_defn_migrations(&mut json_value)?;
+        let version = migration::take_defn_version(&mut json_value)?;
+        translation::take_translations(&mut json_value)?;
+        let unknown_fields = unknown_fields::take_unknown_fields(&mut json_value, unknown_fields)?;
         if schema.is_valid(&json_value) {
             // Read the JSON contents of the file as an instance of 'ConfigHash'.
-            let cfg = serde_json::from_value(json_value)?;
-            return Ok(cfg);
+            let items: ConfigHash = serde_json::from_value(json_value)?;
+            if let Some(k) = policy::undefined_key(&rules, &items)
+                .or_else(|| migration::undefined_key(&migrations, &items))
+            {
+                return Err(CfgError::UnknownKey(k.to_string()));
+            }
+            return Ok(Definition {
+                items,
+                rules,
+                migrations,
+                version,
+                unknown_fields,
+            });
         }
-        if let Some(f) = path.as_ref().to_str() {
+        if let Some(f) = path.to_str() {
             return Err(CfgError::Schema(f.to_string()));
         }
         Err(CfgError::Schema("(non-utf8 path".to_string()))
     }
 
     /// Filters the attributes by action
+    ///
+    /// Equivalent to selecting with `Query::new().action(action)`; see [`Cfg::select`] to combine
+    /// conditions on other fields.  The partition for each action is cached until the
+    /// configuration is next changed, so repeated calls only pay for the clone; see
+    /// [`Cfg::attributes_with_action_iter`] to avoid that too.
     pub fn attributes_with_action(&self, action: ActionBehaviour) -> ConfigHash {
-        let mut attr2 = ConfigHash::new();
-        if let Some(cfg) = self.cfg.clone() {
-            attr2.extend(
-                cfg.iter()
-                    .filter(|(_k, v)| v.action == action)
-                    .map(|(k, v)| (k.clone(), v.clone())),
-            );
+        let mut cache = self
+            .action_cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        cache
+            .entry(action)
+            .or_insert_with(|| self.select_query(&Query::new().action(action)))
+            .clone()
+    }
+
+    /// The attributes with `action`, borrowed rather than cloned, with their keys
+    ///
+    /// For rendering many items without copying them; yields nothing until a configuration is
+    /// loaded.  The order is the iteration order of ConfigHash.
+    pub fn attributes_with_action_iter(
+        &self,
+        action: ActionBehaviour,
+    ) -> impl Iterator<Item = (&str, &Attribute)> + '_ {
+        self.cfg
+            .iter()
+            .flat_map(|c| c.iter())
+            .filter(move |(_, a)| a.action == action)
+            .map(|(k, a)| (k.as_str(), a))
+    }
+
+    /// Discard anything derived from the current ConfigHash
+    fn invalidate_caches(&mut self) {
+        self.action_cache
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clear();
+    }
+
+    /// Check the current value of every configuration item against its `format`, `min` and
+    /// `max`
+    ///
+    /// The violations are returned in key order
+    pub fn format_violations(&self) -> Vec<FormatViolation> {
+        let timed = Timed::new(debug_span!(
+            "format_violations",
+            keys = self.cfg.as_ref().map_or(0, |c| c.len()),
+            violations = Empty,
+            duration_us = Empty,
+        ));
+        let mut violations = Vec::new();
+        if let Some(cfg) = &self.cfg {
+            for (k, v) in cfg {
+                let error = match self.format_matches(v, &v.current) {
+                    Ok(true) => match v.constraint_error(&v.current) {
+                        Some(e) => Some(e),
+                        None => continue,
+                    },
+                    Ok(false) => None,
+                    Err(CfgError::Format(e)) => Some(e),
+                    Err(e) => Some(e.to_string()),
+                };
+                violations.push(FormatViolation {
+                    key: k.clone(),
+                    value: v.shown(&v.current).to_string(),
+                    format: v.format.to_string(),
+                    error,
+                });
+            }
         }
-        attr2
+        violations.sort_by(|a, b| a.key.cmp(&b.key));
+        timed.record("violations", violations.len());
+        violations
     }
 
     /// Output the keys and current values of items to `path`
     ///
-    /// If makeBackup is TRUE then a timestamped backup of the existing INI file is taken
+    /// If makeBackup is TRUE then the existing INI file is backed up first, as decided by the
+    /// strategy set with [`Cfg::set_backup_strategy`]
     ///
     /// Note: The format of the output file is INI with just a general section
     pub fn write_cfg_file<P: AsRef<Path>>(
@@ -208,51 +907,452 @@ impl Cfg {
         path: P,
         make_backup: Option<bool>,
     ) -> Result<(), CfgError> {
+        let options = WriteOptions {
+            make_backup: make_backup.unwrap_or(false),
+            ..WriteOptions::default()
+        };
+        self.write_cfg_file_with(path, &options)
+    }
+
+    /// Output the keys and current values of items to `path` as directed by `options`
+    ///
+    /// The items are streamed to the file through a buffered [`IniWriter`] as they are visited.
+    /// A key containing dots is written to the section named by everything before the last dot,
+    /// so `network.wifi.ssid` becomes `ssid` in section `[network.wifi]`.  An attribute with a
+    /// `section` is written to that section under its key, and failing that a key read from a
+    /// section under its bare name is written back to that section.
+    pub fn write_cfg_file_with<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: &WriteOptions,
+    ) -> Result<(), CfgError> {
+        self.write_ini(path.as_ref(), options)
+            .context(Operation::Write, path)
+    }
+
+    /// Output the keys and current values of items to `out` as INI text
+    ///
+    /// Unlike [`Cfg::write_cfg_file`] the changes are not marked as saved and no sidecar files,
+    /// audit log or notifications are written.
+    pub fn write_cfg<W: Write>(&self, out: &mut W) -> Result<(), CfgError> {
+        self.write_cfg_with(out, &WriteOptions::default())
+    }
+
+    /// Output the keys and current values of items to `out` as directed by `options`
+    ///
+    /// The options that concern the file written, `make_backup`, `backup_dir`, `audit_log`,
+    /// `preserve_layout` and `verify`, are ignored.
+    pub fn write_cfg_with<W: Write>(
+        &self,
+        out: &mut W,
+        options: &WriteOptions,
+    ) -> Result<(), CfgError> {
+        let cfg = self.cfg.as_ref().ok_or(CfgError::Cfg())?;
+        if let Some(key) = self.empty_required_keys().first() {
+            let message = "a value is required".to_string();
+            return Err(CfgError::InvalidValue(message).at_key(Operation::Write, key));
+        }
+        let (general, sections) = self.stored_entries(cfg)?;
+        self.render_ini(out, general, sections, options)?;
+        Ok(())
+    }
+
+    /// The items of `cfg` to be written, general entries first and then those of each section
+    fn stored_entries<'a>(
+        &'a self,
+        cfg: &'a ConfigHash,
+    ) -> Result<(Vec<StoredEntry<'a>>, StoredSections<'a>), CfgError> {
+        let mut general: Vec<StoredEntry> = Vec::new();
+        let mut sections: StoredSections = BTreeMap::new();
+        for (k, v) in cfg {
+            let value = self
+                .stored_text(v, self.persisted_value(k, v))
+                .map_err(|e| e.at_key(Operation::Write, k))?;
+            match self.placement(k, v) {
+                (Some(section), name) => {
+                    sections.entry(section).or_default().push((k, name, value))
+                }
+                (None, name) => general.push((k, name, value)),
+            }
+        }
+        Ok((general, sections))
+    }
+
+    /// Write `general` and `sections` to `out` as INI text
+    fn render_ini<W: Write>(
+        &self,
+        out: W,
+        general: Vec<StoredEntry>,
+        sections: StoredSections,
+        options: &WriteOptions,
+    ) -> Result<W, CfgError> {
+        let mut writer = IniWriter::new(out);
+        writer.set_multiline(options.multiline_values);
+        writer.section(options.general_section.as_deref())?;
+        for (k, name, v) in general {
+            self.write_provenance(&mut writer, k, options)?;
+            writer.entry(name, &v)?;
+        }
+        for (section, entries) in sections {
+            writer.section(Some(section))?;
+            for (k, name, v) in entries {
+                self.write_provenance(&mut writer, k, options)?;
+                writer.entry(name, &v)?;
+            }
+        }
+        Ok(writer.finish()?)
+    }
+
+    fn write_ini(&self, path: &Path, options: &WriteOptions) -> Result<(), CfgError> {
         let c = &self.cfg;
         if let Some(cfg) = c {
-            let mut ini = Ini::new();
-            for (k, v) in cfg {
-                ini.set_to(None::<String>, k.clone(), v.current.clone());
+            if let Some(key) = self.empty_required_keys().first() {
+                let message = "a value is required".to_string();
+                return Err(CfgError::InvalidValue(message).at_key(Operation::Write, key));
             }
-            let mut do_backup: bool = false;
-            if let Some(b) = make_backup {
-                do_backup = b;
-            }
-            if do_backup {
-                match backup(&path) {
-                    Ok(backup_path) => println!("Backup created: {:?}", backup_path),
-                    Err(err) => eprintln!("Failed to create backup: {:?}", err),
+            let _timed = Timed::new(info_span!(
+                "write_cfg_file",
+                file = %path.display(),
+                keys = cfg.len(),
+                changes = self.unsaved_changes().len(),
+                duration_us = Empty,
+            ));
+            let _lock = match options.lock_timeout {
+                Some(timeout) => Some(self.fs.lock(&lock::lock_path(path), timeout)?),
+                None => None,
+            };
+            let backup_path = match options.make_backup {
+                true => self.make_backup(path, options.backup_dir.as_deref()),
+                false => None,
+            };
+            let existing = match self.fs.read_to_string(path) {
+                Ok(text) if options.preserve_layout => Some(text),
+                Err(e) if options.preserve_layout && e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(e.into())
+                }
+                _ => None,
+            };
+            // General entries first, then each section in order
+            let (general, sections) = self.stored_entries(cfg)?;
+            let contents = match existing {
+                Some(text) => {
+                    let mut entries: Vec<layout::Entry> = general
+                        .iter()
+                        .map(|(_, name, v)| (None, *name, v.as_ref()))
+                        .collect();
+                    for (section, es) in &sections {
+                        entries.extend(
+                            es.iter()
+                                .map(|(_, name, v)| (Some(*section), *name, v.as_ref())),
+                        );
+                    }
+                    layout::merge(&text, &entries, options).into_bytes()
                 }
+                None => self.render_ini(Vec::new(), general, sections, options)?,
+            };
+            let access = FileAccess {
+                private: options
+                    .private_file
+                    .unwrap_or_else(|| cfg.values().any(|a| a.secret)),
+                owner: options.owner,
+            };
+            self.fs.write_atomic(path, &contents, &access)?;
+            if options.verify {
+                verify::verify_written(&*self.fs, path, &contents)?;
             }
-            ini.write_to_file(path)?;
+            modified::write_sidecar(&*self.fs, path, &self.modified)?;
+            if let Some(version) = self.defn_version {
+                migration::write_version(&*self.fs, path, version)?;
+            }
+            if options.audit_log {
+                self.save_audit(path, backup_path.as_deref())?;
+            }
+            self.notify(&ChangeSummary {
+                file: path,
+                backup: backup_path.as_deref(),
+                changes: self.unsaved_changes(),
+            });
+            self.mark_saved();
         }
         Ok(())
     }
 
-    /// Read the INI format file 'path' and create a ConfigHash from the matching entries in the
-    /// definition file and update the 'current' field with value from 'path'.
-    fn update_cfg_from_defn<P: AsRef<Path>>(
+    /// Back up the file at `path` as decided by the backup strategy, in `dir` if given, returning
+    /// the copy made
+    ///
+    /// A failed backup is logged as a `tracing` warning but does not stop the file being written.
+    fn make_backup(&self, path: &Path, dir: Option<&Path>) -> Option<PathBuf> {
+        let made = match dir {
+            Some(dir) => self.backup_strategy.backup_in(&*self.fs, path, dir),
+            None => self.backup_strategy.backup(&*self.fs, path),
+        };
+        match made {
+            Ok(Some(p)) => {
+                info!(file = %path.display(), backup = %p.display(), "backup created");
+                Some(p)
+            }
+            Ok(None) => None,
+            Err(err) => {
+                warn!(file = %path.display(), error = %err, "failed to create backup");
+                None
+            }
+        }
+    }
+
+    /// The section `key` is written to, `None` for the general section, and its name there
+    fn placement<'a>(&'a self, key: &'a str, attr: &'a Attribute) -> (Option<&'a str>, &'a str) {
+        let declared = attr.section.as_deref();
+        match declared.or(self.sections.get(key).map(String::as_str)) {
+            Some(section) => (Some(section), key),
+            None => match key.rsplit_once('.') {
+                Some((section, name)) => (Some(section), name),
+                None => (None, key),
+            },
+        }
+    }
+
+    /// Write a comment giving the provenance of `key` if requested in `options`
+    fn write_provenance<W: Write>(
+        &self,
+        writer: &mut IniWriter<W>,
+        key: &str,
+        options: &WriteOptions,
+    ) -> std::io::Result<()> {
+        match self.provenance.get(key) {
+            Some(p) if options.annotate_provenance => writer.comment(&format!("from {}", p)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Read the INI format files and other sources in 'layers' and create a ConfigHash from the
+    /// matching entries in the definition file, updating the 'current' field with the value from
+    /// the highest layer that gives one.
+    ///
+    /// Every item in the definition is kept; those with no entry in any layer take their default
+    /// value and are listed by [`Cfg::defaulted_keys`].  Values that do not satisfy their format
+    /// are treated as `options.invalid_values` directs.  The returned report lists the entries
+    /// that are not defined, the items with no entry and any values replaced.
+    ///
+    /// Entries in a named section are matched against definition keys prefixed with the section
+    /// name and a dot, so `ssid` in `[network.wifi]` is the attribute `network.wifi.ssid`.  If
+    /// there is no such key the bare name is tried, so `ap_ssid` in `[apmode]` is the attribute
+    /// `ap_ssid`, and the section is remembered so the entry is written back to it.
+    /// Entries in the section named by `options.general_section` are read as general entries.
+    fn update_cfg_from_defn(
         &mut self,
         defn: ConfigHash,
-        path: P,
-    ) -> Result<(), CfgError> {
-        // Read existing configuration file
-        let ini = Ini::load_from_file(path)?;
-        // Create new ConfigHash to hold configuration
+        migrations: &[KeyMigration],
+        layers: &[Layer],
+        options: &LoadOptions,
+    ) -> Result<LoadReport, CfgError> {
+        let mut values = HashMap::new();
+        let mut report = LoadReport::default();
+        let mut provenance = HashMap::new();
+        let mut value_layers = HashMap::new();
+        let mut persisted = HashMap::new();
+        let mut sections = HashMap::new();
+        let aliases = alias::aliases(&defn);
+        // Keys whose value so far was read under an alias or a former key
+        let mut aliased = HashSet::new();
+        for (index, layer) in layers.iter().enumerate() {
+            let (path, mut documents) = match layer {
+                Layer::Ini(path) => {
+                    // Read the configuration file and any files it includes
+                    let documents = if options.allow_missing_file && !self.fs.exists(path) {
+                        Vec::new()
+                    } else {
+                        ini_reader::read_documents(&*self.fs, path, options)
+                            .context(Operation::Load, path)?
+                    };
+                    (path.as_path(), documents)
+                }
+                Layer::IniStr(text) => {
+                    let name = Path::new(layer::INI_STR_NAME);
+                    (name, ini_reader::read_str_documents(name, text, options)?)
+                }
+                Layer::Env(prefix) => {
+                    for k in defn.keys() {
+                        let var = format!("{}{}", prefix, export::env_var_name(k));
+                        if let Ok(value) = env::var(&var) {
+                            persisted
+                                .entry(k.clone())
+                                .or_insert_with(|| values.get(k).cloned());
+                            provenance.insert(k.clone(), Provenance::Environment { var });
+                            value_layers.insert(k.clone(), index);
+                            aliased.remove(k);
+                            values.insert(k.clone(), value);
+                            report.env_overrides.push(k.clone());
+                        }
+                    }
+                    continue;
+                }
+                Layer::Overrides(overrides) => {
+                    for (k, value) in overrides {
+                        if !defn.contains_key(k) {
+                            return Err(CfgError::UnknownKey(k.clone()));
+                        }
+                        persisted
+                            .entry(k.clone())
+                            .or_insert_with(|| values.get(k).cloned());
+                        provenance.insert(k.clone(), Provenance::Override);
+                        value_layers.insert(k.clone(), index);
+                        aliased.remove(k);
+                        values.insert(k.clone(), value.clone());
+                    }
+                    continue;
+                }
+            };
+            report.migrated_versions.extend(
+                self.migrate_documents(&mut documents, path)
+                    .context(Operation::Load, path)?,
+            );
+            for document in documents {
+                for (section, properties) in document.ini.iter() {
+                    for (name, v) in properties.iter() {
+                        let mut k = ini_reader::entry_key(section, name, options);
+                        if let Some(s) =
+                            section.filter(|_| !defn.contains_key(&k) && defn.contains_key(name))
+                        {
+                            sections.insert(name.to_string(), s.to_string());
+                            k = name.to_string();
+                        }
+                        let chain = match aliases.get(&k) {
+                            Some(_) => Vec::new(),
+                            None if defn.contains_key(&k) => Vec::new(),
+                            None => migration::chain(migrations, &k),
+                        };
+                        let renamed = aliases.get(&k).or(chain.last().map(|m| &m.to));
+                        if let Some(key) = renamed.cloned() {
+                            let found = AliasedKey {
+                                alias: k,
+                                key: key.clone(),
+                            };
+                            if chain.is_empty() {
+                                report.aliased_keys.push(found);
+                            } else {
+                                report.migrated_keys.push(found);
+                            }
+                            if values.contains_key(&key) && !aliased.contains(&key) {
+                                continue;
+                            }
+                            aliased.insert(key.clone());
+                            k = key;
+                        } else {
+                            aliased.remove(&k);
+                        }
+                        if defn.contains_key(&k) {
+                            let value = if options.expand_env {
+                                ini_reader::expand_env(v)
+                                    .map_err(|e| CfgError::Env(e).at_key(Operation::Load, &k))?
+                            } else {
+                                v.to_string()
+                            };
+                            let value = chain.iter().fold(value, |v, m| m.apply(v));
+                            let origin = Provenance::Ini {
+                                file: document.file.clone(),
+                                section: section.map(str::to_string),
+                            };
+                            provenance.insert(k.clone(), origin);
+                            value_layers.insert(k.clone(), index);
+                            persisted.remove(&k);
+                            values.insert(k, value);
+                        } else {
+                            report.unknown_keys.push(k);
+                        }
+                    }
+                }
+            }
+        }
+        report.env_overrides.sort_unstable();
+        // Create new ConfigHash to hold configuration, in definition order; items without an
+        // entry in the INI file take their default
         let mut cfg = ConfigHash::new();
-        let properties = ini.general_section();
-        for (k, v) in properties.iter() {
-            let attr = defn.get(k);
-            if let Some(aref) = attr {
-                let mut a = aref.clone();
-                a.current = v.to_string();
-                cfg.insert(k.to_string(), a);
-            } else {
-                println!("Key '{}' not defined in configuration", k);
+        for (k, mut a) in defn {
+            #[cfg(feature = "encryption")]
+            if let Some(Some(below)) = persisted.get_mut(&k) {
+                *below = self
+                    .decrypt_value(&a, std::mem::take(below))
+                    .map_err(|e| e.at_key(Operation::Load, &k))?;
+            }
+            match values.remove(&k) {
+                Some(value) => {
+                    #[cfg(feature = "encryption")]
+                    let value = self
+                        .decrypt_value(&a, value)
+                        .map_err(|e| e.at_key(Operation::Load, &k))?;
+                    let valid = options.invalid_values == InvalidValues::Warn
+                        || matches!(self.format_matches(&a, &value), Ok(true));
+                    let unmet = match options.invalid_values {
+                        InvalidValues::Warn => None,
+                        _ if valid => a.constraint_error(&value),
+                        _ => None,
+                    };
+                    a.current = value;
+                    if !valid || unmet.is_some() {
+                        let violation = FormatViolation {
+                            key: k.clone(),
+                            value: a.shown(&a.current).to_string(),
+                            format: a.format.to_string(),
+                            error: unmet,
+                        };
+                        if options.invalid_values == InvalidValues::Reject {
+                            let message = match &violation.error {
+                                Some(e) => e.clone(),
+                                None => format!(
+                                    "'{}' does not match '{}'",
+                                    violation.value, violation.format
+                                ),
+                            };
+                            return Err(CfgError::InvalidValue(message).at_key(Operation::Load, &k));
+                        }
+                        report.replaced_values.push(violation);
+                        a.current = a.default.clone();
+                        provenance.insert(k.clone(), Provenance::Default);
+                    }
+                }
+                None => {
+                    a.current = a.default.clone();
+                    provenance.insert(k.clone(), Provenance::Default);
+                    report.missing_keys.push(k.clone());
+                }
             }
+            cfg.insert(k, a);
         }
+        report.missing_keys.sort_unstable();
+        report.replaced_values.sort_by(|a, b| a.key.cmp(&b.key));
+        self.provenance = provenance;
+        self.value_layers = value_layers;
+        self.persisted = persisted;
+        self.sections = sections;
+        self.modified = match layer::writable_path(layers) {
+            Some(path) => modified::read_sidecar(&*self.fs, path)?,
+            None => HashMap::new(),
+        };
         self.cfg = Some(cfg);
-        Ok(())
+        self.invalidate_caches();
+        Ok(report)
+    }
+}
+
+impl Default for Cfg {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: AsRef<str>> std::ops::Index<K> for Cfg {
+    type Output = Attribute;
+
+    /// The attribute definition for the configuration item defined by `key`
+    ///
+    /// # Panics
+    ///
+    /// Panics if no configuration is loaded or `key` is not defined; use
+    /// [`Cfg::read_attribute`] where either may happen.
+    fn index(&self, key: K) -> &Attribute {
+        let key = key.as_ref();
+        self.read_attribute(key)
+            .unwrap_or_else(|| panic!("no configuration item '{}'", key))
     }
 }
 
@@ -273,24 +1373,30 @@ pub struct Package {
 /// Type alias based on a HashMap
 pub type PackageHash = HashMap<String, Package>;
 
+/// Compiled schema for package definition files, shared by every Pkg instance
+static PKG_SCHEMA: Lazy<JSONSchema> = Lazy::new(Pkg::create_defn_schema);
+
 /// The structure that holds the definition of package items
 pub struct Pkg {
-    schema: JSONSchema,
+    schema: &'static JSONSchema,
     pub packages: Option<PackageHash>,
+    /// Files are read through this
+    fs: Arc<dyn Fs>,
 }
 
 impl Pkg {
     /// Creates a new instance of the structure
     ///
     /// The type definition of PackageHash is used to create a compiled JSON schema that will be used
-    /// to validate the Package definitions being loaded to PackageHash
+    /// to validate the Package definitions being loaded to PackageHash.  The schema is compiled
+    /// once per process and shared by all instances.
     ///
     /// Note: load_packages must be called to fully initialise the structure
     pub fn new() -> Pkg {
-        let schema = Self::create_defn_schema();
         Pkg {
-            schema: schema,
+            schema: &PKG_SCHEMA,
             packages: None,
+            fs: Arc::new(StdFs),
         }
     }
 
@@ -298,9 +1404,7 @@ impl Pkg {
     fn create_defn_schema() -> JSONSchema {
         let src_schema = schema_for!(PackageHash);
         //println!("{}", serde_json::to_string_pretty(&src_schema).unwrap());
-        let schema_string = serde_json::to_string(&src_schema).unwrap();
-        let json_value: Value =
-            serde_json::from_slice(schema_string.as_bytes()).expect("convert schema to json");
+        let json_value = serde_json::to_value(&src_schema).expect("convert schema to json");
         JSONSchema::options()
             .compile(&json_value)
             .expect("A valid schema")
@@ -308,7 +1412,15 @@ impl Pkg {
 
     /// Load the package definitions from `def_path`
     pub fn load_packages<P: AsRef<Path>>(&mut self, def_path: P) -> Result<(), CfgError> {
-        let pkg = Self::read_defn_file(def_path, &self.schema)?;
+        let timed = Timed::new(info_span!(
+            "load_packages",
+            file = %def_path.as_ref().display(),
+            packages = Empty,
+            duration_us = Empty,
+        ));
+        let pkg = Self::read_defn_file(&*self.fs, def_path.as_ref(), self.schema)
+            .context(Operation::Load, &def_path)?;
+        timed.record("packages", pkg.len());
 
         self.packages = Some(pkg);
         Ok(())
@@ -317,12 +1429,12 @@ impl Pkg {
     /// Read the contents of a file as JSON and, if valid against the schema, return an instance
     /// of 'PackageHash'
     fn read_defn_file<P: AsRef<Path>>(
+        fs: &dyn Fs,
         path: P,
         schema: &JSONSchema,
     ) -> Result<PackageHash, CfgError> {
         // Open the file in read-only mode with buffer
-        let file = File::open(path.as_ref())?;
-        let reader = BufReader::new(file);
+        let reader = fs.open(path.as_ref())?;
 
         let json_value: Value = serde_json::from_reader(reader)?;
         if schema.is_valid(&json_value) {
@@ -337,11 +1449,18 @@ impl Pkg {
     }
 }
 
+impl Default for Pkg {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::{load_defn, setup_file, teardown_file, CANID_DISPLAY_NODE_DEFN};
     use dotenv::dotenv;
-    use std::io::Write;
+    use ini::Ini;
     use std::{env, fs};
 
     const CFG_DATA: &str = r#"
@@ -422,15 +1541,6 @@ mod tests {
                   }
         }"#;
 
-    fn setup_file<P: AsRef<Path>>(test_file: P, data: &str) {
-        let mut f = File::create(test_file).expect("file creation failed");
-        f.write_all(data.as_bytes()).expect("file write failed");
-    }
-
-    fn teardown_file<P: AsRef<Path>>(test_file: P) {
-        fs::remove_file(test_file).expect("file deletion failed");
-    }
-
     #[test]
     fn single_attribute() {
         // Some JSON input data as a &str.  Maybe this comes from a file.
@@ -451,23 +1561,48 @@ mod tests {
         assert_eq!(a.action, ActionBehaviour::Display);
     }
 
+    #[test]
+    fn shared_attribute_text() {
+        let cfg = load_defn(
+            "scratch/shared_attribute_text.json",
+            CANID_DISPLAY_NODE_DEFN,
+        );
+        let canid = cfg.read_attribute("canid").unwrap();
+        // Clones share the descriptive text rather than copying it
+        let displayed = cfg.attributes_with_action(ActionBehaviour::Display);
+        let copy = &displayed["canid"];
+        assert!(Arc::ptr_eq(&canid.prompt, &copy.prompt));
+        assert!(Arc::ptr_eq(&canid.tooltip, &copy.tooltip));
+        assert!(Arc::ptr_eq(&canid.format, &copy.format));
+
+        let json = serde_json::to_value(canid).expect("Failed to serialize");
+        assert_eq!(json["prompt"], "CAN Id");
+        let reread: Attribute =
+            serde_json::from_value(json.clone()).expect("Failed to deserialize");
+        assert_eq!(&*reread.tooltip, &*canid.tooltip);
+        assert_eq!(&*reread.format, "[0-9]{1,4}");
+        assert_eq!(serde_json::to_value(&reread).unwrap(), json);
+    }
+
     #[test]
     /// Test creating a ConfigHash
     fn single_good_vector() {
         let defn_file = "scratch/single_good_vector.json";
-        setup_file(&defn_file, DEFN_DATA);
-        let schema = Cfg::create_defn_schema();
-        Cfg::read_defn_file(&defn_file, &schema).expect("parameter definition failed to load");
-        teardown_file(&defn_file);
+        setup_file(defn_file, DEFN_DATA);
+        DefnSource::File(defn_file.into())
+            .read(&Cfg::new(), UnknownFields::Ignore)
+            .expect("parameter definition failed to load");
+        teardown_file(defn_file);
     }
 
     #[test]
     #[should_panic]
     fn single_malformed_vector() {
         let defn_file = "scratch/single_malformed_vector.json";
-        setup_file(&defn_file, BAD_DATA);
-        let schema = Cfg::create_defn_schema();
-        Cfg::read_defn_file(&defn_file, &schema).expect("parameter definition failed to load");
+        setup_file(defn_file, BAD_DATA);
+        DefnSource::File(defn_file.into())
+            .read(&Cfg::new(), UnknownFields::Ignore)
+            .expect("parameter definition failed to load");
     }
 
     #[test]
@@ -475,12 +1610,12 @@ mod tests {
     fn update_with_cfg_test() {
         let cfg_file = "scratch/update_test.cfg";
         let defn_file = "scratch/update_test.json";
-        setup_file(&defn_file, DEFN_DATA);
-        setup_file(&cfg_file, CFG_DATA);
+        setup_file(defn_file, DEFN_DATA);
+        setup_file(cfg_file, CFG_DATA);
         let mut cfg = Cfg::new();
         cfg.load_configuration(&cfg_file, &defn_file)
             .expect("parameter definition failed to load");
-        let ini = Ini::load_from_file(&cfg_file).expect("failed to load .cfg file");
+        let ini = Ini::load_from_file(cfg_file).expect("failed to load .cfg file");
         if let Some(config) = cfg.cfg.clone() {
             let properties = ini.section(None::<String>);
             if let Some(p) = properties {
@@ -489,15 +1624,15 @@ mod tests {
                     if let Some(a) = attr {
                         assert_eq!(a.current, v.to_string(), "attribute {} not updated", k);
                     } else {
-                        assert!(false, "attribute {} missing", k);
+                        panic!("attribute {} missing", k);
                     }
                 }
             }
         } else {
-            assert!(false, "Cfg.cfg is 'None'");
+            panic!("Cfg.cfg is 'None'");
         }
-        teardown_file(&cfg_file);
-        teardown_file(&defn_file);
+        teardown_file(cfg_file);
+        teardown_file(defn_file);
     }
 
     #[test]
@@ -505,8 +1640,8 @@ mod tests {
     fn attributes_with_action_test() {
         let cfg_file = "scratch/attributes_test.cfg";
         let defn_file = "scratch/attributes_test.json";
-        setup_file(&defn_file, DEFN_DATA);
-        setup_file(&cfg_file, CFG_DATA);
+        setup_file(defn_file, DEFN_DATA);
+        setup_file(cfg_file, CFG_DATA);
         let mut cfg = Cfg::new();
         cfg.load_configuration(&cfg_file, &defn_file)
             .expect("config failed to load");
@@ -523,10 +1658,657 @@ mod tests {
             assert_eq!(hidden.len(), 1);
             assert!(hidden.contains_key("node_mode"));
         } else {
-            assert!(false)
+            panic!("Cfg.cfg is 'None'")
+        }
+        teardown_file(cfg_file);
+        teardown_file(defn_file);
+    }
+
+    #[test]
+    /// Test that cached action partitions are refreshed after a write
+    fn attributes_with_action_cache_test() {
+        let cfg_file = "scratch/action_cache_test.cfg";
+        let defn_file = "scratch/action_cache_test.json";
+        setup_file(defn_file, DEFN_DATA);
+        setup_file(cfg_file, CFG_DATA);
+        let mut cfg = Cfg::new();
+        cfg.load_configuration(&cfg_file, &defn_file)
+            .expect("config failed to load");
+        assert_eq!(cfg.attributes_with_action(ActionBehaviour::Edit).len(), 1);
+        let mut node_mode = cfg
+            .read_attribute("node_mode")
+            .expect("node_mode defined")
+            .clone();
+        node_mode.action = ActionBehaviour::Edit;
+        cfg.write_attribute("node_mode", &node_mode)
+            .expect("attribute write failed");
+        assert_eq!(cfg.attributes_with_action(ActionBehaviour::Edit).len(), 2);
+        assert!(cfg.attributes_with_action(ActionBehaviour::Hide).is_empty());
+        teardown_file(cfg_file);
+        teardown_file(defn_file);
+    }
+
+    #[test]
+    /// Test borrowing the attributes with an action
+    fn attributes_with_action_iter_test() {
+        let cfg_file = "scratch/action_iter_test.cfg";
+        let defn_file = "scratch/action_iter_test.json";
+        setup_file(defn_file, DEFN_DATA);
+        setup_file(cfg_file, CFG_DATA);
+        let mut cfg = Cfg::new();
+        assert_eq!(
+            cfg.attributes_with_action_iter(ActionBehaviour::Edit)
+                .count(),
+            0
+        );
+        cfg.load_configuration(&cfg_file, &defn_file)
+            .expect("config failed to load");
+        for action in [
+            ActionBehaviour::Edit,
+            ActionBehaviour::Display,
+            ActionBehaviour::Hide,
+        ] {
+            let cloned = cfg.attributes_with_action(action);
+            let mut keys: Vec<&str> = cfg
+                .attributes_with_action_iter(action)
+                .map(|(k, _)| k)
+                .collect();
+            keys.sort_unstable();
+            let mut expected: Vec<&str> = cloned.keys().map(String::as_str).collect();
+            expected.sort_unstable();
+            assert_eq!(keys, expected);
         }
-        teardown_file(&cfg_file);
-        teardown_file(&defn_file);
+        let (key, attr) = cfg
+            .attributes_with_action_iter(ActionBehaviour::Edit)
+            .next()
+            .expect("an editable item");
+        assert!(std::ptr::eq(attr, cfg.read_attribute(key).unwrap()));
+        teardown_file(cfg_file);
+        teardown_file(defn_file);
+    }
+
+    #[test]
+    /// Test the iteration order of the map type selected by feature
+    fn map_order_test() {
+        let defn = r#"
+            {
+                "node_number" : {
+                    "prompt": "Node Number", "tooltip": "", "current": "", "default": "",
+                    "format": ".*", "action": "Edit"
+                },
+                "canid" : {
+                    "prompt": "CAN Id", "tooltip": "", "current": "", "default": "",
+                    "format": ".*", "action": "Edit"
+                },
+                "start_event_id" : {
+                    "prompt": "Start Event Id", "tooltip": "", "current": "", "default": "",
+                    "format": ".*", "action": "Edit"
+                }
+            }"#;
+        let mut cfg = Cfg::new();
+        cfg.load_configuration_from_str("", defn)
+            .expect("config failed to load");
+        let mut keys: Vec<&str> = cfg.iter().map(|(k, _)| k).collect();
+        if cfg!(feature = "indexmap") {
+            assert_eq!(keys, ["node_number", "canid", "start_event_id"]);
+        } else if cfg!(feature = "btreemap") {
+            assert_eq!(keys, ["canid", "node_number", "start_event_id"]);
+        } else {
+            keys.sort_unstable();
+            assert_eq!(keys, ["canid", "node_number", "start_event_id"]);
+        }
+    }
+
+    #[test]
+    /// Test that writing one attribute leaves the others, and their order, as they were
+    fn write_attribute_in_place_test() {
+        let mut cfg = Cfg::new();
+        cfg.load_configuration_from_str(CFG_DATA, DEFN_DATA)
+            .expect("config failed to load");
+        let snapshot = |cfg: &Cfg| -> Vec<(String, Value)> {
+            cfg.iter()
+                .map(|(k, v)| (k.to_string(), serde_json::to_value(v).unwrap()))
+                .collect()
+        };
+        let before = snapshot(&cfg);
+        let mut attr = cfg.read_attribute("node_number").unwrap().clone();
+        attr.current = "1234".to_string();
+        cfg.write_attribute("node_number", &attr)
+            .expect("write_attribute failed");
+        let after = snapshot(&cfg);
+
+        assert_eq!(after.len(), before.len());
+        for ((key, old), (new_key, new)) in before.iter().zip(&after) {
+            assert_eq!(key, new_key);
+            if key == "node_number" {
+                assert_eq!(new["current"], "1234");
+            } else {
+                assert_eq!(old, new);
+            }
+        }
+    }
+
+    #[test]
+    /// Test indexing the attributes by key
+    fn index_test() {
+        let mut cfg = Cfg::new();
+        cfg.load_configuration_from_str(CFG_DATA, DEFN_DATA)
+            .expect("config failed to load");
+        assert_eq!(
+            cfg["canid"].current,
+            cfg.read_attribute("canid").unwrap().current
+        );
+        assert_eq!(cfg[String::from("canid")].prompt, cfg["canid"].prompt);
+    }
+
+    #[test]
+    #[should_panic(expected = "no configuration item 'no_such_key'")]
+    /// Test indexing by a key that is not defined
+    fn index_missing_test() {
+        let mut cfg = Cfg::new();
+        cfg.load_configuration_from_str(CFG_DATA, DEFN_DATA)
+            .expect("config failed to load");
+        let _ = &cfg["no_such_key"];
+    }
+
+    #[test]
+    /// Test combined filtering of attributes via select()
+    fn select_test() {
+        let cfg_file = "scratch/select_test.cfg";
+        let defn_file = "scratch/select_test.json";
+        setup_file(defn_file, DEFN_DATA);
+        setup_file(cfg_file, "canid=101\nnode_number=4321\nstart_event_id=1\n");
+        let mut cfg = Cfg::new();
+        cfg.load_configuration(&cfg_file, &defn_file)
+            .expect("config failed to load");
+        let modified = cfg
+            .select("action==Display && modified==true")
+            .expect("valid query");
+        assert_eq!(modified.len(), 1);
+        assert!(modified.contains_key("canid"));
+        let others = cfg
+            .select("action!=Display && key==*_id")
+            .expect("valid query");
+        assert_eq!(others.len(), 1);
+        assert!(others.contains_key("start_event_id"));
+        assert!(cfg
+            .select("group==Network")
+            .expect("valid query")
+            .is_empty());
+        assert!(cfg.select("colour==red").is_err());
+        teardown_file(cfg_file);
+        teardown_file(defn_file);
+    }
+
+    #[test]
+    /// Test walking the attributes a page at a time
+    fn page_test() {
+        let cfg_file = "scratch/page_test.cfg";
+        let defn_file = "scratch/page_test.json";
+        setup_file(defn_file, DEFN_DATA);
+        setup_file(cfg_file, CFG_DATA);
+        let mut cfg = Cfg::new();
+        cfg.load_configuration(&cfg_file, &defn_file)
+            .expect("config failed to load");
+        let first = cfg.page(0, 3, SortBy::Key);
+        assert_eq!(first.total, 4);
+        assert!(first.has_more());
+        let keys: Vec<&str> = first.items.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, vec!["canid", "node_mode", "node_number"]);
+        let second = cfg.page(3, 3, SortBy::Key);
+        assert_eq!(second.items.len(), 1);
+        assert_eq!(second.items[0].0, "start_event_id");
+        assert!(!second.has_more());
+        let by_action = cfg.page(0, 1, SortBy::Action);
+        assert_eq!(by_action.items[0].0, "start_event_id");
+        assert!(cfg.page(10, 3, SortBy::Prompt).items.is_empty());
+        teardown_file(cfg_file);
+        teardown_file(defn_file);
+    }
+
+    #[test]
+    /// Test that instances share one compiled schema per type
+    fn shared_schema_test() {
+        assert!(std::ptr::eq(Cfg::new().schema, Cfg::new().schema));
+        assert!(std::ptr::eq(Pkg::new().schema, Pkg::new().schema));
+    }
+
+    #[test]
+    /// Test the schemas compiled from the generated schema values
+    fn defn_schema_test() {
+        let attr_schema = schema_for!(ConfigHash);
+        let text = serde_json::to_string(&attr_schema).expect("schema failed to serialise");
+        assert_eq!(
+            serde_json::to_value(&attr_schema).expect("schema failed to convert"),
+            serde_json::from_str::<Value>(&text).expect("schema failed to parse")
+        );
+        let schema = Cfg::create_defn_schema();
+        assert!(schema.is_valid(&serde_json::from_str(DEFN_DATA).unwrap()));
+        assert!(!schema.is_valid(&serde_json::from_str(BAD_DATA).unwrap()));
+
+        let schema = Pkg::create_defn_schema();
+        let package = serde_json::json!({
+            "canpi": { "cfg_path": "/home/canpi", "ini_file": "canpi.cfg", "json_file": "canpi.json" }
+        });
+        assert!(schema.is_valid(&package));
+        assert!(!schema.is_valid(&serde_json::json!({ "canpi": { "cfg_path": "/home/canpi" } })));
+    }
+
+    #[test]
+    /// Test writing and reading back the general section under a name
+    fn named_general_section_test() {
+        let cfg_file = "scratch/named_section_test.cfg";
+        let defn_file = "scratch/named_section_test.json";
+        setup_file(defn_file, DEFN_DATA);
+        setup_file(cfg_file, CFG_DATA);
+        let mut cfg = Cfg::new();
+        cfg.load_configuration(&cfg_file, &defn_file)
+            .expect("config failed to load");
+        let write_options = WriteOptions {
+            general_section: Some("canpi".to_string()),
+            ..WriteOptions::default()
+        };
+        cfg.write_cfg_file_with(cfg_file, &write_options)
+            .expect("Failed to write cfg file");
+        let ini = Ini::load_from_file(cfg_file).expect("failed to load .cfg file");
+        assert_eq!(ini.get_from(Some("canpi"), "canid"), Some("101"));
+        assert!(ini.general_section().is_empty());
+
+        let load_options = LoadOptions {
+            general_section: Some("canpi".to_string()),
+            ..LoadOptions::default()
+        };
+        let mut reloaded = Cfg::new();
+        reloaded
+            .load_configuration_with(&cfg_file, &defn_file, &load_options)
+            .expect("config failed to reload");
+        let canid = reloaded.read_attribute("canid");
+        assert_eq!(canid.map(|a| a.current.as_str()), Some("101"));
+        teardown_file(cfg_file);
+        teardown_file(defn_file);
+    }
+
+    #[test]
+    /// Test dotted keys round trip through nested sections
+    fn nested_section_test() {
+        let cfg_file = "scratch/nested_section_test.cfg";
+        let defn_file = "scratch/nested_section_test.json";
+        let defn = r#"{
+            "canid": {"prompt": "CAN Id", "tooltip": "", "current": "100", "default": "100",
+                      "format": "[0-9]{1,4}", "action": "Display"},
+            "network.wifi.ssid": {"prompt": "SSID", "tooltip": "", "current": "", "default": "",
+                      "format": ".*", "action": "Edit"}
+        }"#;
+        setup_file(defn_file, defn);
+        setup_file(cfg_file, "canid=101\n[network.wifi]\nssid=home\n");
+        let mut cfg = Cfg::new();
+        cfg.load_configuration(&cfg_file, &defn_file)
+            .expect("config failed to load");
+        let ssid = cfg.read_attribute("network.wifi.ssid");
+        assert_eq!(ssid.map(|a| a.current.as_str()), Some("home"));
+
+        cfg.write_cfg_file(cfg_file, None)
+            .expect("Failed to write cfg file");
+        let ini = Ini::load_from_file(cfg_file).expect("failed to load .cfg file");
+        assert_eq!(ini.get_from(None::<String>, "canid"), Some("101"));
+        assert_eq!(ini.get_from(Some("network.wifi"), "ssid"), Some("home"));
+        teardown_file(cfg_file);
+        teardown_file(defn_file);
+    }
+
+    #[test]
+    /// Test keys defined without a prefix are read from and written back to their sections
+    fn bare_section_key_test() {
+        let cfg_file = "scratch/bare_section_test.cfg";
+        let defn_file = "scratch/bare_section_test.json";
+        setup_file(defn_file, DEFN_DATA);
+        setup_file(
+            cfg_file,
+            "canid=101\n[network]\nnode_number=5432\n[apmode]\nnode_mode=1\n",
+        );
+        let mut cfg = Cfg::new();
+        cfg.load_configuration(&cfg_file, &defn_file)
+            .expect("config failed to load");
+        let node_number = cfg.read_attribute("node_number");
+        assert_eq!(node_number.map(|a| a.current.as_str()), Some("5432"));
+
+        cfg.write_cfg_file(cfg_file, None)
+            .expect("Failed to write cfg file");
+        let ini = Ini::load_from_file(cfg_file).expect("failed to load .cfg file");
+        assert_eq!(ini.get_from(None::<String>, "canid"), Some("101"));
+        assert_eq!(ini.get_from(Some("network"), "node_number"), Some("5432"));
+        assert_eq!(ini.get_from(Some("apmode"), "node_mode"), Some("1"));
+        assert_eq!(ini.get_from(None::<String>, "node_mode"), None);
+        teardown_file(cfg_file);
+        teardown_file(defn_file);
+    }
+
+    #[test]
+    /// Test the section given in the definition decides where an item is written
+    fn defn_section_test() {
+        let cfg_file = "scratch/defn_section_test.cfg";
+        let defn_file = "scratch/defn_section_test.json";
+        let defn = r#"{
+            "canid": {"prompt": "CAN Id", "tooltip": "", "current": "100", "default": "100",
+                      "format": "[0-9]{1,4}", "action": "Display"},
+            "ap_ssid": {"prompt": "SSID", "tooltip": "", "current": "", "default": "",
+                      "format": ".*", "action": "Edit", "section": "apmode"}
+        }"#;
+        setup_file(defn_file, defn);
+        setup_file(cfg_file, "canid=101\nap_ssid=canpiwi\n");
+        let mut cfg = Cfg::new();
+        cfg.load_configuration(&cfg_file, &defn_file)
+            .expect("config failed to load");
+        cfg.write_cfg_file(cfg_file, None)
+            .expect("Failed to write cfg file");
+        let ini = Ini::load_from_file(cfg_file).expect("failed to load .cfg file");
+        assert_eq!(ini.get_from(None::<String>, "canid"), Some("101"));
+        assert_eq!(ini.get_from(Some("apmode"), "ap_ssid"), Some("canpiwi"));
+
+        let mut reloaded = Cfg::new();
+        reloaded
+            .load_configuration(&cfg_file, &defn_file)
+            .expect("config failed to reload");
+        let ssid = reloaded.read_attribute("ap_ssid");
+        assert_eq!(ssid.map(|a| a.current.as_str()), Some("canpiwi"));
+        teardown_file(cfg_file);
+        teardown_file(defn_file);
+    }
+
+    #[test]
+    /// Test comments and order survive a write that preserves layout
+    fn preserve_layout_test() {
+        let cfg_file = "scratch/preserve_layout_test.cfg";
+        let defn_file = "scratch/preserve_layout_test.json";
+        setup_file(defn_file, DEFN_DATA);
+        setup_file(
+            cfg_file,
+            "; node settings\nnode_number=5432\n\ncanid = 101 \n",
+        );
+        let mut cfg = Cfg::new();
+        cfg.load_configuration(&cfg_file, &defn_file)
+            .expect("config failed to load");
+        cfg.set_value("canid", "102").expect("set_value failed");
+        let options = WriteOptions {
+            preserve_layout: true,
+            ..WriteOptions::default()
+        };
+        cfg.write_cfg_file_with(cfg_file, &options)
+            .expect("Failed to write cfg file");
+        let text = fs::read_to_string(cfg_file).expect("failed to read .cfg file");
+        assert!(text.starts_with("; node settings\nnode_number=5432\n\ncanid = 102\n"));
+        teardown_file(modified::sidecar_path(Path::new(cfg_file)));
+        teardown_file(cfg_file);
+        teardown_file(defn_file);
+    }
+
+    #[test]
+    /// Test items missing from the INI file are kept with their defaults
+    fn defaulted_keys_test() {
+        let cfg_file = "scratch/defaulted_keys_test.cfg";
+        let defn_file = "scratch/defaulted_keys_test.json";
+        setup_file(defn_file, DEFN_DATA);
+        setup_file(cfg_file, "canid=101\nnode_mode=1\n");
+        let mut cfg = Cfg::new();
+        cfg.load_configuration(&cfg_file, &defn_file)
+            .expect("config failed to load");
+        assert_eq!(cfg.defaulted_keys(), vec!["node_number", "start_event_id"]);
+        let node_number = cfg.read_attribute("node_number");
+        assert_eq!(node_number.map(|a| a.current.as_str()), Some("4321"));
+        assert_eq!(cfg.provenance("node_number"), Some(&Provenance::Default));
+        cfg.set_value("node_number", "7").expect("set_value failed");
+        assert_eq!(cfg.defaulted_keys(), vec!["start_event_id"]);
+        teardown_file(cfg_file);
+        teardown_file(defn_file);
+    }
+
+    #[test]
+    /// Test the load report lists unknown entries, missing items and invalid values
+    fn load_report_test() {
+        let cfg_file = "scratch/load_report_test.cfg";
+        let defn_file = "scratch/load_report_test.json";
+        setup_file(defn_file, DEFN_DATA);
+        setup_file(
+            cfg_file,
+            "canid=abc\nwifi=on\nnode_number=5432\nnode_mode=1\n",
+        );
+        let mut cfg = Cfg::new();
+        let report = cfg
+            .load_configuration(&cfg_file, &defn_file)
+            .expect("config failed to load");
+        assert_eq!(report.unknown_keys, vec!["wifi"]);
+        assert_eq!(report.missing_keys, vec!["start_event_id"]);
+        assert_eq!(report.invalid_values.len(), 1);
+        assert_eq!(report.invalid_values[0].key, "canid");
+        assert!(!report.is_clean());
+        teardown_file(cfg_file);
+        teardown_file(defn_file);
+    }
+
+    #[test]
+    /// Test invalid INI values can be rejected or replaced by their defaults
+    fn invalid_values_test() {
+        let cfg_file = "scratch/invalid_values_test.cfg";
+        let defn_file = "scratch/invalid_values_test.json";
+        setup_file(defn_file, DEFN_DATA);
+        setup_file(cfg_file, "canid=abc\nnode_number=5432\n");
+        let mut options = LoadOptions {
+            invalid_values: InvalidValues::Reject,
+            ..LoadOptions::default()
+        };
+        let mut cfg = Cfg::new();
+        let err = cfg
+            .load_configuration_with(&cfg_file, &defn_file, &options)
+            .expect_err("invalid value accepted");
+        assert_eq!(err.key(), Some("canid"));
+        assert!(matches!(err.root(), CfgError::InvalidValue(_)));
+
+        options.invalid_values = InvalidValues::UseDefault;
+        let report = cfg
+            .load_configuration_with(&cfg_file, &defn_file, &options)
+            .expect("config failed to load");
+        assert_eq!(report.replaced_values.len(), 1);
+        assert_eq!(report.replaced_values[0].value, "abc");
+        assert!(report.invalid_values.is_empty());
+        assert_eq!(cfg.get_or_default("canid"), "100");
+        assert_eq!(cfg.provenance("canid"), Some(&Provenance::Default));
+        teardown_file(cfg_file);
+        teardown_file(defn_file);
+    }
+
+    #[test]
+    /// Test a missing INI file gives the defaults when allowed
+    fn missing_file_test() {
+        let cfg_file = "scratch/missing_file_test.cfg";
+        let defn_file = "scratch/missing_file_test.json";
+        setup_file(defn_file, DEFN_DATA);
+        let mut cfg = Cfg::new();
+        assert!(cfg.load_configuration(&cfg_file, &defn_file).is_err());
+        let options = LoadOptions {
+            allow_missing_file: true,
+            ..LoadOptions::default()
+        };
+        cfg.load_configuration_with(&cfg_file, &defn_file, &options)
+            .expect("config failed to load");
+        assert_eq!(cfg.defaulted_keys().len(), 4);
+        let canid = cfg.read_attribute("canid");
+        assert_eq!(canid.map(|a| a.current.as_str()), Some("100"));
+        teardown_file(defn_file);
+    }
+
+    #[test]
+    /// Test the INI output can be written to any io::Write
+    fn write_cfg_test() {
+        let mut cfg = Cfg::new();
+        let mut out = Vec::new();
+        assert!(matches!(cfg.write_cfg(&mut out), Err(CfgError::Cfg())));
+        cfg.load_configuration_from_str(CFG_DATA, DEFN_DATA)
+            .expect("config failed to load");
+        cfg.set_value("canid", "102").expect("set_value failed");
+        cfg.write_cfg(&mut out).expect("write failed");
+        let text = String::from_utf8(out).expect("output is not UTF-8");
+        assert!(text.contains("canid=102\n"));
+        assert!(text.contains("node_number=5432\n"));
+        assert_eq!(cfg.unsaved_changes().len(), 1);
+    }
+
+    #[test]
+    /// Test environment variables override the INI file
+    fn env_override_test() {
+        let cfg_file = "scratch/env_override_test.cfg";
+        let defn_file = "scratch/env_override_test.json";
+        setup_file(defn_file, DEFN_DATA);
+        setup_file(cfg_file, CFG_DATA);
+        env::set_var("CANPI_ENV_TEST_CANID", "107");
+        let options = LoadOptions {
+            env_prefix: Some("CANPI_ENV_TEST_".to_string()),
+            ..LoadOptions::default()
+        };
+        let mut cfg = Cfg::new();
+        let report = cfg
+            .load_configuration_with(&cfg_file, &defn_file, &options)
+            .expect("config failed to load");
+        assert_eq!(report.env_overrides, vec!["canid".to_string()]);
+        assert_eq!(cfg.get_or_default("canid"), "107");
+        assert_eq!(
+            cfg.provenance("canid").map(|p| p.to_string()).as_deref(),
+            Some("environment variable CANPI_ENV_TEST_CANID")
+        );
+        assert_eq!(cfg.get_or_default("node_number"), "5432");
+
+        let options = LoadOptions {
+            env_prefix: None,
+            ..options
+        };
+        let report = cfg
+            .load_configuration_with(&cfg_file, &defn_file, &options)
+            .expect("config failed to load");
+        assert!(report.env_overrides.is_empty());
+        assert_eq!(cfg.get_or_default("canid"), "101");
+        env::remove_var("CANPI_ENV_TEST_CANID");
+        teardown_file(cfg_file);
+        teardown_file(defn_file);
+    }
+
+    #[test]
+    /// Test values from the environment are not written back to the INI file
+    fn env_not_written_test() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("canpi.cfg", "canid=100\n");
+        fs.insert("defn.json", DEFN_DATA);
+        env::set_var("CANPI_ENV_WRITE_TEST_CANID", "7");
+        env::set_var("CANPI_ENV_WRITE_TEST_NODE_NUMBER", "1234");
+        let options = LoadOptions {
+            env_prefix: Some("CANPI_ENV_WRITE_TEST_".to_string()),
+            ..LoadOptions::default()
+        };
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.load_configuration_with("canpi.cfg", "defn.json", &options)
+            .expect("config failed to load");
+        env::remove_var("CANPI_ENV_WRITE_TEST_CANID");
+        env::remove_var("CANPI_ENV_WRITE_TEST_NODE_NUMBER");
+        assert_eq!(cfg.get_or_default("canid"), "7");
+
+        cfg.write_cfg_file("canpi.cfg", None)
+            .expect("Failed to write cfg file");
+        let text = fs.contents("canpi.cfg").unwrap();
+        assert!(text.contains("canid=100\n"));
+        assert!(text.contains("node_number=4321\n"));
+        assert_eq!(cfg.get_or_default("canid"), "7");
+
+        cfg.set_value("canid", "8").expect("set_value failed");
+        cfg.write_cfg_file("canpi.cfg", None)
+            .expect("Failed to write cfg file");
+        assert!(fs.contents("canpi.cfg").unwrap().contains("canid=8\n"));
+    }
+
+    #[test]
+    /// Test provenance is tracked through load, edit and export
+    fn provenance_test() {
+        let cfg_file = "scratch/provenance_test.cfg";
+        let defn_file = "scratch/provenance_test.json";
+        setup_file(defn_file, DEFN_DATA);
+        setup_file(cfg_file, CFG_DATA);
+        let mut cfg = Cfg::new();
+        cfg.load_configuration(&cfg_file, &defn_file)
+            .expect("config failed to load");
+        assert_eq!(
+            cfg.provenance("canid"),
+            Some(&Provenance::Ini {
+                file: cfg_file.into(),
+                section: None
+            })
+        );
+        cfg.set_value("canid", "102").expect("set_value failed");
+        assert_eq!(cfg.provenance("canid"), Some(&Provenance::User));
+        assert!(cfg.source_file("canid").is_none());
+        assert!(matches!(
+            cfg.set_value("no_such_key", "1"),
+            Err(CfgError::UnknownKey(_))
+        ));
+
+        let options = WriteOptions {
+            annotate_provenance: true,
+            ..WriteOptions::default()
+        };
+        cfg.write_cfg_file_with(cfg_file, &options)
+            .expect("Failed to write cfg file");
+        let text = fs::read_to_string(cfg_file).expect("failed to read .cfg file");
+        assert!(text.contains("; from user edit\ncanid=102\n"));
+        assert!(text.contains("; from scratch/provenance_test.cfg\nnode_mode=1\n"));
+        teardown_file(modified::sidecar_path(Path::new(cfg_file)));
+        teardown_file(cfg_file);
+        teardown_file(defn_file);
+    }
+
+    #[test]
+    fn anchored_format_test() {
+        let mut attr: Attribute = serde_json::from_str(
+            r#"{"prompt": "", "tooltip": "", "current": "12", "default": "12",
+                "format": "[0-9]{1,2}|none", "action": "Edit"}"#,
+        )
+        .expect("attribute failed to deserialize");
+        assert!(!attr.partial_match);
+        assert_eq!(attr.format_matches("12"), Ok(true));
+        assert_eq!(attr.format_matches("none"), Ok(true));
+        assert_eq!(attr.format_matches("abc12def"), Ok(false));
+        assert_eq!(attr.format_matches("12none"), Ok(false));
+        attr.partial_match = true;
+        assert_eq!(attr.format_matches("abc12def"), Ok(true));
+    }
+
+    #[test]
+    fn last_modified_test() {
+        let cfg_file = "scratch/last_modified_test.cfg";
+        let defn_file = "scratch/last_modified_test.json";
+        setup_file(defn_file, DEFN_DATA);
+        setup_file(cfg_file, CFG_DATA);
+        let mut cfg = Cfg::new();
+        cfg.load_configuration(&cfg_file, &defn_file)
+            .expect("config failed to load");
+        assert!(cfg.last_modified("canid").is_none());
+        let before = SystemTime::now();
+        cfg.set_value("canid", "102").expect("set_value failed");
+        let changed = cfg.last_modified("canid").expect("change not recorded");
+        assert!(changed >= before);
+        cfg.set_value("node_mode", "1").expect("set_value failed");
+        assert!(cfg.last_modified("node_mode").is_none());
+
+        cfg.write_cfg_file(cfg_file, None)
+            .expect("Failed to write cfg file");
+        let mut reloaded = Cfg::new();
+        reloaded
+            .load_configuration(&cfg_file, &defn_file)
+            .expect("config failed to reload");
+        let secs = |t: SystemTime| t.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        assert_eq!(
+            reloaded.last_modified("canid").map(secs),
+            Some(secs(changed))
+        );
+        teardown_file(modified::sidecar_path(Path::new(cfg_file)));
+        teardown_file(cfg_file);
+        teardown_file(defn_file);
     }
 
     #[test]
@@ -0,0 +1,85 @@
+//! Limits on values supplied through the API
+//!
+//! Values set through [`Cfg::set_value`], [`Cfg::apply_changes`], [`Cfg::apply_json_patch`] and
+//! [`Cfg::write_attribute`] may come straight from a web form or REST call.  Anything over the
+//! [`InputLimits`] of the Cfg is refused with [`CfgError::Limit`] before it is stored or matched
+//! against a format.  As the standard regex engine matches in time linear in the length of the
+//! value, this also bounds the time spent checking formats; the fancy engine is bounded by
+//! [`RegexOptions::backtrack_limit`](crate::RegexOptions::backtrack_limit).
+
+use crate::{Cfg, CfgError, InputLimits};
+
+impl Cfg {
+    /// Refuse values and patches larger than `limits` from now on
+    pub fn set_input_limits(&mut self, limits: InputLimits) {
+        self.input_limits = limits;
+    }
+
+    /// The limits applied to values supplied through the API
+    pub fn input_limits(&self) -> &InputLimits {
+        &self.input_limits
+    }
+
+    /// Check the length of a value for `key` supplied through the API
+    pub(crate) fn check_value_len(&self, key: &str, value: &str) -> Result<(), CfgError> {
+        let max = self.input_limits.max_value_len;
+        if value.len() > max {
+            return Err(CfgError::Limit(format!(
+                "value for '{}' is {} bytes, the limit is {}",
+                key,
+                value.len(),
+                max
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check the length of a patch document supplied through the API
+    pub(crate) fn check_patch_len(&self, patch: &str) -> Result<(), CfgError> {
+        let max = self.input_limits.max_patch_len;
+        if patch.len() > max {
+            return Err(CfgError::Limit(format!(
+                "patch is {} bytes, the limit is {}",
+                patch.len(),
+                max
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::load_example_defn;
+
+    #[test]
+    fn oversized_input_is_refused() {
+        let mut cfg = load_example_defn("scratch/limits_test.json");
+        cfg.set_input_limits(InputLimits {
+            max_value_len: 16,
+            max_patch_len: 128,
+        });
+
+        let long = "x".repeat(17);
+        assert!(matches!(
+            cfg.set_value("ap_ssid", &long),
+            Err(CfgError::Limit(_))
+        ));
+        assert!(matches!(
+            cfg.apply_changes(&[("canid", "101"), ("ap_ssid", &long)], None),
+            Err(CfgError::Limit(_))
+        ));
+        assert_eq!(cfg.read_attribute("canid").unwrap().current, "100");
+        let patch = format!(
+            r#"[ {{ "op": "replace", "path": "/ap_ssid", "value": "{}" }} ]"#,
+            "x".repeat(100)
+        );
+        assert!(matches!(
+            cfg.apply_json_patch(&patch),
+            Err(CfgError::Limit(_))
+        ));
+        cfg.set_value("ap_ssid", &long[1..])
+            .expect("value within limit refused");
+    }
+}
@@ -0,0 +1,281 @@
+//! Locale-aware numbers
+//!
+//! Someone typing into the web form may write one and a half as `1,5` or a thousand as
+//! `1.000`.  With a [`NumberLocale`] set, values for attributes of kind [`ValueKind::Integer`]
+//! or [`ValueKind::Decimal`] supplied through [`Cfg::set_value`] or [`Cfg::apply_changes`] are
+//! read using its separators and stored in canonical form: an optional `-`, ASCII digits and a
+//! `.` before any fraction.  The INI file always holds the canonical form; JSON patches and
+//! generated values are taken to be canonical already.
+
+use crate::value::parse_bool;
+use crate::{datetime, Attribute, AttributeValue, Cfg, CfgError, ValueKind};
+
+use std::borrow::Cow;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The separators used when numbers are written for people
+pub struct NumberLocale {
+    /// Separates the whole number from the fraction
+    pub decimal: char,
+    /// Separates groups of three digits in the whole number, if used
+    pub group: Option<char>,
+}
+
+impl NumberLocale {
+    /// `1,234.5`, as used in the UK and US
+    pub const POINT: NumberLocale = NumberLocale {
+        decimal: '.',
+        group: Some(','),
+    };
+    /// `1.234,5`, as used in much of continental Europe
+    pub const COMMA: NumberLocale = NumberLocale {
+        decimal: ',',
+        group: Some('.'),
+    };
+    /// `1 234,5`, as used in France and Scandinavia.  A plain space is accepted as well.
+    pub const COMMA_SPACE: NumberLocale = NumberLocale {
+        decimal: ',',
+        group: Some('\u{a0}'),
+    };
+
+    /// The separators for a BCP 47 language tag such as `en-GB` or `de`
+    ///
+    /// Only the language is considered, except for Swiss German.  Unknown languages get `None`.
+    pub fn for_language_tag(tag: &str) -> Option<NumberLocale> {
+        let tag = tag.to_ascii_lowercase().replace('_', "-");
+        if tag == "de-ch" {
+            return Some(NumberLocale {
+                decimal: '.',
+                group: Some('\''),
+            });
+        }
+        match tag.split('-').next().unwrap_or_default() {
+            "en" | "ja" | "ko" | "zh" | "he" | "th" => Some(NumberLocale::POINT),
+            "de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" | "el" => {
+                Some(NumberLocale::COMMA)
+            }
+            "fr" | "ru" | "pl" | "cs" | "sk" | "sv" | "nb" | "nn" | "no" | "fi" | "uk" => {
+                Some(NumberLocale::COMMA_SPACE)
+            }
+            _ => None,
+        }
+    }
+
+    fn is_group(&self, c: char) -> bool {
+        Some(c) == self.group || (self.group == Some('\u{a0}') && c == ' ')
+    }
+
+    /// Read `input` written with these separators as a number of `kind` in canonical form
+    pub fn parse(&self, kind: ValueKind, input: &str) -> Result<String, String> {
+        let input = input.trim();
+        let (sign, digits) = match input.chars().next() {
+            Some('-') => ("-", &input[1..]),
+            Some('+') => ("", &input[1..]),
+            _ => ("", input),
+        };
+        let (whole, fraction) = match digits.find(self.decimal) {
+            Some(i) => (&digits[..i], Some(&digits[i + self.decimal.len_utf8()..])),
+            None => (digits, None),
+        };
+        if kind == ValueKind::Integer && fraction.is_some() {
+            return Err(format!("'{}' is not a whole number", input));
+        }
+        let groups: Vec<&str> = whole.split(|c| self.is_group(c)).collect();
+        let grouped = groups.len() > 1
+            && (1..=3).contains(&groups[0].len())
+            && groups[1..].iter().all(|g| g.len() == 3);
+        let fraction_ok =
+            fraction.is_none_or(|f| !f.is_empty() && f.bytes().all(|b| b.is_ascii_digit()));
+        if (groups.len() > 1 && !grouped)
+            || (whole.is_empty() && fraction.is_none())
+            || !groups.iter().all(|g| g.bytes().all(|b| b.is_ascii_digit()))
+            || !fraction_ok
+        {
+            return Err(format!("'{}' is not a number", input));
+        }
+        let whole = if whole.is_empty() {
+            "0".to_string()
+        } else {
+            groups.concat()
+        };
+        Ok(match fraction {
+            Some(f) => format!("{}{}.{}", sign, whole, f),
+            None => format!("{}{}", sign, whole),
+        })
+    }
+
+    /// Write the canonical number `value` with these separators
+    ///
+    /// Anything that is not a canonical number is returned unchanged.
+    pub fn format(&self, value: &str) -> String {
+        let (sign, digits) = match value.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", value),
+        };
+        let (whole, fraction) = match digits.split_once('.') {
+            Some((w, f)) => (w, Some(f)),
+            None => (digits, None),
+        };
+        if whole.is_empty()
+            || !whole.bytes().all(|b| b.is_ascii_digit())
+            || !fraction.is_none_or(|f| f.bytes().all(|b| b.is_ascii_digit()))
+        {
+            return value.to_string();
+        }
+        let mut text = sign.to_string();
+        for (i, c) in whole.chars().enumerate() {
+            if i > 0 && (whole.len() - i) % 3 == 0 {
+                if let Some(group) = self.group {
+                    text.push(group);
+                }
+            }
+            text.push(c);
+        }
+        if let Some(f) = fraction {
+            text.push(self.decimal);
+            text.push_str(f);
+        }
+        text
+    }
+}
+
+impl Attribute {
+    /// Whether values of the attribute are numbers
+    pub fn is_numeric(&self) -> bool {
+        matches!(self.kind, ValueKind::Integer | ValueKind::Decimal)
+    }
+}
+
+impl Cfg {
+    /// Read numeric values supplied through the API using `locale`, or only in canonical form
+    /// if `None`
+    pub fn set_number_locale(&mut self, locale: Option<NumberLocale>) {
+        self.number_locale = locale;
+    }
+
+    /// The locale numeric values supplied through the API are read with
+    pub fn number_locale(&self) -> Option<NumberLocale> {
+        self.number_locale
+    }
+
+    /// The current value of `key` as it should be shown to the user
+    ///
+    /// Numeric values are written using the number locale and datetimes in the display
+    /// timezone; other values are as stored.
+    pub fn localized_value<K: AsRef<str>>(&self, key: K) -> Option<String> {
+        let key = key.as_ref();
+        let attr = self.cfg.as_ref()?.get(key)?;
+        match self.number_locale {
+            Some(locale) if attr.is_numeric() => Some(locale.format(&attr.current)),
+            _ if attr.kind == ValueKind::DateTime => Some(
+                datetime::parse(&attr.current, self.display_timezone)
+                    .map(|time| time.with_timezone(&self.display_timezone).to_rfc3339())
+                    .unwrap_or_else(|_| attr.current.clone()),
+            ),
+            _ => Some(attr.current.clone()),
+        }
+    }
+
+    /// Convert a value for `attr` supplied through the API to the form stored in the INI file
+    ///
+    /// Flags are stored as `1` or `0`.  An empty datetime or flag is left empty, meaning none is
+    /// set.
+    pub(crate) fn canonical_input<'a>(
+        &self,
+        key: &str,
+        attr: &Attribute,
+        value: &'a str,
+    ) -> Result<Cow<'a, str>, CfgError> {
+        let converted = match self.number_locale {
+            Some(locale) if attr.is_numeric() => locale.parse(attr.kind, value),
+            _ if attr.kind == ValueKind::DateTime && !value.is_empty() => {
+                datetime::canonical_input(value, self.display_timezone)
+            }
+            _ if attr.kind == ValueKind::Boolean && !value.is_empty() => parse_bool(value)
+                .map(|flag| AttributeValue::Boolean(flag).to_string())
+                .ok_or_else(|| format!("'{}' is not a flag", attr.shown(value))),
+            _ => return Ok(Cow::Borrowed(value)),
+        };
+        converted
+            .map(Cow::Owned)
+            .map_err(|e| CfgError::InvalidValue(format!("value for '{}': {}", key, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn parse_and_format() {
+        let comma = NumberLocale::COMMA;
+        assert_eq!(comma.parse(ValueKind::Decimal, "1,5"), Ok("1.5".into()));
+        assert_eq!(
+            comma.parse(ValueKind::Decimal, "-1.234,25"),
+            Ok("-1234.25".into())
+        );
+        assert_eq!(comma.parse(ValueKind::Decimal, ",5"), Ok("0.5".into()));
+        assert!(comma.parse(ValueKind::Decimal, "1.5").is_err());
+        assert!(comma.parse(ValueKind::Integer, "1,5").is_err());
+        assert!(comma.parse(ValueKind::Integer, "").is_err());
+        let point = NumberLocale::POINT;
+        assert_eq!(
+            point.parse(ValueKind::Integer, " 12,345 "),
+            Ok("12345".into())
+        );
+        assert!(point.parse(ValueKind::Decimal, "1,5").is_err());
+        let space = NumberLocale::COMMA_SPACE;
+        assert_eq!(
+            space.parse(ValueKind::Decimal, "1 234,5"),
+            Ok("1234.5".into())
+        );
+
+        assert_eq!(comma.format("-1234567.5"), "-1.234.567,5");
+        assert_eq!(point.format("123"), "123");
+        assert_eq!(comma.format("abc"), "abc");
+        assert_eq!(
+            NumberLocale::for_language_tag("de_DE"),
+            Some(NumberLocale::COMMA)
+        );
+        assert_eq!(NumberLocale::for_language_tag("xx"), None);
+    }
+
+    #[test]
+    fn localized_input_is_stored_canonically() {
+        let defn_file = "scratch/locale_test.json";
+        fs::write(
+            defn_file,
+            r#"{
+                "ratio": { "prompt": "Ratio", "tooltip": "", "current": "0.5", "default": "0.5",
+                           "format": "[0-9]+(\\.[0-9]+)?", "action": "Edit", "kind": "Decimal" },
+                "label": { "prompt": "Label", "tooltip": "", "current": "a", "default": "a",
+                           "format": ".*", "action": "Edit" }
+            }"#,
+        )
+        .expect("file write failed");
+        let mut cfg = Cfg::new();
+        cfg.load_definition(defn_file)
+            .expect("definition failed to load");
+        fs::remove_file(defn_file).expect("file deletion failed");
+
+        cfg.set_number_locale(NumberLocale::for_language_tag("de"));
+        cfg.set_value("ratio", "1,5").expect("set_value failed");
+        cfg.set_value("label", "1,5").expect("set_value failed");
+        let cfg_hash = cfg.cfg.as_ref().unwrap();
+        assert_eq!(cfg_hash["ratio"].current, "1.5");
+        assert_eq!(cfg_hash["label"].current, "1,5");
+        assert_eq!(cfg.localized_value("ratio").as_deref(), Some("1,5"));
+        assert!(matches!(
+            cfg.apply_changes(&[("label", "b"), ("ratio", "x")], None),
+            Err(CfgError::InvalidValue(_))
+        ));
+        assert_eq!(cfg.localized_value("label").as_deref(), Some("1,5"));
+        cfg.apply_json_patch(r#"[{"op": "replace", "path": "/ratio", "value": 2.5}]"#)
+            .expect("patch failed");
+        assert_eq!(cfg.cfg.as_ref().unwrap()["ratio"].current, "2.5");
+        cfg.apply_json_patch(r#"[{"op": "replace", "path": "/ratio", "value": "3,5"}]"#)
+            .expect("patch failed");
+        assert_eq!(cfg.cfg.as_ref().unwrap()["ratio"].current, "3.5");
+    }
+}
@@ -0,0 +1,125 @@
+//! Advisory locking between writers
+//!
+//! The web front end and a command line tool may both write the same INI file.  A writer holds
+//! an exclusive advisory lock on a `.lock` file beside the INI file, rather than on the INI file
+//! itself, because each write replaces the INI file with a new one.  Set
+//! [`WriteOptions::lock_timeout`](crate::WriteOptions::lock_timeout) to take the lock for each
+//! write, or hold a [`FileLock`] from [`Cfg::lock_cfg_file`] across a whole load, edit and write.
+
+use crate::context::ResultExt;
+use crate::{Cfg, CfgError, Operation};
+
+use std::fs::{self, File, TryLockError};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait between attempts to take a lock held by another writer
+const RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug)]
+/// An exclusive lock, released when dropped
+pub struct FileLock {
+    file: Option<File>,
+}
+
+impl FileLock {
+    /// A lock that excludes nothing, for filesystems without locking
+    pub fn none() -> FileLock {
+        FileLock { file: None }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        if let Some(file) = &self.file {
+            let _ = file.unlock();
+        }
+    }
+}
+
+/// The lock file guarding the INI file `path`
+pub fn lock_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".lock");
+    path.with_file_name(name)
+}
+
+/// Take an exclusive lock on the file `path`, creating it if needed, waiting up to `timeout`
+pub(crate) fn lock_file(path: &Path, timeout: Duration) -> io::Result<FileLock> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(FileLock { file: Some(file) }),
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                thread::sleep(RETRY_INTERVAL)
+            }
+            Err(TryLockError::WouldBlock) => {
+                return Err(io::Error::new(
+                    ErrorKind::TimedOut,
+                    format!("{} is locked by another writer", path.display()),
+                ))
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+    }
+}
+
+impl Cfg {
+    /// Take the lock that writers of the INI file `path` hold, waiting up to `timeout`
+    ///
+    /// Hold the lock from loading the file until the changes are written so that no other
+    /// writer's changes are lost.  Writes made while holding it must not also set
+    /// [`WriteOptions::lock_timeout`](crate::WriteOptions::lock_timeout).
+    pub fn lock_cfg_file<P: AsRef<Path>>(
+        &self,
+        path: P,
+        timeout: Duration,
+    ) -> Result<FileLock, CfgError> {
+        let lock = self.fs.lock(&lock_path(path.as_ref()), timeout);
+        lock.map_err(CfgError::from).context(Operation::Write, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::CANID_DEFN;
+    use crate::WriteOptions;
+
+    #[test]
+    fn writers_exclude_each_other() {
+        let dir = std::env::temp_dir().join(format!("canpi-lock-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("cannot create test directory");
+        let path = dir.join("canpi.cfg");
+        let cfg = Cfg::new();
+        let held = cfg
+            .lock_cfg_file(&path, Duration::ZERO)
+            .expect("lock failed");
+        assert!(lock_path(&path).exists());
+        let err = cfg
+            .lock_cfg_file(&path, Duration::from_millis(30))
+            .unwrap_err();
+        assert!(matches!(err.root(), CfgError::Io(e) if e.kind() == ErrorKind::TimedOut));
+
+        let mut cfg = Cfg::new();
+        cfg.load_configuration_from_str("canid=101\n", CANID_DEFN)
+            .expect("configuration failed to load");
+        let options = WriteOptions {
+            lock_timeout: Some(Duration::from_millis(30)),
+            ..WriteOptions::default()
+        };
+        assert!(cfg.write_cfg_file_with(&path, &options).is_err());
+        drop(held);
+        cfg.write_cfg_file_with(&path, &options)
+            .expect("lock not released");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "canid=101\n");
+        fs::remove_dir_all(&dir).expect("cannot remove test directory");
+    }
+}
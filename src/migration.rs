@@ -0,0 +1,296 @@
+//! Upgrading INI files written under earlier definitions
+//!
+//! When a later definition renames a key, its `migrations` table records the change, e.g.
+//! `{ "from": "ap_on", "to": "ap_mode", "values": { "on": "1", "off": "0" } }`.  Loading an INI
+//! file written under the earlier definition then reads the entry `ap_on` as `ap_mode`, with
+//! its value translated through `values`, and reports it in [`LoadReport::migrated_keys`].
+//! Renames are followed, so a key renamed twice is still found.  An entry under the current key
+//! takes precedence over a migrated one.
+//!
+//! Changes a table cannot express are made by functions registered with
+//! [`Cfg::register_migration`] against the `defn_version` that needs them.  The version an INI
+//! file was written under is saved alongside it (`<cfg file>.version`), and loading a file from
+//! an earlier version, or one with no saved version, runs the functions of each later version in
+//! order on its entries before they are read.  The file is only upgraded once written, so the
+//! functions should leave entries they have already changed alone.
+//!
+//! [`LoadReport::migrated_keys`]: crate::LoadReport::migrated_keys
+
+use crate::ini_reader::IniDocument;
+use crate::{Cfg, CfgError, ConfigHash, Fs};
+
+use ini::Ini;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// A change to the entries of an INI file written under an earlier definition version
+pub type Migration = Box<dyn Fn(&mut Ini) -> Result<(), CfgError> + Send + Sync>;
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+/// A key renamed by a later definition
+pub struct KeyMigration {
+    /// The key used by the earlier definition
+    pub from: String,
+    /// The key it was renamed to
+    pub to: String,
+    /// Replacements for values that changed with the key; other values are kept
+    #[serde(default)]
+    pub values: BTreeMap<String, String>,
+}
+
+impl KeyMigration {
+    /// `value` as it is held under the new key
+    pub fn apply(&self, value: String) -> String {
+        match self.values.get(value.trim()) {
+            Some(new) => new.clone(),
+            None => value,
+        }
+    }
+}
+
+/// Remove a top-level `migrations` array from a definition file, returning the renames it held
+///
+/// An object under `migrations` is an attribute of that name and is left alone.
+pub(crate) fn take_defn_migrations(json_value: &mut Value) -> Result<Vec<KeyMigration>, CfgError> {
+    match json_value.as_object_mut() {
+        Some(map) if map.get("migrations").is_some_and(Value::is_array) => {
+            let migrations = map.remove("migrations").unwrap_or_default();
+            serde_json::from_value(migrations)
+                .map_err(|_| CfgError::Schema("definition migrations".to_string()))
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Remove a top-level `defn_version` number from a definition file, returning it
+///
+/// An object under `defn_version` is an attribute of that name and is left alone.
+pub(crate) fn take_defn_version(json_value: &mut Value) -> Result<Option<u32>, CfgError> {
+    match json_value.as_object_mut() {
+        Some(map) if map.get("defn_version").is_some_and(Value::is_number) => {
+            let version = map.remove("defn_version").unwrap_or_default();
+            serde_json::from_value(version)
+                .map(Some)
+                .map_err(|_| CfgError::Schema("definition version".to_string()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Path of the file holding the definition version the INI file at `path` was written under
+pub(crate) fn version_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".version");
+    PathBuf::from(name)
+}
+
+/// The definition version the INI file at `path` was written under, if saved
+fn read_version(fs: &dyn Fs, path: &Path) -> Result<Option<u32>, CfgError> {
+    let text = match fs.read_to_string(&version_path(path)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    text.trim().parse().map(Some).map_err(|_| {
+        CfgError::InvalidValue(format!("'{}' is not a definition version", text.trim()))
+    })
+}
+
+/// Save `version` as the definition version the INI file at `path` was written under
+pub(crate) fn write_version(fs: &dyn Fs, path: &Path, version: u32) -> Result<(), CfgError> {
+    fs.write(&version_path(path), format!("{}\n", version).as_bytes())?;
+    Ok(())
+}
+
+impl Cfg {
+    /// Register `migration` to upgrade INI files written before definition version `version`
+    ///
+    /// Migrations for the same version run in the order registered.
+    pub fn register_migration<F>(&mut self, version: u32, migration: F)
+    where
+        F: Fn(&mut Ini) -> Result<(), CfgError> + Send + Sync + 'static,
+    {
+        self.migrations
+            .entry(version)
+            .or_default()
+            .push(Box::new(migration));
+    }
+
+    /// The `defn_version` of the definition file loaded, if it gives one
+    pub fn defn_version(&self) -> Option<u32> {
+        self.defn_version
+    }
+
+    /// Run the registered migrations the INI file at `path`, read as `documents`, needs to reach
+    /// the loaded definition version, returning the versions migrated to
+    pub(crate) fn migrate_documents(
+        &self,
+        documents: &mut [IniDocument],
+        path: &Path,
+    ) -> Result<Vec<u32>, CfgError> {
+        let target = match self.defn_version {
+            Some(version) if !documents.is_empty() => version,
+            _ => return Ok(Vec::new()),
+        };
+        let from = read_version(&*self.fs, path)?.unwrap_or(0);
+        if from >= target {
+            return Ok(Vec::new());
+        }
+        let mut applied = Vec::new();
+        for (version, migrations) in self.migrations.range(from + 1..=target) {
+            for migration in migrations {
+                for document in documents.iter_mut() {
+                    migration(&mut document.ini)?;
+                }
+            }
+            applied.push(*version);
+        }
+        Ok(applied)
+    }
+}
+
+/// The first key `migrations` renames to that is not in `cfg`, if any
+pub(crate) fn undefined_key<'a>(
+    migrations: &'a [KeyMigration],
+    cfg: &ConfigHash,
+) -> Option<&'a str> {
+    migrations
+        .iter()
+        .map(|m| m.to.as_str())
+        .find(|k| !cfg.contains_key(*k) && !migrations.iter().any(|m| m.from == *k))
+}
+
+/// The renames that take `key` to a defined key, in the order they apply
+pub(crate) fn chain<'a>(migrations: &'a [KeyMigration], key: &str) -> Vec<&'a KeyMigration> {
+    let mut chain: Vec<&KeyMigration> = Vec::new();
+    let mut key = key;
+    // A chain longer than the table must be a cycle
+    while chain.len() < migrations.len() {
+        match migrations.iter().find(|m| m.from == key) {
+            Some(m) => {
+                chain.push(m);
+                key = &m.to;
+            }
+            None => break,
+        }
+    }
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cfg, MemoryFs};
+    use std::sync::Arc;
+
+    const DEFN_DATA: &str = r#"
+        {
+            "ap_mode" : {
+                "prompt": "Access point mode",
+                "tooltip": "Run as a Wi-Fi access point",
+                "current": "0",
+                "default": "0",
+                "format": "[01]",
+                "action": "Edit"
+            },
+            "canid" : {
+                "prompt": "CAN Id",
+                "tooltip": "The CAN Id used by the CAN Pi CAP/Zero on the CBUS",
+                "current": "100",
+                "default": "100",
+                "format": "[0-9]{1,4}",
+                "action": "Edit"
+            },
+            "migrations": [
+                { "from": "ap_on", "to": "ap", "values": { "on": "yes", "off": "no" } },
+                { "from": "ap", "to": "ap_mode", "values": { "yes": "1", "no": "0" } },
+                { "from": "can_id", "to": "canid" }
+            ]
+        }"#;
+
+    #[test]
+    fn renamed_keys_are_migrated() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        fs.insert("canpi.cfg", "ap_on=on\ncanid=101\ncan_id=102\n");
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs);
+        let report = cfg
+            .load_configuration("canpi.cfg", "defn.json")
+            .expect("configuration failed to load");
+        assert_eq!(cfg.get_or_default("ap_mode"), "1");
+        assert_eq!(cfg.get_or_default("canid"), "101");
+        let migrated: Vec<(&str, &str)> = report
+            .migrated_keys
+            .iter()
+            .map(|m| (m.alias.as_str(), m.key.as_str()))
+            .collect();
+        assert_eq!(migrated, vec![("ap_on", "ap_mode"), ("can_id", "canid")]);
+        assert!(report.unknown_keys.is_empty());
+        assert!(report.invalid_values.is_empty());
+    }
+
+    #[test]
+    fn versioned_migrations() {
+        let defn = DEFN_DATA.replacen('{', r#"{ "defn_version": 3,"#, 1);
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", defn);
+        fs.insert("canpi.cfg", "canbus_id=7\n");
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.register_migration(2, |ini| {
+            if let Some(entries) = ini.section_mut(None::<String>) {
+                if let Some(value) = entries.remove("canbus_id") {
+                    entries.insert("canid", value);
+                }
+            }
+            Ok(())
+        });
+        cfg.register_migration(3, |ini| {
+            if let Some(entries) = ini.section_mut(None::<String>) {
+                if let Some(value) = entries.get("canid").and_then(|v| v.parse::<u32>().ok()) {
+                    entries.insert("canid", (value * 100).to_string());
+                }
+            }
+            Ok(())
+        });
+        let report = cfg
+            .load_configuration("canpi.cfg", "defn.json")
+            .expect("configuration failed to load");
+        assert_eq!(cfg.defn_version(), Some(3));
+        assert_eq!(report.migrated_versions, vec![2, 3]);
+        assert_eq!(cfg.get_or_default("canid"), "700");
+
+        cfg.write_cfg_file("canpi.cfg", None).expect("write failed");
+        assert_eq!(fs.contents("canpi.cfg.version").unwrap(), "3\n");
+        let report = cfg
+            .load_configuration("canpi.cfg", "defn.json")
+            .expect("configuration failed to load");
+        assert!(report.migrated_versions.is_empty());
+        assert_eq!(cfg.get_or_default("canid"), "700");
+
+        fs.insert("canpi.cfg.version", "2\n");
+        let report = cfg
+            .load_configuration("canpi.cfg", "defn.json")
+            .expect("configuration failed to load");
+        assert_eq!(report.migrated_versions, vec![3]);
+        assert_eq!(cfg.get_or_default("canid"), "70000");
+    }
+
+    #[test]
+    fn migrations_must_reach_defined_keys() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert(
+            "defn.json",
+            DEFN_DATA.replace(r#""to": "canid""#, r#""to": "can""#),
+        );
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs);
+        let err = cfg.load_definition("defn.json").unwrap_err();
+        assert!(err.to_string().contains("'can'"));
+    }
+}
@@ -0,0 +1,107 @@
+//! Last-modified timestamps for current values
+//!
+//! The time each current value was last changed through the API is kept in memory and saved
+//! alongside the INI file in a JSON sidecar (`<cfg file>.modified.json`) mapping keys to seconds
+//! since the Unix epoch.  The sidecar is read back when the INI file is loaded.
+
+use crate::{Cfg, CfgError, FileAccess, Fs};
+
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Path of the timestamp sidecar for the INI file at `path`
+pub(crate) fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".modified.json");
+    PathBuf::from(name)
+}
+
+/// Read the timestamps saved alongside the INI file at `path`; a missing sidecar gives none
+pub(crate) fn read_sidecar(
+    fs: &dyn Fs,
+    path: &Path,
+) -> Result<HashMap<String, SystemTime>, CfgError> {
+    let file = match fs.open(&sidecar_path(path)) {
+        Ok(f) => f,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let secs: HashMap<String, u64> = serde_json::from_reader(file)?;
+    Ok(secs
+        .into_iter()
+        .map(|(k, s)| (k, UNIX_EPOCH + Duration::from_secs(s)))
+        .collect())
+}
+
+/// Save `timestamps` alongside the INI file at `path`
+pub(crate) fn write_sidecar(
+    fs: &dyn Fs,
+    path: &Path,
+    timestamps: &HashMap<String, SystemTime>,
+) -> Result<(), CfgError> {
+    let sidecar = sidecar_path(path);
+    if timestamps.is_empty() {
+        return match fs.remove(&sidecar) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        };
+    }
+    let secs: HashMap<&String, u64> = timestamps
+        .iter()
+        .map(|(k, t)| {
+            let s = t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            (k, s)
+        })
+        .collect();
+    let contents = serde_json::to_vec_pretty(&secs)?;
+    fs.write_atomic(&sidecar, &contents, &FileAccess::default())?;
+    Ok(())
+}
+
+impl Cfg {
+    /// When the current value of `key` was last changed, if that is known
+    ///
+    /// Changes made through [`Cfg::set_value`] or [`Cfg::write_attribute`] are recorded, as are
+    /// those saved in the sidecar of the INI file that was loaded.
+    pub fn last_modified<K: AsRef<str>>(&self, key: K) -> Option<SystemTime> {
+        let key = key.as_ref();
+        self.modified.get(key).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryFs;
+
+    #[test]
+    fn sidecar_is_replaced() {
+        let fs = MemoryFs::new();
+        let path = Path::new("/canpi/canpi.cfg");
+        fs.insert("/canpi/canpi.cfg.modified.json", r#"{"canid": 1}"#);
+        let timestamps: HashMap<String, SystemTime> = vec![
+            (
+                "canid".to_string(),
+                UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            ),
+            (
+                "node_number".to_string(),
+                UNIX_EPOCH + Duration::from_secs(1_700_000_001),
+            ),
+        ]
+        .into_iter()
+        .collect();
+        write_sidecar(&fs, path, &timestamps).expect("write failed");
+        assert_eq!(read_sidecar(&fs, path).unwrap(), timestamps);
+        assert_eq!(
+            fs.paths(),
+            vec![PathBuf::from("/canpi/canpi.cfg.modified.json")]
+        );
+
+        write_sidecar(&fs, path, &HashMap::new()).expect("write failed");
+        assert!(read_sidecar(&fs, path).unwrap().is_empty());
+        assert!(fs.paths().is_empty());
+    }
+}
@@ -0,0 +1,140 @@
+//! Notification of saved changes
+//!
+//! Any number of [`Notifier`]s can be registered on a [`Cfg`] with [`Cfg::add_notifier`].  Each
+//! is handed a [`ChangeSummary`] after every successful write of the INI file, so new transports
+//! (MQTT, D-Bus, ...) can be added without touching the save logic.  [`LogNotifier`] logs the
+//! changes and, with the `http` feature, [`WebhookNotifier`](crate::WebhookNotifier) posts them
+//! to a URL.
+
+use crate::audit::{epoch_secs, AuditEntry};
+use crate::Cfg;
+
+use serde_json::json;
+use tracing::{info, warn};
+
+use std::error::Error;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// The error type returned by notifiers
+pub type NotifyError = Box<dyn Error + Send + Sync>;
+
+#[derive(Clone, Copy, Debug)]
+/// The changes written to an INI file by a single save
+pub struct ChangeSummary<'a> {
+    /// The INI file written
+    pub file: &'a Path,
+    /// Backup of the previous file, if one was taken
+    pub backup: Option<&'a Path>,
+    /// The changes made since the previous save, oldest first
+    pub changes: &'a [AuditEntry],
+}
+
+impl ChangeSummary<'_> {
+    /// The summary as a JSON object with `file`, `backup`, `time` and `changes` members
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "file": self.file.display().to_string(),
+            "backup": self.backup.map(|b| b.display().to_string()),
+            "time": epoch_secs(SystemTime::now()),
+            "changes": self.changes.iter().map(AuditEntry::to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Receives a summary of the changes after each successful save
+pub trait Notifier: Send + Sync {
+    /// Deliver `summary`; an error is reported but does not fail the save
+    fn notify(&self, summary: &ChangeSummary) -> Result<(), NotifyError>;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+/// Logs each change saved as a `tracing` event at `info` level
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn notify(&self, summary: &ChangeSummary) -> Result<(), NotifyError> {
+        for change in summary.changes {
+            let by = change
+                .annotation
+                .as_ref()
+                .map(|a| format!(" by {} ({})", a.actor, a.reason))
+                .unwrap_or_default();
+            info!(
+                "{}: '{}' changed from '{}' to '{}'{}",
+                summary.file.display(),
+                change.key,
+                change.old,
+                change.new,
+                by
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Cfg {
+    /// Register `notifier` to be told of the changes written by each successful save
+    pub fn add_notifier<N: Notifier + 'static>(&mut self, notifier: N) {
+        self.notifiers.push(Box::new(notifier));
+    }
+
+    /// Pass `summary` to every registered notifier, logging any that fail as `tracing` warnings
+    pub(crate) fn notify(&self, summary: &ChangeSummary) {
+        for notifier in &self.notifiers {
+            if let Err(err) = notifier.notify(summary) {
+                warn!(error = %err, "failed to send change notification");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{load_defn, CANID_DISPLAY_DEFN};
+    use crate::WriteOptions;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Notifier for Recorder {
+        fn notify(&self, summary: &ChangeSummary) -> Result<(), NotifyError> {
+            let keys = summary.changes.iter().map(|c| c.key.as_str());
+            self.0
+                .lock()
+                .unwrap()
+                .push(keys.collect::<Vec<_>>().join(","));
+            Ok(())
+        }
+    }
+
+    struct Failing;
+
+    impl Notifier for Failing {
+        fn notify(&self, _summary: &ChangeSummary) -> Result<(), NotifyError> {
+            Err("transport down".into())
+        }
+    }
+
+    #[test]
+    fn notifiers_see_each_save() {
+        let cfg_file = "scratch/notify_test.cfg";
+        let mut cfg = load_defn("scratch/notify_test.json", CANID_DISPLAY_DEFN);
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        cfg.add_notifier(Failing);
+        cfg.add_notifier(Recorder(Arc::clone(&seen)));
+        cfg.add_notifier(LogNotifier);
+        cfg.set_value("canid", "101").expect("set_value failed");
+        cfg.write_cfg_file_with(cfg_file, &WriteOptions::default())
+            .expect("Failed to write cfg file");
+        cfg.write_cfg_file_with(cfg_file, &WriteOptions::default())
+            .expect("Failed to write cfg file");
+        assert_eq!(*seen.lock().unwrap(), vec!["canid", ""]);
+        fs::remove_file(crate::modified::sidecar_path(Path::new(cfg_file)))
+            .expect("file deletion failed");
+        fs::remove_file(cfg_file).expect("file deletion failed");
+    }
+}
@@ -0,0 +1,206 @@
+//! Options controlling how configuration files are read and written
+
+use crate::FileOwner;
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Prefix of the environment variables that override INI values by default
+pub const ENV_PREFIX: &str = "CANPI_";
+
+#[derive(Clone, Debug)]
+/// Options for [`Cfg::load_configuration_with`](crate::Cfg::load_configuration_with)
+pub struct LoadOptions {
+    /// Name of an INI section whose keys are treated as belonging to the general section
+    ///
+    /// Keys in the named section take precedence over the same keys in the general section.
+    pub general_section: Option<String>,
+    /// Treat indented lines as continuations of the previous value
+    ///
+    /// Leave this off for files whose keys are indented.
+    pub multiline_values: bool,
+    /// Directive that includes another INI file, e.g. `!include`; `None` disables includes
+    ///
+    /// A line holding the directive, whitespace and a file name is replaced by the entries of
+    /// that file.  Entries after the directive override those included.
+    pub include_directive: Option<String>,
+    /// Replace `${VAR}` references in values with the environment variable `VAR`
+    ///
+    /// `${VAR:-fallback}` supplies a value for when `VAR` is unset and `$$` gives a literal `$`.
+    pub expand_env: bool,
+    /// Policy file of cross-field rules to load once the definitions are read
+    ///
+    /// See [`Policy`](crate::Policy) for the format.
+    pub policy: Option<PathBuf>,
+    /// Load every item with its default when the INI file does not exist, as on first boot
+    ///
+    /// Without this a missing INI file is an error.
+    pub allow_missing_file: bool,
+    /// What happens to INI values that do not satisfy their format
+    pub invalid_values: InvalidValues,
+    /// Prefix of the environment variables that override INI values; `None` ignores the
+    /// environment
+    ///
+    /// The variable for a key is the prefix followed by the key in upper case with anything but
+    /// letters and digits replaced by `_`, e.g. `CANPI_NODE_NUMBER` for `node_number`.  Defaults
+    /// to [`ENV_PREFIX`].  Values from the environment are not written back to the INI file.
+    pub env_prefix: Option<String>,
+    /// What happens to fields of the definition file that the crate does not know
+    pub unknown_fields: UnknownFields,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        LoadOptions {
+            general_section: None,
+            multiline_values: false,
+            include_directive: None,
+            expand_env: false,
+            policy: None,
+            allow_missing_file: false,
+            invalid_values: InvalidValues::default(),
+            env_prefix: Some(ENV_PREFIX.to_string()),
+            unknown_fields: UnknownFields::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// How [`LoadOptions::invalid_values`] treats INI values that do not satisfy their format
+pub enum InvalidValues {
+    /// Keep the value and list it in [`LoadReport::invalid_values`](crate::LoadReport)
+    #[default]
+    Warn,
+    /// Fail the load with [`CfgError::InvalidValue`](crate::CfgError) for the first such value
+    Reject,
+    /// Use the default instead, listing the value in
+    /// [`LoadReport::replaced_values`](crate::LoadReport)
+    UseDefault,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// How [`LoadOptions::unknown_fields`] treats fields of the definition file that the crate does
+/// not know: fields of an attribute and top level members that are not attributes
+pub enum UnknownFields {
+    /// Drop unknown fields of attributes; a top level member that is not an attribute fails the
+    /// schema
+    #[default]
+    Ignore,
+    /// Drop them all, listing them in [`LoadReport::unknown_fields`](crate::LoadReport)
+    Warn,
+    /// Fail the load with [`CfgError::UnknownField`](crate::CfgError) for the first of them
+    Reject,
+}
+
+#[derive(Clone, Debug, Default)]
+/// Options for [`Cfg::write_cfg_file_with`](crate::Cfg::write_cfg_file_with)
+pub struct WriteOptions {
+    /// Back up the existing INI file before it is replaced
+    ///
+    /// How is decided by the [`BackupStrategy`](crate::BackupStrategy) of the Cfg.
+    pub make_backup: bool,
+    /// Keep the backup in this directory, e.g. on a USB stick, rather than where the backup
+    /// strategy would put it
+    ///
+    /// List the backups kept there with
+    /// [`Cfg::list_backups_in`](crate::Cfg::list_backups_in).
+    pub backup_dir: Option<PathBuf>,
+    /// Write the general section keys under this section name rather than before any header
+    pub general_section: Option<String>,
+    /// Write values containing newlines as indented continuation lines instead of escaping them
+    ///
+    /// Files written this way must be loaded with `LoadOptions::multiline_values` set.
+    pub multiline_values: bool,
+    /// Precede each entry with a comment saying where its value came from
+    pub annotate_provenance: bool,
+    /// Append unsaved changes to the audit log and list any backup in the backup manifest
+    ///
+    /// Both are JSON lines files kept alongside the INI file; see [`Annotation`].
+    ///
+    /// [`Annotation`]: crate::Annotation
+    pub audit_log: bool,
+    /// Update the values in the existing file, keeping its comments, blank lines and key order
+    ///
+    /// Keys missing from the file are added to their sections.  Provenance comments are not
+    /// written in this mode.  A file that does not exist yet is written as usual.
+    pub preserve_layout: bool,
+    /// Take the writers' lock on the INI file for the write, waiting up to this long for another
+    /// writer to finish; `None` writes without locking
+    ///
+    /// See [`Cfg::lock_cfg_file`](crate::Cfg::lock_cfg_file).
+    pub lock_timeout: Option<Duration>,
+    /// Make the INI file readable and writable by its owner only (mode 0600)
+    ///
+    /// `None` does so when any attribute is `secret`.
+    pub private_file: Option<bool>,
+    /// Give the INI file to this user and group, e.g. that of the canpi service
+    ///
+    /// See [`FileOwner::of_user`](crate::FileOwner::of_user).
+    pub owner: Option<FileOwner>,
+    /// Read the INI file back once written and fail with
+    /// [`CfgError::Verify`](crate::CfgError::Verify) if it does not hold what was written
+    pub verify: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// The regular expression engine formats are compiled with
+pub enum RegexEngine {
+    /// The `regex` crate: linear time matching without look-around or backreferences
+    #[default]
+    Standard,
+    /// The `fancy-regex` crate: adds look-around and backreferences, using backtracking
+    #[cfg(feature = "fancy-regex")]
+    Fancy,
+}
+
+#[derive(Clone, Debug)]
+/// Options for compiling attribute formats, see [`Cfg::set_regex_options`](crate::Cfg::set_regex_options)
+pub struct RegexOptions {
+    /// The engine used
+    pub engine: RegexEngine,
+    /// Make `\w`, `\d`, `\s` and case-insensitive matching Unicode-aware (the default)
+    ///
+    /// With this off those classes are ASCII only, and formats that could match text that is
+    /// not UTF-8, such as `.` or `[^a]`, fail to compile.
+    pub unicode: bool,
+    /// Limit in bytes on the size of a compiled format; `None` keeps the engine's default
+    pub size_limit: Option<usize>,
+    /// Steps the fancy engine may backtrack before giving up; `None` keeps its default
+    pub backtrack_limit: Option<usize>,
+    /// Accept values where any format matches only part of the value, as if every attribute set
+    /// `partial_match`
+    ///
+    /// Formats used to be matched anywhere in the value; this keeps definitions written for that
+    /// working until their formats are anchored.
+    pub partial_match: bool,
+}
+
+impl Default for RegexOptions {
+    fn default() -> Self {
+        RegexOptions {
+            engine: RegexEngine::default(),
+            unicode: true,
+            size_limit: None,
+            backtrack_limit: None,
+            partial_match: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// Limits on input supplied through the API, see [`Cfg::set_input_limits`](crate::Cfg::set_input_limits)
+pub struct InputLimits {
+    /// Longest value accepted for a configuration item, in bytes (default 1024)
+    pub max_value_len: usize,
+    /// Longest JSON Patch document accepted, in bytes (default 64 KiB)
+    pub max_patch_len: usize,
+}
+
+impl Default for InputLimits {
+    fn default() -> Self {
+        InputLimits {
+            max_value_len: 1024,
+            max_patch_len: 64 * 1024,
+        }
+    }
+}
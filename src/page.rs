@@ -0,0 +1,65 @@
+//! Paged listing of configuration items
+//!
+//! Items are put in a stable order before the page is cut so that consecutive requests with
+//! increasing offsets visit every item exactly once.  Only the items on the requested page are
+//! cloned.
+
+use crate::{Attribute, Cfg};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Order in which [`Cfg::page`] lists configuration items
+pub enum SortBy {
+    /// Ascending by key
+    Key,
+    /// Ascending by prompt text, then by key
+    Prompt,
+    /// Edit items first, then Display and then Hide, each group by key
+    Action,
+}
+
+#[derive(Clone, Debug)]
+/// One page of configuration items
+pub struct Page {
+    /// The items on this page as (key, attribute) pairs
+    pub items: Vec<(String, Attribute)>,
+    /// Offset of the first item on this page
+    pub offset: usize,
+    /// Total number of items available across all pages
+    pub total: usize,
+}
+
+impl Page {
+    /// True if there are items beyond this page
+    pub fn has_more(&self) -> bool {
+        self.offset + self.items.len() < self.total
+    }
+}
+
+impl Cfg {
+    /// Return at most `limit` configuration items starting at `offset` in the order `sort`
+    ///
+    /// An `offset` beyond the last item gives an empty page; `total` is always the full count.
+    pub fn page(&self, offset: usize, limit: usize, sort: SortBy) -> Page {
+        let mut refs: Vec<(&String, &Attribute)> = match &self.cfg {
+            Some(cfg) => cfg.iter().collect(),
+            None => Vec::new(),
+        };
+        match sort {
+            SortBy::Key => refs.sort_by(|a, b| a.0.cmp(b.0)),
+            SortBy::Prompt => refs.sort_by(|a, b| a.1.prompt.cmp(&b.1.prompt).then(a.0.cmp(b.0))),
+            SortBy::Action => refs.sort_by(|a, b| a.1.action.cmp(&b.1.action).then(a.0.cmp(b.0))),
+        }
+        let total = refs.len();
+        let items = refs
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        Page {
+            items,
+            offset,
+            total,
+        }
+    }
+}
@@ -0,0 +1,377 @@
+//! JSON Patch (RFC 6902) for current values
+
+use crate::{ini_reader, Cfg, CfgError, LoadOptions};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug, Deserialize)]
+struct Operation {
+    op: String,
+    path: String,
+    value: Option<Value>,
+    from: Option<String>,
+}
+
+/// Convert a JSON pointer naming a single configuration item to its key
+fn pointer_key(pointer: &str) -> Result<String, CfgError> {
+    match pointer.strip_prefix('/') {
+        Some(token) if !token.contains('/') => Ok(token.replace("~1", "/").replace("~0", "~")),
+        _ => Err(CfgError::Patch(format!(
+            "'{}' does not name a configuration item",
+            pointer
+        ))),
+    }
+}
+
+/// Convert a configuration key to a JSON pointer
+fn key_pointer(key: &str) -> String {
+    format!("/{}", key.replace('~', "~0").replace('/', "~1"))
+}
+
+/// The value of an operation as INI text
+fn value_text(op: &Operation) -> Result<String, CfgError> {
+    match &op.value {
+        Some(Value::String(s)) => Ok(s.clone()),
+        Some(v @ Value::Number(_)) | Some(v @ Value::Bool(_)) => Ok(v.to_string()),
+        Some(_) => Err(CfgError::Patch(format!(
+            "{} '{}': value must be a string, number or boolean",
+            op.op, op.path
+        ))),
+        None => Err(CfgError::Patch(format!(
+            "{} '{}' has no value",
+            op.op, op.path
+        ))),
+    }
+}
+
+impl Cfg {
+    /// Compare the INI files `old` and `new` and produce a JSON Patch turning one into the other
+    ///
+    /// Keys are named as when loading with `options`, so sections become dotted prefixes.  Keys
+    /// only in `new` are added, changed values are replaced and keys only in `old` are removed,
+    /// which [`Cfg::apply_json_patch`] treats as resetting them to their defaults.  The patch can
+    /// be applied to any configuration with the same definition.  The files are read through the
+    /// filesystem set with [`Cfg::set_fs`].
+    ///
+    /// The definition must be loaded, so that the values of secret items can be redacted as
+    /// [`REDACTED`](crate::REDACTED) unless [`Cfg::set_export_secrets`] is set.
+    pub fn cfg_file_patch<P: AsRef<Path>>(
+        &self,
+        old: P,
+        new: P,
+        options: &LoadOptions,
+    ) -> Result<String, CfgError> {
+        let cfg = self.cfg.as_ref().ok_or(CfgError::Cfg())?;
+        let old = ini_reader::read_values(&*self.fs, old.as_ref(), options)?;
+        let new = ini_reader::read_values(&*self.fs, new.as_ref(), options)?;
+        let mut operations = Vec::new();
+        for (key, value) in &new {
+            let op = match old.get(key) {
+                Some(v) if v == value => continue,
+                Some(_) => "replace",
+                None => "add",
+            };
+            let value = match cfg.get(key) {
+                Some(attr) => self.exported(attr, value),
+                None => value,
+            };
+            operations.push(json!({ "op": op, "path": key_pointer(key), "value": value }));
+        }
+        for key in old.keys().filter(|k| !new.contains_key(*k)) {
+            operations.push(json!({ "op": "remove", "path": key_pointer(key) }));
+        }
+        Ok(serde_json::to_string_pretty(&operations)?)
+    }
+
+    /// Apply the JSON Patch document `patch` to the current values
+    ///
+    /// Each configuration item is addressed by a JSON pointer made from its key, e.g. `/canid`.
+    /// The operations act on current values only, since the definition fixes which items exist:
+    ///
+    /// | Operation           | Effect                                                     |
+    /// |---------------------|------------------------------------------------------------|
+    /// | `add`, `replace`    | Set the current value to `value`                           |
+    /// | `remove`            | Reset the current value to the default                     |
+    /// | `test`              | Fail the patch unless the current value equals `value`     |
+    /// | `copy`, `move`      | Set the value at `path` from `from`; `move` resets `from`  |
+    ///
+    /// Values may be JSON strings, numbers or booleans, read as by [`Cfg::set_value`] so that e.g.
+    /// `true` sets a Boolean item to `1`; strings use the number locale.  The value of a secret
+    /// item may only be copied or moved to another secret item.  Nothing is changed unless every operation succeeds and every new
+    /// value is within the input limits and satisfies its format, `min`, `max` and `choices`.
+    pub fn apply_json_patch(&mut self, patch: &str) -> Result<(), CfgError> {
+        self.check_patch_len(patch)?;
+        let operations: Vec<Operation> = serde_json::from_str(patch)?;
+        let cfg = self.cfg.as_ref().ok_or(CfgError::Cfg())?;
+        // New values by key, in the order first changed
+        let mut staged: HashMap<String, String> = HashMap::new();
+        let mut order: Vec<String> = Vec::new();
+        for op in &operations {
+            let key = pointer_key(&op.path)?;
+            let attr = cfg
+                .get(&key)
+                .ok_or_else(|| CfgError::UnknownKey(key.clone()))?;
+            let current = |k: &str| {
+                staged
+                    .get(k)
+                    .cloned()
+                    .or_else(|| cfg.get(k).map(|a| a.current.clone()))
+            };
+            let mut changes = Vec::new();
+            match op.op.as_str() {
+                "add" | "replace" => {
+                    let value = value_text(op)?;
+                    // A JSON number is already in canonical form, whatever the number locale
+                    let value = match &op.value {
+                        Some(Value::Number(_)) if attr.is_numeric() => value,
+                        _ => self.canonical_input(&key, attr, &value)?.into_owned(),
+                    };
+                    changes.push((key, value))
+                }
+                "remove" => changes.push((key, attr.default.clone())),
+                "test" => {
+                    let expected = value_text(op)?;
+                    if current(&key).as_deref() != Some(expected.as_str()) {
+                        return Err(CfgError::Patch(format!(
+                            "test '{}' failed: value is not '{}'",
+                            op.path, expected
+                        )));
+                    }
+                }
+                "copy" | "move" => {
+                    let from = op.from.as_deref().ok_or_else(|| {
+                        CfgError::Patch(format!("{} '{}' has no from", op.op, op.path))
+                    })?;
+                    let from_key = pointer_key(from)?;
+                    let value =
+                        current(&from_key).ok_or_else(|| CfgError::UnknownKey(from_key.clone()))?;
+                    if cfg[&from_key].secret && !attr.secret {
+                        return Err(CfgError::Patch(format!(
+                            "{} '{}': the secret '{}' cannot be copied to an item that is not secret",
+                            op.op, op.path, from
+                        )));
+                    }
+                    if op.op == "move" && from_key != key {
+                        changes.push((from_key.clone(), cfg[&from_key].default.clone()));
+                    }
+                    changes.push((key, value));
+                }
+                other => {
+                    return Err(CfgError::Patch(format!(
+                        "unsupported operation '{}'",
+                        other
+                    )))
+                }
+            }
+            for (k, v) in changes {
+                if !staged.contains_key(&k) {
+                    order.push(k.clone());
+                }
+                staged.insert(k, v);
+            }
+        }
+
+        for key in &order {
+            let attr = &cfg[key];
+            self.check_value_len(key, &staged[key])?;
+            let value = attr.sanitize(key, &staged[key])?;
+            self.check_constraints(key, attr, &value)?;
+        }
+        let changes: Vec<(&str, &str)> = order
+            .iter()
+            .map(|k| (k.as_str(), staged[k].as_str()))
+            .collect();
+        self.store_changes(&changes, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryFs;
+    use std::fs;
+    use std::sync::Arc;
+
+    const DEFN_DATA: &str = r#"
+        {
+            "canid" : {
+                "prompt": "CAN Id",
+                "tooltip": "The CAN Id used by the CAN Pi CAP/Zero on the CBUS",
+                "current": "100",
+                "default": "100",
+                "format": "^[0-9]{1,4}$",
+                "action": "Display"
+            },
+            "node_number" : {
+                "prompt": "Node Number",
+                "tooltip": "The node number of the CAN Pi CAP/Zero on the CBUS",
+                "current": "7",
+                "default": "4321",
+                "format": "^[0-9]{1,5}$",
+                "action": "Edit",
+                "max": 9999
+            },
+            "router_password" : {
+                "prompt": "Router password",
+                "tooltip": "",
+                "current": "secret",
+                "default": "secret",
+                "format": "^[a-z]+$",
+                "action": "Edit",
+                "secret": true
+            },
+            "ap/ssid" : {
+                "prompt": "SSID",
+                "tooltip": "Access point network name",
+                "current": "canpi",
+                "default": "canpi",
+                "format": ".*",
+                "action": "Edit"
+            },
+            "ap_mode" : {
+                "prompt": "Access point mode",
+                "tooltip": "",
+                "current": "0",
+                "default": "0",
+                "format": "[01]",
+                "action": "Edit",
+                "kind": "Boolean"
+            }
+        }"#;
+
+    fn setup(name: &str) -> Cfg {
+        let defn_file = format!("scratch/{}.json", name);
+        fs::write(&defn_file, DEFN_DATA).expect("file write failed");
+        let mut cfg = Cfg::new();
+        cfg.load_definition(&defn_file)
+            .expect("definition failed to load");
+        fs::remove_file(defn_file).expect("file deletion failed");
+        cfg
+    }
+
+    fn current(cfg: &Cfg, key: &str) -> String {
+        cfg.read_attribute(key).unwrap().current.clone()
+    }
+
+    #[test]
+    fn operations_apply() {
+        let mut cfg = setup("patch_apply_test");
+        cfg.apply_json_patch(
+            r#"[
+                { "op": "test", "path": "/canid", "value": 100 },
+                { "op": "replace", "path": "/canid", "value": 101 },
+                { "op": "remove", "path": "/node_number" },
+                { "op": "add", "path": "/ap~1ssid", "value": "club" }
+            ]"#,
+        )
+        .expect("patch failed");
+        assert_eq!(current(&cfg, "canid"), "101");
+        assert_eq!(current(&cfg, "node_number"), "4321");
+        assert_eq!(current(&cfg, "ap/ssid"), "club");
+
+        cfg.apply_json_patch(r#"[ { "op": "move", "from": "/canid", "path": "/node_number" } ]"#)
+            .expect("patch failed");
+        assert_eq!(current(&cfg, "canid"), "100");
+        assert_eq!(current(&cfg, "node_number"), "101");
+    }
+
+    #[test]
+    fn patch_between_files() {
+        let mut cfg = setup("patch_files_test");
+        let mem = Arc::new(MemoryFs::new());
+        mem.insert("old.cfg", "canid=100\nnode_number=7\n");
+        mem.insert("new.cfg", "canid=101\n[ap]\nssid=club\n");
+        cfg.set_fs(mem);
+        let patch = cfg
+            .cfg_file_patch("old.cfg", "new.cfg", &LoadOptions::default())
+            .expect("patch generation failed");
+        let ops: Value = serde_json::from_str(&patch).expect("patch is not JSON");
+        assert_eq!(
+            ops,
+            json!([
+                { "op": "add", "path": "/ap.ssid", "value": "club" },
+                { "op": "replace", "path": "/canid", "value": "101" },
+                { "op": "remove", "path": "/node_number" }
+            ])
+        );
+        assert_eq!(key_pointer("ap/ssid~1"), "/ap~1ssid~01");
+        assert_eq!(pointer_key(&key_pointer("ap/ssid~1")).unwrap(), "ap/ssid~1");
+
+        let patch = patch.replace("/ap.ssid", "/ap~1ssid");
+        cfg.apply_json_patch(&patch).expect("patch failed");
+        assert_eq!(current(&cfg, "canid"), "101");
+        assert_eq!(current(&cfg, "node_number"), "4321");
+        assert_eq!(current(&cfg, "ap/ssid"), "club");
+    }
+
+    #[test]
+    fn failed_patch_changes_nothing() {
+        let mut cfg = setup("patch_reject_test");
+        let rejected = [
+            r#"[ { "op": "replace", "path": "/canid", "value": 5 },
+                 { "op": "replace", "path": "/node_number", "value": "abc" } ]"#,
+            r#"[ { "op": "replace", "path": "/canid", "value": 5 },
+                 { "op": "test", "path": "/canid", "value": "100" } ]"#,
+            r#"[ { "op": "replace", "path": "/canid", "value": 5 },
+                 { "op": "add", "path": "/no_such_key", "value": 1 } ]"#,
+            r#"[ { "op": "replace", "path": "/canid/current", "value": 5 } ]"#,
+            r#"[ { "op": "frobnicate", "path": "/canid" } ]"#,
+            r#"[ { "op": "replace", "path": "/canid" } ]"#,
+            r#"[ { "op": "replace", "path": "/node_number", "value": 10000 } ]"#,
+        ];
+        for patch in rejected {
+            assert!(cfg.apply_json_patch(patch).is_err(), "{} applied", patch);
+        }
+        assert_eq!(current(&cfg, "canid"), "100");
+        assert!(cfg.audit_log().is_empty());
+
+        let err = cfg
+            .apply_json_patch(
+                r#"[ { "op": "replace", "path": "/router_password", "value": "Hunter2" } ]"#,
+            )
+            .unwrap_err();
+        assert!(!err.to_string().contains("Hunter2"), "{}", err);
+    }
+
+    #[test]
+    fn values_of_any_type_are_canonical() {
+        let mut cfg = setup("patch_canonical_test");
+        cfg.apply_json_patch(r#"[ { "op": "replace", "path": "/ap_mode", "value": true } ]"#)
+            .expect("patch failed");
+        assert_eq!(current(&cfg, "ap_mode"), "1");
+        cfg.apply_json_patch(r#"[ { "op": "replace", "path": "/ap_mode", "value": "off" } ]"#)
+            .expect("patch failed");
+        assert_eq!(current(&cfg, "ap_mode"), "0");
+        cfg.apply_json_patch(r#"[ { "op": "replace", "path": "/ap_mode", "value": 1 } ]"#)
+            .expect("patch failed");
+        assert_eq!(current(&cfg, "ap_mode"), "1");
+        assert!(cfg
+            .apply_json_patch(r#"[ { "op": "replace", "path": "/ap_mode", "value": 2 } ]"#)
+            .is_err());
+    }
+
+    #[test]
+    fn secrets_stay_secret() {
+        let mut cfg = setup("patch_secret_test");
+        for op in ["copy", "move"] {
+            let patch = format!(
+                r#"[ {{ "op": "{}", "from": "/router_password", "path": "/ap~1ssid" }} ]"#,
+                op
+            );
+            assert!(cfg.apply_json_patch(&patch).is_err(), "{} applied", op);
+        }
+        assert_eq!(current(&cfg, "ap/ssid"), "canpi");
+        assert_eq!(current(&cfg, "router_password"), "secret");
+        assert!(cfg.audit_log().is_empty());
+
+        cfg.apply_json_patch(
+            r#"[ { "op": "copy", "from": "/ap~1ssid", "path": "/router_password" } ]"#,
+        )
+        .expect("patch failed");
+        assert_eq!(current(&cfg, "router_password"), "canpi");
+    }
+}
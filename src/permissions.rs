@@ -0,0 +1,124 @@
+//! Who may read a written INI file
+//!
+//! The INI file holds Wi-Fi credentials, so when any attribute is `secret` it is written
+//! readable and writable by its owner only (mode 0600), and may be given to the user the canpi
+//! service runs as.  See [`WriteOptions::private_file`] and [`WriteOptions::owner`].
+//!
+//! [`WriteOptions::private_file`]: crate::WriteOptions::private_file
+//! [`WriteOptions::owner`]: crate::WriteOptions::owner
+
+use std::fs;
+use std::io::{self, ErrorKind};
+
+/// The mode of a private file
+pub const PRIVATE_MODE: u32 = 0o600;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The user and group a file is given to
+pub struct FileOwner {
+    /// User id
+    pub uid: u32,
+    /// Group id
+    pub gid: u32,
+}
+
+impl FileOwner {
+    /// The user `name`, with their primary group, as listed in `/etc/passwd`
+    pub fn of_user(name: &str) -> io::Result<FileOwner> {
+        let passwd = fs::read_to_string("/etc/passwd")?;
+        passwd_entry(&passwd, name)
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("no user named '{}'", name)))
+    }
+}
+
+/// The owner for user `name` in the text of a passwd file
+fn passwd_entry(passwd: &str, name: &str) -> Option<FileOwner> {
+    passwd.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next() != Some(name) {
+            return None;
+        }
+        let mut ids = fields.skip(1).map(|f| f.parse().ok());
+        Some(FileOwner {
+            uid: ids.next()??,
+            gid: ids.next()??,
+        })
+    })
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// Restrictions applied to a file as it is written
+pub struct FileAccess {
+    /// Make the file readable and writable by its owner only
+    pub private: bool,
+    /// Give the file to this user and group
+    pub owner: Option<FileOwner>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cfg, StdFs, WriteOptions};
+    use std::sync::Arc;
+
+    const DEFN_DATA: &str = r#"
+        {
+            "router_password" : {
+                "prompt": "Router password", "tooltip": "", "current": "", "default": "",
+                "format": ".*", "action": "Edit", "secret": true
+            }
+        }"#;
+
+    #[test]
+    fn passwd_entries() {
+        let passwd = "root:x:0:0:root:/root:/bin/bash\ncanpi:x:1001:1002::/home/canpi:/bin/sh\n";
+        assert_eq!(
+            passwd_entry(passwd, "canpi"),
+            Some(FileOwner {
+                uid: 1001,
+                gid: 1002
+            })
+        );
+        assert_eq!(passwd_entry(passwd, "pi"), None);
+        assert_eq!(passwd_entry("pi:x:bad:1000::/:/bin/sh", "pi"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn secrets_are_written_privately() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("canpi-private-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("cannot create test directory");
+        let path = dir.join("canpi.cfg");
+        let mode = |path: &std::path::Path| fs::metadata(path).unwrap().permissions().mode();
+        let mut cfg = Cfg::new();
+        cfg.set_fs(Arc::new(StdFs));
+        cfg.load_configuration_from_str("router_password=hunter22\n", DEFN_DATA)
+            .expect("configuration failed to load");
+        cfg.write_cfg_file(&path, None).expect("write failed");
+        assert_eq!(mode(&path) & 0o777, PRIVATE_MODE);
+        let env = dir.join("canpi.env");
+        cfg.write_cfg_env(&env).expect("write failed");
+        assert_eq!(mode(&env) & 0o777, PRIVATE_MODE);
+
+        let options = WriteOptions {
+            private_file: Some(false),
+            ..WriteOptions::default()
+        };
+        cfg.write_cfg_file_with(&path, &options)
+            .expect("write failed");
+        assert_eq!(mode(&path) & 0o777, PRIVATE_MODE);
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        cfg.write_cfg_file_with(&path, &options)
+            .expect("write failed");
+        assert_eq!(mode(&path) & 0o777, 0o640);
+        let plain = dir.join("plain.cfg");
+        cfg.write_cfg_file_with(&plain, &options)
+            .expect("write failed");
+        fs::write(dir.join("umask"), "").expect("cannot write test file");
+        assert_eq!(mode(&plain), mode(&dir.join("umask")));
+        fs::remove_dir_all(&dir).expect("cannot remove test directory");
+    }
+}
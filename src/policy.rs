@@ -0,0 +1,445 @@
+//! Cross-field constraint rules
+//!
+//! A policy file is a JSON document, validated against a schema generated from [`Policy`], that
+//! lists rules relating the values of several configuration items, e.g.
+//!
+//! ```json
+//! { "rules": [
+//!     { "rule": "mutually_exclusive", "keys": ["ap_mode", "router_mode"] },
+//!     { "rule": "required_if", "key": "router_ssid", "when": "router_mode", "equals": "true" },
+//!     { "rule": "compare", "left": "start_event_id", "relation": "<=", "right": 99 }
+//! ] }
+//! ```
+//!
+//! A value counts as *set* for `mutually_exclusive` unless it is empty or one of `0`, `false`,
+//! `no` or `off` (ignoring case).  A comparison may be limited to when another item holds a
+//! value, e.g. `"when": { "key": "node_mode", "value": "1" }`.
+//!
+//! The same rules may be given as a top-level `rules` array in the definition file, where they
+//! are checked along with those of the policy by [`Cfg::validate_all`].
+
+use crate::context::ResultExt;
+use crate::trace::Timed;
+use crate::{Cfg, CfgError, ConfigHash, Dependency, Operation};
+
+use jsonschema::JSONSchema;
+use once_cell::sync::Lazy;
+use schemars::{schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::field::Empty;
+use tracing::info_span;
+
+use std::fmt;
+use std::path::Path;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema, PartialEq)]
+/// A set of rules loaded from a policy file
+pub struct Policy {
+    /// The rules, all of which must hold
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+/// A constraint relating the current values of configuration items
+pub enum Rule {
+    /// At most one of `keys` may be set
+    MutuallyExclusive { keys: Vec<String> },
+    /// `key` must not be empty when the value of `when` is `equals`
+    RequiredIf {
+        key: String,
+        when: String,
+        equals: String,
+    },
+    /// The numeric value of `left` must stand in `relation` to `right`, if `when` is met
+    Compare {
+        left: String,
+        relation: Relation,
+        right: Operand,
+        #[serde(default)]
+        when: Option<Dependency>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+/// A numeric comparison
+pub enum Relation {
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    Le,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = ">=")]
+    Ge,
+    #[serde(rename = "==")]
+    Eq,
+    #[serde(rename = "!=")]
+    Ne,
+}
+
+impl Relation {
+    fn holds(self, left: f64, right: f64) -> bool {
+        match self {
+            Relation::Lt => left < right,
+            Relation::Le => left <= right,
+            Relation::Gt => left > right,
+            Relation::Ge => left >= right,
+            Relation::Eq => left == right,
+            Relation::Ne => left != right,
+        }
+    }
+}
+
+impl fmt::Display for Relation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Relation::Lt => "<",
+            Relation::Le => "<=",
+            Relation::Gt => ">",
+            Relation::Ge => ">=",
+            Relation::Eq => "==",
+            Relation::Ne => "!=",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(untagged)]
+/// The right hand side of a comparison: a number or the key of another configuration item
+pub enum Operand {
+    Number(f64),
+    Key(String),
+}
+
+impl Rule {
+    /// Keys of the configuration items the rule refers to
+    fn keys(&self) -> Vec<&str> {
+        match self {
+            Rule::MutuallyExclusive { keys } => keys.iter().map(String::as_str).collect(),
+            Rule::RequiredIf { key, when, .. } => vec![key, when],
+            Rule::Compare {
+                left, right, when, ..
+            } => {
+                let mut keys = vec![left.as_str()];
+                if let Operand::Key(k) = right {
+                    keys.push(k);
+                }
+                if let Some(when) = when {
+                    keys.push(&when.key);
+                }
+                keys
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// A policy rule that the current values do not satisfy
+pub struct PolicyViolation {
+    /// Keys of the configuration items involved
+    pub keys: Vec<String>,
+    /// Description of the problem
+    pub message: String,
+}
+
+/// Compiled schema for policy files
+static POLICY_SCHEMA: Lazy<JSONSchema> = Lazy::new(|| {
+    let json_value = serde_json::to_value(schema_for!(Policy)).expect("convert schema to json");
+    JSONSchema::options()
+        .compile(&json_value)
+        .expect("A valid schema")
+});
+
+/// Remove a top-level `rules` array from a definition file, returning the rules it held
+///
+/// An object under `rules` is an attribute of that name and is left alone.
+pub(crate) fn take_defn_rules(json_value: &mut Value) -> Result<Vec<Rule>, CfgError> {
+    let rules = match json_value.as_object_mut() {
+        Some(map) if map.get("rules").is_some_and(Value::is_array) => map.remove("rules"),
+        _ => None,
+    };
+    let policy = match rules {
+        Some(rules) => serde_json::json!({ "rules": rules }),
+        None => return Ok(Vec::new()),
+    };
+    if !POLICY_SCHEMA.is_valid(&policy) {
+        return Err(CfgError::Schema("definition rules".to_string()));
+    }
+    let policy: Policy = serde_json::from_value(policy)?;
+    Ok(policy.rules)
+}
+
+/// The first key named by `rules` that is not in `cfg`, if any
+pub(crate) fn undefined_key<'a>(rules: &'a [Rule], cfg: &ConfigHash) -> Option<&'a str> {
+    rules
+        .iter()
+        .flat_map(Rule::keys)
+        .find(|k| !cfg.contains_key(*k))
+}
+
+pub(crate) fn is_set(value: &str) -> bool {
+    let v = value.trim().to_lowercase();
+    !matches!(v.as_str(), "" | "0" | "false" | "no" | "off")
+}
+
+impl Cfg {
+    /// Load the policy rules in `path` to be checked by [`Cfg::policy_violations`]
+    ///
+    /// The definitions must already be loaded; a rule naming an undefined key is an error.
+    pub fn load_policy<P: AsRef<Path>>(&mut self, path: P) -> Result<(), CfgError> {
+        let timed = Timed::new(info_span!(
+            "load_policy",
+            file = %path.as_ref().display(),
+            rules = Empty,
+            duration_us = Empty,
+        ));
+        let policy = self
+            .read_policy(path.as_ref())
+            .context(Operation::Load, &path)?;
+        timed.record("rules", policy.rules.len());
+        self.policy = policy;
+        Ok(())
+    }
+
+    fn read_policy(&self, path: &Path) -> Result<Policy, CfgError> {
+        let cfg = self.cfg.as_ref().ok_or(CfgError::Cfg())?;
+        let reader = self.fs.open(path)?;
+        let json_value: Value = serde_json::from_reader(reader)?;
+        if !POLICY_SCHEMA.is_valid(&json_value) {
+            return Err(CfgError::Schema(path.display().to_string()));
+        }
+        let policy: Policy = serde_json::from_value(json_value)?;
+        if let Some(k) = undefined_key(&policy.rules, cfg) {
+            return Err(CfgError::UnknownKey(k.to_string()));
+        }
+        Ok(policy)
+    }
+
+    /// Check the current values against every rule of the loaded policy
+    pub fn policy_violations(&self) -> Vec<PolicyViolation> {
+        self.violations_of(&self.policy.rules)
+    }
+
+    /// Check the current values against the rules of the definition file and of the loaded
+    /// policy, in that order
+    pub fn validate_all(&self) -> Vec<PolicyViolation> {
+        let mut violations = self.violations_of(&self.defn_rules);
+        violations.extend(self.policy_violations());
+        violations
+    }
+
+    fn violations_of(&self, rules: &[Rule]) -> Vec<PolicyViolation> {
+        let mut violations = Vec::new();
+        let cfg = match &self.cfg {
+            Some(c) => c,
+            None => return violations,
+        };
+        let value = |k: &str| cfg.get(k).map(|a| a.current.as_str()).unwrap_or("");
+        let number = |k: &str| value(k).trim().parse::<f64>().ok();
+        for rule in rules {
+            let keys = || rule.keys().into_iter().map(String::from).collect();
+            let message = match rule {
+                Rule::MutuallyExclusive { keys } => {
+                    let set: Vec<&str> = keys
+                        .iter()
+                        .map(String::as_str)
+                        .filter(|k| is_set(value(k)))
+                        .collect();
+                    if set.len() < 2 {
+                        continue;
+                    }
+                    format!("only one of {} may be set", set.join(", "))
+                }
+                Rule::RequiredIf { key, when, equals } => {
+                    if value(when) != equals || !value(key).trim().is_empty() {
+                        continue;
+                    }
+                    format!("'{}' is required when '{}' is '{}'", key, when, equals)
+                }
+                Rule::Compare {
+                    left,
+                    relation,
+                    right,
+                    when,
+                } => {
+                    let skip = when.as_ref().is_some_and(|when| {
+                        !cfg.get(&when.key)
+                            .is_some_and(|a| when.is_met_by(a, value(&when.key)))
+                    });
+                    if skip {
+                        continue;
+                    }
+                    let (r, r_text) = match right {
+                        Operand::Number(n) => (Some(*n), n.to_string()),
+                        Operand::Key(k) => (number(k), format!("'{}'", k)),
+                    };
+                    match (number(left), r) {
+                        (Some(l), Some(r)) if relation.holds(l, r) => continue,
+                        (Some(_), Some(_)) => {
+                            format!("'{}' must be {} {}", left, relation, r_text)
+                        }
+                        _ => format!("'{}' {} {} needs numeric values", left, relation, r_text),
+                    }
+                }
+            };
+            violations.push(PolicyViolation {
+                keys: keys(),
+                message,
+            });
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryFs;
+    use std::fs;
+    use std::sync::Arc;
+
+    const DEFN_DATA: &str = r#"
+        {
+            "ap_mode" : {
+                "prompt": "Access point mode",
+                "tooltip": "Run a wifi access point",
+                "current": "true",
+                "default": "true",
+                "format": "true|false",
+                "action": "Edit"
+            },
+            "router_mode" : {
+                "prompt": "Router mode",
+                "tooltip": "Join an existing wifi network",
+                "current": "false",
+                "default": "false",
+                "format": "true|false",
+                "action": "Edit"
+            },
+            "router_ssid" : {
+                "prompt": "Router SSID",
+                "tooltip": "Network to join in router mode",
+                "current": "",
+                "default": "",
+                "format": ".*",
+                "action": "Edit"
+            },
+            "start_event_id" : {
+                "prompt": "Start Event Id",
+                "tooltip": "The event that will be generated when the services start",
+                "current": "1",
+                "default": "1",
+                "format": "[0-9]{1,2}",
+                "action": "Edit"
+            }
+        }"#;
+
+    const POLICY_DATA: &str = r#"
+        { "rules": [
+            { "rule": "mutually_exclusive", "keys": ["ap_mode", "router_mode"] },
+            { "rule": "required_if", "key": "router_ssid", "when": "router_mode", "equals": "true" },
+            { "rule": "compare", "left": "start_event_id", "relation": "<=", "right": 50 }
+        ] }"#;
+
+    fn load(name: &str, policy: &str) -> Result<Cfg, CfgError> {
+        let defn_file = format!("scratch/{}.json", name);
+        let policy_file = format!("scratch/{}_policy.json", name);
+        fs::write(&defn_file, DEFN_DATA).expect("file write failed");
+        fs::write(&policy_file, policy).expect("file write failed");
+        let mut cfg = Cfg::new();
+        cfg.load_definition(&defn_file)
+            .expect("definition failed to load");
+        let result = cfg.load_policy(&policy_file);
+        fs::remove_file(defn_file).expect("file deletion failed");
+        fs::remove_file(policy_file).expect("file deletion failed");
+        result.map(|_| cfg)
+    }
+
+    #[test]
+    fn rules_are_enforced() {
+        let mut cfg = load("policy_test", POLICY_DATA).expect("policy failed to load");
+        assert!(cfg.policy_violations().is_empty());
+
+        cfg.set_value("router_mode", "true").expect("set failed");
+        cfg.set_value("start_event_id", "51").expect("set failed");
+        let violations = cfg.policy_violations();
+        assert_eq!(violations.len(), 3);
+        assert_eq!(violations[0].keys, vec!["ap_mode", "router_mode"]);
+        assert_eq!(
+            violations[1].message,
+            "'router_ssid' is required when 'router_mode' is 'true'"
+        );
+        assert_eq!(violations[2].message, "'start_event_id' must be <= 50");
+
+        cfg.set_value("ap_mode", "false").expect("set failed");
+        cfg.set_value("router_ssid", "club").expect("set failed");
+        // A hand edited INI file need not satisfy the formats
+        cfg.iter_mut().for_each(|(k, a)| {
+            if k == "start_event_id" {
+                a.current = "x".to_string()
+            }
+        });
+        let violations = cfg.policy_violations();
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].message.contains("needs numeric values"));
+    }
+
+    #[test]
+    fn malformed_policies_are_rejected() {
+        let err = load(
+            "policy_schema_test",
+            r#"{ "rules": [ { "rule": "sometimes" } ] }"#,
+        )
+        .err()
+        .expect("policy loaded");
+        assert!(matches!(err.root(), CfgError::Schema(_)));
+        assert_eq!(err.operation(), Some(Operation::Load));
+        let err = load(
+            "policy_key_test",
+            r#"{ "rules": [ { "rule": "mutually_exclusive", "keys": ["ap_mode", "wifi"] } ] }"#,
+        )
+        .err()
+        .expect("policy loaded");
+        assert!(matches!(err.root(), CfgError::UnknownKey(k) if k == "wifi"));
+        assert_eq!(err.key(), Some("wifi"));
+    }
+
+    #[test]
+    fn definition_rules() {
+        let mut defn: Value = serde_json::from_str(DEFN_DATA).unwrap();
+        defn["rules"] = serde_json::json!([
+            { "rule": "compare", "left": "start_event_id", "relation": "<", "right": 10,
+              "when": { "key": "router_mode", "value": "true" } }
+        ]);
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", defn.to_string());
+        fs.insert("policy.json", POLICY_DATA);
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.load_definition("defn.json")
+            .expect("definition failed to load");
+        cfg.set_value("start_event_id", "20").expect("set failed");
+        assert!(cfg.validate_all().is_empty());
+
+        cfg.set_value("router_mode", "true").expect("set failed");
+        cfg.set_value("ap_mode", "false").expect("set failed");
+        cfg.set_value("router_ssid", "club").expect("set failed");
+        let violations = cfg.validate_all();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].message, "'start_event_id' must be < 10");
+        assert_eq!(violations[0].keys, vec!["start_event_id", "router_mode"]);
+
+        cfg.load_policy("policy.json")
+            .expect("policy failed to load");
+        assert_eq!(cfg.validate_all().len(), 1);
+        assert!(cfg.policy_violations().is_empty());
+
+        defn["rules"][0]["left"] = "wifi".into();
+        fs.insert("defn.json", defn.to_string());
+        let err = cfg.load_definition("defn.json").unwrap_err();
+        assert!(matches!(err.root(), CfgError::UnknownKey(k) if k == "wifi"));
+    }
+}
@@ -0,0 +1,100 @@
+//! Where each current value came from
+//!
+//! Every configuration item records the origin of its current value so that a surprising value
+//! can be traced back to the definition file, the INI file and section that set it, an
+//! environment variable that overrode it, or an edit made through the API since loading.
+
+use crate::{Attribute, Cfg};
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// The origin of a configuration item's current value
+pub enum Provenance {
+    /// The value given in the attribute definition file
+    Definition,
+    /// Read from an INI file, possibly one that was included by another
+    Ini {
+        /// The file containing the entry
+        file: PathBuf,
+        /// The section containing the entry, `None` for the general section
+        section: Option<String>,
+    },
+    /// The attribute's default, as the INI file had no valid entry for it
+    Default,
+    /// Read from an environment variable, overriding any INI entry
+    Environment {
+        /// The name of the variable
+        var: String,
+    },
+    /// Given by a [`Layer::Overrides`](crate::Layer::Overrides) layer when loading
+    Override,
+    /// Changed through the API since the configuration was loaded
+    User,
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Provenance::Definition => write!(f, "definition"),
+            Provenance::Ini {
+                file,
+                section: Some(s),
+            } => write!(f, "{} [{}]", file.display(), s),
+            Provenance::Ini {
+                file,
+                section: None,
+            } => write!(f, "{}", file.display()),
+            Provenance::Default => write!(f, "default"),
+            Provenance::Environment { var } => write!(f, "environment variable {}", var),
+            Provenance::Override => write!(f, "runtime override"),
+            Provenance::User => write!(f, "user edit"),
+        }
+    }
+}
+
+impl Cfg {
+    /// The value of `key` to write to the INI file
+    ///
+    /// A value set by an environment variable or an override is not saved: the INI value it
+    /// replaced, or else the default, is written until the value is changed through the API.
+    pub(crate) fn persisted_value<'a>(&'a self, key: &str, attr: &'a Attribute) -> &'a str {
+        match (self.provenance.get(key), self.persisted.get(key)) {
+            (Some(Provenance::User), _) | (_, None) => &attr.current,
+            (_, Some(below)) => below.as_deref().unwrap_or(&attr.default),
+        }
+    }
+
+    /// Where the current value of `key` came from
+    pub fn provenance<K: AsRef<str>>(&self, key: K) -> Option<&Provenance> {
+        let key = key.as_ref();
+        self.provenance.get(key)
+    }
+
+    /// The keys whose current value is their default because the INI file had no valid entry for
+    /// them
+    ///
+    /// The keys are in sorted order.  A key stops being listed once its value is changed.
+    pub fn defaulted_keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = self
+            .provenance
+            .iter()
+            .filter(|(_, p)| **p == Provenance::Default)
+            .map(|(k, _)| k.as_str())
+            .collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    /// The INI file, possibly an included one, that supplied the current value of `key`
+    ///
+    /// Returns `None` if the value did not come from an INI file.
+    pub fn source_file<K: AsRef<str>>(&self, key: K) -> Option<&Path> {
+        let key = key.as_ref();
+        match self.provenance.get(key) {
+            Some(Provenance::Ini { file, .. }) => Some(file),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! Attribute filter expressions
+//!
+//! A [`Query`] combines conditions on several dimensions of an attribute.  It can be built
+//! in code or parsed from a small expression language of `&&` separated clauses, e.g.
+//!
+//! ```text
+//! action==Edit && key==ap_* && modified==true
+//! ```
+//!
+//! | Field      | Value                                          |
+//! |------------|------------------------------------------------|
+//! | `action`   | `Edit`, `Display` or `Hide`                    |
+//! | `key`      | key pattern, `*` and `?` are wildcards         |
+//! | `modified` | `true` when `current` differs from `default`   |
+//! | `category` | category name, `group` is accepted as an alias |
+//!
+//! Each clause may use `==` or `!=`.
+
+use crate::{ActionBehaviour, Attribute, Cfg, CfgError, ConfigHash};
+
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Field {
+    Action(ActionBehaviour),
+    Key(String),
+    Modified(bool),
+    Category(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Condition {
+    field: Field,
+    negate: bool,
+}
+
+impl Condition {
+    fn matches(&self, key: &str, attr: &Attribute) -> bool {
+        let result = match &self.field {
+            Field::Action(a) => attr.action == *a,
+            Field::Key(pattern) => wildcard_match(pattern, key),
+            Field::Modified(m) => (attr.current != attr.default) == *m,
+            Field::Category(name) => attr.category.as_deref() == Some(name.as_str()),
+        };
+        result != self.negate
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// A set of conditions that must all hold for an attribute to be selected
+pub struct Query {
+    conditions: Vec<Condition>,
+}
+
+impl Query {
+    /// Creates a query that matches every attribute
+    pub fn new() -> Query {
+        Query::default()
+    }
+
+    /// Require the attribute to have `action`
+    pub fn action(self, action: ActionBehaviour) -> Query {
+        self.with(Field::Action(action), false)
+    }
+
+    /// Require the attribute not to have `action`
+    pub fn not_action(self, action: ActionBehaviour) -> Query {
+        self.with(Field::Action(action), true)
+    }
+
+    /// Require the key to match `pattern` where `*` matches any run of characters and `?`
+    /// matches a single character
+    pub fn key_pattern(self, pattern: &str) -> Query {
+        self.with(Field::Key(pattern.to_string()), false)
+    }
+
+    /// Require the current value to differ from (`true`) or equal (`false`) the default
+    pub fn modified(self, modified: bool) -> Query {
+        self.with(Field::Modified(modified), false)
+    }
+
+    /// Require the attribute to belong to the category `name`
+    pub fn category(self, name: &str) -> Query {
+        self.with(Field::Category(name.to_string()), false)
+    }
+
+    /// Check whether the attribute stored under `key` satisfies every condition
+    pub fn matches(&self, key: &str, attr: &Attribute) -> bool {
+        self.conditions.iter().all(|c| c.matches(key, attr))
+    }
+
+    fn with(mut self, field: Field, negate: bool) -> Query {
+        self.conditions.push(Condition { field, negate });
+        self
+    }
+}
+
+impl FromStr for Query {
+    type Err = CfgError;
+
+    /// Parse a query expression such as `action==Edit && modified==true`
+    fn from_str(expr: &str) -> Result<Self, Self::Err> {
+        let mut query = Query::new();
+        if expr.trim().is_empty() {
+            return Ok(query);
+        }
+        for clause in expr.split("&&") {
+            let clause = clause.trim();
+            let (name, value, negate) = if let Some((n, v)) = clause.split_once("!=") {
+                (n, v, true)
+            } else if let Some((n, v)) = clause.split_once("==") {
+                (n, v, false)
+            } else {
+                return Err(CfgError::Query(format!(
+                    "'{}' has no == or != operator",
+                    clause
+                )));
+            };
+            let value = value.trim();
+            let field = match name.trim() {
+                "action" => Field::Action(parse_action(value)?),
+                "key" => Field::Key(value.to_string()),
+                "modified" => Field::Modified(value.parse().map_err(|_| {
+                    CfgError::Query(format!("modified expects true or false, not '{}'", value))
+                })?),
+                "category" | "group" => Field::Category(value.to_string()),
+                other => return Err(CfgError::Query(format!("unknown field '{}'", other))),
+            };
+            query = query.with(field, negate);
+        }
+        Ok(query)
+    }
+}
+
+fn parse_action(value: &str) -> Result<ActionBehaviour, CfgError> {
+    match value.to_lowercase().as_str() {
+        "edit" => Ok(ActionBehaviour::Edit),
+        "display" => Ok(ActionBehaviour::Display),
+        "hide" => Ok(ActionBehaviour::Hide),
+        _ => Err(CfgError::Query(format!("unknown action '{}'", value))),
+    }
+}
+
+/// Match `text` against a pattern where `*` is any run of characters and `?` is one character
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            backtrack = Some((pi, ti));
+            pi += 1;
+        } else if let Some((star, matched)) = backtrack {
+            pi = star + 1;
+            ti = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+impl Cfg {
+    /// Select the attributes matching the query expression `expr`
+    ///
+    /// See [`Query`] for the expression syntax
+    pub fn select(&self, expr: &str) -> Result<ConfigHash, CfgError> {
+        let query: Query = expr.parse()?;
+        Ok(self.select_query(&query))
+    }
+
+    /// Select the attributes matching `query`
+    pub fn select_query(&self, query: &Query) -> ConfigHash {
+        let mut selected = ConfigHash::new();
+        if let Some(cfg) = &self.cfg {
+            selected.extend(
+                cfg.iter()
+                    .filter(|(k, v)| query.matches(k, v))
+                    .map(|(k, v)| (k.clone(), v.clone())),
+            );
+        }
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match("ap_*", "ap_ssid"));
+        assert!(wildcard_match("*_pin", "red_led_pin"));
+        assert!(wildcard_match("can?d", "canid"));
+        assert!(wildcard_match("*", ""));
+        assert!(!wildcard_match("ap_*", "router_ssid"));
+        assert!(!wildcard_match("can?d", "canxxd"));
+    }
+
+    #[test]
+    fn parse_expressions() {
+        let q: Query = "action==Edit && key != ap_* && modified==true"
+            .parse()
+            .expect("query should parse");
+        assert_eq!(
+            q,
+            Query::new()
+                .action(ActionBehaviour::Edit)
+                .with(Field::Key("ap_*".to_string()), true)
+                .modified(true)
+        );
+        assert!("action=Edit".parse::<Query>().is_err());
+        assert!("colour==red".parse::<Query>().is_err());
+        assert!("action==Sometimes".parse::<Query>().is_err());
+        assert_eq!(
+            "group==CAN".parse::<Query>().expect("query should parse"),
+            Query::new().category("CAN")
+        );
+    }
+
+    #[test]
+    fn category_condition() {
+        let mut attr = Attribute::new("CAN Id", "", "100", ".*", ActionBehaviour::Edit);
+        let query: Query = "category==CAN".parse().expect("query should parse");
+        assert!(!query.matches("canid", &attr));
+        assert!("category!=CAN"
+            .parse::<Query>()
+            .expect("query should parse")
+            .matches("canid", &attr));
+        attr.category = Some("CAN".into());
+        assert!(query.matches("canid", &attr));
+        attr.category = Some("Wi-Fi".into());
+        assert!(!query.matches("canid", &attr));
+    }
+}
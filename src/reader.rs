@@ -0,0 +1,81 @@
+//! Loading from strings and readers
+//!
+//! An embedding application, or a test, may hold the definitions and the INI file in memory.
+//! [`Cfg::load_configuration_from_str`] and [`Cfg::load_configuration_from_reader`] load them
+//! without writing temporary files.  Include directives in the INI text are not followed.
+
+use crate::builder::DefnSource;
+use crate::layer::INI_STR_NAME;
+use crate::{Cfg, CfgError, Layer, LoadOptions, LoadReport};
+
+use std::io::Read;
+use std::path::Path;
+
+impl Cfg {
+    /// Load the attribute definitions from the text `defn_str` and then update the current
+    /// values from the INI text `ini_str`
+    ///
+    /// As [`Cfg::load_configuration`] otherwise; the definitions may be in any format a
+    /// definition file may use.
+    pub fn load_configuration_from_str(
+        &mut self,
+        ini_str: &str,
+        defn_str: &str,
+    ) -> Result<LoadReport, CfgError> {
+        let options = LoadOptions::default();
+        let mut layers = vec![Layer::IniStr(ini_str.to_string())];
+        if let Some(prefix) = &options.env_prefix {
+            layers.push(Layer::Env(prefix.clone()));
+        }
+        let defn = DefnSource::Text(defn_str.to_string());
+        self.load_stack(Path::new(INI_STR_NAME), &defn, layers, &options)
+    }
+
+    /// Load the attribute definitions from `defn` and then update the current values from the
+    /// INI text read from `ini`
+    pub fn load_configuration_from_reader<R: Read, D: Read>(
+        &mut self,
+        mut ini: R,
+        mut defn: D,
+    ) -> Result<LoadReport, CfgError> {
+        let mut ini_str = String::new();
+        ini.read_to_string(&mut ini_str)?;
+        let mut defn_str = String::new();
+        defn.read_to_string(&mut defn_str)?;
+        self.load_configuration_from_str(&ini_str, &defn_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::CANID_NODE_DEFN;
+    use crate::Provenance;
+
+    #[test]
+    fn load_from_memory() {
+        let mut cfg = Cfg::new();
+        let report = cfg
+            .load_configuration_from_str("canid=101\n", CANID_NODE_DEFN)
+            .expect("configuration failed to load");
+        assert_eq!(report.missing_keys, vec!["node_number".to_string()]);
+        assert_eq!(cfg.get_or_default("canid"), "101");
+        assert_eq!(
+            cfg.provenance("canid"),
+            Some(&Provenance::Ini {
+                file: INI_STR_NAME.into(),
+                section: None
+            })
+        );
+
+        let ini = "canid=102\nnode_number=5000\n".as_bytes();
+        cfg.load_configuration_from_reader(ini, CANID_NODE_DEFN.as_bytes())
+            .expect("configuration failed to load");
+        assert_eq!(cfg.get_or_default("node_number"), "5000");
+
+        let err = cfg
+            .load_configuration_from_str("canid=101\n", "{ \"canid\": 1 }")
+            .unwrap_err();
+        assert!(matches!(err.root(), CfgError::Schema(_)));
+    }
+}
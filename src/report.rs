@@ -0,0 +1,58 @@
+//! What loading a configuration found
+//!
+//! [`Cfg::load_configuration`](crate::Cfg::load_configuration) returns a [`LoadReport`] so that
+//! an embedding application, such as a web server, can show problems with the INI file to the
+//! user instead of them going to stdout.
+
+use crate::{AliasedKey, FormatViolation};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// Problems found while loading an INI file against its definitions
+pub struct LoadReport {
+    /// Keys of INI entries that are not defined, in the order read; the entries were ignored
+    pub unknown_keys: Vec<String>,
+    /// INI entries found under a deprecated alias of their key, in the order read
+    pub aliased_keys: Vec<AliasedKey>,
+    /// INI entries found under a key renamed by the definition's `migrations`, in the order read
+    pub migrated_keys: Vec<AliasedKey>,
+    /// Definition versions whose registered migrations were run on the INI file, in order
+    pub migrated_versions: Vec<u32>,
+    /// Defined keys with no INI entry, which took their default, in sorted order
+    pub missing_keys: Vec<String>,
+    /// Current values that do not satisfy their format
+    pub invalid_values: Vec<FormatViolation>,
+    /// INI values that did not satisfy their format and were replaced by the default
+    ///
+    /// Only filled when loading with [`InvalidValues::UseDefault`].
+    ///
+    /// [`InvalidValues::UseDefault`]: crate::InvalidValues::UseDefault
+    pub replaced_values: Vec<FormatViolation>,
+    /// Keys whose INI value was overridden by an environment variable, in sorted order
+    ///
+    /// See [`LoadOptions::env_prefix`](crate::LoadOptions::env_prefix).
+    pub env_overrides: Vec<String>,
+    /// Keys of required items whose current value is empty, in sorted order
+    ///
+    /// The INI file cannot be written until they are given values.
+    pub empty_required: Vec<String>,
+    /// Fields of the definition file that are not known and were dropped, as `key.field` or, for
+    /// a top level member that is not an attribute, its name, in sorted order
+    ///
+    /// Only filled when loading with [`UnknownFields::Warn`](crate::UnknownFields::Warn).
+    pub unknown_fields: Vec<String>,
+}
+
+impl LoadReport {
+    /// True if every INI entry was defined under its current key, every item had an entry, every
+    /// value is valid, every required item has a value and every definition field is known
+    pub fn is_clean(&self) -> bool {
+        self.unknown_keys.is_empty()
+            && self.aliased_keys.is_empty()
+            && self.migrated_keys.is_empty()
+            && self.missing_keys.is_empty()
+            && self.invalid_values.is_empty()
+            && self.replaced_values.is_empty()
+            && self.empty_required.is_empty()
+            && self.unknown_fields.is_empty()
+    }
+}
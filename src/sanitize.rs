@@ -0,0 +1,73 @@
+//! Control characters in values
+//!
+//! A newline in a value could smuggle another `key=value` line into an INI file read by a
+//! less careful parser, and other control characters are rarely intended.  Each attribute's
+//! [`ControlChars`] policy decides what happens to them in values supplied through the API.
+
+use crate::{Attribute, CfgError};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use std::borrow::Cow;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+/// What to do with control characters in a value supplied through the API
+pub enum ControlChars {
+    /// Refuse the value
+    #[default]
+    Reject,
+    /// Remove the control characters and keep the rest
+    Strip,
+    /// Keep them, e.g. for multiline values such as certificates
+    Allow,
+}
+
+/// Control characters plus the Unicode line and paragraph separators
+fn is_unsafe(c: char) -> bool {
+    c.is_control() || c == '\u{2028}' || c == '\u{2029}'
+}
+
+impl Attribute {
+    /// Apply the attribute's control character policy to `value` supplied for `key`
+    pub fn sanitize<'a>(&self, key: &str, value: &'a str) -> Result<Cow<'a, str>, CfgError> {
+        if self.control_chars == ControlChars::Allow || !value.contains(is_unsafe) {
+            return Ok(Cow::Borrowed(value));
+        }
+        match self.control_chars {
+            ControlChars::Strip => Ok(Cow::Owned(value.replace(is_unsafe, ""))),
+            _ => Err(CfgError::InvalidValue(format!(
+                "value for '{}' contains control characters",
+                key
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies() {
+        let mut attr: Attribute = serde_json::from_str(
+            r#"{"prompt": "", "tooltip": "", "current": "", "default": "",
+                "format": ".*", "action": "Edit"}"#,
+        )
+        .expect("attribute failed to deserialize");
+        let injected = "club\nap_mode=true";
+        assert_eq!(attr.control_chars, ControlChars::Reject);
+        assert!(matches!(
+            attr.sanitize("ap_ssid", injected),
+            Err(CfgError::InvalidValue(_))
+        ));
+        assert_eq!(attr.sanitize("ap_ssid", "club").unwrap(), "club");
+        attr.control_chars = ControlChars::Strip;
+        assert_eq!(
+            attr.sanitize("ap_ssid", "club\r\n\u{2028}\t1").unwrap(),
+            "club1"
+        );
+        attr.control_chars = ControlChars::Allow;
+        assert_eq!(attr.sanitize("ap_ssid", injected).unwrap(), injected);
+    }
+}
@@ -0,0 +1,104 @@
+//! Schemas for definition files supplied by the application
+//!
+//! Definition files are validated against a schema generated from [`Attribute`](crate::Attribute)
+//! unless [`Cfg::with_schema_file`] gives another.  A project can then tighten what its
+//! definitions may say, e.g. restrict keys to a pattern, limit `action` to some of its values or
+//! require members that are otherwise optional, without forking the crate.  The schema applies to
+//! the attribute items; the `rules`, `migrations`, `defn_version`, `translations` and
+//! `"$signature"` members are taken out before the definition is validated.
+
+use crate::context::ResultExt;
+use crate::{Cfg, CfgError, Operation};
+
+use jsonschema::JSONSchema;
+use serde_json::Value;
+
+use std::path::Path;
+use std::sync::Arc;
+
+impl Cfg {
+    /// Validate the definition files loaded from now on against the JSON schema in the file at
+    /// `path` rather than the generated one
+    pub fn with_schema_file<P: AsRef<Path>>(mut self, path: P) -> Result<Cfg, CfgError> {
+        let schema = self
+            .read_schema_file(path.as_ref())
+            .context(Operation::Load, path)?;
+        self.custom_schema = Some(Arc::new(schema));
+        Ok(self)
+    }
+
+    fn read_schema_file(&self, path: &Path) -> Result<JSONSchema, CfgError> {
+        let text = self.fs.read_to_string(path)?;
+        let json_value: Value = serde_json::from_str(&text)?;
+        JSONSchema::options()
+            .compile(&json_value)
+            .map_err(|e| CfgError::SchemaFile(e.to_string()))
+    }
+
+    /// The schema definition files are validated against
+    pub(crate) fn defn_schema(&self) -> &JSONSchema {
+        match &self.custom_schema {
+            Some(schema) => schema,
+            None => self.schema,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Attribute, MemoryFs};
+
+    const SCHEMA: &str = r#"{
+        "type": "object",
+        "propertyNames": { "pattern": "^[a-z_]+$" },
+        "additionalProperties": {
+            "type": "object",
+            "required": ["prompt", "tooltip", "current", "default", "format", "action", "kind"],
+            "properties": { "action": { "enum": ["Edit", "Display"] } }
+        }
+    }"#;
+
+    const DEFN_DATA: &str = r#"{
+        "canid" : {
+            "prompt": "CAN Id", "tooltip": "", "current": "100", "default": "100",
+            "format": "[0-9]{1,4}", "action": "Edit", "kind": "Integer"
+        }
+    }"#;
+
+    #[test]
+    fn user_schema_is_used() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("schema.json", SCHEMA);
+        fs.insert("defn.json", DEFN_DATA);
+        fs.insert("hidden.json", DEFN_DATA.replace("Edit", "Hide"));
+        fs.insert(
+            "untyped.json",
+            DEFN_DATA.replace(", \"kind\": \"Integer\"", ""),
+        );
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.load_definition("hidden.json")
+            .expect("generated schema not used");
+
+        let mut cfg = cfg
+            .with_schema_file("schema.json")
+            .expect("schema not read");
+        cfg.load_definition("defn.json")
+            .expect("definition failed to load");
+        let attr: &Attribute = cfg.read_attribute("canid").unwrap();
+        assert_eq!(attr.current, "100");
+        for path in ["hidden.json", "untyped.json"] {
+            let err = cfg.load_definition(path).unwrap_err();
+            assert!(matches!(err.root(), CfgError::Schema(_)));
+        }
+
+        fs.insert("bad.json", r#"{ "type": 7 }"#);
+        let err = Cfg::new().with_schema_file("missing.json").err().unwrap();
+        assert!(matches!(err.root(), CfgError::Io(_)));
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs);
+        let err = cfg.with_schema_file("bad.json").err().unwrap();
+        assert!(matches!(err.root(), CfgError::SchemaFile(_)));
+    }
+}
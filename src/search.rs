@@ -0,0 +1,178 @@
+//! Text search over configuration items
+//!
+//! Matching is case-insensitive and looks at the key, prompt and tooltip of each attribute.
+//! Results are ranked so that matches on the key beat matches on the prompt, which in turn beat
+//! matches on the tooltip, and whole-field and prefix matches beat matches part way through.
+
+use crate::{Attribute, Cfg};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+/// The attribute field that produced a search match
+pub enum MatchField {
+    /// Matched the tooltip text
+    Tooltip,
+    /// Matched the prompt text
+    Prompt,
+    /// Matched the configuration item key
+    Key,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// A configuration item found by [`Cfg::search`] or [`Cfg::fuzzy_search`]
+pub struct SearchHit {
+    /// Key of the matching configuration item
+    pub key: String,
+    /// Best scoring field for this item
+    pub field: MatchField,
+    /// Relevance of the match, higher is better
+    pub score: u32,
+}
+
+impl Cfg {
+    /// Find the configuration items whose key, prompt or tooltip contain `query`
+    ///
+    /// The comparison is case-insensitive.  Results are ordered by descending score then key.
+    /// An empty query matches nothing.
+    pub fn search(&self, query: &str) -> Vec<SearchHit> {
+        self.ranked_search(query, substring_score)
+    }
+
+    /// As [`Cfg::search`] but also accepts the characters of `query` appearing in order with
+    /// gaps between them, e.g. "evid" finds `start_event_id`
+    pub fn fuzzy_search(&self, query: &str) -> Vec<SearchHit> {
+        self.ranked_search(query, |text, query| {
+            substring_score(text, query).or_else(|| subsequence_score(text, query))
+        })
+    }
+
+    fn ranked_search<F>(&self, query: &str, score_fn: F) -> Vec<SearchHit>
+    where
+        F: Fn(&str, &str) -> Option<u32>,
+    {
+        let query = query.trim().to_lowercase();
+        let mut hits = Vec::new();
+        if query.is_empty() {
+            return hits;
+        }
+        if let Some(cfg) = &self.cfg {
+            for (k, v) in cfg {
+                if let Some(hit) = best_hit(k, v, &query, &score_fn) {
+                    hits.push(hit);
+                }
+            }
+        }
+        hits.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.key.cmp(&b.key)));
+        hits
+    }
+}
+
+/// Score each searchable field of an attribute and keep the best
+fn best_hit<F>(key: &str, attr: &Attribute, query: &str, score_fn: &F) -> Option<SearchHit>
+where
+    F: Fn(&str, &str) -> Option<u32>,
+{
+    [
+        (MatchField::Key, key),
+        (MatchField::Prompt, &attr.prompt),
+        (MatchField::Tooltip, &attr.tooltip),
+    ]
+    .iter()
+    .filter_map(|(field, text)| {
+        score_fn(&text.to_lowercase(), query).map(|s| (*field, s * field_weight(*field)))
+    })
+    .max_by_key(|(field, score)| (*score, *field))
+    .map(|(field, score)| SearchHit {
+        key: key.to_string(),
+        field,
+        score,
+    })
+}
+
+fn field_weight(field: MatchField) -> u32 {
+    match field {
+        MatchField::Key => 3,
+        MatchField::Prompt => 2,
+        MatchField::Tooltip => 1,
+    }
+}
+
+/// Score a case-folded substring match of `query` within `text`
+fn substring_score(text: &str, query: &str) -> Option<u32> {
+    if text == query {
+        Some(100)
+    } else if text.starts_with(query) {
+        Some(80)
+    } else if text.contains(query) {
+        Some(60)
+    } else {
+        None
+    }
+}
+
+/// Score the characters of `query` appearing in order within `text`
+///
+/// Tighter matches (fewer skipped characters) score higher but always below a substring match.
+fn subsequence_score(text: &str, query: &str) -> Option<u32> {
+    let mut chars = text.chars();
+    let mut skipped = 0;
+    for q in query.chars() {
+        loop {
+            match chars.next() {
+                Some(c) if c == q => break,
+                Some(_) => skipped += 1,
+                None => return None,
+            }
+        }
+    }
+    Some(40u32.saturating_sub(skipped).max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::load_defn;
+
+    const DEFN_DATA: &str = r#"
+        {
+            "canid" : {
+                "prompt": "CAN Id",
+                "tooltip": "The CAN Id used by the CAN Pi CAP/Zero on the CBUS",
+                "current": "100",
+                "default": "100",
+                "format": "[0-9]{1,4}",
+                "action": "Display"
+            },
+            "start_event_id" : {
+                "prompt": "Start Event Id",
+                "tooltip": "The event that will be generated when the services start",
+                "current": "1",
+                "default": "1",
+                "format": "[0-9]{1,2}",
+                "action": "Edit"
+            }
+        }"#;
+
+    #[test]
+    fn search_ranks_key_over_tooltip() {
+        let cfg = load_defn("scratch/search_test.json", DEFN_DATA);
+
+        let hits = cfg.search("CAN");
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].key, "canid");
+        assert_eq!(hits[0].field, MatchField::Key);
+
+        let hits = cfg.search("event");
+        assert_eq!(hits[0].key, "start_event_id");
+        assert!(cfg.search("").is_empty());
+        assert!(cfg.search("sevid").is_empty());
+        assert_eq!(cfg.fuzzy_search("sevid")[0].key, "start_event_id");
+    }
+
+    #[test]
+    fn subsequence_scoring() {
+        assert_eq!(subsequence_score("start_event_id", "xyz"), None);
+        assert!(subsequence_score("start_event_id", "evid").is_some());
+        assert!(subsequence_score("event_id", "evid") > subsequence_score("start_event_id", "sid"));
+        assert!(substring_score("canid", "can") > subsequence_score("canid", "cad"));
+    }
+}
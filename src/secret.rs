@@ -0,0 +1,167 @@
+//! Redaction of secret values
+//!
+//! An attribute marked `secret` in the definition, such as a Wi-Fi password, never has its
+//! values shown outside the INI file: they are replaced by [`REDACTED`] in Debug output, load
+//! reports, format violations, validation and conversion errors, the audit trail (and so change
+//! notifications) and snapshots.  The values themselves are read and written as usual.
+//!
+//! Exports in other formats and patches made by [`Cfg::cfg_file_patch`] redact secret values
+//! too, unless [`Cfg::set_export_secrets`] asks for them, e.g. for tooling that needs the
+//! Wi-Fi credentials.
+
+use crate::{Attribute, Cfg};
+
+use std::fmt;
+
+/// What is shown in place of a secret value
+pub const REDACTED: &str = "***";
+
+impl Attribute {
+    /// `value` as it may be shown for this attribute: [`REDACTED`] if the attribute is secret
+    pub fn shown<'a>(&self, value: &'a str) -> &'a str {
+        if self.secret {
+            REDACTED
+        } else {
+            value
+        }
+    }
+}
+
+impl Cfg {
+    /// Write secret values in plain text, rather than [`REDACTED`], in exports and patches
+    ///
+    /// The default is to redact them.
+    pub fn set_export_secrets(&mut self, include: bool) {
+        self.export_secrets = include;
+    }
+
+    /// `value` of `attr` as it is exported
+    pub(crate) fn exported<'a>(&self, attr: &Attribute, value: &'a str) -> &'a str {
+        if self.export_secrets {
+            value
+        } else {
+            attr.shown(value)
+        }
+    }
+}
+
+impl fmt::Debug for Attribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Attribute")
+            .field("prompt", &self.prompt)
+            .field("tooltip", &self.tooltip)
+            .field("current", &self.shown(&self.current))
+            .field("default", &self.shown(&self.default))
+            .field("format", &self.format)
+            .field("action", &self.action)
+            .field("partial_match", &self.partial_match)
+            .field("control_chars", &self.control_chars)
+            .field("kind", &self.kind)
+            .field("section", &self.section)
+            .field("choices", &self.choices)
+            .field("min", &self.min)
+            .field("max", &self.max)
+            .field("step", &self.step)
+            .field("depends_on", &self.depends_on)
+            .field("category", &self.category)
+            .field("display_order", &self.display_order)
+            .field("units", &self.units)
+            .field("placeholder", &self.placeholder)
+            .field("required", &self.required)
+            .field("secret", &self.secret)
+            .field("widget", &self.widget)
+            .field("translations", &self.translations)
+            .field("deprecated_aliases", &self.deprecated_aliases)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LoadOptions, MemoryFs, WriteOptions};
+    use std::sync::Arc;
+
+    const DEFN_DATA: &str = r#"
+        {
+            "router_password" : {
+                "prompt": "Router password",
+                "tooltip": "Password of the network to join",
+                "current": "hunter22",
+                "default": "",
+                "format": "[a-z0-9]{8,}",
+                "action": "Edit",
+                "secret": true
+            }
+        }"#;
+
+    #[test]
+    fn secrets_are_not_shown() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        fs.insert("canpi.cfg", "router_password=Hunter!\n");
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        let report = cfg
+            .load_configuration("canpi.cfg", "defn.json")
+            .expect("configuration failed to load");
+        assert_eq!(report.invalid_values[0].value, REDACTED);
+
+        let attr = cfg.read_attribute("router_password").unwrap();
+        assert!(!format!("{:?}", attr).contains("Hunter!"));
+        let err = cfg.validate_value("router_password", "Bad!").unwrap_err();
+        assert!(!err.to_string().contains("Bad!"));
+        assert!(!cfg.snapshot().contains("Hunter!"));
+
+        cfg.set_value("router_password", "letmein99")
+            .expect("set_value failed");
+        assert_eq!(cfg.audit_log()[0].new, REDACTED);
+        let options = WriteOptions {
+            audit_log: true,
+            ..WriteOptions::default()
+        };
+        cfg.write_cfg_file_with("canpi.cfg", &options)
+            .expect("write failed");
+        assert!(fs
+            .contents("canpi.cfg")
+            .unwrap()
+            .contains("router_password=letmein99"));
+        assert!(!fs
+            .contents("canpi.cfg.audit.jsonl")
+            .unwrap()
+            .contains("letmein99"));
+    }
+
+    #[test]
+    fn secrets_are_not_exported() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        fs.insert("old.cfg", "router_password=hunter22\n");
+        fs.insert("new.cfg", "router_password=letmein99\n");
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.load_definition("defn.json")
+            .expect("definition failed to load");
+        let patch = |cfg: &Cfg| {
+            cfg.cfg_file_patch("old.cfg", "new.cfg", &LoadOptions::default())
+                .expect("patch generation failed")
+        };
+        assert!(!patch(&cfg).contains("letmein99"));
+        assert!(patch(&cfg).contains(REDACTED));
+        cfg.write_cfg_toml("canpi.toml").expect("write failed");
+        cfg.write_cfg_env("canpi.env").expect("write failed");
+        assert!(!fs.contents("canpi.toml").unwrap().contains("hunter22"));
+        assert_eq!(
+            fs.contents("canpi.env").unwrap(),
+            "ROUTER_PASSWORD=\"***\"\n"
+        );
+
+        cfg.set_export_secrets(true);
+        assert!(patch(&cfg).contains("letmein99"));
+        cfg.write_cfg_env("canpi.env").expect("write failed");
+        assert_eq!(
+            fs.contents("canpi.env").unwrap(),
+            "ROUTER_PASSWORD=\"hunter22\"\n"
+        );
+    }
+}
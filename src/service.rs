@@ -0,0 +1,350 @@
+//! A long-running owner of a configuration
+//!
+//! [`CfgService::start`] moves a loaded [`Cfg`] and the path of its INI file onto a thread of its
+//! own.  Every read and change is sent to that thread as a command through a [`CfgHandle`], so
+//! any number of clients (the web front end, canpid, ...) see one consistent configuration.
+//! Changes are written once no further change has arrived for [`ServiceOptions::debounce`], and
+//! subscribers are sent a [`ServiceEvent`] for each change and each save.
+
+use crate::audit::{Annotation, AuditEntry};
+use crate::{Cfg, CfgError, WriteOptions};
+
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+/// How a [`CfgService`] persists changes
+pub struct ServiceOptions {
+    /// How long to wait after a change for further changes before writing the INI file
+    pub debounce: Duration,
+    /// Options for each write of the INI file
+    pub write: WriteOptions,
+}
+
+impl Default for ServiceOptions {
+    fn default() -> Self {
+        ServiceOptions {
+            debounce: Duration::from_millis(500),
+            write: WriteOptions::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// Sent to subscribers as the configuration changes and is saved
+pub enum ServiceEvent {
+    /// A current value was changed
+    Changed(AuditEntry),
+    /// The INI file was written
+    Saved(PathBuf),
+    /// Writing the INI file failed; the changes remain pending
+    SaveFailed(String),
+}
+
+type Reply<T> = Sender<Result<T, CfgError>>;
+
+enum Command {
+    Read(Box<dyn FnOnce(&Cfg) + Send>),
+    Apply(Vec<(String, String)>, Option<Annotation>, Reply<()>),
+    Flush(Reply<()>),
+    Subscribe(Sender<ServiceEvent>),
+    Shutdown(Option<Reply<()>>),
+}
+
+/// Runs on the service thread and owns the configuration
+struct Worker {
+    cfg: Cfg,
+    path: PathBuf,
+    options: ServiceOptions,
+    subscribers: Vec<Sender<ServiceEvent>>,
+    /// When the pending changes are to be written, if there are any
+    due: Option<Instant>,
+}
+
+impl Worker {
+    fn run(mut self, commands: Receiver<Command>) -> Cfg {
+        loop {
+            let command = match self.due {
+                Some(at) => {
+                    match commands.recv_timeout(at.saturating_duration_since(Instant::now())) {
+                        Ok(command) => command,
+                        Err(RecvTimeoutError::Timeout) => {
+                            let _ = self.save();
+                            continue;
+                        }
+                        Err(RecvTimeoutError::Disconnected) => Command::Shutdown(None),
+                    }
+                }
+                None => commands.recv().unwrap_or(Command::Shutdown(None)),
+            };
+            match command {
+                Command::Read(f) => f(&self.cfg),
+                Command::Apply(changes, annotation, reply) => {
+                    let _ = reply.send(self.apply(&changes, annotation.as_ref()));
+                }
+                Command::Flush(reply) => {
+                    let _ = reply.send(self.flush());
+                }
+                Command::Subscribe(subscriber) => self.subscribers.push(subscriber),
+                Command::Shutdown(reply) => {
+                    let result = self.flush();
+                    if let Some(reply) = reply {
+                        let _ = reply.send(result);
+                    }
+                    return self.cfg;
+                }
+            }
+        }
+    }
+
+    fn apply(
+        &mut self,
+        changes: &[(String, String)],
+        annotation: Option<&Annotation>,
+    ) -> Result<(), CfgError> {
+        let changes: Vec<(&str, &str)> = changes
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        let recorded = self.cfg.audit_log().len();
+        self.cfg.apply_changes(&changes, annotation)?;
+        let entries = self.cfg.audit_log()[recorded..].to_vec();
+        if !entries.is_empty() {
+            self.due = Some(Instant::now() + self.options.debounce);
+        }
+        for entry in entries {
+            self.emit(ServiceEvent::Changed(entry));
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), CfgError> {
+        match self.due {
+            Some(_) => self.save(),
+            None => Ok(()),
+        }
+    }
+
+    /// Write the INI file; on failure the changes are retried after another debounce interval
+    fn save(&mut self) -> Result<(), CfgError> {
+        match self
+            .cfg
+            .write_cfg_file_with(&self.path, &self.options.write)
+        {
+            Ok(()) => {
+                self.due = None;
+                self.emit(ServiceEvent::Saved(self.path.clone()));
+                Ok(())
+            }
+            Err(err) => {
+                self.due = Some(Instant::now() + self.options.debounce);
+                self.emit(ServiceEvent::SaveFailed(err.to_string()));
+                Err(err)
+            }
+        }
+    }
+
+    /// Send `event` to every subscriber, dropping those that have gone away
+    fn emit(&mut self, event: ServiceEvent) {
+        self.subscribers.retain(|s| s.send(event.clone()).is_ok());
+    }
+}
+
+#[derive(Clone)]
+/// A client's connection to a [`CfgService`]
+///
+/// Every method returns [`CfgError::ServiceStopped`] once the service has shut down.
+pub struct CfgHandle {
+    commands: Sender<Command>,
+}
+
+impl CfgHandle {
+    fn send(&self, command: Command) -> Result<(), CfgError> {
+        self.commands
+            .send(command)
+            .map_err(|_| CfgError::ServiceStopped)
+    }
+
+    fn call<T>(&self, command: impl FnOnce(Reply<T>) -> Command) -> Result<T, CfgError> {
+        let (reply, result) = mpsc::channel();
+        self.send(command(reply))?;
+        result.recv().map_err(|_| CfgError::ServiceStopped)?
+    }
+
+    /// The result of `f` run against the configuration
+    pub fn read<R, F>(&self, f: F) -> Result<R, CfgError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&Cfg) -> R + Send + 'static,
+    {
+        let (reply, result) = mpsc::channel();
+        self.send(Command::Read(Box::new(move |cfg| {
+            let _ = reply.send(f(cfg));
+        })))?;
+        result.recv().map_err(|_| CfgError::ServiceStopped)
+    }
+
+    /// The current value of `key`, or `None` if it is not defined
+    pub fn get<K: AsRef<str>>(&self, key: K) -> Result<Option<String>, CfgError> {
+        let key = key.as_ref().to_string();
+        self.read(move |cfg| cfg.read_attribute(key).map(|a| a.current.clone()))
+    }
+
+    /// As [`Cfg::set_value`]
+    pub fn set_value<K: AsRef<str>>(&self, key: K, value: &str) -> Result<(), CfgError> {
+        let key = key.as_ref();
+        self.apply_changes(&[(key, value)], None)
+    }
+
+    /// As [`Cfg::apply_changes`]; the changes are written after the debounce interval
+    pub fn apply_changes(
+        &self,
+        changes: &[(&str, &str)],
+        annotation: Option<&Annotation>,
+    ) -> Result<(), CfgError> {
+        let changes = changes
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let annotation = annotation.cloned();
+        self.call(|reply| Command::Apply(changes, annotation, reply))
+    }
+
+    /// Write any pending changes now
+    pub fn flush(&self) -> Result<(), CfgError> {
+        self.call(Command::Flush)
+    }
+
+    /// Receive an event for every change and save from now on
+    pub fn subscribe(&self) -> Result<Receiver<ServiceEvent>, CfgError> {
+        let (events, receiver) = mpsc::channel();
+        self.send(Command::Subscribe(events))?;
+        Ok(receiver)
+    }
+}
+
+/// The thread owning a configuration, and a handle to it
+pub struct CfgService {
+    handle: CfgHandle,
+    thread: Option<JoinHandle<Cfg>>,
+}
+
+impl CfgService {
+    /// Move `cfg`, loaded from the INI file at `path`, onto a service thread
+    pub fn start<P: Into<PathBuf>>(cfg: Cfg, path: P, options: ServiceOptions) -> CfgService {
+        let (commands, receiver) = mpsc::channel();
+        let worker = Worker {
+            cfg,
+            path: path.into(),
+            options,
+            subscribers: Vec::new(),
+            due: None,
+        };
+        let thread = thread::Builder::new()
+            .name("canpi-config".to_string())
+            .spawn(move || worker.run(receiver))
+            .expect("failed to spawn configuration service thread");
+        CfgService {
+            handle: CfgHandle { commands },
+            thread: Some(thread),
+        }
+    }
+
+    /// A new handle to the service
+    pub fn handle(&self) -> CfgHandle {
+        self.handle.clone()
+    }
+
+    /// Write any pending changes, stop the service and give back the configuration
+    ///
+    /// Handles still held fail with [`CfgError::ServiceStopped`] from now on.
+    pub fn shutdown(mut self) -> Result<Cfg, CfgError> {
+        let flushed = self.handle.call(|reply| Command::Shutdown(Some(reply)));
+        let cfg = self
+            .thread
+            .take()
+            .expect("service thread")
+            .join()
+            .map_err(|_| CfgError::ServiceStopped)?;
+        flushed.map(|_| cfg)
+    }
+}
+
+impl Drop for CfgService {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = self.handle.send(Command::Shutdown(None));
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::CANID_DISPLAY_DEFN;
+    use crate::{MemoryFs, NoBackup};
+    use std::sync::Arc;
+
+    fn start(debounce: Duration) -> (Arc<MemoryFs>, CfgService) {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("/canpi/defn.json", CANID_DISPLAY_DEFN);
+        fs.insert("/canpi/canpi.cfg", "canid=100\n");
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.set_backup_strategy(NoBackup);
+        cfg.load_configuration("/canpi/canpi.cfg", "/canpi/defn.json")
+            .expect("configuration failed to load");
+        let options = ServiceOptions {
+            debounce,
+            ..ServiceOptions::default()
+        };
+        (fs, CfgService::start(cfg, "/canpi/canpi.cfg", options))
+    }
+
+    #[test]
+    fn changes_are_saved_after_debounce() {
+        let (fs, service) = start(Duration::from_millis(20));
+        let handle = service.handle();
+        let events = handle.subscribe().unwrap();
+        handle.set_value("canid", "101").expect("set_value failed");
+        assert!(handle.set_value("wifi", "on").is_err());
+        assert_eq!(handle.get("canid").unwrap().as_deref(), Some("101"));
+
+        let wait = || events.recv_timeout(Duration::from_secs(5)).unwrap();
+        match wait() {
+            ServiceEvent::Changed(entry) => {
+                assert_eq!((entry.old, entry.new), ("100".into(), "101".into()))
+            }
+            e => panic!("unexpected event {:?}", e),
+        }
+        assert_eq!(
+            wait(),
+            ServiceEvent::Saved(PathBuf::from("/canpi/canpi.cfg"))
+        );
+        assert!(fs
+            .contents("/canpi/canpi.cfg")
+            .unwrap()
+            .contains("canid=101"));
+    }
+
+    #[test]
+    fn shutdown_flushes_pending_changes() {
+        let (fs, service) = start(Duration::from_secs(3600));
+        let handle = service.handle();
+        handle.set_value("canid", "102").expect("set_value failed");
+        assert!(!fs
+            .contents("/canpi/canpi.cfg")
+            .unwrap()
+            .contains("canid=102"));
+        let cfg = service.shutdown().expect("shutdown failed");
+        assert!(fs
+            .contents("/canpi/canpi.cfg")
+            .unwrap()
+            .contains("canid=102"));
+        assert_eq!(cfg.get_or_default("canid"), "102");
+        assert!(matches!(handle.get("canid"), Err(CfgError::ServiceStopped)));
+    }
+}
@@ -0,0 +1,83 @@
+//! Typed settings structs
+//!
+//! A consumer such as canpid can work with a plain struct of typed fields rather than looking
+//! values up by key.  With the `derive` feature `#[derive(CanpiConfig)]` implements
+//! [`CanpiConfig`] for a struct with named fields, mapping each to the configuration item of the
+//! same name unless given `#[canpi(key = "...")]`, or leaving it out with `#[canpi(skip)]`.
+//!
+//! The definition can be kept in the same struct: `#[derive(CanpiDefinition)]` implements
+//! [`CanpiDefinition`] from the `prompt`, `tooltip`, `default`, `format`, `action`, `kind` and
+//! `section` given in each field's `#[canpi(...)]`, the tooltip falling back to the field's doc comment.
+//! [`definition_json`] then writes the definition file.
+
+use crate::defn_writer::defn_items;
+use crate::{Cfg, CfgError, ConfigHash, CFG_SCHEMA};
+
+use serde_json::Value;
+
+/// A struct whose fields are configuration items
+pub trait CanpiConfig: Sized {
+    /// Read the fields from the current values in `cfg`
+    fn from_cfg(cfg: &Cfg) -> Result<Self, CfgError>;
+
+    /// Set the current values in `cfg` from the fields, as one change
+    fn apply_to(&self, cfg: &mut Cfg) -> Result<(), CfgError>;
+}
+
+/// A struct whose fields define configuration items
+pub trait CanpiDefinition {
+    /// The attribute definitions, by key
+    fn definition() -> ConfigHash;
+}
+
+/// The definition file for `T`, checked against the definition schema
+pub fn definition_json<T: CanpiDefinition>() -> Result<String, CfgError> {
+    let defn = defn_items(&T::definition(), &CFG_SCHEMA).map_err(|e| match e {
+        CfgError::Schema(_) => CfgError::Schema(std::any::type_name::<T>().to_string()),
+        e => e,
+    })?;
+    Ok(serde_json::to_string_pretty(&Value::Object(defn))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ActionBehaviour, Attribute};
+    use std::fs;
+
+    struct Settings;
+
+    impl CanpiDefinition for Settings {
+        fn definition() -> ConfigHash {
+            let mut defn = ConfigHash::default();
+            defn.insert(
+                "canid".to_string(),
+                Attribute::new(
+                    "CAN Id",
+                    "The CAN Id used on the CBUS",
+                    "100",
+                    "[0-9]{1,4}",
+                    ActionBehaviour::Display,
+                ),
+            );
+            defn
+        }
+    }
+
+    #[test]
+    fn definition_loads() {
+        let defn_file = "scratch/settings_test.json";
+        fs::write(
+            defn_file,
+            definition_json::<Settings>().expect("definition failed"),
+        )
+        .expect("file write failed");
+        let mut cfg = Cfg::new();
+        cfg.load_definition(defn_file)
+            .expect("definition failed to load");
+        fs::remove_file(defn_file).expect("file deletion failed");
+        let attr = cfg.read_attribute("canid").unwrap();
+        assert_eq!(&*attr.prompt, "CAN Id");
+        assert_eq!(attr.current, "100");
+    }
+}
@@ -0,0 +1,223 @@
+//! Signed definition files
+//!
+//! On a managed layout the definition file decides which items may be seen and edited, so a
+//! tampered definition, such as one changing `Hide` to `Edit`, would expose settings.  With the
+//! `signing` feature and a [`DefnPublicKey`] set by [`Cfg::set_defn_public_key`], a definition is
+//! only accepted with a valid ed25519 signature, either
+//!
+//! - detached, as the base64 signature of the bytes of the file in a file of the same name with
+//!   `.sig` appended, or
+//! - embedded, as the base64 signature in the top level `"$signature"` member, of the rest of the
+//!   definition as given by [`defn_signing_bytes`].
+//!
+//! An embedded signature is ignored when no key is set.
+
+#[cfg(feature = "signing")]
+use crate::builder::DefnSource;
+#[cfg(feature = "signing")]
+use crate::{defn_format, Cfg, CfgError, Fs};
+
+#[cfg(feature = "signing")]
+use base64::engine::general_purpose::STANDARD;
+#[cfg(feature = "signing")]
+use base64::Engine;
+#[cfg(feature = "signing")]
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde_json::Value;
+
+#[cfg(feature = "signing")]
+use std::convert::TryInto;
+#[cfg(feature = "signing")]
+use std::path::{Path, PathBuf};
+
+/// Member of a definition holding its embedded signature
+const SIGNATURE_MEMBER: &str = "$signature";
+
+/// Remove any embedded signature from the definition `json_value`, returning it
+pub(crate) fn take_signature(json_value: &mut Value) -> Option<Value> {
+    json_value.as_object_mut()?.remove(SIGNATURE_MEMBER)
+}
+
+#[cfg(feature = "signing")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// The ed25519 public key definition files are signed for
+pub struct DefnPublicKey([u8; 32]);
+
+#[cfg(feature = "signing")]
+impl DefnPublicKey {
+    /// The key made of `bytes`
+    pub fn from_bytes(bytes: [u8; 32]) -> DefnPublicKey {
+        DefnPublicKey(bytes)
+    }
+
+    /// Read a key from its base64 text, as held in a key file
+    pub fn from_base64(text: &str) -> Result<DefnPublicKey, CfgError> {
+        let bytes = STANDARD
+            .decode(text.trim())
+            .map_err(|e| CfgError::Signature(format!("key is not base64: {}", e)))?;
+        let bytes: [u8; 32] = bytes.try_into().map_err(|b: Vec<u8>| {
+            CfgError::Signature(format!("key is {} bytes, not 32", b.len()))
+        })?;
+        Ok(DefnPublicKey(bytes))
+    }
+
+    /// Check that `signature`, in base64, is a signature of `message` by this key
+    fn verify(&self, message: &[u8], signature: &str) -> Result<(), CfgError> {
+        let signature = STANDARD
+            .decode(signature.trim())
+            .map_err(|e| CfgError::Signature(format!("signature is not base64: {}", e)))?;
+        UnparsedPublicKey::new(&ED25519, self.0)
+            .verify(message, &signature)
+            .map_err(|_| CfgError::Signature("signature does not match".to_string()))
+    }
+}
+
+#[cfg(feature = "signing")]
+/// The bytes an embedded signature of the definition `defn` signs
+///
+/// They are the definition without its `"$signature"` member written as compact JSON with the
+/// members of each object in key order, so are the same whether the definition is written in
+/// JSON, TOML or YAML.
+pub fn defn_signing_bytes(defn: &Value) -> Vec<u8> {
+    let mut defn = defn.clone();
+    take_signature(&mut defn);
+    let mut out = String::new();
+    write_sorted(&defn, &mut out);
+    out.into_bytes()
+}
+
+#[cfg(feature = "signing")]
+/// Write `value` to `out` as compact JSON with object members in key order
+fn write_sorted(value: &Value, out: &mut String) {
+    match value {
+        Value::Object(map) => {
+            let mut members: Vec<_> = map.iter().collect();
+            members.sort_by(|a, b| a.0.cmp(b.0));
+            out.push('{');
+            for (i, (key, value)) in members.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::from(key.as_str()).to_string());
+                out.push(':');
+                write_sorted(value, out);
+            }
+            out.push('}');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_sorted(item, out);
+            }
+            out.push(']');
+        }
+        scalar => out.push_str(&scalar.to_string()),
+    }
+}
+
+#[cfg(feature = "signing")]
+/// The file holding the detached signature of the definition file `path`
+pub(crate) fn signature_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".sig");
+    PathBuf::from(name)
+}
+
+#[cfg(feature = "signing")]
+/// Check the signature of `text`, the definition read from `source`, against `key`
+pub(crate) fn verify_defn(
+    key: &DefnPublicKey,
+    fs: &dyn Fs,
+    source: &DefnSource,
+    text: &str,
+) -> Result<(), CfgError> {
+    let mut defn = defn_format::parse_defn(source.path(), text)?;
+    let unsigned = || CfgError::Signature(format!("{} is not signed", source.path().display()));
+    match take_signature(&mut defn) {
+        Some(Value::String(signature)) => key.verify(&defn_signing_bytes(&defn), &signature),
+        Some(_) => Err(CfgError::Signature(
+            "embedded signature is not a string".to_string(),
+        )),
+        None => match source {
+            DefnSource::File(path) => {
+                let signature = fs
+                    .read_to_string(&signature_path(path))
+                    .map_err(|_| unsigned())?;
+                key.verify(text.as_bytes(), &signature)
+            }
+            DefnSource::Text(_) => Err(unsigned()),
+        },
+    }
+}
+
+#[cfg(feature = "signing")]
+impl Cfg {
+    /// Accept only definitions signed for `key`, or any definition if `None`
+    ///
+    /// Applies to the definitions loaded after it is set.
+    pub fn set_defn_public_key(&mut self, key: Option<DefnPublicKey>) {
+        self.defn_key = key;
+    }
+}
+
+#[cfg(all(test, feature = "signing"))]
+mod tests {
+    use super::*;
+    use crate::{ActionBehaviour, MemoryFs};
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use std::sync::Arc;
+
+    const DEFN_DATA: &str = r#"{
+    "router_password" : {
+        "prompt": "Router password", "tooltip": "", "current": "", "default": "",
+        "format": ".*", "action": "Hide"
+    }
+}"#;
+
+    #[test]
+    fn signatures_are_verified() {
+        let pair = Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap();
+        let sign = |message: &[u8]| STANDARD.encode(pair.sign(message));
+        let key = DefnPublicKey::from_bytes(pair.public_key().as_ref().try_into().unwrap());
+        assert_eq!(
+            DefnPublicKey::from_base64(&STANDARD.encode(pair.public_key())).unwrap(),
+            key
+        );
+
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.set_defn_public_key(Some(key));
+        let err = cfg.load_definition("defn.json").unwrap_err();
+        assert!(matches!(err.root(), CfgError::Signature(m) if m.contains("not signed")));
+
+        // Detached
+        fs.insert("defn.json.sig", sign(DEFN_DATA.as_bytes()));
+        cfg.load_definition("defn.json")
+            .expect("signed definition rejected");
+        let tampered = DEFN_DATA.replace("Hide", "Edit");
+        fs.insert("defn.json", &tampered);
+        let err = cfg.load_definition("defn.json").unwrap_err();
+        assert!(matches!(err.root(), CfgError::Signature(m) if m.contains("does not match")));
+
+        // Embedded
+        let mut defn: Value = serde_json::from_str(DEFN_DATA).unwrap();
+        let signature = sign(&defn_signing_bytes(&defn));
+        defn[SIGNATURE_MEMBER] = Value::from(signature);
+        let signed = serde_json::to_string_pretty(&defn).unwrap();
+        cfg.load_configuration_from_str("", &signed)
+            .expect("signed definition rejected");
+        let attr = cfg.read_attribute("router_password").unwrap();
+        assert_eq!(attr.action, ActionBehaviour::Hide);
+        let tampered = signed.replace("Hide", "Edit");
+        assert!(cfg.load_configuration_from_str("", &tampered).is_err());
+
+        cfg.set_defn_public_key(None);
+        cfg.load_configuration_from_str("", &tampered)
+            .expect("embedded signature not ignored");
+    }
+}
@@ -0,0 +1,179 @@
+//! Golden-file snapshots
+//!
+//! [`Cfg::snapshot`] renders every attribute in a canonical text form: items in key order, one
+//! field per line, values quoted and escaped.  [`Cfg::check_snapshot`] compares that text with a
+//! stored file so a regression test of a definition is one line.  A missing snapshot file is
+//! created, and setting the environment variable `CANPI_UPDATE_SNAPSHOTS` rewrites existing
+//! ones after an intended change.
+
+use crate::{Cfg, CfgError};
+
+use std::env;
+use std::fmt::Write as _;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Environment variable that makes [`Cfg::check_snapshot`] overwrite stored snapshots
+pub const UPDATE_SNAPSHOTS_VAR: &str = "CANPI_UPDATE_SNAPSHOTS";
+
+/// Lines of unchanged text shown either side of a difference
+const CONTEXT: usize = 2;
+
+impl Cfg {
+    /// Render the configuration in the canonical snapshot form
+    pub fn snapshot(&self) -> String {
+        let mut text = String::new();
+        if let Some(cfg) = &self.cfg {
+            let mut keys: Vec<&String> = cfg.keys().collect();
+            keys.sort();
+            for k in keys {
+                let a = &cfg[k];
+                if !text.is_empty() {
+                    text.push('\n');
+                }
+                // Writing to a String cannot fail
+                let _ = writeln!(text, "[{}]", k);
+                let _ = writeln!(text, "prompt = {:?}", &*a.prompt);
+                let _ = writeln!(text, "tooltip = {:?}", &*a.tooltip);
+                let _ = writeln!(text, "current = {:?}", a.shown(&a.current));
+                let _ = writeln!(text, "default = {:?}", a.shown(&a.default));
+                let _ = writeln!(text, "format = {:?}", &*a.format);
+                let _ = writeln!(text, "action = {:?}", a.action);
+            }
+        }
+        text
+    }
+
+    /// Compare [`Cfg::snapshot`] with the snapshot stored in `path`
+    ///
+    /// The snapshot is written instead when the file does not exist or `CANPI_UPDATE_SNAPSHOTS`
+    /// is set.  A mismatch is a [`CfgError::Snapshot`] holding a line diff, `-` marking stored
+    /// lines and `+` current ones.
+    pub fn check_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<(), CfgError> {
+        let actual = self.snapshot();
+        let update = env::var_os(UPDATE_SNAPSHOTS_VAR).is_some();
+        let expected = match self.fs.read_to_string(path.as_ref()) {
+            Ok(text) if !update => text,
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {
+                self.fs.write(path.as_ref(), actual.as_bytes())?;
+                return Ok(());
+            }
+        };
+        if expected == actual {
+            return Ok(());
+        }
+        Err(CfgError::Snapshot(format!(
+            "{} differs from the current configuration\n{}",
+            path.as_ref().display(),
+            line_diff(&expected, &actual)
+        )))
+    }
+
+    /// As [`Cfg::check_snapshot`] but panic with the diff on a mismatch, for use in tests
+    pub fn assert_snapshot<P: AsRef<Path>>(&self, path: P) {
+        if let Err(e) = self.check_snapshot(path) {
+            panic!("{}", e);
+        }
+    }
+}
+
+/// A line diff of `expected` and `actual` with a little unchanged context around each change
+///
+/// Each group of lines is headed by the `[key]` of the item it falls in.
+fn line_diff(expected: &str, actual: &str) -> String {
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+    // Longest common subsequence lengths of the suffixes of both
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut lines: Vec<(char, &str)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(('-', old[i]));
+            i += 1;
+        } else {
+            lines.push(('+', new[j]));
+            j += 1;
+        }
+    }
+
+    let changed: Vec<usize> = (0..lines.len()).filter(|n| lines[*n].0 != ' ').collect();
+    let near_change = |n: usize| {
+        changed
+            .iter()
+            .any(|c| n + CONTEXT >= *c && n <= c + CONTEXT)
+    };
+    // Each run of lines shown starts with the item it belongs to, unless that is shown anyway
+    let mut diff = String::new();
+    let mut item: Option<&str> = None;
+    let mut showing = false;
+    for (n, (marker, line)) in lines.iter().enumerate() {
+        if near_change(n) {
+            if !showing && !line.starts_with('[') {
+                if let Some(header) = item {
+                    let _ = writeln!(diff, "@@ {} @@", header);
+                }
+            }
+            let _ = writeln!(diff, "{} {}", marker, line);
+            showing = true;
+        } else if showing {
+            diff.push_str("  ...\n");
+            showing = false;
+        }
+        if line.starts_with('[') && *marker != '+' {
+            item = Some(line);
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::load_example_defn;
+    use std::fs;
+
+    #[test]
+    fn snapshots_match_and_differ() {
+        let snapshot_file = "scratch/snapshot_test.snap";
+        let mut cfg = load_example_defn("scratch/snapshot_test.json");
+
+        cfg.check_snapshot(snapshot_file)
+            .expect("snapshot not recorded");
+        cfg.assert_snapshot(snapshot_file);
+        assert!(cfg.snapshot().starts_with("[ap_channel]\nprompt = "));
+
+        cfg.set_value("canid", "101").expect("set_value failed");
+        let result = cfg.check_snapshot(snapshot_file);
+        fs::remove_file(snapshot_file).expect("file deletion failed");
+        match result {
+            Err(CfgError::Snapshot(diff)) => {
+                assert!(diff.contains("- current = \"100\"\n+ current = \"101\"\n"));
+                assert!(diff.starts_with(
+                    "scratch/snapshot_test.snap differs from the current configuration\n@@ [canid] @@\n"
+                ));
+                assert!(diff.ends_with("  ...\n"));
+            }
+            other => panic!("expected a snapshot mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn diff_of_inserted_lines() {
+        assert_eq!(line_diff("a\nb\n", "a\nx\nb\n"), "  a\n+ x\n  b\n");
+    }
+}
@@ -0,0 +1,115 @@
+//! Property-based testing strategies
+//!
+//! [`proptest`] strategies producing valid attributes, configurations and the INI text a
+//! configuration is saved as, so downstream crates can property-test their handling of any
+//! valid configuration.  Every generated current and default value satisfies its format.
+
+use crate::{ActionBehaviour, Attribute, ConfigHash, ControlChars, IniWriter, ValueKind};
+
+use proptest::prelude::*;
+use proptest::string::string_regex;
+
+/// Formats the generated attributes are given
+const FORMATS: &[&str] = &[
+    "[0-9]{1,4}",
+    "[0-9]{1,5}",
+    "[a-zA-Z0-9_]{1,16}",
+    "true|false",
+    "INFO|WARN|ERROR|DEBUG",
+    "[ -~]{0,24}",
+];
+
+/// Any [`ActionBehaviour`]
+pub fn arb_action() -> impl Strategy<Value = ActionBehaviour> {
+    prop_oneof![
+        Just(ActionBehaviour::Edit),
+        Just(ActionBehaviour::Display),
+        Just(ActionBehaviour::Hide),
+    ]
+}
+
+/// An [`Attribute`] whose current and default values match its format
+pub fn arb_attribute() -> impl Strategy<Value = Attribute> {
+    prop::sample::select(FORMATS).prop_flat_map(|format| {
+        let value = || string_regex(format).expect("valid format");
+        (
+            "[A-Za-z][A-Za-z0-9 ]{0,20}",
+            "[ -~]{0,40}",
+            value(),
+            value(),
+            arb_action(),
+        )
+            .prop_map(
+                move |(prompt, tooltip, current, default, action)| Attribute {
+                    prompt: prompt.into(),
+                    tooltip: tooltip.into(),
+                    current,
+                    default,
+                    format: format.into(),
+                    action,
+                    partial_match: false,
+                    control_chars: ControlChars::Reject,
+                    kind: ValueKind::Text,
+                    section: None,
+                    choices: Vec::new(),
+                    min: None,
+                    max: None,
+                    step: None,
+                    depends_on: None,
+                    category: None,
+                    display_order: None,
+                    units: None,
+                    placeholder: None,
+                    required: false,
+                    secret: false,
+                    widget: None,
+                    translations: None,
+                    deprecated_aliases: Vec::new(),
+                },
+            )
+    })
+}
+
+/// A key as used in the general section of the INI file
+pub fn arb_key() -> impl Strategy<Value = String> {
+    "[a-z][a-z0-9_]{0,15}"
+}
+
+/// A [`ConfigHash`] of up to 16 valid attributes
+pub fn arb_config_hash() -> impl Strategy<Value = ConfigHash> {
+    prop::collection::vec((arb_key(), arb_attribute()), 0..16)
+        .prop_map(|items| items.into_iter().collect())
+}
+
+/// A [`ConfigHash`] with the INI text holding its current values
+pub fn arb_ini_content() -> impl Strategy<Value = (ConfigHash, String)> {
+    arb_config_hash().prop_map(|cfg| {
+        let mut writer = IniWriter::new(Vec::new());
+        for (k, v) in &cfg {
+            writer.entry(k, &v.current).expect("write to memory");
+        }
+        let text = writer.finish().expect("write to memory");
+        (cfg, String::from_utf8(text).expect("INI text is UTF-8"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ini_reader;
+    use crate::LoadOptions;
+    use ini::Ini;
+
+    proptest! {
+        #[test]
+        fn ini_content_round_trips((cfg, text) in arb_ini_content()) {
+            let options = ini_reader::parse_options(&LoadOptions::default());
+            let ini = Ini::load_from_str_opt(&text, options).expect("INI text parses");
+            for (k, v) in &cfg {
+                prop_assert_eq!(ini.general_section().get(k), Some(v.current.as_str()));
+                prop_assert_eq!(v.format_matches(&v.current), Ok(true));
+                prop_assert_eq!(v.format_matches(&v.default), Ok(true));
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Definitions and scratch files shared by the unit tests
+
+use crate::Cfg;
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+/// The example definition file that most tests load
+pub(crate) const EXAMPLE_DEFN_FILE: &str = "tests/good-example-config-defn.json";
+
+/// One editable item, `canid`
+pub(crate) const CANID_DEFN: &str = r#"
+    {
+        "canid" : {
+            "prompt": "CAN Id", "tooltip": "", "current": "100", "default": "100",
+            "format": "[0-9]{1,4}", "action": "Edit"
+        }
+    }"#;
+
+/// One display only item, `canid`
+pub(crate) const CANID_DISPLAY_DEFN: &str = r#"
+    {
+        "canid" : {
+            "prompt": "CAN Id",
+            "tooltip": "The CAN Id used by the CAN Pi CAP/Zero on the CBUS",
+            "current": "100",
+            "default": "100",
+            "format": "[0-9]{1,4}",
+            "action": "Display"
+        }
+    }"#;
+
+/// Two editable items, `canid` and `node_number`
+pub(crate) const CANID_NODE_DEFN: &str = r#"
+    {
+        "canid" : {
+            "prompt": "CAN Id", "tooltip": "", "current": "100", "default": "100",
+            "format": "[0-9]{1,4}", "action": "Edit"
+        },
+        "node_number" : {
+            "prompt": "Node Number", "tooltip": "", "current": "4321", "default": "4321",
+            "format": "[0-9]{1,4}", "action": "Edit"
+        }
+    }"#;
+
+/// A display only `canid` and an editable `node_number`
+pub(crate) const CANID_DISPLAY_NODE_DEFN: &str = r#"
+    {
+        "canid" : {
+            "prompt": "CAN Id",
+            "tooltip": "The CAN Id used by the CAN Pi CAP/Zero on the CBUS",
+            "current": "100",
+            "default": "100",
+            "format": "[0-9]{1,4}",
+            "action": "Display"
+        },
+        "node_number" : {
+            "prompt": "Node Number",
+            "tooltip": "The node number of the CAN Pi CAP/Zero on the CBUS",
+            "current": "4321",
+            "default": "4321",
+            "format": "[0-9]{1,5}",
+            "action": "Edit"
+        }
+    }"#;
+
+pub(crate) fn setup_file<P: AsRef<Path>>(test_file: P, data: &str) {
+    let mut f = File::create(test_file).expect("file creation failed");
+    f.write_all(data.as_bytes()).expect("file write failed");
+}
+
+pub(crate) fn teardown_file<P: AsRef<Path>>(test_file: P) {
+    fs::remove_file(test_file).expect("file deletion failed");
+}
+
+/// Load the definition `data` through the scratch file `defn_file`, which is then removed
+pub(crate) fn load_defn(defn_file: &str, data: &str) -> Cfg {
+    setup_file(defn_file, data);
+    let mut cfg = Cfg::new();
+    cfg.load_definition(defn_file)
+        .expect("definition failed to load");
+    teardown_file(defn_file);
+    cfg
+}
+
+/// Load [`EXAMPLE_DEFN_FILE`] through the scratch file `defn_file`, which is then removed
+pub(crate) fn load_example_defn(defn_file: &str) -> Cfg {
+    fs::copy(EXAMPLE_DEFN_FILE, defn_file).expect("file copy failed");
+    let mut cfg = Cfg::new();
+    cfg.load_definition(defn_file)
+        .expect("definition failed to load");
+    teardown_file(defn_file);
+    cfg
+}
@@ -0,0 +1,131 @@
+//! Tracing instrumentation
+//!
+//! The major operations run inside `tracing` spans carrying the files involved, key counts and
+//! a `duration_us` field recorded when the operation finishes, whether or not it succeeded.
+//! Failures that do not stop an operation, such as a backup or change notification that could
+//! not be made, are `warn` events; the crate never prints.  Nothing is emitted unless the
+//! application installs a subscriber.
+
+use std::time::Instant;
+
+use tracing::field::Value;
+use tracing::span::EnteredSpan;
+use tracing::Span;
+
+/// An entered span that records its elapsed time in `duration_us` when dropped
+pub(crate) struct Timed {
+    span: EnteredSpan,
+    start: Instant,
+}
+
+impl Timed {
+    /// Enter `span`, which must declare a `duration_us` field
+    pub fn new(span: Span) -> Timed {
+        Timed {
+            span: span.entered(),
+            start: Instant::now(),
+        }
+    }
+
+    /// Record `value` in the span field `field`
+    pub fn record<V: Value>(&self, field: &str, value: V) {
+        self.span.record(field, value);
+    }
+}
+
+impl Drop for Timed {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed().as_micros() as u64;
+        self.span.record("duration_us", elapsed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::CANID_DEFN;
+    use crate::{Cfg, MemoryFs};
+    use std::collections::HashMap;
+    use std::fmt::Debug;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    type Fields = HashMap<String, String>;
+
+    /// Keeps the name and fields of every span, a span's id being its position plus one
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<(&'static str, Fields)>>>);
+
+    struct Visitor<'a>(&'a mut Fields);
+
+    impl Visit for Visitor<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut fields = Fields::new();
+            span.record(&mut Visitor(&mut fields));
+            let mut spans = self.0.lock().unwrap();
+            spans.push((span.metadata().name(), fields));
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            let mut spans = self.0.lock().unwrap();
+            values.record(&mut Visitor(&mut spans[span.into_u64() as usize - 1].1));
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn operations_are_timed() {
+        let recorder = Recorder::default();
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", CANID_DEFN);
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut cfg = Cfg::new();
+            cfg.set_fs(fs);
+            cfg.load_definition("defn.json")
+                .expect("definition failed to load");
+            assert!(cfg.load_definition("missing.json").is_err());
+            cfg.set_value("canid", "101").expect("set_value failed");
+            cfg.write_cfg_file("canpi.cfg", None).expect("write failed");
+        });
+
+        let spans = recorder.0.lock().unwrap();
+        let named = |name: &str| -> Vec<&Fields> {
+            spans
+                .iter()
+                .filter(|(n, _)| *n == name)
+                .map(|(_, f)| f)
+                .collect()
+        };
+        let loads = named("load_definition");
+        assert_eq!(loads.len(), 2);
+        assert_eq!(loads[0]["defn_file"], "defn.json");
+        assert_eq!(loads[0]["keys"], "1");
+        assert!(!loads[1].contains_key("keys"));
+        assert!(loads.iter().all(|f| f.contains_key("duration_us")));
+        let writes = named("write_cfg_file");
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0]["file"], "canpi.cfg");
+        assert_eq!(writes[0]["changes"], "1");
+        assert!(writes[0].contains_key("duration_us"));
+    }
+}
@@ -0,0 +1,197 @@
+//! Translated prompts and tooltips
+//!
+//! In the definition file `prompt` and `tooltip` may each be a map of locale to text, e.g.
+//! `{ "en": "Node Number", "de": "Knotennummer" }`, in place of a single string.  The map is kept
+//! in the attribute's [`Translations`] and its `en` entry, or failing that its first, becomes
+//! the text shown.  [`Cfg::set_locale`] then selects which text [`Cfg::read_attribute`] and
+//! every other accessor return.
+
+use crate::{Attribute, Cfg, CfgError, ConfigHash};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Locale whose text is shown when none is selected, if the definition gives it
+const DEFAULT_LOCALE: &str = "en";
+
+/// The fields that may be translated
+const FIELDS: [&str; 2] = ["prompt", "tooltip"];
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+/// The text of an attribute's prompt and tooltip by locale
+pub struct Translations {
+    /// Prompt by locale
+    #[serde(default)]
+    pub prompt: BTreeMap<String, Arc<str>>,
+    /// Tooltip by locale
+    #[serde(default)]
+    pub tooltip: BTreeMap<String, Arc<str>>,
+}
+
+/// The text for `locale` in `texts`
+///
+/// A locale such as `de-CH` falls back to `de`, and a locale with no text to the default.
+fn text_for<'a>(
+    texts: &'a BTreeMap<String, Arc<str>>,
+    locale: Option<&str>,
+) -> Option<&'a Arc<str>> {
+    let language = locale.map(|l| l.split(['-', '_']).next().unwrap_or(l));
+    locale
+        .and_then(|l| texts.get(l))
+        .or_else(|| language.and_then(|l| texts.get(l)))
+        .or_else(|| texts.get(DEFAULT_LOCALE))
+        .or_else(|| texts.values().next())
+}
+
+/// Move each translated `prompt` and `tooltip` of a definition file into `translations`,
+/// leaving the default text in its place
+pub(crate) fn take_translations(json_value: &mut Value) -> Result<(), CfgError> {
+    let items = match json_value.as_object_mut() {
+        Some(items) => items,
+        None => return Ok(()),
+    };
+    for (key, item) in items.iter_mut() {
+        let item = match item.as_object_mut() {
+            Some(item) => item,
+            None => continue,
+        };
+        let mut translations = serde_json::Map::new();
+        for field in FIELDS {
+            if let Some(Value::Object(texts)) = item.get(field) {
+                let texts: BTreeMap<String, Arc<str>> =
+                    serde_json::from_value(Value::Object(texts.clone()))?;
+                let text = text_for(&texts, None).ok_or_else(|| {
+                    CfgError::Schema(format!("'{}' of '{}' has no translations", field, key))
+                })?;
+                item.insert(field.to_string(), Value::String(text.to_string()));
+                translations.insert(field.to_string(), serde_json::to_value(texts)?);
+            }
+        }
+        if !translations.is_empty() {
+            item.insert("translations".to_string(), Value::Object(translations));
+        }
+    }
+    Ok(())
+}
+
+impl Attribute {
+    /// The prompt in `locale`, or the default prompt if there is no translation for it
+    pub fn prompt_in(&self, locale: &str) -> &str {
+        match &self.translations {
+            Some(t) => text_for(&t.prompt, Some(locale)).unwrap_or(&self.prompt),
+            None => &self.prompt,
+        }
+    }
+
+    /// The tooltip in `locale`, or the default tooltip if there is no translation for it
+    pub fn tooltip_in(&self, locale: &str) -> &str {
+        match &self.translations {
+            Some(t) => text_for(&t.tooltip, Some(locale)).unwrap_or(&self.tooltip),
+            None => &self.tooltip,
+        }
+    }
+
+    /// Show the prompt and tooltip in `locale`, or in the default locale if `None`
+    fn localize(&mut self, locale: Option<&str>) {
+        if let Some(t) = self.translations.clone() {
+            if let Some(text) = text_for(&t.prompt, locale) {
+                self.prompt = text.clone();
+            }
+            if let Some(text) = text_for(&t.tooltip, locale) {
+                self.tooltip = text.clone();
+            }
+        }
+    }
+}
+
+impl Cfg {
+    /// Show prompts and tooltips in `locale`, e.g. `de`
+    ///
+    /// Applies to the items loaded and to those loaded later.  Text that has no translation for
+    /// the locale, nor for its language, is shown in the default locale.
+    pub fn set_locale(&mut self, locale: &str) {
+        self.locale = Some(locale.to_string());
+        if let Some(mut cfg) = self.cfg.take() {
+            self.localize(&mut cfg);
+            self.cfg = Some(cfg);
+            self.invalidate_caches();
+        }
+    }
+
+    /// The locale prompts and tooltips are shown in, if one has been selected
+    pub fn locale(&self) -> Option<&str> {
+        self.locale.as_deref()
+    }
+
+    /// Show the prompts and tooltips of `cfg` in the selected locale
+    pub(crate) fn localize(&self, cfg: &mut ConfigHash) {
+        for attr in cfg.values_mut() {
+            attr.localize(self.locale.as_deref());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryFs;
+
+    const DEFN_DATA: &str = r#"
+        {
+            "node_number" : {
+                "prompt": { "en": "Node Number", "de": "Knotennummer" },
+                "tooltip": {
+                    "en": "The node number of the CAN Pi CAP/Zero on the CBUS",
+                    "de": "Die Knotennummer des CAN Pi CAP/Zero am CBUS",
+                    "fr": "Le numéro de nœud du CAN Pi CAP/Zero sur le CBUS"
+                },
+                "current": "4321",
+                "default": "4321",
+                "format": "[0-9]{1,4}",
+                "action": "Edit"
+            },
+            "canid" : {
+                "prompt": "CAN Id",
+                "tooltip": "The CAN Id used by the CAN Pi CAP/Zero on the CBUS",
+                "current": "100",
+                "default": "100",
+                "format": "[0-9]{1,4}",
+                "action": "Edit"
+            }
+        }"#;
+
+    #[test]
+    fn prompts_follow_the_locale() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs);
+        cfg.load_definition("defn.json")
+            .expect("definition failed to load");
+        let prompt = |cfg: &Cfg, key: &str| cfg.read_attribute(key).unwrap().prompt.clone();
+        assert_eq!(&*prompt(&cfg, "node_number"), "Node Number");
+
+        cfg.set_locale("de-AT");
+        assert_eq!(cfg.locale(), Some("de-AT"));
+        assert_eq!(&*prompt(&cfg, "node_number"), "Knotennummer");
+        assert_eq!(&*prompt(&cfg, "canid"), "CAN Id");
+
+        cfg.set_locale("fr");
+        let attr = cfg.read_attribute("node_number").unwrap();
+        assert_eq!(&*attr.prompt, "Node Number");
+        assert!(attr.tooltip.starts_with("Le numéro"));
+        assert_eq!(attr.prompt_in("de"), "Knotennummer");
+
+        cfg.load_definition("defn.json")
+            .expect("definition failed to load");
+        assert!(cfg
+            .read_attribute("node_number")
+            .unwrap()
+            .tooltip
+            .starts_with("Le numéro"));
+    }
+}
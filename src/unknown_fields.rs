@@ -0,0 +1,126 @@
+//! Fields of a definition file the crate does not know
+//!
+//! Fields of an attribute that the crate does not know are dropped as the definition is read, so
+//! a misspelt optional field such as `"requried": true` goes unnoticed, while a top level member
+//! that is not an attribute fails the schema without saying which.  [`LoadOptions::unknown_fields`]
+//! selects whether such fields are ignored, listed in [`LoadReport::unknown_fields`] and dropped,
+//! or fail the load.
+//!
+//! [`LoadOptions::unknown_fields`]: crate::LoadOptions::unknown_fields
+//! [`LoadReport::unknown_fields`]: crate::LoadReport::unknown_fields
+
+use crate::{Attribute, CfgError, UnknownFields};
+
+use once_cell::sync::Lazy;
+use schemars::schema_for;
+use serde_json::Value;
+
+use std::collections::BTreeSet;
+
+/// Names of the fields an attribute may have
+static ATTRIBUTE_FIELDS: Lazy<BTreeSet<String>> = Lazy::new(|| {
+    let schema = schema_for!(Attribute);
+    schema
+        .schema
+        .object
+        .map(|object| object.properties.into_keys().collect())
+        .unwrap_or_default()
+});
+
+/// The top level members of the definition `json_value` that are not attributes, and the fields
+/// of its attributes that are not known, as `key.field`, in order
+fn unknown_fields(json_value: &Value) -> Vec<String> {
+    let mut unknown = Vec::new();
+    if let Some(map) = json_value.as_object() {
+        for (key, item) in map {
+            match item.as_object() {
+                Some(fields) => unknown.extend(
+                    fields
+                        .keys()
+                        .filter(|f| !ATTRIBUTE_FIELDS.contains(*f))
+                        .map(|f| format!("{}.{}", key, f)),
+                ),
+                None => unknown.push(key.clone()),
+            }
+        }
+    }
+    unknown.sort();
+    unknown
+}
+
+/// Deal with the unknown fields of the definition `json_value` as `treatment` says, returning
+/// those dropped
+///
+/// The definition's own members, such as `rules`, must have been taken out first.
+pub(crate) fn take_unknown_fields(
+    json_value: &mut Value,
+    treatment: UnknownFields,
+) -> Result<Vec<String>, CfgError> {
+    if treatment == UnknownFields::Ignore {
+        return Ok(Vec::new());
+    }
+    let unknown = unknown_fields(json_value);
+    if let (UnknownFields::Reject, Some(field)) = (treatment, unknown.first()) {
+        return Err(CfgError::UnknownField(field.clone()));
+    }
+    if let Some(map) = json_value.as_object_mut() {
+        for field in &unknown {
+            match field.split_once('.') {
+                Some((key, name)) if map.get(key).is_some_and(Value::is_object) => {
+                    if let Some(Value::Object(fields)) = map.get_mut(key) {
+                        fields.remove(name);
+                    }
+                }
+                _ => {
+                    map.remove(field);
+                }
+            }
+        }
+    }
+    Ok(unknown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cfg, LoadOptions, MemoryFs};
+    use std::sync::Arc;
+
+    const DEFN_DATA: &str = r#"{
+        "canid" : {
+            "prompt": "CAN Id", "tooltip": "", "current": "100", "default": "100",
+            "format": "[0-9]{1,4}", "action": "Edit", "requried": true
+        },
+        "colour": "red"
+    }"#;
+
+    #[test]
+    fn unknown_fields_are_treated_as_selected() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        fs.insert("canpi.cfg", "canid=101\n");
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs);
+        let load = |cfg: &mut Cfg, unknown_fields| {
+            let options = LoadOptions {
+                unknown_fields,
+                ..LoadOptions::default()
+            };
+            cfg.load_configuration_with("canpi.cfg", "defn.json", &options)
+        };
+
+        let err = load(&mut cfg, UnknownFields::Ignore).unwrap_err();
+        assert!(matches!(err.root(), CfgError::Schema(_)));
+        let report = load(&mut cfg, UnknownFields::Warn).expect("configuration failed to load");
+        assert_eq!(report.unknown_fields, ["canid.requried", "colour"]);
+        assert_eq!(cfg.get_or_default("canid"), "101");
+        let err = load(&mut cfg, UnknownFields::Reject).unwrap_err();
+        assert!(matches!(err.root(), CfgError::UnknownField(f) if f == "canid.requried"));
+
+        let mut json_value: Value = serde_json::from_str(DEFN_DATA).unwrap();
+        json_value.as_object_mut().unwrap().remove("colour");
+        let ignored = take_unknown_fields(&mut json_value, UnknownFields::Ignore).unwrap();
+        assert!(ignored.is_empty());
+        assert!(json_value["canid"].get("requried").is_some());
+    }
+}
@@ -0,0 +1,147 @@
+//! Validation of every package listed in a [`Pkg`]
+//!
+//! Each package is loaded and checked independently, so the work is spread across the
+//! available CPU cores with scoped threads.
+
+use crate::trace::Timed;
+use crate::{
+    ActionBehaviour, Cfg, CfgError, FormatViolation, Fs, LoadOptions, Package, Pkg, StdFs,
+};
+
+use tracing::field::Empty;
+use tracing::{info_span, Span};
+
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+#[derive(Debug)]
+/// Outcome of validating a single package
+pub struct PackageValidation {
+    /// Name of the package in the package definitions
+    pub name: String,
+    /// The format violations found, or the error that stopped the package loading
+    pub result: Result<Vec<FormatViolation>, CfgError>,
+    /// Problems that leave the package valid, such as INI keys that are not defined, in order
+    pub warnings: Vec<String>,
+}
+
+impl PackageValidation {
+    /// True if the package loaded and every current value satisfies its format
+    ///
+    /// Warnings do not count against the package.
+    pub fn is_ok(&self) -> bool {
+        matches!(&self.result, Ok(v) if v.is_empty())
+    }
+}
+
+impl Package {
+    /// Load the attribute definitions and INI file of this package and check the current values
+    ///
+    /// The values are those in the INI file; environment variables do not override them.
+    pub fn validate(&self) -> Result<Vec<FormatViolation>, CfgError> {
+        self.validate_in(Arc::new(StdFs))
+            .map(|(violations, _)| violations)
+    }
+
+    /// As [`Package::validate`], reading the files through `fs`, with the warnings found
+    fn validate_in(
+        &self,
+        fs: Arc<dyn Fs>,
+    ) -> Result<(Vec<FormatViolation>, Vec<String>), CfgError> {
+        let dir = Path::new(&self.cfg_path);
+        let ini_path = dir.join(&self.ini_file);
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs);
+        let options = LoadOptions {
+            env_prefix: None,
+            ..LoadOptions::default()
+        };
+        let report =
+            cfg.load_configuration_with(&ini_path, &dir.join(&self.json_file), &options)?;
+        let mut warnings: Vec<String> = report
+            .unknown_keys
+            .iter()
+            .map(|k| format!("key '{}' in {} is not defined", k, ini_path.display()))
+            .collect();
+        for action in [ActionBehaviour::Edit, ActionBehaviour::Display] {
+            for (k, v) in cfg.attributes_with_action_iter(action) {
+                if v.prompt.is_empty() {
+                    warnings.push(format!("attribute '{}' is visible but has no prompt", k));
+                }
+            }
+        }
+        warnings.sort();
+        Ok((cfg.format_violations(), warnings))
+    }
+}
+
+impl Pkg {
+    /// Validate every package in parallel
+    ///
+    /// The results are returned in package name order.  An empty list is returned if
+    /// load_packages has not been called.
+    pub fn validate_all(&self) -> Vec<PackageValidation> {
+        let mut packages: Vec<(&String, &Package)> = match &self.packages {
+            Some(p) => p.iter().collect(),
+            None => return Vec::new(),
+        };
+        packages.sort_by(|a, b| a.0.cmp(b.0));
+        if packages.is_empty() {
+            return Vec::new();
+        }
+        let timed = Timed::new(info_span!(
+            "validate_all",
+            packages = packages.len(),
+            workers = Empty,
+            failed = Empty,
+            duration_us = Empty,
+        ));
+        // Worker threads do not inherit the current span, so it is passed to them explicitly
+        let parent = Span::current();
+
+        let workers = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .min(packages.len());
+        timed.record("workers", workers);
+        let chunk_size = packages.len().div_ceil(workers);
+        let results: Vec<PackageValidation> = thread::scope(|s| {
+            let handles: Vec<_> = packages
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let parent = &parent;
+                    s.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|(name, package)| {
+                                let _timed = Timed::new(info_span!(
+                                    parent: parent,
+                                    "validate_package",
+                                    package = name.as_str(),
+                                    duration_us = Empty,
+                                ));
+                                let (result, warnings) = match package.validate_in(self.fs.clone())
+                                {
+                                    Ok((violations, warnings)) => (Ok(violations), warnings),
+                                    Err(e) => (Err(e.in_package(name)), Vec::new()),
+                                };
+                                PackageValidation {
+                                    name: name.to_string(),
+                                    result,
+                                    warnings,
+                                }
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().expect("package validation thread panicked"))
+                .collect()
+        });
+        timed.record("failed", results.iter().filter(|r| !r.is_ok()).count());
+        results
+    }
+}
@@ -0,0 +1,371 @@
+//! Typed values
+//!
+//! The INI file holds every value as text.  [`Attribute::value`] reads the text according to the
+//! attribute's [`ValueKind`] so consumers are handed an [`AttributeValue`] instead of parsing the
+//! string themselves, and [`Cfg::set_typed_value`] writes one back in the form stored.
+
+use crate::check::split_selection;
+use crate::datetime::{self, utc};
+use crate::{Attribute, Cfg, CfgError, ValueKind};
+
+use chrono::{DateTime, FixedOffset};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use std::borrow::Cow;
+use std::fmt;
+use std::net::IpAddr;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", content = "value")]
+/// A current or default value read according to its attribute's kind
+pub enum AttributeValue {
+    /// Any text
+    Text(String),
+    /// A whole number
+    Integer(i64),
+    /// A number that may have a fraction
+    Decimal(f64),
+    /// A flag
+    Boolean(bool),
+    /// An instant, in the offset it was stored with
+    #[serde(with = "rfc3339")]
+    #[schemars(with = "String")]
+    DateTime(DateTime<FixedOffset>),
+    /// An IPv4 or IPv6 address
+    IpAddr(IpAddr),
+    /// One of the attribute's choices
+    Enum(String),
+    /// The choices selected, in the order given
+    MultiSelect(Vec<String>),
+}
+
+mod rfc3339 {
+    use chrono::{DateTime, FixedOffset};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        time: &DateTime<FixedOffset>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&time.to_rfc3339())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<FixedOffset>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        DateTime::parse_from_rfc3339(&text).map_err(D::Error::custom)
+    }
+}
+
+impl AttributeValue {
+    /// The kind of attribute that holds values like this one
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            AttributeValue::Text(_) => ValueKind::Text,
+            AttributeValue::Integer(_) => ValueKind::Integer,
+            AttributeValue::Decimal(_) => ValueKind::Decimal,
+            AttributeValue::Boolean(_) => ValueKind::Boolean,
+            AttributeValue::DateTime(_) => ValueKind::DateTime,
+            AttributeValue::IpAddr(_) => ValueKind::IpAddr,
+            AttributeValue::Enum(_) => ValueKind::Enum,
+            AttributeValue::MultiSelect(_) => ValueKind::MultiSelect,
+        }
+    }
+}
+
+/// The value in the form stored in the INI file; flags are written as `1` or `0` and selections
+/// separated by commas
+impl fmt::Display for AttributeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttributeValue::Text(text) | AttributeValue::Enum(text) => f.write_str(text),
+            AttributeValue::Integer(n) => write!(f, "{}", n),
+            AttributeValue::Decimal(n) => write!(f, "{}", n),
+            AttributeValue::Boolean(flag) => f.write_str(if *flag { "1" } else { "0" }),
+            AttributeValue::DateTime(time) => f.write_str(&datetime::canonical(time)),
+            AttributeValue::IpAddr(addr) => write!(f, "{}", addr),
+            AttributeValue::MultiSelect(selected) => f.write_str(&selected.join(",")),
+        }
+    }
+}
+
+/// Read a flag: `1`, `true`, `yes` and `on` are true and `0`, `false`, `no` and `off` false
+pub(crate) fn parse_bool(text: &str) -> Option<bool> {
+    match text.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+impl Attribute {
+    /// Read `text` as a value of this attribute's kind
+    ///
+    /// Enum values, and each item of a MultiSelect value, must be one of `choices`, unless there
+    /// are none.
+    pub fn parse_value(&self, text: &str) -> Result<AttributeValue, String> {
+        let trimmed = text.trim();
+        let shown = self.shown(text);
+        match self.kind {
+            ValueKind::Text => Ok(AttributeValue::Text(text.to_string())),
+            ValueKind::Integer => trimmed
+                .parse()
+                .map(AttributeValue::Integer)
+                .map_err(|e| format!("'{}' is not a whole number: {}", shown, e)),
+            ValueKind::Decimal => trimmed
+                .parse()
+                .map(AttributeValue::Decimal)
+                .map_err(|e| format!("'{}' is not a number: {}", shown, e)),
+            ValueKind::Boolean => parse_bool(trimmed)
+                .map(AttributeValue::Boolean)
+                .ok_or_else(|| format!("'{}' is not a flag", shown)),
+            ValueKind::DateTime => datetime::parse(trimmed, utc()).map(AttributeValue::DateTime),
+            ValueKind::IpAddr => trimmed
+                .parse()
+                .map(AttributeValue::IpAddr)
+                .map_err(|e| format!("'{}' is not an IP address: {}", shown, e)),
+            ValueKind::Enum => {
+                if self.choices.is_empty() || self.choices.iter().any(|c| **c == *trimmed) {
+                    Ok(AttributeValue::Enum(trimmed.to_string()))
+                } else {
+                    Err(format!("'{}' is not one of the choices", shown))
+                }
+            }
+            ValueKind::MultiSelect => match self.choice_error(text) {
+                Some(e) => Err(e),
+                None => Ok(AttributeValue::MultiSelect(
+                    split_selection(text).map(String::from).collect(),
+                )),
+            },
+        }
+    }
+
+    /// The current value, read according to the attribute's kind
+    pub fn value(&self) -> Result<AttributeValue, String> {
+        self.parse_value(&self.current)
+    }
+
+    /// The default value, read according to the attribute's kind
+    pub fn default_value(&self) -> Result<AttributeValue, String> {
+        self.parse_value(&self.default)
+    }
+}
+
+impl Cfg {
+    /// The current value of `key`, read according to its kind
+    ///
+    /// A value that does not read as its kind gives [`CfgError::Conversion`].
+    pub fn get_value<K: AsRef<str>>(&self, key: K) -> Result<AttributeValue, CfgError> {
+        let key = key.as_ref();
+        let attr = self.defined(key)?;
+        attr.value().map_err(|reason| CfgError::Conversion {
+            key: key.to_string(),
+            value: attr.shown(&attr.current).to_string(),
+            target: kind_name(attr.kind),
+            reason,
+        })
+    }
+
+    /// Set the current value of `key` from a typed value
+    ///
+    /// The value must be of the attribute's kind; it is then set as by [`Cfg::set_value`].
+    pub fn set_typed_value<K: AsRef<str>>(
+        &mut self,
+        key: K,
+        value: &AttributeValue,
+    ) -> Result<(), CfgError> {
+        let key = key.as_ref();
+        let attr = self.defined(key)?;
+        if value.kind() != attr.kind {
+            return Err(CfgError::InvalidValue(format!(
+                "'{}' holds {} values, not {}",
+                key,
+                kind_name(attr.kind),
+                kind_name(value.kind())
+            )));
+        }
+        let text = value.to_string();
+        attr.parse_value(&text)
+            .map_err(|e| CfgError::InvalidValue(format!("value for '{}': {}", key, e)))?;
+        self.set_value(key, &text)
+    }
+
+    /// The current value of `attr` in the form written to the INI file
+    ///
+    /// Flags are written as `1` or `0` and, with the `encryption` feature, secret values may be
+    /// encrypted.
+    pub(crate) fn stored_value<'a>(&self, attr: &'a Attribute) -> Result<Cow<'a, str>, CfgError> {
+        self.stored_text(attr, &attr.current)
+    }
+
+    /// `value`, a value of `attr`, in the form written to the INI file
+    pub(crate) fn stored_text<'a>(
+        &self,
+        attr: &Attribute,
+        value: &'a str,
+    ) -> Result<Cow<'a, str>, CfgError> {
+        let value = match parse_bool(value) {
+            Some(flag) if attr.kind == ValueKind::Boolean => {
+                Cow::Borrowed(if flag { "1" } else { "0" })
+            }
+            _ => Cow::Borrowed(value),
+        };
+        #[cfg(feature = "encryption")]
+        let value = self.encrypt_value(attr, value)?;
+        Ok(value)
+    }
+
+    /// The attribute of `key`, which must be defined
+    pub(crate) fn defined(&self, key: &str) -> Result<&Attribute, CfgError> {
+        self.cfg
+            .as_ref()
+            .ok_or(CfgError::Cfg())?
+            .get(key)
+            .ok_or_else(|| CfgError::UnknownKey(key.to_string()))
+    }
+}
+
+fn kind_name(kind: ValueKind) -> &'static str {
+    match kind {
+        ValueKind::Text => "text",
+        ValueKind::Integer => "integer",
+        ValueKind::Decimal => "decimal",
+        ValueKind::Boolean => "boolean",
+        ValueKind::DateTime => "datetime",
+        ValueKind::IpAddr => "IP address",
+        ValueKind::Enum => "enum",
+        ValueKind::MultiSelect => "multi-select",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryFs;
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+
+    const DEFN_DATA: &str = r#"
+        {
+            "canid" : {
+                "prompt": "CAN Id",
+                "tooltip": "The CAN Id used by the CAN Pi CAP/Zero on the CBUS",
+                "current": "100",
+                "default": "100",
+                "format": "[0-9]{1,4}",
+                "action": "Edit",
+                "kind": "Integer"
+            },
+            "ap_mode" : {
+                "prompt": "Access point mode",
+                "tooltip": "Run as a Wi-Fi access point",
+                "current": "yes",
+                "default": "0",
+                "format": ".*",
+                "action": "Edit",
+                "kind": "Boolean"
+            },
+            "router_ip" : {
+                "prompt": "Router address",
+                "tooltip": "",
+                "current": "192.168.1.1",
+                "default": "",
+                "format": ".*",
+                "action": "Edit",
+                "kind": "IpAddr"
+            },
+            "loglevel" : {
+                "prompt": "Log level",
+                "tooltip": "",
+                "current": "INFO",
+                "default": "WARN",
+                "format": ".*",
+                "action": "Edit",
+                "kind": "Enum",
+                "choices": ["INFO", "WARN", "DEBUG"]
+            },
+            "services" : {
+                "prompt": "Enabled services",
+                "tooltip": "",
+                "current": "ed, wifi",
+                "default": "",
+                "format": ".*",
+                "action": "Edit",
+                "kind": "MultiSelect",
+                "choices": ["ed", "ftp", "wifi"]
+            }
+        }"#;
+
+    fn cfg() -> Cfg {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs);
+        cfg.load_definition("defn.json")
+            .expect("definition failed to load");
+        cfg
+    }
+
+    #[test]
+    fn values_read_by_kind() {
+        let cfg = cfg();
+        assert_eq!(
+            cfg.get_value("canid").unwrap(),
+            AttributeValue::Integer(100)
+        );
+        assert_eq!(
+            cfg.get_value("ap_mode").unwrap(),
+            AttributeValue::Boolean(true)
+        );
+        assert_eq!(
+            cfg.get_value("router_ip").unwrap(),
+            AttributeValue::IpAddr(Ipv4Addr::new(192, 168, 1, 1).into())
+        );
+        let loglevel = cfg.read_attribute("loglevel").unwrap();
+        assert_eq!(
+            loglevel.default_value().unwrap(),
+            AttributeValue::Enum("WARN".into())
+        );
+        assert!(loglevel.parse_value("TRACE").is_err());
+        assert_eq!(
+            cfg.get_value("services").unwrap(),
+            AttributeValue::MultiSelect(vec!["ed".into(), "wifi".into()])
+        );
+        assert!(matches!(
+            cfg.get_value("no_such_key"),
+            Err(CfgError::UnknownKey(_))
+        ));
+    }
+
+    #[test]
+    fn typed_values_are_set_in_stored_form() {
+        let mut cfg = cfg();
+        cfg.set_typed_value("ap_mode", &AttributeValue::Boolean(false))
+            .expect("set_typed_value failed");
+        assert_eq!(cfg.get_or_default("ap_mode"), "0");
+        cfg.set_typed_value("loglevel", &AttributeValue::Enum("DEBUG".into()))
+            .expect("set_typed_value failed");
+        assert_eq!(cfg.get_or_default("loglevel"), "DEBUG");
+        let services = AttributeValue::MultiSelect(vec!["ftp".into(), "ed".into()]);
+        cfg.set_typed_value("services", &services)
+            .expect("set_typed_value failed");
+        assert_eq!(cfg.get_or_default("services"), "ftp,ed");
+        let services = AttributeValue::MultiSelect(vec!["ssh".into()]);
+        assert!(cfg.set_typed_value("services", &services).is_err());
+        assert!(cfg
+            .set_typed_value("loglevel", &AttributeValue::Enum("TRACE".into()))
+            .is_err());
+        assert!(cfg
+            .set_typed_value("canid", &AttributeValue::Text("101".into()))
+            .is_err());
+
+        let json = serde_json::to_string(&AttributeValue::Integer(101)).unwrap();
+        assert_eq!(json, r#"{"kind":"Integer","value":101}"#);
+        let value: AttributeValue = serde_json::from_str(&json).unwrap();
+        cfg.set_typed_value("canid", &value)
+            .expect("set_typed_value failed");
+        assert_eq!(cfg.try_get::<u16>("canid").unwrap(), 101);
+    }
+}
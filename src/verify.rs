@@ -0,0 +1,66 @@
+//! Checking a written INI file
+//!
+//! SD cards in a Raspberry Pi can lose or truncate writes without reporting an error.  With
+//! [`WriteOptions::verify`](crate::WriteOptions::verify) set, the INI file is read back once it
+//! has been written and compared with what was written, failing with [`CfgError::Verify`] if
+//! they differ.
+
+use crate::{CfgError, Fs};
+
+use std::path::Path;
+
+/// Check that the file at `path` in `fs` holds exactly `expected`
+pub(crate) fn verify_written(fs: &dyn Fs, path: &Path, expected: &[u8]) -> Result<(), CfgError> {
+    let written = fs.read(path)?;
+    if written == expected {
+        return Ok(());
+    }
+    let message = match written.iter().zip(expected).position(|(w, e)| w != e) {
+        Some(offset) => format!("{} differs at byte {}", path.display(), offset),
+        None => format!(
+            "{} holds {} bytes of the {} written",
+            path.display(),
+            written.len(),
+            expected.len()
+        ),
+    };
+    Err(CfgError::Verify(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cfg, MemoryFs, WriteOptions};
+    use std::sync::Arc;
+
+    #[test]
+    fn written_file_is_verified() {
+        let fs = Arc::new(MemoryFs::new());
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.load_configuration_from_str(
+            "canid=101\n",
+            r#"{
+                "canid" : {
+                    "prompt": "CAN Id", "tooltip": "", "current": "100", "default": "100",
+                    "format": "[0-9]{1,4}", "action": "Edit"
+                }
+            }"#,
+        )
+        .expect("configuration failed to load");
+        let options = WriteOptions {
+            verify: true,
+            ..WriteOptions::default()
+        };
+        cfg.write_cfg_file_with("canpi.cfg", &options)
+            .expect("write failed verification");
+
+        let path = Path::new("canpi.cfg");
+        fs.insert("canpi.cfg", "canid=1");
+        let err = verify_written(&*fs, path, b"canid=101\n").unwrap_err();
+        assert!(matches!(err, CfgError::Verify(m) if m.contains("7 bytes of the 10")));
+        fs.insert("canpi.cfg", "canid=102\n");
+        let err = verify_written(&*fs, path, b"canid=101\n").unwrap_err();
+        assert!(matches!(err, CfgError::Verify(m) if m.contains("byte 8")));
+    }
+}
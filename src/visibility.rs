@@ -0,0 +1,158 @@
+//! Conditional visibility
+//!
+//! An attribute with a [`Dependency`] in `depends_on` is only presented while another item holds
+//! a given value, e.g. the access point password only while `ap_mode` is set.  Dependencies are
+//! followed, so an item depending on a hidden item is hidden too.
+
+use crate::value::parse_bool;
+use crate::{ActionBehaviour, Attribute, Cfg, ValueKind};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+/// The value another configuration item must hold for an attribute to be presented
+pub struct Dependency {
+    /// Key of the item depended on
+    pub key: String,
+    /// Value it must hold; for a `Boolean` item any spelling of the same flag will do
+    pub value: String,
+}
+
+impl Dependency {
+    /// Check whether `attr`, the item depended on, holds the expected value
+    pub(crate) fn is_met_by(&self, attr: &Attribute, current: &str) -> bool {
+        if attr.kind == ValueKind::Boolean {
+            if let (Some(a), Some(b)) = (parse_bool(current), parse_bool(&self.value)) {
+                return a == b;
+            }
+        }
+        current.trim() == self.value.trim()
+    }
+}
+
+impl Cfg {
+    /// Whether the configuration item `key` should be presented
+    ///
+    /// It must be defined, its action must not be `Hide` and its dependency, if any, must be met
+    /// by an item that is itself visible.  A dependency on an undefined item is never met.
+    pub fn is_visible<K: AsRef<str>>(&self, key: K) -> bool {
+        let key = key.as_ref();
+        let cfg = match &self.cfg {
+            Some(cfg) => cfg,
+            None => return false,
+        };
+        let mut key = key;
+        // A chain longer than the number of items must be a cycle
+        for _ in 0..=cfg.len() {
+            let attr = match cfg.get(key) {
+                Some(attr) if attr.action != ActionBehaviour::Hide => attr,
+                _ => return false,
+            };
+            let dependency = match &attr.depends_on {
+                Some(dependency) => dependency,
+                None => return true,
+            };
+            match cfg.get(&dependency.key) {
+                Some(other)
+                    if dependency.is_met_by(other, self.get_or_default(&dependency.key)) =>
+                {
+                    key = &dependency.key
+                }
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    /// The keys of every visible configuration item, in key order
+    pub fn visible_keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = match &self.cfg {
+            Some(cfg) => cfg
+                .keys()
+                .map(String::as_str)
+                .filter(|k| self.is_visible(k))
+                .collect(),
+            None => Vec::new(),
+        };
+        keys.sort_unstable();
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryFs;
+    use std::sync::Arc;
+
+    const DEFN_DATA: &str = r#"
+        {
+            "ap_mode" : {
+                "prompt": "Access point mode",
+                "tooltip": "Run as a Wi-Fi access point",
+                "current": "0",
+                "default": "0",
+                "format": "bool",
+                "action": "Edit",
+                "kind": "Boolean"
+            },
+            "ap_ssid" : {
+                "prompt": "Access point SSID",
+                "tooltip": "",
+                "current": "canpi",
+                "default": "canpi",
+                "format": ".*",
+                "action": "Edit",
+                "depends_on": { "key": "ap_mode", "value": "true" }
+            },
+            "ap_password" : {
+                "prompt": "Access point password",
+                "tooltip": "",
+                "current": "",
+                "default": "",
+                "format": ".*",
+                "action": "Edit",
+                "depends_on": { "key": "ap_ssid", "value": "canpi" }
+            },
+            "internal" : {
+                "prompt": "",
+                "tooltip": "",
+                "current": "",
+                "default": "",
+                "format": ".*",
+                "action": "Hide"
+            },
+            "cycle" : {
+                "prompt": "",
+                "tooltip": "",
+                "current": "",
+                "default": "",
+                "format": ".*",
+                "action": "Edit",
+                "depends_on": { "key": "cycle", "value": "" }
+            }
+        }"#;
+
+    #[test]
+    fn dependencies_decide_visibility() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs);
+        cfg.load_definition("defn.json")
+            .expect("definition failed to load");
+
+        assert_eq!(cfg.visible_keys(), vec!["ap_mode"]);
+        cfg.set_value("ap_mode", "1").expect("set_value failed");
+        assert_eq!(
+            cfg.visible_keys(),
+            vec!["ap_mode", "ap_password", "ap_ssid"]
+        );
+        cfg.set_value("ap_ssid", "club").expect("set_value failed");
+        assert!(cfg.is_visible("ap_ssid"));
+        assert!(!cfg.is_visible("ap_password"));
+        assert!(!cfg.is_visible("internal"));
+        assert!(!cfg.is_visible("no_such_key"));
+    }
+}
@@ -0,0 +1,134 @@
+//! Change notification over HTTP
+//!
+//! [`WebhookNotifier`] posts the [`ChangeSummary`] of each save as JSON, e.g.
+//!
+//! ```text
+//! {"file":"/etc/canpi/canpi.cfg","backup":null,"time":1700000000,
+//!  "changes":[{"key":"canid","old":"100","new":"101","time":1700000000,"actor":null,"reason":null}]}
+//! ```
+//!
+//! The post is made as part of the save, so it gives up after [`WebhookNotifier::timeout`]
+//! rather than holding up the save while an unreachable host times out.
+
+use crate::notify::{ChangeSummary, Notifier, NotifyError};
+
+use std::time::Duration;
+
+/// How long to wait to connect to the webhook host, and then for each read or write
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug)]
+/// Posts a JSON summary of the changes to a URL after each save
+pub struct WebhookNotifier {
+    url: String,
+    agent: ureq::Agent,
+}
+
+/// An agent that gives up on the connection, and on each read or write, after `timeout`
+fn agent(timeout: Duration) -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout_connect(timeout)
+        .timeout_read(timeout)
+        .timeout_write(timeout)
+        .build()
+}
+
+impl WebhookNotifier {
+    /// Creates a notifier that posts to `url`, waiting up to five seconds to connect and then
+    /// for each read or write
+    pub fn new(url: &str) -> WebhookNotifier {
+        WebhookNotifier {
+            url: url.to_string(),
+            agent: agent(DEFAULT_TIMEOUT),
+        }
+    }
+
+    /// Wait up to `timeout` to connect and then for each read or write
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.agent = agent(timeout);
+        self
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(&self, summary: &ChangeSummary) -> Result<(), NotifyError> {
+        self.agent
+            .post(&self.url)
+            .send_json(summary.to_json())
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AuditEntry;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::thread;
+    use std::time::{Instant, SystemTime};
+
+    #[test]
+    fn summary_is_posted() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind failed");
+        let url = format!("http://{}/canpi", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("accept failed");
+            let mut reader = BufReader::new(stream);
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).expect("read failed");
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().expect("bad length");
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).expect("read failed");
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+                .expect("write failed");
+            String::from_utf8(body).expect("body not UTF-8")
+        });
+
+        let change = AuditEntry {
+            time: SystemTime::now(),
+            key: "canid".to_string(),
+            old: "100".to_string(),
+            new: "101".to_string(),
+            annotation: None,
+        };
+        let changes = [change];
+        let summary = ChangeSummary {
+            file: Path::new("canpi.cfg"),
+            backup: None,
+            changes: &changes,
+        };
+        WebhookNotifier::new(&url)
+            .notify(&summary)
+            .expect("post failed");
+        let body = server.join().expect("server failed");
+        assert!(body.contains(r#""file":"canpi.cfg""#));
+        assert!(body.contains(r#""new":"101""#));
+        assert!(WebhookNotifier::new("http://127.0.0.1:1/")
+            .notify(&summary)
+            .is_err());
+
+        let silent = TcpListener::bind("127.0.0.1:0").expect("bind failed");
+        let url = format!("http://{}/canpi", silent.local_addr().unwrap());
+        let start = Instant::now();
+        let result = WebhookNotifier::new(&url)
+            .timeout(Duration::from_millis(200))
+            .notify(&summary);
+        assert!(result.is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,137 @@
+//! Presentation hints
+//!
+//! An attribute may name the [`Widget`] a front end should present it with, such as a slider
+//! for a timeout that runs from its `min` to its `max` in steps of `step`.  Without one the widget
+//! follows from the attribute's kind, so a `Boolean` item is shown as a checkbox rather than a
+//! text box with a `[01]` format.
+
+use crate::{Attribute, ValueKind};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq, Hash)]
+/// The control a configuration item is presented with
+pub enum Widget {
+    /// An edit box
+    Text,
+    /// A checkbox, ticked when the value is true
+    Checkbox,
+    /// A number box with buttons that move the value up and down by `step`
+    Spinner,
+    /// A slider running from `min` to `max` in steps of `step`
+    Slider,
+}
+
+impl Attribute {
+    /// The control to present this item with: the `widget` given or else one suited to its kind
+    pub fn widget(&self) -> Widget {
+        match (self.widget, self.kind) {
+            (Some(widget), _) => widget,
+            (None, ValueKind::Boolean) => Widget::Checkbox,
+            (None, _) => Widget::Text,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cfg, MemoryFs};
+    use std::sync::Arc;
+
+    const DEFN_DATA: &str = r#"
+        {
+            "ap_mode" : {
+                "prompt": "Access point mode",
+                "tooltip": "Run as a Wi-Fi access point",
+                "current": "0",
+                "default": "0",
+                "format": "[01]",
+                "action": "Edit",
+                "kind": "Boolean"
+            },
+            "ap_channel" : {
+                "prompt": "Access point channel",
+                "tooltip": "",
+                "current": "6",
+                "default": "6",
+                "format": "[0-9]{1,2}",
+                "action": "Edit"
+            },
+            "timeout" : {
+                "prompt": "Timeout",
+                "tooltip": "",
+                "current": "30",
+                "default": "30",
+                "format": "u16",
+                "action": "Edit",
+                "kind": "Integer",
+                "min": 10,
+                "max": 120,
+                "step": 5,
+                "widget": "Slider"
+            },
+            "debug" : {
+                "prompt": "Debug",
+                "tooltip": "",
+                "current": "0",
+                "default": "0",
+                "format": "[01]",
+                "action": "Edit",
+                "kind": "Boolean",
+                "widget": "Text"
+            }
+        }"#;
+
+    #[test]
+    fn flags_are_checkboxes() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert("defn.json", DEFN_DATA);
+        fs.insert("canpi.cfg", "ap_mode=0\n");
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs.clone());
+        cfg.load_configuration("canpi.cfg", "defn.json")
+            .expect("configuration failed to load");
+        let widget = |key: &str| cfg.read_attribute(key).unwrap().widget();
+        assert_eq!(widget("ap_mode"), Widget::Checkbox);
+        assert_eq!(widget("ap_channel"), Widget::Text);
+        assert_eq!(widget("debug"), Widget::Text);
+        assert_eq!(widget("timeout"), Widget::Slider);
+
+        cfg.set_value("ap_mode", "Yes").expect("set_value failed");
+        assert_eq!(cfg.get_or_default("ap_mode"), "1");
+        cfg.set_value("debug", "true").expect("set_value failed");
+        assert!(cfg.set_value("ap_mode", "maybe").is_err());
+        cfg.write_cfg_file("canpi.cfg", None).expect("write failed");
+        let text = fs.contents("canpi.cfg").unwrap();
+        assert!(text.contains("ap_mode=1"));
+        assert!(text.contains("debug=1"));
+    }
+
+    #[test]
+    fn spinners_keep_to_their_steps() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.insert(
+            "defn.json",
+            r#"{
+                "retries" : {
+                    "prompt": "Retries", "tooltip": "", "current": "3", "default": "3",
+                    "format": "[0-9]{1,2}", "action": "Edit", "kind": "Integer",
+                    "min": 1, "max": 9, "step": 2, "widget": "Spinner"
+                }
+            }"#,
+        );
+        let mut cfg = Cfg::new();
+        cfg.set_fs(fs);
+        cfg.load_definition("defn.json")
+            .expect("definition failed to load");
+        assert_eq!(
+            cfg.read_attribute("retries").unwrap().widget(),
+            Widget::Spinner
+        );
+        assert_eq!(cfg.validate_value("retries", "7"), Ok(()));
+        assert!(cfg.validate_value("retries", "4").is_err());
+        assert!(cfg.validate_value("retries", "11").is_err());
+    }
+}
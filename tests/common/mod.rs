@@ -0,0 +1,50 @@
+//! Definitions and scratch files shared by the integration tests
+#![allow(dead_code)]
+
+use canpi_config::Cfg;
+use std::fs;
+use std::path::Path;
+
+/// The example definition file that most tests load
+pub const EXAMPLE_DEFN_FILE: &str = "tests/good-example-config-defn.json";
+
+/// One display only item, `canid`
+pub const CANID_DISPLAY_DEFN: &str = r#"
+    {
+        "canid" : {
+            "prompt": "CAN Id",
+            "tooltip": "The CAN Id used by the CAN Pi CAP/Zero on the CBUS",
+            "current": "100",
+            "default": "100",
+            "format": "[0-9]{1,4}",
+            "action": "Display"
+        }
+    }"#;
+
+pub fn setup_file<P: AsRef<Path>>(test_file: P, data: &str) {
+    fs::write(test_file, data).expect("file write failed");
+}
+
+pub fn teardown_file<P: AsRef<Path>>(test_file: P) {
+    fs::remove_file(test_file).expect("file deletion failed");
+}
+
+/// Load the definition `data` through the scratch file `defn_file`, which is then removed
+pub fn load_defn(defn_file: &str, data: &str) -> Cfg {
+    setup_file(defn_file, data);
+    let mut cfg = Cfg::new();
+    cfg.load_definition(defn_file)
+        .expect("definition failed to load");
+    teardown_file(defn_file);
+    cfg
+}
+
+/// Load [`EXAMPLE_DEFN_FILE`] through the scratch file `defn_file`, which is then removed
+pub fn load_example_defn(defn_file: &str) -> Cfg {
+    fs::copy(EXAMPLE_DEFN_FILE, defn_file).expect("file copy failed");
+    let mut cfg = Cfg::new();
+    cfg.load_definition(defn_file)
+        .expect("definition failed to load");
+    teardown_file(defn_file);
+    cfg
+}
@@ -0,0 +1,77 @@
+#![cfg(feature = "derive")]
+
+mod common;
+
+use canpi_config::{definition_json, CanpiConfig, CanpiDefinition, ValueKind};
+use common::{load_defn, load_example_defn};
+
+#[derive(CanpiConfig, Debug, PartialEq)]
+struct Settings {
+    canid: u16,
+    #[canpi(key = "node_number")]
+    node: u32,
+    #[canpi(skip)]
+    note: String,
+}
+
+#[test]
+fn settings_round_trip() {
+    let mut cfg = load_example_defn("scratch/derive_test.json");
+
+    let mut settings = Settings::from_cfg(&cfg).expect("from_cfg failed");
+    assert_eq!(settings.canid, 100);
+    assert_eq!(settings.note, "");
+    settings.canid = 101;
+    settings.node = 7;
+    settings.apply_to(&mut cfg).expect("apply_to failed");
+    assert_eq!(cfg.try_get::<u16>("canid").unwrap(), 101);
+    assert_eq!(Settings::from_cfg(&cfg).unwrap(), settings);
+}
+
+#[derive(CanpiConfig, CanpiDefinition)]
+struct Defined {
+    /// The CAN Id used by the CAN Pi CAP/Zero on the CBUS
+    #[canpi(
+        prompt = "CAN Id",
+        default = "100",
+        format = "[0-9]{1,4}",
+        kind = "Integer",
+        placeholder = "1 to 127"
+    )]
+    canid: u16,
+    #[canpi(
+        tooltip = "Name of the access point",
+        default = "canpi",
+        action = "Display",
+        section = "apmode"
+    )]
+    ap_ssid: String,
+    #[canpi(skip)]
+    #[allow(dead_code)]
+    scratch: bool,
+}
+
+#[test]
+fn definition_from_struct() {
+    let json = definition_json::<Defined>().expect("definition failed");
+    let cfg = load_defn("scratch/derive_defn_test.json", &json);
+
+    let canid = cfg.read_attribute("canid").unwrap();
+    assert_eq!(&*canid.prompt, "CAN Id");
+    assert_eq!(
+        &*canid.tooltip,
+        "The CAN Id used by the CAN Pi CAP/Zero on the CBUS"
+    );
+    assert_eq!(canid.kind, ValueKind::Integer);
+    assert_eq!(canid.placeholder.as_deref(), Some("1 to 127"));
+    assert_eq!(canid.units, None);
+    assert_eq!(&*cfg.read_attribute("ap_ssid").unwrap().prompt, "ap_ssid");
+    assert_eq!(
+        cfg.read_attribute("ap_ssid").unwrap().section.as_deref(),
+        Some("apmode")
+    );
+    assert!(cfg.read_attribute("scratch").is_none());
+    let defined = Defined::from_cfg(&cfg).expect("from_cfg failed");
+    assert_eq!((defined.canid, defined.ap_ssid.as_str()), (100, "canpi"));
+    assert_eq!(Defined::definition().len(), 2);
+}
@@ -0,0 +1,87 @@
+use canpi_config::{Cfg, CfgError, LoadOptions, Operation};
+use std::fs;
+use std::path::Path;
+
+const DEFN_DATA: &str = r#"
+        {
+            "canid" : {
+                "prompt": "CAN Id",
+                "tooltip": "The CAN Id used by the CAN Pi CAP/Zero on the CBUS",
+                "current": "100",
+                "default": "100",
+                "format": "[0-9]{1,4}",
+                "action": "Display"
+            },
+            "network.router_ssid" : {
+                "prompt": "Router SSID",
+                "tooltip": "Name of the network to join",
+                "current": "",
+                "default": "",
+                "format": ".*",
+                "action": "Edit"
+            }
+        }"#;
+
+fn include_options() -> LoadOptions {
+    LoadOptions {
+        include_directive: Some("!include".to_string()),
+        ..LoadOptions::default()
+    }
+}
+
+#[test]
+fn include_site_settings() {
+    let dir = "scratch/include_test";
+    fs::create_dir_all(format!("{}/site", dir)).expect("directory creation failed");
+    fs::write(format!("{}/defn.json", dir), DEFN_DATA).expect("file write failed");
+    fs::write(
+        format!("{}/site/common.cfg", dir),
+        "canid=200\n[network]\nrouter_ssid=club\n",
+    )
+    .expect("file write failed");
+    fs::write(
+        format!("{}/canpi.cfg", dir),
+        "[network]\n!include site/common.cfg\ncanid_ignored=1\n",
+    )
+    .expect("file write failed");
+
+    let mut cfg = Cfg::new();
+    let result = cfg.load_configuration_with(
+        format!("{}/canpi.cfg", dir),
+        format!("{}/defn.json", dir),
+        &include_options(),
+    );
+    fs::remove_dir_all(dir).expect("directory deletion failed");
+    result.expect("config failed to load");
+
+    let canid = cfg.read_attribute("canid").expect("canid");
+    assert_eq!(canid.current, "200");
+    assert_eq!(
+        cfg.source_file("canid"),
+        Some(Path::new("scratch/include_test/site/common.cfg"))
+    );
+    let ssid = cfg
+        .read_attribute("network.router_ssid")
+        .expect("router_ssid");
+    assert_eq!(ssid.current, "club");
+}
+
+#[test]
+fn include_cycle_is_rejected() {
+    let dir = "scratch/include_cycle_test";
+    fs::create_dir_all(dir).expect("directory creation failed");
+    fs::write(format!("{}/defn.json", dir), DEFN_DATA).expect("file write failed");
+    fs::write(format!("{}/a.cfg", dir), "canid=1\n!include b.cfg\n").expect("file write failed");
+    fs::write(format!("{}/b.cfg", dir), "!include a.cfg\n").expect("file write failed");
+
+    let mut cfg = Cfg::new();
+    let result = cfg.load_configuration_with(
+        format!("{}/a.cfg", dir),
+        format!("{}/defn.json", dir),
+        &include_options(),
+    );
+    fs::remove_dir_all(dir).expect("directory deletion failed");
+    let err = result.unwrap_err();
+    assert!(matches!(err.root(), CfgError::Include(_)));
+    assert_eq!(err.operation(), Some(Operation::Load));
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use canpi_config::Pkg;
+use common::CANID_DISPLAY_DEFN;
+use std::env;
+use std::fs;
+
+const PKG_DATA: &str = r#"
+        {
+            "good" : {
+                "cfg_path": "scratch/validate_all/good",
+                "ini_file": "canpi.cfg",
+                "json_file": "canpi.json"
+            },
+            "bad" : {
+                "cfg_path": "scratch/validate_all/bad",
+                "ini_file": "canpi.cfg",
+                "json_file": "canpi.json"
+            },
+            "missing" : {
+                "cfg_path": "scratch/validate_all/missing",
+                "ini_file": "canpi.cfg",
+                "json_file": "canpi.json"
+            }
+        }"#;
+
+fn setup_package(dir: &str, cfg_data: &str) {
+    fs::create_dir_all(dir).expect("directory creation failed");
+    fs::write(format!("{}/canpi.json", dir), CANID_DISPLAY_DEFN).expect("file write failed");
+    fs::write(format!("{}/canpi.cfg", dir), cfg_data).expect("file write failed");
+}
+
+#[test]
+fn validate_all_packages() {
+    setup_package("scratch/validate_all/good", "canid=101\n");
+    setup_package("scratch/validate_all/bad", "canid=abc\n");
+    fs::write("scratch/validate_all/packages.json", PKG_DATA).expect("file write failed");
+
+    // The files are validated as they are, whatever the environment overrides
+    env::set_var("CANPI_CANID", "101");
+    let mut pkg = Pkg::new();
+    pkg.load_packages("scratch/validate_all/packages.json")
+        .expect("package definitions failed to load");
+    let results = pkg.validate_all();
+    env::remove_var("CANPI_CANID");
+    fs::remove_dir_all("scratch/validate_all").expect("directory deletion failed");
+
+    let names: Vec<&str> = results.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(names, vec!["bad", "good", "missing"]);
+    assert_eq!(results[0].result.as_ref().expect("bad loads").len(), 1);
+    assert!(results[1].is_ok());
+    assert!(results[2].result.is_err());
+}
+
+#[test]
+fn validate_all_warnings() {
+    let dir = "scratch/validate_all_warnings";
+    setup_package(dir, "canid=101\nunknown_key=1\n");
+    let packages = format!(
+        r#"{{ "warned": {{ "cfg_path": "{}", "ini_file": "canpi.cfg", "json_file": "canpi.json" }} }}"#,
+        dir
+    );
+    fs::write(format!("{}/packages.json", dir), packages).expect("file write failed");
+    let mut pkg = Pkg::new();
+    pkg.load_packages(format!("{}/packages.json", dir))
+        .expect("package definitions failed to load");
+    let results = pkg.validate_all();
+    fs::remove_dir_all(dir).expect("directory deletion failed");
+
+    assert!(results[0].is_ok());
+    assert_eq!(
+        results[0].warnings,
+        vec![format!(
+            "key 'unknown_key' in {}/canpi.cfg is not defined",
+            dir
+        )]
+    );
+}
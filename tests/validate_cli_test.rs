@@ -0,0 +1,129 @@
+mod common;
+
+use common::{setup_file, teardown_file, CANID_DISPLAY_DEFN};
+use std::fs;
+use std::process::Command;
+
+const CFG_DATA: &str = r#"
+        canid=abc
+        node_number=5432
+        unknown_key=1
+        "#;
+
+fn run_validate(args: &[&str]) -> i32 {
+    Command::new(env!("CARGO_BIN_EXE_canpi-cfg"))
+        .arg("validate")
+        .args(args)
+        .output()
+        .expect("failed to run canpi-cfg")
+        .status
+        .code()
+        .expect("canpi-cfg terminated by signal")
+}
+
+#[test]
+fn validate_good_defn() {
+    assert_eq!(
+        run_validate(&[
+            "--defn",
+            "tests/good-example-config-defn.json",
+            "--partial-match"
+        ]),
+        0
+    );
+    // Its SSID formats, written when formats were matched anywhere in the value, reject '-'
+    assert_eq!(
+        run_validate(&["--defn", "tests/good-example-config-defn.json"]),
+        2
+    );
+}
+
+#[test]
+fn validate_bad_defn() {
+    assert_eq!(
+        run_validate(&["--defn", "tests/bad-example-config-defn.json"]),
+        3
+    );
+}
+
+#[test]
+fn validate_format_violation() {
+    let cfg_file = "scratch/validate_cli_test.cfg";
+    setup_file(cfg_file, CFG_DATA);
+    let code = run_validate(&[
+        "--defn",
+        "tests/good-example-config-defn.json",
+        "--cfg",
+        cfg_file,
+    ]);
+    teardown_file(cfg_file);
+    assert_eq!(code, 2);
+}
+
+#[test]
+fn validate_ignores_environment() {
+    let cfg_file = "scratch/validate_cli_env_test.cfg";
+    setup_file(cfg_file, CFG_DATA);
+    let code = Command::new(env!("CARGO_BIN_EXE_canpi-cfg"))
+        .args(["validate", "--defn", "tests/good-example-config-defn.json"])
+        .args(["--cfg", cfg_file, "--partial-match"])
+        .env("CANPI_CANID", "101")
+        .status()
+        .expect("failed to run canpi-cfg")
+        .code()
+        .expect("canpi-cfg terminated by signal");
+    teardown_file(cfg_file);
+    assert_eq!(code, 2);
+}
+
+#[test]
+fn validate_policy_violation() {
+    let policy_file = "scratch/validate_cli_policy.json";
+    setup_file(
+        policy_file,
+        r#"{ "rules": [ { "rule": "compare", "left": "canid", "relation": ">", "right": 200 } ] }"#,
+    );
+    let code = run_validate(&[
+        "--defn",
+        "tests/good-example-config-defn.json",
+        "--policy",
+        policy_file,
+    ]);
+    teardown_file(policy_file);
+    assert_eq!(code, 2);
+}
+
+#[test]
+fn validate_usage_error() {
+    assert_eq!(run_validate(&[]), 4);
+    assert_eq!(run_validate(&["--defn", "scratch/no_such_file.json"]), 4);
+}
+
+#[test]
+fn validate_all_exit_codes() {
+    let dir = "scratch/validate_cli_all";
+    let pkg_file = format!("{}/packages.json", dir);
+    let run = |cfg_data: &str| {
+        fs::create_dir_all(dir).expect("directory creation failed");
+        setup_file(format!("{}/canpi.json", dir), CANID_DISPLAY_DEFN);
+        setup_file(format!("{}/canpi.cfg", dir), cfg_data);
+        setup_file(
+            &pkg_file,
+            &format!(
+                r#"{{ "canpi": {{ "cfg_path": "{}", "ini_file": "canpi.cfg", "json_file": "canpi.json" }} }}"#,
+                dir
+            ),
+        );
+        let code = Command::new(env!("CARGO_BIN_EXE_canpi-cfg"))
+            .args(["validate-all", "--pkg", &pkg_file])
+            .status()
+            .expect("failed to run canpi-cfg")
+            .code()
+            .expect("canpi-cfg terminated by signal");
+        fs::remove_dir_all(dir).expect("directory deletion failed");
+        code
+    };
+    assert_eq!(run("canid=101\n"), 0);
+    assert_eq!(run("canid=101\nunknown_key=1\n"), 1);
+    assert_eq!(run("canid=abc\nunknown_key=1\n"), 2);
+}
@@ -61,34 +61,52 @@ fn teardown_file<P: AsRef<Path>>(test_file: P) {
 fn write_attr_good() {
     let cfg_file = "scratch/wattr_test.cfg";
     let defn_file = "scratch/wattr_test.json";
-    setup_file(&defn_file, DEFN_DATA);
-    setup_file(&cfg_file, CFG_DATA);
+    setup_file(defn_file, DEFN_DATA);
+    setup_file(cfg_file, CFG_DATA);
     let mut cfg = Cfg::new();
     cfg.load_configuration(&cfg_file, &defn_file)
         .expect("parameter definition failed to load");
-    let start_event_id = cfg.read_attribute("start_event_id".to_string());
+    let start_event_id = cfg.read_attribute("start_event_id");
     if let Some(sei) = start_event_id {
-        assert_eq!(sei.prompt, "Start Event Id", "Field 'prompt'");
+        assert_eq!(&*sei.prompt, "Start Event Id", "Field 'prompt'");
         assert_eq!(sei.current, "2", "Field 'current'");
         assert_eq!(sei.default, "1", "Field 'default'");
     }
     let new_start_event_id = canpi_config::Attribute {
-        prompt: "sTART eVENT iD".to_string(),
-        tooltip: "new tooltip".to_string(),
+        prompt: "sTART eVENT iD".into(),
+        tooltip: "new tooltip".into(),
         current: "1".to_string(),
         default: "2".to_string(),
-        format: "[1-8]".to_string(),
+        format: "[1-8]".into(),
         action: ActionBehaviour::Hide,
+        partial_match: false,
+        control_chars: ControlChars::Reject,
+        kind: ValueKind::Text,
+        section: None,
+        choices: Vec::new(),
+        min: None,
+        max: None,
+        step: None,
+        depends_on: None,
+        category: None,
+        display_order: None,
+        units: None,
+        placeholder: None,
+        required: false,
+        secret: false,
+        widget: None,
+        translations: None,
+        deprecated_aliases: Vec::new(),
     };
-    cfg.write_attribute("start_event_id".to_string(), &new_start_event_id).expect("attribute write failed");
-    let new_start_event_id = cfg.read_attribute("start_event_id".to_string());
+    cfg.write_attribute("start_event_id", &new_start_event_id).expect("attribute write failed");
+    let new_start_event_id = cfg.read_attribute("start_event_id");
     if let Some(nsei) = new_start_event_id {
-        assert_eq!(nsei.prompt, "sTART eVENT iD", "Field 'prompt'");
+        assert_eq!(&*nsei.prompt, "sTART eVENT iD", "Field 'prompt'");
         assert_eq!(nsei.current, "1", "Field 'current'");
         assert_eq!(nsei.default, "2", "Field 'default'");
     }
-    teardown_file(&cfg_file);
-    teardown_file(&defn_file);
+    teardown_file(cfg_file);
+    teardown_file(defn_file);
 }
 
 #[test]
@@ -96,12 +114,30 @@ fn write_attr_good() {
 fn write_attr_bad() {
     let mut cfg = Cfg::new();
     let new_start_event_id = canpi_config::Attribute {
-        prompt: "sTART eVENT iD".to_string(),
-        tooltip: "new tooltip".to_string(),
+        prompt: "sTART eVENT iD".into(),
+        tooltip: "new tooltip".into(),
         current: "1".to_string(),
         default: "2".to_string(),
-        format: "[1-8]".to_string(),
+        format: "[1-8]".into(),
         action: ActionBehaviour::Hide,
+        partial_match: false,
+        control_chars: ControlChars::Reject,
+        kind: ValueKind::Text,
+        section: None,
+        choices: Vec::new(),
+        min: None,
+        max: None,
+        step: None,
+        depends_on: None,
+        category: None,
+        display_order: None,
+        units: None,
+        placeholder: None,
+        required: false,
+        secret: false,
+        widget: None,
+        translations: None,
+        deprecated_aliases: Vec::new(),
     };
-    cfg.write_attribute("start_event_id".to_string(), &new_start_event_id).expect("attribute write failed");
+    cfg.write_attribute("start_event_id", &new_start_event_id).expect("attribute write failed");
 }
\ No newline at end of file